@@ -0,0 +1,79 @@
+//! Measures the CPU time of recording 1000 instances that share one material, which are drawn in
+//! a single batch, against 1000 instances that each have their own material.
+//!
+//! Run with `cargo run --release --example batching`.
+
+use std::time::Duration;
+use ash::vk;
+use color_eyre::Result;
+use glam::{Mat4, Vec3};
+use raxa::renderer::config::RenderConfig;
+use raxa::renderer::resources::mesh::Mesh;
+use raxa::renderer::scene::Transform;
+use raxa::renderer::shader_data::PerFrameData;
+use raxa::renderer::stats::RenderStats;
+use raxa::renderer::Renderer;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 256,
+    height: 256,
+};
+const INSTANCE_COUNT: usize = 1000;
+const FRAME_COUNT: u32 = 200;
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    env_logger::init();
+
+    for shared_material in [true, false] {
+        let (record_time, stats) = measure(shared_material)?;
+        println!(
+            "{} instances, {}: {:?} per frame recording the scene, {} pipeline binds, {} \
+            descriptor binds",
+            INSTANCE_COUNT,
+            if shared_material { "one shared material" } else { "a material each" },
+            record_time,
+            stats.pipeline_binds,
+            stats.descriptor_binds,
+        );
+    }
+
+    Ok(())
+}
+
+/// Average scene record time over `FRAME_COUNT` frames, and the stats of the last one
+fn measure(shared_material: bool) -> Result<(Duration, RenderStats)> {
+    let mut renderer = Renderer::new_headless(EXTENT, &RenderConfig::default())?;
+    let model = renderer.add_model(vec![Mesh::new_quad()])?;
+    let shared = renderer.create_material()?;
+    // A grid of small quads covering clip space
+    let side = (INSTANCE_COUNT as f32).sqrt().ceil() as usize;
+    let scale = 1.0 / side as f32;
+    for index in 0..INSTANCE_COUNT {
+        let material = if shared_material { shared } else { renderer.create_material()? };
+        let (x, y) = ((index % side) as f32, (index / side) as f32);
+        let translation = Vec3::new(
+            (2.0 * x + 1.0) * scale - 1.0,
+            (2.0 * y + 1.0) * scale - 1.0,
+            0.0,
+        );
+        renderer.add_instance(model, material, Transform {
+            translation,
+            scale: Vec3::splat(scale),
+            ..Default::default()
+        });
+    }
+    renderer.set_frame_data(PerFrameData::new(Mat4::IDENTITY, 0.1, 100.0));
+
+    let image = renderer.get_device().create_offscreen_image(EXTENT.width, EXTENT.height)?;
+    let mut record_time = Duration::ZERO;
+    for _ in 0..FRAME_COUNT {
+        renderer.draw_to_image(image.image, EXTENT, vk::ImageLayout::GENERAL)?;
+        record_time += renderer.get_last_frame_stats().scene_record_time;
+    }
+    unsafe {
+        renderer.get_device().logical.device_wait_idle()?;
+    }
+
+    Ok((record_time / FRAME_COUNT, renderer.get_last_frame_stats()))
+}
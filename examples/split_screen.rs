@@ -0,0 +1,157 @@
+//! Renders the same scene from two cameras side by side, each into its own half of the image
+//! through `Renderer::set_viewport`, and writes the result to `split_screen.png`.
+//!
+//! Every frame clears the whole draw image, so each half is drawn in a frame of its own and the
+//! halves are put together after reading them back.
+//!
+//! Run with `cargo run --example split_screen`.
+
+use std::time::{Duration, Instant};
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use glam::Vec3;
+use raxa::renderer::camera::Camera;
+use raxa::renderer::config::RenderConfig;
+use raxa::renderer::resources::mesh::Mesh;
+use raxa::renderer::resources::readback::ReadbackBuffer;
+use raxa::renderer::scene::Transform;
+use raxa::renderer::shader_data::PerMaterialData;
+use raxa::renderer::Renderer;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 512,
+    height: 256,
+};
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    env_logger::init();
+
+    let mut renderer = Renderer::new_headless(EXTENT, &RenderConfig::default())?;
+    let model = renderer.add_model(vec![Mesh::new_quad()])?;
+    let solid = |color: [u8; 4]| image::DynamicImage::ImageRgba8(
+        image::RgbaImage::from_pixel(1, 1, image::Rgba(color)),
+    );
+    // A red quad in front of a green one
+    for (color, translation) in [
+        ([255, 0, 0, 255], Vec3::new(-0.5, 0.0, 0.5)),
+        ([0, 255, 0, 255], Vec3::new(0.5, 0.0, -0.5)),
+    ] {
+        let texture = renderer.add_texture(&solid(color))?;
+        let material = renderer.create_material()?;
+        renderer.set_material_data(material, &PerMaterialData {
+            texture_index: texture.get_index(),
+            sampler_index: renderer
+                .get_texture_sampler_index(texture)
+                .ok_or_else(|| eyre!("Texture has no sampler"))?,
+        })?;
+        renderer.add_instance(model, material, Transform {
+            translation,
+            ..Default::default()
+        });
+    }
+
+    // One camera looks from the front, the other from the side
+    let mut cameras = [Camera::new(), Camera::new()];
+    cameras[0].set_position(Vec3::new(0.0, 0.0, 4.0));
+    cameras[1].set_position(Vec3::new(4.0, 1.0, 0.0));
+    for camera in &mut cameras {
+        camera.look_at(Vec3::ZERO);
+    }
+
+    let half_width = EXTENT.width / 2;
+    let mut output = image::RgbaImage::new(EXTENT.width, EXTENT.height);
+    for (view_index, camera) in cameras.iter().enumerate() {
+        let x_offset = view_index as u32 * half_width;
+        renderer.set_viewport(Some(vk::Rect2D {
+            offset: vk::Offset2D {
+                x: x_offset as i32,
+                y: 0,
+            },
+            extent: vk::Extent2D {
+                width: half_width,
+                height: EXTENT.height,
+            },
+        }))?;
+        // The aspect ratio comes from the viewport, so set the camera after it
+        renderer.set_camera(camera);
+
+        let pixels = draw_and_read_back(&mut renderer)?;
+        for y in 0..EXTENT.height {
+            for x in x_offset..x_offset + half_width {
+                let pixel = pixels[(y * EXTENT.width + x) as usize];
+                output.put_pixel(x, y, image::Rgba(pixel));
+            }
+        }
+    }
+
+    output.save("split_screen.png")?;
+    println!("Wrote split_screen.png");
+
+    Ok(())
+}
+
+/// Draw a frame into a new image and read it back as RGBA8 pixels, row by row
+fn draw_and_read_back(renderer: &mut Renderer) -> Result<Vec<[u8; 4]>> {
+    let device = renderer.get_device();
+    let image = device.create_offscreen_image(EXTENT.width, EXTENT.height)?;
+    let size = EXTENT.width as u64 * EXTENT.height as u64 * 4;
+    let buffer = device.create_buffer(
+        size,
+        16,
+        vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+        vk_mem::MemoryUsage::AutoPreferDevice,
+        false,
+    )?;
+    let mut readback = ReadbackBuffer::new(size, 1, device)?;
+
+    renderer.draw_to_image(image.image, EXTENT, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)?;
+
+    let device = renderer.get_device();
+    let queue = device.graphics_queue.clone();
+    device.immediate_submit_on(&queue, |cmd, device| {
+        let barriers = [vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)];
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(&barriers);
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(vk::Extent3D {
+                width: EXTENT.width,
+                height: EXTENT.height,
+                depth: 1,
+            });
+        unsafe {
+            device.cmd_pipeline_barrier2(cmd, &dependency_info);
+            device.cmd_copy_image_to_buffer(
+                cmd,
+                image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[region],
+            );
+        }
+        Ok(())
+    })?;
+    readback.schedule_copy(buffer.buffer, 0, &queue, device)?;
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(pixels) = readback.try_read_as::<[u8; 4]>()? {
+            return Ok(pixels);
+        }
+        if Instant::now() > deadline {
+            return Err(eyre!("Readback did not finish within 5 seconds"));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
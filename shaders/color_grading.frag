@@ -0,0 +1,44 @@
+#version 450
+
+// The frame's sRGB color image, so sampling returns linear colors
+layout(set = 0, binding = 0) uniform sampler2D in_image;
+// Maps display-referred colors to graded ones, with red along x
+layout(set = 0, binding = 1) uniform sampler3D lut;
+
+layout(push_constant) uniform ColorGradingData {
+    vec4 domain_min;
+    vec4 domain_max;
+    float size;
+} grading;
+
+layout(location = 0) in vec2 in_texcoord;
+
+layout(location = 0) out vec4 out_color;
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(
+        color * 12.92,
+        1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055,
+        step(vec3(0.0031308), color)
+    );
+}
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(
+        color / 12.92,
+        pow((color + 0.055) / 1.055, vec3(2.4)),
+        step(vec3(0.04045), color)
+    );
+}
+
+void main() {
+    // .cube files are authored against display-referred colors
+    vec3 color = linear_to_srgb(texture(in_image, in_texcoord).rgb);
+    vec3 domain_min = grading.domain_min.rgb;
+    vec3 domain_max = grading.domain_max.rgb;
+    vec3 coords = clamp((color - domain_min) / (domain_max - domain_min), 0.0, 1.0);
+    // The first and last entries sit at the centers of the edge texels, not at the edges
+    coords = (coords * (grading.size - 1.0) + 0.5) / grading.size;
+
+    out_color = vec4(srgb_to_linear(texture(lut, coords).rgb), 1.0);
+}
@@ -0,0 +1,9 @@
+#version 450
+
+layout(location = 0) out vec2 out_texcoord;
+
+void main() {
+    // One triangle that covers the screen, generated from the vertex index without a vertex buffer
+    out_texcoord = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(out_texcoord * 2.0 - 1.0, 0.0, 1.0);
+}
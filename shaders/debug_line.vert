@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec4 in_position;
+layout(location = 1) in vec4 in_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // `DebugLines` transforms the vertices on the CPU
+    gl_Position = in_position;
+    out_color = in_color;
+}
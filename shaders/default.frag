@@ -37,9 +37,9 @@ layout(location = 0) out vec4 out_color;
 
 void main() {
     uint object_index = per_draw.object_index;
-    uint material_index = per_draw.material_index;
-    uint texture_index = per_material.data[material_index].texture_index;
-    uint sampler_index = per_material.data[material_index].sampler_index;
+    // The material's descriptor set points the buffer at that material's data alone
+    uint texture_index = per_material.data[0].texture_index;
+    uint sampler_index = per_material.data[0].sampler_index;
 
     out_color = texture(
         sampler2D(
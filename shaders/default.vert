@@ -39,13 +39,13 @@ layout(location = 0) out vec2 out_texcoord;
 
 void main() {
     uint object_index = per_draw.object_index;
-    uint material_index = per_draw.material_index;
-    uint texture_index = per_material.data[material_index].texture_index;
-    uint sampler_index = per_material.data[material_index].sampler_index;
+    // The material's descriptor set points the buffer at that material's data alone
+    uint texture_index = per_material.data[0].texture_index;
+    uint sampler_index = per_material.data[0].sampler_index;
 
     mat4 model = per_object.data[object_index].model;
     mat4 viewproj = per_frame.data.viewproj;
 
-    gl_Position = viewproj * model * vec4(0.0, 0.0, 0.0, 1.0);
+    gl_Position = viewproj * model * vec4(in_position, 1.0);
     out_texcoord = in_texcoord;
 }
\ No newline at end of file
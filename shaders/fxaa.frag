@@ -0,0 +1,63 @@
+#version 450
+
+// The sRGB draw image of the frame, so sampling returns linear colors
+layout(set = 0, binding = 0) uniform sampler2D in_image;
+
+layout(push_constant) uniform FxaaData {
+    vec2 inverse_size;
+} fxaa;
+
+layout(location = 0) in vec2 in_texcoord;
+
+layout(location = 0) out vec4 out_color;
+
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+// Edges are found on the luma of the gamma encoded color, which follows perceived brightness much
+// more closely than linear luma does. sqrt is a cheap approximation of the sRGB curve.
+float luma(vec3 linear_color) {
+    return dot(sqrt(linear_color), vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    vec2 uv = in_texcoord;
+    vec2 texel = fxaa.inverse_size;
+
+    float luma_nw = luma(texture(in_image, uv + vec2(-1.0, -1.0) * texel).rgb);
+    float luma_ne = luma(texture(in_image, uv + vec2(1.0, -1.0) * texel).rgb);
+    float luma_sw = luma(texture(in_image, uv + vec2(-1.0, 1.0) * texel).rgb);
+    float luma_se = luma(texture(in_image, uv + vec2(1.0, 1.0) * texel).rgb);
+    vec3 rgb_m = texture(in_image, uv).rgb;
+    float luma_m = luma(rgb_m);
+
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, which runs perpendicular to the luma gradient
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    float dir_reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * REDUCE_MUL),
+        REDUCE_MIN
+    );
+    float inverse_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * inverse_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+    vec3 rgb_a = 0.5 * (
+        texture(in_image, uv + dir * (1.0 / 3.0 - 0.5)).rgb
+        + texture(in_image, uv + dir * (2.0 / 3.0 - 0.5)).rgb
+    );
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        texture(in_image, uv + dir * -0.5).rgb
+        + texture(in_image, uv + dir * 0.5).rgb
+    );
+
+    // The wider blur crossed another edge, so fall back to the narrow one
+    float luma_b = luma(rgb_b);
+    vec3 color = (luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b;
+    out_color = vec4(color, 1.0);
+}
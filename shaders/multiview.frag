@@ -0,0 +1,53 @@
+#version 450
+#extension GL_EXT_nonuniform_qualifier : require
+
+const uint VIEW_COUNT = 2;
+
+struct PerFrameData {
+    mat4 viewprojs[VIEW_COUNT];
+    float near;
+    float far;
+    float _padding[2];
+};
+struct PerMaterialData {
+    uint texture_index;
+    uint sampler_index;
+};
+struct PerObjectData {
+    mat4 model;
+};
+
+layout(set = 0, binding = 0) uniform PerFrameBuffer {
+    PerFrameData data;
+} per_frame;
+layout(set = 0, binding = 1) buffer PerMaterialBuffer {
+    PerMaterialData data[];
+} per_material;
+layout(set = 0, binding = 2) buffer PerObjectBuffer {
+    PerObjectData data[];
+} per_object;
+layout(set = 0, binding = 3) uniform sampler samplers[];
+layout(set = 0, binding = 4) uniform texture2D textures[];
+
+layout(push_constant) uniform PerDrawData {
+    uint object_index;
+    uint material_index;
+} per_draw;
+
+layout(location = 0) in vec2 in_texcoord;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    uint object_index = per_draw.object_index;
+    // The material's descriptor set points the buffer at that material's data alone
+    uint texture_index = per_material.data[0].texture_index;
+    uint sampler_index = per_material.data[0].sampler_index;
+
+    out_color = texture(
+        sampler2D(
+            textures[nonuniformEXT(texture_index)],
+            samplers[nonuniformEXT(sampler_index)]
+        ),
+        in_texcoord
+    );
+}
\ No newline at end of file
@@ -0,0 +1,37 @@
+#version 450
+#extension GL_EXT_nonuniform_qualifier : require
+
+struct PerMaterialData {
+    uint texture_index;
+    uint sampler_index;
+};
+
+layout(set = 0, binding = 1) buffer PerMaterialBuffer {
+    PerMaterialData data[];
+} per_material;
+layout(set = 0, binding = 3) uniform sampler samplers[];
+layout(set = 0, binding = 4) uniform texture2D textures[];
+
+layout(push_constant) uniform PerDrawData {
+    uint object_index;
+    uint material_index;
+} per_draw;
+
+layout(location = 0) in vec2 in_texcoord;
+layout(location = 1) in vec4 in_color;
+layout(location = 2) flat in uint in_texture_index;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // Every sprite in the batch shares the sprite material's sampler, but picks its own texture
+    uint sampler_index = per_material.data[0].sampler_index;
+
+    out_color = in_color * texture(
+        sampler2D(
+            textures[nonuniformEXT(in_texture_index)],
+            samplers[nonuniformEXT(sampler_index)]
+        ),
+        in_texcoord
+    );
+}
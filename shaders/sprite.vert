@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec2 in_texcoord;
+layout(location = 2) in vec4 in_color;
+layout(location = 3) in uint in_texture_index;
+
+layout(location = 0) out vec2 out_texcoord;
+layout(location = 1) out vec4 out_color;
+layout(location = 2) flat out uint out_texture_index;
+
+void main() {
+    // The sprite batch transforms the vertices on the CPU
+    gl_Position = vec4(in_position, 1.0);
+    out_texcoord = in_texcoord;
+    out_color = in_color;
+    out_texture_index = in_texture_index;
+}
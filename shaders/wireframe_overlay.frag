@@ -0,0 +1,23 @@
+#version 450
+
+layout(push_constant) uniform WireframeData {
+    vec4 color;
+    float thickness;
+} wireframe;
+
+layout(location = 0) in vec3 in_barycentric;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // A barycentric coordinate is 0 on the edge opposite its corner. Dividing by its change per
+    // pixel gives the distance to each edge in pixels, whatever the triangle's size on screen.
+    vec3 distance = in_barycentric / max(fwidth(in_barycentric), vec3(1e-6));
+    float edge_distance = min(min(distance.x, distance.y), distance.z);
+    // Fade over one pixel to keep the edges smooth
+    float coverage = clamp(wireframe.thickness * 0.5 + 0.5 - edge_distance, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    out_color = vec4(wireframe.color.rgb, wireframe.color.a * coverage);
+}
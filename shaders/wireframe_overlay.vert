@@ -0,0 +1,12 @@
+#version 450
+
+layout(location = 0) in vec4 in_position;
+layout(location = 1) in vec3 in_barycentric;
+
+layout(location = 0) out vec3 out_barycentric;
+
+void main() {
+    // `WireframeOverlayPass` transforms the vertices on the CPU
+    gl_Position = in_position;
+    out_barycentric = in_barycentric;
+}
@@ -2,12 +2,21 @@ use std::f32::consts::PI;
 use glam::{FloatExt, Mat4, Vec2, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
 use winit::dpi::PhysicalPosition;
 use winit::error::ExternalError;
-use winit::window::Window;
+use winit::window::{CursorGrabMode, Window};
 use crate::app::input_state::InputState;
-use crate::renderer::camera::{calculate_direction, calculate_pitch, calculate_yaw, Camera};
+use crate::app::key_bindings::{Action, KeyBindings};
+use raxa::renderer::camera::{
+    calculate_direction,
+    calculate_pitch,
+    calculate_yaw,
+    Camera,
+    ProjectionKind,
+};
 
 pub struct CameraController {
     camera: Camera,
+    // The camera before the last fixed update, to interpolate from when drawing between updates
+    previous_camera: Camera,
 
     rotation_sensitivity: f32,
     rotation_smoothing_speed: f32,
@@ -19,6 +28,9 @@ pub struct CameraController {
     zoom_smoothing_speed: f32,
     zoom_desired_distance: f32,
     zoom_current_distance: f32,
+
+    // In distances to the pivot per second
+    move_speed: f32,
 }
 
 impl CameraController {
@@ -26,6 +38,7 @@ impl CameraController {
         let zoom_current_distance = camera.get_pivot().distance(camera.get_position());
         let rotation_current_pivot_to_eye = camera.get_position() - camera.get_pivot();
         Self {
+            previous_camera: camera.clone(),
             camera,
 
             rotation_sensitivity: 2.0,
@@ -38,23 +51,41 @@ impl CameraController {
             zoom_smoothing_speed: 4.0,
             zoom_desired_distance: zoom_current_distance,
             zoom_current_distance,
-        }
-    }
 
-    pub fn get_camera(&self) -> &Camera {
-        &self.camera
+            move_speed: 1.0,
+        }
     }
 
     pub fn get_camera_mut(&mut self) -> &mut Camera {
         &mut self.camera
     }
 
+    /// The camera `alpha` of the way from where it was before the last fixed update to where it is
+    /// now, so motion stays smooth when frames don't line up with the updates
+    pub fn get_interpolated_camera(&self, alpha: f32) -> Camera {
+        let previous = &self.previous_camera;
+        let mut camera = self.camera.clone();
+        if let (
+            ProjectionKind::Orthographic { height: previous_height },
+            ProjectionKind::Orthographic { height },
+        ) = (previous.get_projection_kind(), self.camera.get_projection_kind()) {
+            let height = previous_height.lerp(height, alpha);
+            camera.set_projection_kind(ProjectionKind::Orthographic { height });
+        }
+        camera.set_position(previous.get_position().lerp(self.camera.get_position(), alpha));
+        camera.look_at(previous.get_pivot().lerp(self.camera.get_pivot(), alpha));
+        camera
+    }
+
     pub fn process_input(
         &mut self,
         input_state: &mut InputState,
+        key_bindings: &KeyBindings,
         window: &Window,
         delta_time: f32,
     ) {
+        self.previous_camera.clone_from(&self.camera);
+
         let window_size = window.inner_size();
         let window_center = Vec2::new(
             window_size.width as f32 / 2.0,
@@ -68,8 +99,10 @@ impl CameraController {
                 self.set_window_mouse_pos(window, window_center);
                 input_state.mouse_curr_pos = window_center;
                 input_state.mouse_prev_pos = input_state.mouse_curr_pos;
+                Self::grab_cursor(window);
             }
             else if input_state.mouse_right_just_released {
+                Self::release_cursor(window);
                 window.set_cursor_visible(true);
                 // Reset the cursor position to the position where the right mouse button was pressed
                 self.set_window_mouse_pos(window, input_state.mouse_right_just_pressed_pos);
@@ -78,10 +111,16 @@ impl CameraController {
             }
         }
 
-        if input_state.mouse_right_down {
+        if input_state.mouse_right_down && input_state.mouse_raw_motion_available {
+            // The hidden cursor can be ignored, raw motion doesn't stop at the screen edges
             self.set_desired_rotation_pivot_to_eye(
-                input_state.mouse_prev_pos,
-                input_state.mouse_curr_pos,
+                input_state.mouse_raw_delta,
+                window_size.width as f32,
+                window_size.height as f32,
+            );
+        } else if input_state.mouse_right_down {
+            self.set_desired_rotation_pivot_to_eye(
+                input_state.mouse_curr_pos - input_state.mouse_prev_pos,
                 window_size.width as f32,
                 window_size.height as f32,
             );
@@ -100,6 +139,7 @@ impl CameraController {
 
         self.set_desired_zoom_distance(input_state.mouse_wheel_delta_y * self.zoom_sensitivity);
 
+        self.update_movement(input_state, key_bindings, delta_time);
         self.update_zoom_lerp(delta_time);
         self.update_rotation_slerp(delta_time);
     }
@@ -123,8 +163,7 @@ impl CameraController {
 
     fn set_desired_rotation_pivot_to_eye(
         &mut self,
-        prev_mouse_pos: Vec2,
-        curr_mouse_pos: Vec2,
+        mouse_delta: Vec2,
         viewport_width: f32,
         viewport_height: f32,
     ) {
@@ -133,8 +172,8 @@ impl CameraController {
         // Calculate the amount of rotation given the mouse movement
         let delta_angle_x = 2.0 * PI / viewport_width; // Left to right = 2*PI = 360deg
         let delta_angle_y = PI / viewport_height; // Top to bottom = PI = 180deg
-        let angle_x = (prev_mouse_pos.x - curr_mouse_pos.x) * delta_angle_x * self.rotation_sensitivity;
-        let angle_y = (prev_mouse_pos.y - curr_mouse_pos.y) * delta_angle_y * self.rotation_sensitivity;
+        let angle_x = -mouse_delta.x * delta_angle_x * self.rotation_sensitivity;
+        let angle_y = -mouse_delta.y * delta_angle_y * self.rotation_sensitivity;
 
         if angle_x == 0.0 && angle_y == 0.0 {
             return;
@@ -163,6 +202,35 @@ impl CameraController {
         }
     }
 
+    /// Slide the camera and its pivot along the camera's axes while the movement keys are held.
+    /// The speed scales with the distance to the pivot, so it feels the same zoomed in and out.
+    fn update_movement(
+        &mut self,
+        input_state: &InputState,
+        key_bindings: &KeyBindings,
+        delta_time: f32,
+    ) {
+        let axis = |positive: Action, negative: Action| {
+            let held = |action: Action| key_bindings.is_held(action, input_state) as i32 as f32;
+            held(positive) - held(negative)
+        };
+        let cam = &self.camera;
+        let direction = cam.get_forward() * axis(Action::MoveForward, Action::MoveBackward)
+            + cam.get_right() * axis(Action::MoveRight, Action::MoveLeft)
+            + cam.get_world_up() * axis(Action::MoveUp, Action::MoveDown);
+        if direction == Vec3::ZERO {
+            return;
+        }
+
+        let offset = direction.normalize()
+            * self.move_speed
+            * self.zoom_current_distance
+            * delta_time;
+        let pivot = cam.get_pivot() + offset;
+        self.camera.set_position(self.camera.get_position() + offset);
+        self.camera.look_at(pivot);
+    }
+
     fn update_rotation_slerp(&mut self, delta_time: f32) {
         let t = 1.0 - (-self.rotation_smoothing_speed * delta_time).exp();
         //let t = self.rotation_smoothing_speed * delta_time;
@@ -177,10 +245,18 @@ impl CameraController {
     fn update_zoom_lerp(&mut self, delta_time: f32) {
         let t = 1.0 - (-self.zoom_smoothing_speed * delta_time).exp();
         //let t = self.zoom_smoothing_speed * delta_time;
+        let previous_distance = self.zoom_current_distance;
         self.zoom_current_distance = self.zoom_current_distance.lerp(
             self.zoom_desired_distance,
             t,
         );
+        // Moving the eye doesn't change an orthographic view, so scale what it covers along with
+        // the distance. The distance stays clamped between the clip planes, which bounds the
+        // height too.
+        if let ProjectionKind::Orthographic { height } = self.camera.get_projection_kind() {
+            let height = height * self.zoom_current_distance / previous_distance;
+            self.camera.set_projection_kind(ProjectionKind::Orthographic { height });
+        }
         self.camera.set_position(self.camera.get_pivot() - self.camera.get_forward() * self.zoom_current_distance);
     }
 
@@ -201,6 +277,24 @@ impl CameraController {
             .unwrap();
     }
 
+    /// Keep the cursor from leaving the window while looking around. `Locked` pins it in place,
+    /// but only some platforms support it, like macOS and Wayland. `Confined` works on the rest,
+    /// like Windows and X11, where the cursor is recentered at the window border instead.
+    fn grab_cursor(window: &Window) {
+        let result = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+        if let Err(e) = result {
+            log::warn!("Failed to grab the cursor: {e}");
+        }
+    }
+
+    fn release_cursor(window: &Window) {
+        if let Err(e) = window.set_cursor_grab(CursorGrabMode::None) {
+            log::error!("Failed to release the cursor: {e}");
+        }
+    }
+
     fn mouse_just_left_border(
         &self,
         input_state: &InputState,
@@ -1,5 +1,7 @@
+use std::collections::HashSet;
 use glam::Vec2;
-use winit::event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event::{DeviceEvent, ElementState, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::keyboard::{Key, PhysicalKey};
 
 #[derive(Default)]
 pub struct InputState {
@@ -15,6 +17,19 @@ pub struct InputState {
     pub mouse_right_just_released_pos: Vec2,
     pub mouse_just_left: bool,
     pub mouse_just_entered: bool,
+    // Relative mouse motion since the last frame, without the OS acceleration and without
+    // stopping at the screen edges like the cursor does
+    pub mouse_raw_delta: Vec2,
+    // Set once raw motion arrives, since not every platform delivers it
+    pub mouse_raw_motion_available: bool,
+
+    // Held modifier keys, either side of the keyboard
+    pub shift_down: bool,
+    pub ctrl_down: bool,
+    pub alt_down: bool,
+    // Held keys, by their position on the keyboard and by what they type, see `Binding`
+    pub physical_keys_down: HashSet<PhysicalKey>,
+    pub logical_keys_down: HashSet<Key>,
 }
 
 impl InputState {
@@ -66,10 +81,43 @@ impl InputState {
             WindowEvent::CursorEntered { .. } => {
                 self.mouse_just_entered = true;
             }
+            WindowEvent::KeyboardInput {
+                event,
+                ..
+            } => {
+                match event.state {
+                    ElementState::Pressed => {
+                        self.physical_keys_down.insert(event.physical_key);
+                        self.logical_keys_down.insert(event.logical_key.clone());
+                    }
+                    ElementState::Released => {
+                        self.physical_keys_down.remove(&event.physical_key);
+                        self.logical_keys_down.remove(&event.logical_key);
+                    }
+                }
+            }
+            // Keys released while another window has focus never report it
+            WindowEvent::Focused(false) => {
+                self.physical_keys_down.clear();
+                self.logical_keys_down.clear();
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                let state = modifiers.state();
+                self.shift_down = state.shift_key();
+                self.ctrl_down = state.control_key();
+                self.alt_down = state.alt_key();
+            }
             _ => {}
         }
     }
 
+    pub fn process_device_events(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.mouse_raw_delta += Vec2::new(delta.0 as f32, delta.1 as f32);
+            self.mouse_raw_motion_available = true;
+        }
+    }
+
     /// Reset the input states for the next frame.
     pub fn reset_frame(&mut self) {
         self.mouse_wheel_delta_y = 0.0;
@@ -78,5 +126,6 @@ impl InputState {
         self.mouse_right_just_released = false;
         self.mouse_just_left = false;
         self.mouse_just_entered = false;
+        self.mouse_raw_delta = Vec2::ZERO;
     }
 }
\ No newline at end of file
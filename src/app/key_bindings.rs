@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use winit::event::KeyEvent;
+use winit::keyboard::{Key, KeyCode, NamedKey, PhysicalKey};
+use crate::app::input_state::InputState;
+
+/// Something the app does in response to a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    ToggleRedraws,
+    TogglePaused,
+    // Advance one frame while paused
+    Step,
+    // Only does something with the `renderdoc` feature
+    CaptureFrame,
+    ToggleVsync,
+    ToggleBorderlessFullscreen,
+    ToggleExclusiveFullscreen,
+    Quit,
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+}
+
+/// A key that triggers an action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Binding {
+    /// The key at this position on the keyboard, whatever the layout prints on it. For bindings
+    /// chosen for where they are, like movement on WASD, which would otherwise be scattered on
+    /// AZERTY or Dvorak.
+    Physical(KeyCode),
+    /// The key that types this in the current layout. For bindings named after what they do, like
+    /// R for toggling redraws, which users look for by its label.
+    Logical(Key),
+}
+
+impl Binding {
+    fn matches(&self, physical_key: PhysicalKey, logical_key: &Key) -> bool {
+        match self {
+            Self::Physical(code) => physical_key == PhysicalKey::Code(*code),
+            Self::Logical(key) => logical_key == key,
+        }
+    }
+}
+
+/// Which key triggers each action
+pub struct KeyBindings {
+    keys: HashMap<Action, Binding>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let character = |c: &str| Binding::Logical(Key::Character(c.into()));
+        let named = |key: NamedKey| Binding::Logical(Key::Named(key));
+        let mut key_bindings = Self {
+            keys: HashMap::new(),
+        };
+        for (action, binding) in [
+            (Action::ToggleRedraws, character("r")),
+            (Action::TogglePaused, named(NamedKey::Space)),
+            (Action::Step, character(".")),
+            (Action::CaptureFrame, named(NamedKey::F12)),
+            (Action::ToggleVsync, character("v")),
+            (Action::ToggleBorderlessFullscreen, named(NamedKey::F11)),
+            (Action::ToggleExclusiveFullscreen, named(NamedKey::F10)),
+            (Action::Quit, named(NamedKey::Escape)),
+            (Action::MoveForward, Binding::Physical(KeyCode::KeyW)),
+            (Action::MoveBackward, Binding::Physical(KeyCode::KeyS)),
+            (Action::MoveLeft, Binding::Physical(KeyCode::KeyA)),
+            (Action::MoveRight, Binding::Physical(KeyCode::KeyD)),
+            (Action::MoveUp, Binding::Physical(KeyCode::KeyE)),
+            (Action::MoveDown, Binding::Physical(KeyCode::KeyQ)),
+        ] {
+            key_bindings.set_binding(action, binding);
+        }
+        key_bindings
+    }
+}
+
+impl KeyBindings {
+    pub fn get_binding(&self, action: Action) -> Option<&Binding> {
+        self.keys.get(&action)
+    }
+
+    /// Bind `action` to `binding` instead of its current key. Another action bound to the same key
+    /// loses its binding, so a key never triggers two actions.
+    pub fn set_binding(&mut self, action: Action, binding: Binding) {
+        self.keys.retain(|_, existing| *existing != binding);
+        self.keys.insert(action, binding);
+    }
+
+    /// Whether the key bound to `action` is held down, for actions that last as long as the key
+    /// is held, like movement
+    pub fn is_held(&self, action: Action, input_state: &InputState) -> bool {
+        match self.get_binding(action) {
+            Some(Binding::Physical(code)) => {
+                input_state.physical_keys_down.contains(&PhysicalKey::Code(*code))
+            }
+            Some(Binding::Logical(key)) => input_state.logical_keys_down.contains(key),
+            None => false,
+        }
+    }
+
+    /// The action that the key of `event` triggers, if any. A key can match both a physical and
+    /// a logical binding; the physical one wins, since it was chosen for that exact key.
+    pub fn get_action(&self, event: &KeyEvent) -> Option<Action> {
+        self.get_action_for_key(event.physical_key, &event.logical_key)
+    }
+
+    /// Like `get_action`, for the key at `physical_key` that types `logical_key`
+    pub fn get_action_for_key(
+        &self,
+        physical_key: PhysicalKey,
+        logical_key: &Key,
+    ) -> Option<Action> {
+        let matching = |physical: bool| {
+            self.keys
+                .iter()
+                .find(|(_, binding)| {
+                    matches!(binding, Binding::Physical(_)) == physical
+                        && binding.matches(physical_key, logical_key)
+                })
+                .map(|(action, _)| *action)
+        };
+        matching(true).or_else(|| matching(false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remapped_action_follows_its_new_key() {
+        let mut key_bindings = KeyBindings::default();
+        let w = (PhysicalKey::Code(KeyCode::KeyW), Key::Character("w".into()));
+        let up = (PhysicalKey::Code(KeyCode::ArrowUp), Key::Named(NamedKey::ArrowUp));
+        assert_eq!(key_bindings.get_action_for_key(w.0, &w.1), Some(Action::MoveForward));
+        assert_eq!(key_bindings.get_action_for_key(up.0, &up.1), None);
+
+        key_bindings.set_binding(Action::MoveForward, Binding::Physical(KeyCode::ArrowUp));
+        assert_eq!(key_bindings.get_action_for_key(w.0, &w.1), None);
+        assert_eq!(key_bindings.get_action_for_key(up.0, &up.1), Some(Action::MoveForward));
+    }
+
+    #[test]
+    fn binding_a_taken_key_unbinds_the_other_action() {
+        let mut key_bindings = KeyBindings::default();
+        let s = (PhysicalKey::Code(KeyCode::KeyS), Key::Character("s".into()));
+
+        key_bindings.set_binding(Action::MoveForward, Binding::Physical(KeyCode::KeyS));
+        assert_eq!(key_bindings.get_action_for_key(s.0, &s.1), Some(Action::MoveForward));
+        assert_eq!(key_bindings.get_binding(Action::MoveBackward), None);
+    }
+}
@@ -1,35 +1,54 @@
 mod input_state;
 mod camera_controller;
+mod key_bindings;
 
-use super::renderer::Renderer;
+use raxa::renderer::Renderer;
 use color_eyre::Result;
 use std::sync::Arc;
 use std::time::Instant;
+use ash::vk;
 use color_eyre::eyre::OptionExt;
 use winit::application::ApplicationHandler;
-use winit::event::{ElementState, KeyEvent, StartCause, WindowEvent};
+use winit::event::{DeviceEvent, DeviceId, ElementState, KeyEvent, StartCause, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop};
-use winit::keyboard::{Key, NamedKey};
 use winit::window::{Window, WindowId};
 use crate::app::camera_controller::CameraController;
 use crate::app::input_state::InputState;
-use crate::renderer::camera::Camera;
+use crate::app::key_bindings::{Action, KeyBindings};
+use raxa::renderer::camera::Camera;
+use raxa::renderer::config::{FullscreenMode, PresentModePreference};
 
 pub struct App {
     window: Option<Arc<Window>>,
     renderer: Option<Renderer>,
     event_loop: Option<EventLoop<()>>,
     camera_controller: CameraController,
+    key_bindings: KeyBindings,
 
     // State
     input_state: InputState,
     prev_frame_time: Instant,
     delta_time_secs: f32,
+    // Frames drawn since the FPS readout in the window title was last updated
+    fps_frame_count: u32,
+    fps_update_time: Instant,
+    // Simulation time not yet consumed by fixed updates
+    update_accumulator_secs: f32,
+    // Debug mode: while paused, nothing updates or renders until a single step is requested
+    paused: bool,
+    step_requested: bool,
+    // Nothing updates or renders while the window is hidden, e.g. minimized or covered
+    occluded: bool,
     request_redraws: bool,
     close_requested: bool,
 }
 
 impl App {
+    const FPS_UPDATE_INTERVAL_SECS: f32 = 1.0;
+    const FIXED_TIMESTEP_SECS: f32 = 1.0 / 60.0;
+    // Drop simulation time beyond this after a long stall instead of trying to catch up
+    const MAX_UPDATES_PER_FRAME: u32 = 8;
+
     pub fn new() -> Result<Self> {
         let event_loop = EventLoop::new()?;
         let camera = Camera::new();
@@ -40,10 +59,17 @@ impl App {
             renderer: None,
             event_loop: Some(event_loop),
             camera_controller,
+            key_bindings: KeyBindings::default(),
 
             input_state: InputState::default(),
             prev_frame_time: Instant::now(),
             delta_time_secs: 0.0,
+            fps_frame_count: 0,
+            fps_update_time: Instant::now(),
+            update_accumulator_secs: 0.0,
+            paused: false,
+            step_requested: false,
+            occluded: false,
             request_redraws: false,
             close_requested: false,
         })
@@ -57,6 +83,138 @@ impl App {
         event_loop.run_app(self)?;
         Ok(())
     }
+
+    /// Run as many fixed updates as the elapsed time calls for. The time left over is drawn by
+    /// interpolating between the last two updates.
+    fn update(&mut self, delta_time_secs: f32) {
+        self.update_accumulator_secs += delta_time_secs;
+        let mut update_count = 0;
+        while self.update_accumulator_secs >= Self::FIXED_TIMESTEP_SECS {
+            if update_count == Self::MAX_UPDATES_PER_FRAME {
+                self.update_accumulator_secs = 0.0;
+                break;
+            }
+            self.fixed_update(Self::FIXED_TIMESTEP_SECS);
+            self.update_accumulator_secs -= Self::FIXED_TIMESTEP_SECS;
+            update_count += 1;
+        }
+    }
+
+    /// Advance the simulation by exactly `delta_time_secs`, which keeps frame stepping
+    /// deterministic
+    fn fixed_update(&mut self, delta_time_secs: f32) {
+        if let Some(window) = self.window.as_ref() {
+            self.camera_controller.process_input(
+                &mut self.input_state,
+                &self.key_bindings,
+                window,
+                delta_time_secs,
+            );
+        }
+        self.input_state.reset_frame();
+    }
+
+    /// Pause or resume updating and rendering, for stepping through frames one at a time
+    fn toggle_paused(&mut self) {
+        self.paused = !self.paused;
+        self.step_requested = false;
+        // Don't replay the time spent paused
+        self.update_accumulator_secs = 0.0;
+        log::debug!("paused: {}", self.paused);
+        if !self.paused && let Some(window) = self.window.as_ref() {
+            window.request_redraw();
+        }
+    }
+
+    /// Stop updating and rendering while the window can't be seen. When it shows again, the time
+    /// it was hidden is dropped, so the camera smoothing doesn't jump to catch up.
+    fn set_occluded(&mut self, occluded: bool) {
+        self.occluded = occluded;
+        if occluded {
+            return;
+        }
+        let now = Instant::now();
+        self.prev_frame_time = now;
+        self.delta_time_secs = 0.0;
+        self.update_accumulator_secs = 0.0;
+        self.fps_frame_count = 0;
+        self.fps_update_time = now;
+        if let Some(window) = self.window.as_ref() {
+            window.request_redraw();
+        }
+    }
+
+    /// While paused, run one fixed update and render one frame
+    fn request_step(&mut self) {
+        if !self.paused {
+            return;
+        }
+        self.step_requested = true;
+        if let Some(window) = self.window.as_ref() {
+            window.request_redraw();
+        }
+    }
+
+    /// Go fullscreen in `mode`, or back to windowed if the window is already fullscreen
+    fn toggle_fullscreen(&mut self, mode: FullscreenMode) {
+        let renderer = self.renderer.as_mut().unwrap();
+        let new_mode = match renderer.get_fullscreen() {
+            Some(_) => None,
+            None => Some(mode),
+        };
+        if let Err(e) = renderer.set_fullscreen(new_mode) {
+            log::warn!("Failed to change fullscreen mode: {e}");
+        } else {
+            log::debug!("fullscreen: {:?}", new_mode);
+        }
+    }
+
+    /// Switch between vsync and the low latency present mode, e.g. to compare frame times
+    fn toggle_vsync(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        let preference = match renderer.get_present_mode() {
+            Some(vk::PresentModeKHR::FIFO) => PresentModePreference::LowLatency,
+            _ => PresentModePreference::Vsync,
+        };
+        if let Err(e) = renderer.set_present_mode(preference) {
+            log::warn!("Failed to change present mode: {e}");
+        } else {
+            log::debug!("present mode: {:?}", renderer.get_present_mode());
+        }
+    }
+
+    /// Resize events can arrive before the renderer is created, which sizes itself to the window
+    fn request_resize(&mut self) {
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.request_resize();
+        }
+    }
+
+    /// Show the FPS and the last frame's render stats in the window title about once a second
+    fn update_debug_readout(&mut self) {
+        self.fps_frame_count += 1;
+        let elapsed_secs = self.fps_update_time.elapsed().as_secs_f32();
+        if elapsed_secs < Self::FPS_UPDATE_INTERVAL_SECS {
+            return;
+        }
+
+        let fps = self.fps_frame_count as f32 / elapsed_secs;
+        let stats = self.renderer.as_ref().unwrap().get_last_frame_stats();
+        self.window.as_ref().unwrap().set_title(&format!(
+            "raxa | {:.0} fps | {:.2} ms recording | {} draws | {} tris | {} instances | \
+            {} pipeline binds | {} descriptor binds",
+            fps,
+            stats.scene_record_time.as_secs_f64() * 1000.0,
+            stats.draw_calls,
+            stats.triangles,
+            stats.instances,
+            stats.pipeline_binds,
+            stats.descriptor_binds,
+        ));
+
+        self.fps_frame_count = 0;
+        self.fps_update_time = Instant::now();
+    }
 }
 
 impl ApplicationHandler for App {
@@ -73,10 +231,18 @@ impl ApplicationHandler for App {
                     .create_window(Window::default_attributes())
                     .unwrap()
             ));
+
+            // Resuming after a suspend, so the renderer needs a surface for the new window
+            if let Some(renderer) = self.renderer.as_mut() {
+                renderer.resume(self.window.clone().unwrap()).unwrap();
+            }
         }
 
         if self.renderer.is_none() {
-            self.renderer = Some(Renderer::new(self.window.clone()).unwrap());
+            let mut renderer = Renderer::new(self.window.clone().unwrap()).unwrap();
+            let scale_factor = renderer.get_scale_factor() as f32;
+            renderer.get_sprite_batch_mut().set_scale_factor(scale_factor);
+            self.renderer = Some(renderer);
         }
     }
 
@@ -91,7 +257,7 @@ impl ApplicationHandler for App {
         window_id: WindowId,
         event: WindowEvent
     ) {
-        if window_id != self.window.as_ref().unwrap().id() {
+        if self.window.as_ref().is_none_or(|window| window_id != window.id()) {
             return;
         }
 
@@ -102,28 +268,74 @@ impl ApplicationHandler for App {
                 self.close_requested = true;
             }
             WindowEvent::Resized(_new_size) => {
-                self.renderer.as_mut().unwrap().request_resize();
+                self.request_resize();
             }
-            WindowEvent::ScaleFactorChanged { .. } => {
-                self.renderer.as_mut().unwrap().request_resize();
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if let Some(renderer) = self.renderer.as_mut() {
+                    renderer.get_sprite_batch_mut().set_scale_factor(scale_factor as f32);
+                }
+                self.request_resize();
+            }
+            WindowEvent::Occluded(occluded) => {
+                self.set_occluded(occluded);
             }
             WindowEvent::RedrawRequested => {
-                self.renderer.as_mut().unwrap().draw().unwrap();
+                if self.occluded {
+                    return;
+                }
+                if self.paused {
+                    if !self.step_requested {
+                        return;
+                    }
+                    self.step_requested = false;
+                    self.fixed_update(Self::FIXED_TIMESTEP_SECS);
+                } else {
+                    self.update(self.delta_time_secs);
+                }
+                // Draw between the last two fixed updates. A single step shows its own result.
+                let alpha = if self.paused {
+                    1.0
+                } else {
+                    self.update_accumulator_secs / Self::FIXED_TIMESTEP_SECS
+                };
+                let camera = self.camera_controller.get_interpolated_camera(alpha);
+                let renderer = self.renderer.as_mut().unwrap();
+                renderer.set_camera(&camera);
+                renderer.draw().unwrap();
+                self.update_debug_readout();
             }
             WindowEvent::KeyboardInput {
-                event:
-                KeyEvent {
-                    logical_key: key,
+                event: ref key_event @ KeyEvent {
                     state: ElementState::Pressed,
                     ..
                 },
                 ..
-            } => match key.as_ref() {
-                Key::Character("r") => {
+            } => match self.key_bindings.get_action(key_event) {
+                Some(Action::ToggleRedraws) => {
                     self.request_redraws = !self.request_redraws;
                     log::info!("request_redraws: {}", self.request_redraws);
                 }
-                Key::Named(NamedKey::Escape) => {
+                Some(Action::TogglePaused) => {
+                    self.toggle_paused();
+                }
+                Some(Action::Step) => {
+                    self.request_step();
+                }
+                #[cfg(feature = "renderdoc")]
+                Some(Action::CaptureFrame) => {
+                    self.renderer.as_mut().unwrap().capture_next_frame();
+                    self.window.as_ref().unwrap().request_redraw();
+                }
+                Some(Action::ToggleVsync) => {
+                    self.toggle_vsync();
+                }
+                Some(Action::ToggleBorderlessFullscreen) => {
+                    self.toggle_fullscreen(FullscreenMode::Borderless);
+                }
+                Some(Action::ToggleExclusiveFullscreen) => {
+                    self.toggle_fullscreen(FullscreenMode::Exclusive);
+                }
+                Some(Action::Quit) => {
                     self.close_requested = true;
                 }
                 _ => {}
@@ -132,14 +344,21 @@ impl ApplicationHandler for App {
         }
     }
 
-    /*
-    fn device_event(&mut self, event_loop: &ActiveEventLoop, device_id: DeviceId, event: DeviceEvent) {
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        _device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        self.input_state.process_device_events(&event);
     }
-     */
 
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
-        if self.request_redraws {
-            self.window.as_ref().unwrap().request_redraw();
+        if self.request_redraws
+            && !self.occluded
+            && let Some(window) = self.window.as_ref()
+        {
+            window.request_redraw();
         }
 
         if self.close_requested {
@@ -147,10 +366,16 @@ impl ApplicationHandler for App {
         }
     }
 
-    /*
-    fn suspended(&mut self, event_loop: &ActiveEventLoop) {
+    /// Platforms like Android destroy the window's surface while the app is suspended, so drop
+    /// the window and the renderer's swapchain. The renderer keeps its device and resources.
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.suspend().unwrap();
+        }
+        self.window = None;
     }
 
+    /*
     fn exiting(&mut self, event_loop: &ActiveEventLoop) {
     }
 
@@ -0,0 +1,4 @@
+extern crate vk_mem;
+
+pub mod prelude;
+pub mod renderer;
@@ -1,7 +1,4 @@
-extern crate vk_mem;
-
-pub mod app;
-pub mod renderer;
+mod app;
 
 use color_eyre::Result;
 use app::App;
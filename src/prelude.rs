@@ -0,0 +1,35 @@
+//! Re-exports the types most programs need to set up a renderer and fill a scene, so they can
+//! be imported with a single `use raxa::prelude::*`.
+
+pub use crate::renderer::builder::RendererBuilder;
+pub use crate::renderer::camera::{
+    Camera,
+    ProjectionKind,
+};
+pub use crate::renderer::config::{
+    AntiAliasingMode,
+    CoordinateSystem,
+    Handedness,
+    PresentModePreference,
+    RenderConfig,
+    UpAxis,
+    WireframeOverlay,
+};
+pub use crate::renderer::resources::material::{
+    ComputeMaterialFactoryBuilder,
+    GraphicsMaterialFactoryBuilder,
+};
+pub use crate::renderer::resources::mesh::Mesh;
+pub use crate::renderer::resources::model::Model;
+pub use crate::renderer::resources::sampler::SamplerDesc;
+pub use crate::renderer::resources::texture::{ArrayTexture, ColorTexture};
+pub use crate::renderer::resources::vertex::Vertex;
+pub use crate::renderer::scene::{
+    ArrayTextureHandle,
+    InstanceHandle,
+    MaterialHandle,
+    ModelHandle,
+    TextureHandle,
+    Transform,
+};
+pub use crate::renderer::Renderer;
@@ -0,0 +1,100 @@
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::path::PathBuf;
+use std::sync::Arc;
+use winit::window::Window;
+use crate::renderer::config::{
+    AntiAliasingMode,
+    CoordinateSystem,
+    PresentModePreference,
+    RenderConfig,
+    WireframeOverlay,
+};
+use crate::renderer::Renderer;
+
+/// Collects the settings for a `Renderer` and creates it, either for a window with `with_window`
+/// or offscreen with `headless`
+#[derive(Default)]
+pub struct RendererBuilder {
+    config: RenderConfig,
+    window: Option<Arc<Window>>,
+    headless_extent: Option<vk::Extent2D>,
+}
+
+impl RendererBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the whole config, including settings made earlier like `with_present_mode`
+    pub fn with_config(mut self, config: RenderConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_window(mut self, window: Arc<Window>) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Render offscreen into draw images of the given size, see `Renderer::draw_to_image`
+    pub fn headless(mut self, width: u32, height: u32) -> Self {
+        self.headless_extent = Some(vk::Extent2D { width, height });
+        self
+    }
+
+    /// Only used with a window; falls back to `FIFO` if the surface doesn't support it
+    pub fn with_present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.config.present_mode = Some(present_mode);
+        self
+    }
+
+    /// Like `with_present_mode`, picking the mode from `preference`
+    pub fn with_present_mode_preference(self, preference: PresentModePreference) -> Self {
+        self.with_present_mode(preference.into())
+    }
+
+    pub fn with_anti_aliasing(mut self, anti_aliasing: AntiAliasingMode) -> Self {
+        self.config.anti_aliasing = anti_aliasing;
+        self
+    }
+
+    pub fn with_wireframe_overlay(mut self, wireframe_overlay: WireframeOverlay) -> Self {
+        self.config.wireframe_overlay = Some(wireframe_overlay);
+        self
+    }
+
+    pub fn with_downscale_oversized_textures(mut self, downscale: bool) -> Self {
+        self.config.downscale_oversized_textures = downscale;
+        self
+    }
+
+    pub fn with_import_coordinate_system(mut self, coordinate_system: CoordinateSystem) -> Self {
+        self.config.import_coordinate_system = coordinate_system;
+        self
+    }
+
+    pub fn with_max_bindless_descriptors(mut self, max_descriptors: u32) -> Self {
+        self.config.max_bindless_descriptors = Some(max_descriptors);
+        self
+    }
+
+    pub fn with_pipeline_cache_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.pipeline_cache_path = Some(path.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Renderer> {
+        match (self.window, self.headless_extent) {
+            (Some(window), None) => Renderer::new_with_config(window, &self.config),
+            (None, Some(extent)) => Renderer::new_headless(extent, &self.config),
+            (Some(_), Some(_)) => Err(eyre!(
+                "Renderer cannot be both windowed and headless; use either with_window or headless"
+            )),
+            (None, None) => Err(eyre!(
+                "Renderer needs a window or a headless extent; use either with_window or headless"
+            )),
+        }
+    }
+}
@@ -1,15 +1,41 @@
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Quat, Vec3, Vec4};
+use crate::renderer::shader_data::PerFrameData;
 
+/// Where pixel (0, 0) is when the camera is in 2D mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScreenOrigin {
+    /// Y grows downwards, like UI and most image formats
+    #[default]
+    TopLeft,
+    /// Y grows upwards, like a regular graph
+    BottomLeft,
+}
+
+/// How the camera projects the scene outside of 2D mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProjectionKind {
+    /// Things shrink with distance. `fov_y_deg` is the vertical field of view in degrees.
+    Perspective { fov_y_deg: f32 },
+    /// Things keep their size at any distance. `height` is how many world units the view covers
+    /// vertically.
+    Orthographic { height: f32 },
+}
+
+#[derive(Clone)]
 pub struct Camera {
     position: Vec3,
     forward: Vec3,
     up: Vec3,
     right: Vec3,
     world_up: Vec3,
-    fov_y_deg: f32,
+    projection: ProjectionKind,
     near: f32,
     far: f32,
     pivot: Vec3,
+    // Orthographic projection in pixel coordinates for sprites and UI when set
+    mode_2d: Option<ScreenOrigin>,
+    // Map the near plane to depth 1 and the far plane to 0 in the 3D projections
+    reverse_z: bool,
 }
 
 impl Camera {
@@ -22,10 +48,14 @@ impl Camera {
             up: Vec3::Y,
             right: Vec3::X,
             world_up: Vec3::Y,
-            fov_y_deg: Self::DEFAULT_FOV_Y_DEG,
+            projection: ProjectionKind::Perspective {
+                fov_y_deg: Self::DEFAULT_FOV_Y_DEG,
+            },
             near: 0.1,
             far: 100.0,
             pivot: Vec3::ZERO,
+            mode_2d: None,
+            reverse_z: false,
         }
     }
 
@@ -51,7 +81,22 @@ impl Camera {
         self.get_proj_mat(window) * self.get_view_mat()
     }
 
+    /// Like `get_viewproj_mat`, for a target of `width` by `height` pixels instead of a window
+    pub fn get_viewproj_mat_for_size(&self, width: f32, height: f32) -> Mat4 {
+        self.get_proj_mat_for_size(width, height) * self.get_view_mat()
+    }
+
+    /// What the shaders read from the per-frame uniform buffer when drawing a target of `width` by
+    /// `height` pixels with this camera
+    pub fn get_frame_data(&self, width: f32, height: f32) -> PerFrameData {
+        PerFrameData::new(self.get_viewproj_mat_for_size(width, height), self.near, self.far)
+    }
+
+    /// In 2D mode only the x and y of the position are used, to pan by that many pixels
     pub fn get_view_mat(&self) -> Mat4 {
+        if self.mode_2d.is_some() {
+            return Mat4::from_translation(Vec3::new(-self.position.x, -self.position.y, 0.0));
+        }
         Mat4::look_to_rh(self.position, self.forward, self.up)
     }
 
@@ -60,12 +105,122 @@ impl Camera {
         window: &winit::window::Window,
     ) -> Mat4 {
         let size = window.inner_size();
-        let aspect_ratio = size.width as f32 / size.height as f32;
-        Mat4::perspective_rh(
-            self.fov_y_deg.to_radians(),
-            aspect_ratio,
-            self.near,
-            self.far,
+        self.get_proj_mat_for_size(size.width as f32, size.height as f32)
+    }
+
+    /// Like `get_proj_mat`, for a target of `width` by `height` pixels instead of a window
+    pub fn get_proj_mat_for_size(&self, width: f32, height: f32) -> Mat4 {
+        if self.mode_2d.is_some() {
+            return self.get_ortho_proj_mat(width, height);
+        }
+        self.get_perspective_proj_mat(width / height)
+    }
+
+    /// Right-handed projection of the camera's `ProjectionKind`, which is perspective unless
+    /// `set_projection_kind` says otherwise, ignoring 2D mode. Depth runs from 0 at the near plane
+    /// to 1 at the far plane, or the other way around with reverse-Z.
+    pub fn get_perspective_proj_mat(&self, aspect_ratio: f32) -> Mat4 {
+        let (near, far) = self.get_depth_range();
+        match self.projection {
+            ProjectionKind::Perspective { fov_y_deg } => {
+                Mat4::perspective_rh(fov_y_deg.to_radians(), aspect_ratio, near, far)
+            }
+            ProjectionKind::Orthographic { height } => {
+                let (half_width, half_height) = (height * aspect_ratio * 0.5, height * 0.5);
+                Mat4::orthographic_rh(-half_width, half_width, -half_height, half_height, near, far)
+            }
+        }
+    }
+
+    pub fn set_projection_kind(&mut self, projection: ProjectionKind) {
+        self.projection = projection;
+    }
+
+    pub fn get_projection_kind(&self) -> ProjectionKind {
+        self.projection
+    }
+
+    /// Map the near plane to depth 1 and the far plane to 0 in the 3D projections, which
+    /// spreads the precision of floating point depth buffers much more evenly. Depth tests then
+    /// have to pass on greater values and depth has to be cleared to 0.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    pub fn get_reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    // Planes that depth 0 and 1 map to. Swapping them is all reverse-Z takes.
+    fn get_depth_range(&self) -> (f32, f32) {
+        if self.reverse_z {
+            (self.far, self.near)
+        } else {
+            (self.near, self.far)
+        }
+    }
+
+    /// Switch between the perspective projection and a 2D orthographic projection where one unit
+    /// is one pixel, with the origin at the given corner
+    pub fn set_2d_mode(&mut self, origin: Option<ScreenOrigin>) {
+        self.mode_2d = origin;
+    }
+
+    pub fn get_2d_mode(&self) -> Option<ScreenOrigin> {
+        self.mode_2d
+    }
+
+    /// Orthographic projection covering `width` by `height` pixels. Depth runs from z = 0 (near)
+    /// to z = -1 (far), so layers can be given small negative z values. Without 2D mode the origin
+    /// is the top left.
+    pub fn get_ortho_proj_mat(&self, width: f32, height: f32) -> Mat4 {
+        // Vulkan clip space has y pointing down, so "bottom" maps to the top of the screen
+        match self.mode_2d.unwrap_or_default() {
+            ScreenOrigin::TopLeft => Mat4::orthographic_rh(0.0, width, 0.0, height, 0.0, 1.0),
+            ScreenOrigin::BottomLeft => Mat4::orthographic_rh(0.0, width, height, 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// View matrix for an eye whose pose is given relative to the camera, such as one located by an
+    /// XR runtime in its tracking space
+    pub fn get_eye_view_mat(
+        &self,
+        eye_position: Vec3,
+        eye_orientation: Quat,
+    ) -> Mat4 {
+        let eye_to_camera = Mat4::from_rotation_translation(eye_orientation, eye_position);
+        eye_to_camera.inverse() * self.get_view_mat()
+    }
+
+    /// Projection matrix for an asymmetric eye frustum. The angles are in radians from the view
+    /// direction, with left and down negative.
+    pub fn get_eye_proj_mat(
+        &self,
+        angle_left: f32,
+        angle_right: f32,
+        angle_up: f32,
+        angle_down: f32,
+    ) -> Mat4 {
+        let tan_left = angle_left.tan();
+        let tan_right = angle_right.tan();
+        let tan_up = angle_up.tan();
+        let tan_down = angle_down.tan();
+        let tan_width = tan_right - tan_left;
+        let tan_height = tan_up - tan_down;
+        // Same depth mapping as `Mat4::perspective_rh`
+        let (near, far) = self.get_depth_range();
+        let r = far / (near - far);
+
+        Mat4::from_cols(
+            Vec4::new(2.0 / tan_width, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 2.0 / tan_height, 0.0, 0.0),
+            Vec4::new(
+                (tan_right + tan_left) / tan_width,
+                (tan_up + tan_down) / tan_height,
+                r,
+                -1.0,
+            ),
+            Vec4::new(0.0, 0.0, r * near, 0.0),
         )
     }
 
@@ -128,3 +283,71 @@ pub fn calculate_direction(pitch: f32, yaw: f32) -> Vec3 {
         yaw.sin() * pitch.cos(),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    // Normalized device coordinates of `point` seen by `camera` on a `width` by `height` target
+    fn project(camera: &Camera, point: Vec3, width: f32, height: f32) -> Vec3 {
+        camera.get_viewproj_mat_for_size(width, height).project_point3(point)
+    }
+
+    #[test]
+    fn pivot_projects_to_the_screen_center() {
+        let mut camera = Camera::new();
+        let pivot = Vec3::new(1.0, -2.0, 3.0);
+        camera.look_at(pivot);
+        camera.set_position(Vec3::new(4.0, 5.0, 10.0));
+
+        for reverse_z in [false, true] {
+            camera.set_reverse_z(reverse_z);
+            let ndc = project(&camera, pivot, 1280.0, 720.0);
+            assert!(ndc.x.abs() < EPSILON && ndc.y.abs() < EPSILON, "{:?}", ndc);
+            assert!((0.0..=1.0).contains(&ndc.z), "{:?}", ndc);
+        }
+    }
+
+    #[test]
+    fn reverse_z_maps_the_near_plane_to_one() {
+        let mut camera = Camera::new();
+        let near_point = camera.get_position() + camera.get_forward() * camera.near;
+        let far_point = camera.get_position() + camera.get_forward() * camera.far;
+
+        assert!(project(&camera, near_point, 1.0, 1.0).z.abs() < EPSILON);
+        assert!((project(&camera, far_point, 1.0, 1.0).z - 1.0).abs() < EPSILON);
+        camera.set_reverse_z(true);
+        assert!((project(&camera, near_point, 1.0, 1.0).z - 1.0).abs() < EPSILON);
+        assert!(project(&camera, far_point, 1.0, 1.0).z.abs() < EPSILON);
+    }
+    #[test]
+    fn orthographic_ndc_is_linear_in_view_space() {
+        let mut camera = Camera::new();
+        camera.set_projection_kind(ProjectionKind::Orthographic { height: 4.0 });
+        let (width, height) = (200.0, 100.0);
+        let (near, far) = (camera.near, camera.far);
+        // 8 by 4 world units are visible, centered on the view direction
+        let at = |right: f32, up: f32, distance: f32| {
+            project(
+                &camera,
+                camera.get_position()
+                    + camera.get_right() * right
+                    + camera.get_up() * up
+                    + camera.get_forward() * distance,
+                width,
+                height,
+            )
+        };
+
+        for distance in [near, 1.0, 50.0, far] {
+            let depth = (distance - near) / (far - near);
+            for (right, up) in [(0.0, 0.0), (4.0, 2.0), (-2.0, 1.0), (1.0, -0.5)] {
+                let ndc = at(right, up, distance);
+                let expected = Vec3::new(right / 4.0, up / 2.0, depth);
+                assert!((ndc - expected).abs().max_element() < 1e-4, "{:?} != {:?}", ndc, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::path::PathBuf;
+use ash::vk;
+use glam::{Mat4, Vec4};
+
+/// Options for creating a `Renderer`
+#[derive(Debug, Clone)]
+pub struct RenderConfig {
+    /// Allow picking a CPU implementation like llvmpipe when there is no GPU. It renders very
+    /// slowly, so disabling this makes device selection fail instead, which is clearer on CI.
+    pub allow_software_rendering: bool,
+    /// Log every block of device memory the allocator allocates or frees, at debug level under the
+    /// `raxa::vulkan_allocations` target. Very chatty, so it is off by default.
+    pub log_vulkan_allocations: bool,
+    /// Present mode of the swapchain. An unsupported mode falls back to `FIFO`, which is always
+    /// supported, with a warning. `None` prefers `MAILBOX` and also falls back to `FIFO`. Can be
+    /// changed later with `Renderer::set_present_mode`.
+    pub present_mode: Option<vk::PresentModeKHR>,
+    /// Sizes of the bindless descriptor tables. Checked against the device limits when the
+    /// renderer is created.
+    pub bindless_limits: BindlessLimits,
+    /// Descriptors that all bindless descriptor sets may hold together. Every material takes a set
+    /// sized by `bindless_limits`, so this caps how many materials exist at once. `None` allows
+    /// as many as the device does, which is checked when the renderer is created.
+    pub max_bindless_descriptors: Option<u32>,
+    /// How edges are smoothed
+    pub anti_aliasing: AntiAliasingMode,
+    /// Draw the edges of every triangle in the scene over the shaded geometry. Can be changed
+    /// later with `Renderer::set_wireframe_overlay`.
+    pub wireframe_overlay: Option<WireframeOverlay>,
+    /// Shrink textures larger than the device's maximum image dimension to fit when they are
+    /// added, instead of failing
+    pub downscale_oversized_textures: bool,
+    /// Coordinate system the meshes passed to `Renderer::add_model` are authored in. They are
+    /// converted to the renderer's Y-up, right-handed system when added. Can be changed later with
+    /// `Renderer::set_import_coordinate_system`.
+    pub import_coordinate_system: CoordinateSystem,
+    /// File to load the pipeline cache from when the renderer is created and save it to when the
+    /// renderer is dropped, which makes creating the same pipelines faster on later runs. A file
+    /// from another device or driver is ignored. `None` keeps the cache in memory only.
+    pub pipeline_cache_path: Option<PathBuf>,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            allow_software_rendering: true,
+            log_vulkan_allocations: false,
+            present_mode: None,
+            bindless_limits: BindlessLimits::default(),
+            max_bindless_descriptors: None,
+            anti_aliasing: AntiAliasingMode::default(),
+            wireframe_overlay: None,
+            downscale_oversized_textures: false,
+            import_coordinate_system: CoordinateSystem::default(),
+            pipeline_cache_path: None,
+        }
+    }
+}
+
+/// Number of descriptors in each bindless table, i.e. how many of each resource can be bound at
+/// once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BindlessLimits {
+    pub samplers: u32,
+    pub sampled_images: u32,
+}
+
+impl Default for BindlessLimits {
+    fn default() -> Self {
+        Self {
+            samplers: 16,
+            sampled_images: 1024,
+        }
+    }
+}
+
+/// How frames are paced against the display's refresh, as a friendlier way to pick a present mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresentModePreference {
+    /// Wait for the vertical blank, without tearing (`FIFO`)
+    Vsync,
+    /// Replace the queued frame with the newest one at each vertical blank, without tearing
+    /// (`MAILBOX`)
+    LowLatency,
+    /// Present right away, which can tear (`IMMEDIATE`). Useful for benchmarking.
+    Immediate,
+}
+
+impl From<PresentModePreference> for vk::PresentModeKHR {
+    fn from(preference: PresentModePreference) -> Self {
+        match preference {
+            PresentModePreference::Vsync => vk::PresentModeKHR::FIFO,
+            PresentModePreference::LowLatency => vk::PresentModeKHR::MAILBOX,
+            PresentModePreference::Immediate => vk::PresentModeKHR::IMMEDIATE,
+        }
+    }
+}
+
+/// Anti-aliasing applied to the draw images
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AntiAliasingMode {
+    #[default]
+    None,
+    /// Multisampling with this many samples per pixel. Not supported yet, since the draw images
+    /// are single-sampled; creating a renderer with it fails.
+    Msaa(u32),
+    /// Fast approximate anti-aliasing, a fullscreen pass after the scene and sprites. Cheap, but
+    /// slightly blurs textures and text along with the edges.
+    Fxaa,
+}
+
+/// Look of the wireframe overlay. The edges are found from barycentric coordinates in the fragment
+/// shader, so any thickness works without the `wide_lines` device feature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WireframeOverlay {
+    /// Alpha blends the edges with the shaded geometry
+    pub color: [f32; 4],
+    /// Width of the edges in pixels
+    pub thickness: f32,
+}
+
+impl Default for WireframeOverlay {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            thickness: 1.0,
+        }
+    }
+}
+
+/// Axis that points up in a coordinate system
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UpAxis {
+    /// Like glTF and the renderer itself
+    #[default]
+    Y,
+    /// Like Blender, 3ds Max and Unreal
+    Z,
+}
+
+/// Whether X, Y and Z follow the right hand or the left hand rule
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+/// Coordinate system of an asset. The default, Y-up and right-handed with -Z forward, is the one
+/// the renderer and its camera work in, so assets in it are used as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoordinateSystem {
+    pub up: UpAxis,
+    pub handedness: Handedness,
+}
+
+impl CoordinateSystem {
+    /// Matrix that takes positions from this coordinate system to the renderer's. X is kept and the
+    /// other two axes are arranged around the up axis:
+    /// - Y-up, right-handed: identity
+    /// - Y-up, left-handed: `(x, y, z) -> (x, y, -z)`
+    /// - Z-up, right-handed: `(x, y, z) -> (x, z, -y)`, a -90 degree rotation around X
+    /// - Z-up, left-handed: `(x, y, z) -> (x, z, y)`
+    ///
+    /// The left-handed cases mirror, so they also reverse the winding of triangles.
+    pub fn get_conversion_matrix(&self) -> Mat4 {
+        let (y_axis, z_axis) = match (self.up, self.handedness) {
+            (UpAxis::Y, Handedness::Right) => (Vec4::Y, Vec4::Z),
+            (UpAxis::Y, Handedness::Left) => (Vec4::Y, Vec4::NEG_Z),
+            (UpAxis::Z, Handedness::Right) => (Vec4::NEG_Z, Vec4::Y),
+            (UpAxis::Z, Handedness::Left) => (Vec4::Z, Vec4::Y),
+        };
+        Mat4::from_cols(Vec4::X, y_axis, z_axis, Vec4::W)
+    }
+}
+
+/// How the window covers its monitor when fullscreen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    /// A borderless window the size of the monitor, without changing the display mode
+    Borderless,
+    /// Take over the display with its largest, fastest video mode
+    Exclusive,
+}
@@ -4,16 +4,61 @@ use ash::vk;
 use color_eyre::eyre::eyre;
 use crate::renderer::contexts::device_ctx::command_encoder_allocator::CommandEncoderAllocator;
 use crate::renderer::contexts::device_ctx::queue::Queue;
-use crate::renderer::resources::image::Image;
+use crate::renderer::resources::image::{transition_image_layout, Image};
+use crate::renderer::resources::mesh::IndexType;
 
 use super::command_encoder_allocator::CommandEncoderAllocatorExt;
 
+/// What happens to an attachment's previous contents when rendering begins, and to what was
+/// rendered into it when rendering ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachmentOps {
+    pub load: vk::AttachmentLoadOp,
+    pub store: vk::AttachmentStoreOp,
+}
+
+impl AttachmentOps {
+    /// Start from the clear value and keep the result, like the main pass does
+    pub const fn clear() -> Self {
+        Self {
+            load: vk::AttachmentLoadOp::CLEAR,
+            store: vk::AttachmentStoreOp::STORE,
+        }
+    }
+
+    /// Render on top of the previous contents and keep the result, e.g. for overlays or
+    /// accumulating over several passes
+    pub const fn load() -> Self {
+        Self {
+            load: vk::AttachmentLoadOp::LOAD,
+            store: vk::AttachmentStoreOp::STORE,
+        }
+    }
+
+    /// Throw the result away when rendering ends, for attachments only needed during the pass
+    /// like a depth buffer nothing reads afterwards
+    pub const fn with_store_discarded(mut self) -> Self {
+        self.store = vk::AttachmentStoreOp::DONT_CARE;
+        self
+    }
+}
+
+impl Default for AttachmentOps {
+    fn default() -> Self {
+        Self::clear()
+    }
+}
+
 pub struct CommandEncoder {
     pub command_buffer: vk::CommandBuffer,
+    pub level: vk::CommandBufferLevel,
     pub queue: Arc<Queue>,
 
     is_recording: bool,
+    // Depth direction of the passes recorded into it, see `set_reverse_z`
+    reverse_z: bool,
 
+    command_pool: vk::CommandPool,
     device: Arc<ash::Device>,
     allocator: Option<CommandEncoderAllocator>,
 }
@@ -21,29 +66,71 @@ pub struct CommandEncoder {
 impl CommandEncoder {
     pub fn new(
         command_buffer: vk::CommandBuffer,
+        command_pool: vk::CommandPool,
+        level: vk::CommandBufferLevel,
         queue: Arc<Queue>,
         device: Arc<ash::Device>,
         allocator: CommandEncoderAllocator,
     ) -> Self {
         Self {
             command_buffer,
+            level,
             queue,
+            command_pool,
             device,
             allocator: Some(allocator),
             is_recording: false,
+            reverse_z: false,
         }
     }
 
+    /// The pool the command buffer was allocated from, which is specific to the allocating thread
+    pub fn get_command_pool(&self) -> vk::CommandPool {
+        self.command_pool
+    }
 
     pub fn begin_recording(&mut self) -> Result<()> {
-        if self.is_recording {
-            return Err(eyre!("Command buffer is already recording"));
+        if self.level != vk::CommandBufferLevel::PRIMARY {
+            return Err(eyre!("Secondary command buffers must be recorded with begin_secondary_recording"));
         }
 
         let begin_info = vk::CommandBufferBeginInfo::default()
             .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        self.begin(&begin_info)
+    }
+
+    /// Begin recording a secondary command buffer that continues a dynamic rendering instance
+    /// with the given attachment formats. It is not one-time-submit, so it can be replayed in
+    /// later frames until it is re-recorded.
+    pub fn begin_secondary_recording(
+        &mut self,
+        color_format: vk::Format,
+        depth_format: vk::Format,
+    ) -> Result<()> {
+        if self.level != vk::CommandBufferLevel::SECONDARY {
+            return Err(eyre!("Primary command buffers must be recorded with begin_recording"));
+        }
+
+        let color_formats = [color_format];
+        let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::default()
+            .color_attachment_formats(&color_formats)
+            .depth_attachment_format(depth_format)
+            .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+        let inheritance_info = vk::CommandBufferInheritanceInfo::default()
+            .push_next(&mut rendering_info);
+        let begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+            .inheritance_info(&inheritance_info);
+        self.begin(&begin_info)
+    }
+
+    fn begin(&mut self, begin_info: &vk::CommandBufferBeginInfo) -> Result<()> {
+        if self.is_recording {
+            return Err(eyre!("Command buffer is already recording"));
+        }
+
         unsafe {
-            self.device.begin_command_buffer(self.command_buffer, &begin_info)?;
+            self.device.begin_command_buffer(self.command_buffer, begin_info)?;
         }
 
         self.is_recording = true;
@@ -78,6 +165,37 @@ impl CommandEncoder {
         )
     }
 
+    /// Transition `image` from the layout it was last transitioned to. Records nothing if it is
+    /// already in `new_layout`.
+    pub fn transition_image(&self, image: &mut Image, new_layout: vk::ImageLayout) {
+        image.transition_to(self.command_buffer, new_layout)
+    }
+
+    /// Transition an image that is not owned by an `Image`, such as a swapchain image
+    pub fn transition_vkimage_layout(
+        &self,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) {
+        transition_image_layout(
+            self.command_buffer,
+            image,
+            aspect,
+            old_layout,
+            new_layout,
+            &self.device,
+        )
+    }
+
+    /// Record a pipeline barrier, e.g. one a render graph worked out
+    pub fn pipeline_barrier(&self, dependency_info: &vk::DependencyInfo) {
+        unsafe {
+            self.device.cmd_pipeline_barrier2(self.command_buffer, dependency_info);
+        }
+    }
+
     pub fn copy_image_to_image(
         &self,
         src_image: &Image,
@@ -88,6 +206,262 @@ impl CommandEncoder {
             dst_image,
         )
     }
+
+    pub fn copy_image_to_vkimage(
+        &self,
+        src_image: &Image,
+        dst_image: vk::Image,
+        dst_image_extent: vk::Extent2D,
+    ) {
+        src_image.copy_to_vkimage(
+            self.command_buffer,
+            dst_image,
+            dst_image_extent,
+        )
+    }
+
+    /// Blit one layer of `src_image` into `dst_image`, which must be in `TRANSFER_DST_OPTIMAL`
+    pub fn copy_image_layer_to_vkimage(
+        &self,
+        src_image: &Image,
+        layer: u32,
+        dst_image: vk::Image,
+        dst_image_extent: vk::Extent2D,
+    ) {
+        src_image.copy_layer_to_vkimage(
+            self.command_buffer,
+            layer,
+            dst_image,
+            dst_image_extent,
+        )
+    }
+
+    /// Begin dynamic rendering into a color and depth image, each paired with what happens to its
+    /// previous and new contents. The clear values are only used by attachments that are cleared.
+    /// The images are expected to be in `COLOR_ATTACHMENT_OPTIMAL` and `DEPTH_ATTACHMENT_OPTIMAL`.
+    /// Pass `RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS` when the draws are executed from
+    /// secondary command buffers.
+    pub fn begin_rendering(
+        &self,
+        color: (&Image, AttachmentOps),
+        depth: (&Image, AttachmentOps),
+        clear_color: [f32; 4],
+        flags: vk::RenderingFlags,
+    ) {
+        self.begin_multiview_rendering(color, depth, clear_color, flags, 0);
+    }
+
+    /// Begin rendering to each view in `view_mask`, one layer of the attachments per view. A view
+    /// mask of 0 renders to a single layer like `begin_rendering`.
+    pub fn begin_multiview_rendering(
+        &self,
+        color: (&Image, AttachmentOps),
+        depth: (&Image, AttachmentOps),
+        clear_color: [f32; 4],
+        flags: vk::RenderingFlags,
+        view_mask: u32,
+    ) {
+        self.begin_rendering_with(color, Some(depth), clear_color, flags, view_mask);
+    }
+
+    /// Begin rendering on top of what is already in `color_image`, without clearing it and without
+    /// a depth attachment. Used for overlays like sprites.
+    pub fn begin_overlay_rendering(&self, color_image: &Image) {
+        self.begin_rendering_with(
+            (color_image, AttachmentOps::load()),
+            None,
+            [0.0; 4],
+            vk::RenderingFlags::empty(),
+            0,
+        );
+    }
+
+    /// Like `begin_overlay_rendering`, but also loads `depth_image` so overlays can be hidden
+    /// behind the scene's geometry
+    pub fn begin_overlay_rendering_with_depth(&self, color_image: &Image, depth_image: &Image) {
+        self.begin_rendering_with(
+            (color_image, AttachmentOps::load()),
+            Some((depth_image, AttachmentOps::load())),
+            [0.0; 4],
+            vk::RenderingFlags::empty(),
+            0,
+        );
+    }
+
+    fn begin_rendering_with(
+        &self,
+        (color_image, color_ops): (&Image, AttachmentOps),
+        depth: Option<(&Image, AttachmentOps)>,
+        clear_color: [f32; 4],
+        flags: vk::RenderingFlags,
+        view_mask: u32,
+    ) {
+        let color_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(color_image.view)
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(color_ops.load)
+            .store_op(color_ops.store)
+            .clear_value(vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear_color,
+                },
+            });
+        let depth_attachment = depth.map(|(depth_image, depth_ops)| {
+            vk::RenderingAttachmentInfo::default()
+                .image_view(depth_image.view)
+                .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .load_op(depth_ops.load)
+                .store_op(depth_ops.store)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        // The far plane
+                        depth: if self.reverse_z { 0.0 } else { 1.0 },
+                        stencil: 0,
+                    },
+                })
+        });
+
+        let color_attachments = [color_attachment];
+        let mut rendering_info = vk::RenderingInfo::default()
+            .render_area(vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: vk::Extent2D {
+                    width: color_image.extent.width,
+                    height: color_image.extent.height,
+                },
+            })
+            .flags(flags)
+            // Ignored when the view mask is not 0
+            .layer_count(1)
+            .view_mask(view_mask)
+            .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = depth_attachment.as_ref() {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+        }
+
+        unsafe {
+            self.device.cmd_begin_rendering(self.command_buffer, &rendering_info);
+        }
+    }
+
+    pub fn end_rendering(&self) {
+        unsafe {
+            self.device.cmd_end_rendering(self.command_buffer);
+        }
+    }
+
+    pub fn execute_commands(&self, secondary_encoders: &[&CommandEncoder]) {
+        if secondary_encoders.is_empty() {
+            return;
+        }
+
+        let command_buffers = secondary_encoders
+            .iter()
+            .map(|encoder| encoder.command_buffer)
+            .collect::<Vec<_>>();
+        unsafe {
+            self.device.cmd_execute_commands(self.command_buffer, &command_buffers);
+        }
+    }
+
+    /// Set the dynamic viewport and scissor to cover the whole extent
+    pub fn set_viewport_and_scissor(&self, extent: vk::Extent2D) {
+        let rect = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        self.set_viewport_and_scissor_rects(rect, rect);
+    }
+
+    /// Set the dynamic viewport and scissor to parts of the attachments, e.g. for split-screen
+    pub fn set_viewport_and_scissor_rects(&self, viewport: vk::Rect2D, scissor: vk::Rect2D) {
+        let viewport = vk::Viewport {
+            x: viewport.offset.x as f32,
+            y: viewport.offset.y as f32,
+            width: viewport.extent.width as f32,
+            height: viewport.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+
+        unsafe {
+            self.device.cmd_set_viewport(self.command_buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(self.command_buffer, 0, &[scissor]);
+        }
+    }
+
+    /// Record passes for a camera using reverse-Z, see `Camera::set_reverse_z`. Depth attachments
+    /// are then cleared to 0 instead of 1, and `apply_depth_compare_op` passes greater depth
+    /// instead of lesser.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    pub fn get_reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// Set the depth compare op that keeps the nearest fragments for the depth direction picked
+    /// with `set_reverse_z`. Only for pipelines whose compare op is dynamic, which binding a
+    /// pipeline with a fixed one undoes, so it has to follow every bind.
+    pub fn apply_depth_compare_op(&self) {
+        let compare_op = if self.reverse_z {
+            vk::CompareOp::GREATER_OR_EQUAL
+        } else {
+            vk::CompareOp::LESS_OR_EQUAL
+        };
+        unsafe {
+            self.device.cmd_set_depth_compare_op(self.command_buffer, compare_op);
+        }
+    }
+
+    /// Only for pipelines built with `with_dynamic_depth_bounds`
+    pub fn set_depth_bounds(&self, min: f32, max: f32) {
+        unsafe {
+            self.device.cmd_set_depth_bounds(self.command_buffer, min, max);
+        }
+    }
+
+    pub fn bind_vertex_buffer(&self, buffer: vk::Buffer, offset: u64) {
+        unsafe {
+            self.device.cmd_bind_vertex_buffers(
+                self.command_buffer,
+                0,
+                &[buffer],
+                &[offset],
+            );
+        }
+    }
+
+    pub fn bind_index_buffer(&self, buffer: vk::Buffer, offset: u64, index_type: IndexType) {
+        unsafe {
+            self.device.cmd_bind_index_buffer(
+                self.command_buffer,
+                buffer,
+                offset,
+                index_type.vk_index_type(),
+            );
+        }
+    }
+
+    pub fn draw(&self, vertex_count: u32, first_vertex: u32) {
+        unsafe {
+            self.device.cmd_draw(self.command_buffer, vertex_count, 1, first_vertex, 0);
+        }
+    }
+
+    pub fn draw_indexed(&self, index_count: u32, first_index: u32, vertex_offset: i32) {
+        unsafe {
+            self.device.cmd_draw_indexed(
+                self.command_buffer,
+                index_count,
+                1,
+                first_index,
+                vertex_offset,
+                0,
+            );
+        }
+    }
 }
 
 impl Drop for CommandEncoder {
@@ -1,5 +1,6 @@
 use std::collections::{hash_map, HashMap};
 use std::sync::{Arc, Mutex};
+use std::thread::{self, ThreadId};
 use ash::vk;
 use color_eyre::eyre::OptionExt;
 use color_eyre::Result;
@@ -16,15 +17,35 @@ impl Clone for CommandEncoderAllocator {
     }
 }
 
+/// What happens to a command buffer when its `CommandEncoder` is freed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecyclePolicy {
+    /// Reset the command buffer and keep it in a free list for the next allocation from the same
+    /// pool, avoiding a free/allocate round trip for buffers that churn every frame
+    #[default]
+    Recycle,
+    /// Free the command buffer back to its pool
+    Free,
+}
+
 pub trait CommandEncoderAllocatorExt<A> {
     fn new(device: Arc<ash::Device>) -> Result<A>;
     fn allocate(&mut self, queue: Arc<Queue>) -> Result<CommandEncoder>;
+    fn allocate_secondary(&mut self, queue: Arc<Queue>) -> Result<CommandEncoder>;
     fn free(&mut self, command_encoder: &CommandEncoder) -> Result<()>;
 }
 
+// Command pools are not thread-safe, so every thread gets its own pool per queue family. Command
+// buffers must be recorded on the thread that allocated them, or at least never at the same time
+// as another command buffer from the same pool.
+type CommandPoolKey = (QueueFamily, ThreadId);
+
 struct CommandEncoderAllocatorInner {
-    command_pools: HashMap<QueueFamily, vk::CommandPool>,
-    allocated_command_buffers: HashMap<QueueFamily, Vec<vk::CommandBuffer>>,
+    command_pools: HashMap<CommandPoolKey, vk::CommandPool>,
+    allocated_command_buffers: HashMap<vk::CommandPool, Vec<vk::CommandBuffer>>,
+    // Reset command buffers waiting to be handed out again, per pool and level
+    free_command_buffers: HashMap<(vk::CommandPool, vk::CommandBufferLevel), Vec<vk::CommandBuffer>>,
+    recycle_policy: RecyclePolicy,
     device: Arc<ash::Device>,
 }
 
@@ -36,20 +57,99 @@ impl CommandEncoderAllocatorExt<CommandEncoderAllocator> for CommandEncoderAlloc
             Arc::new(Mutex::new(CommandEncoderAllocatorInner {
                 command_pools: HashMap::new(),
                 allocated_command_buffers: HashMap::new(),
+                free_command_buffers: HashMap::new(),
+                recycle_policy: RecyclePolicy::default(),
                 device,
             }
         ))))
     }
 
     fn allocate(&mut self, queue: Arc<Queue>) -> Result<CommandEncoder> {
-        let (command_buffer, device) = {
+        self.allocate_with_level(queue, vk::CommandBufferLevel::PRIMARY)
+    }
+
+    fn allocate_secondary(&mut self, queue: Arc<Queue>) -> Result<CommandEncoder> {
+        self.allocate_with_level(queue, vk::CommandBufferLevel::SECONDARY)
+    }
+
+    fn free(&mut self, command_encoder: &CommandEncoder) -> Result<()> {
+        let mut guard = self.0
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+
+        let command_pool = command_encoder.get_command_pool();
+        let command_buffer = command_encoder.command_buffer;
+        let command_buffers = guard.allocated_command_buffers
+            .get_mut(&command_pool)
+            .ok_or_eyre(format!("Failed to get command buffers for command pool: {:?}", command_pool))?;
+        let index = command_buffers
+            .iter()
+            .position(|&cb| cb == command_buffer)
+            .ok_or_eyre(format!("Failed to find command buffer in vec for command pool: {:?}", command_pool))?;
+        command_buffers.swap_remove(index);
+        let pool_in_use = !command_buffers.is_empty();
+
+        match guard.recycle_policy {
+            RecyclePolicy::Recycle => {
+                unsafe {
+                    guard.device.reset_command_buffer(
+                        command_buffer,
+                        vk::CommandBufferResetFlags::empty(),
+                    )?;
+                }
+                guard.free_command_buffers
+                    .entry((command_pool, command_encoder.level))
+                    .or_insert_with(Vec::new)
+                    .push(command_buffer);
+            }
+            RecyclePolicy::Free => {
+                unsafe {
+                    guard.device.free_command_buffers(command_pool, &[command_buffer]);
+                }
+
+                // Destroy pools that no longer have command buffers so pools of finished threads
+                // don't accumulate
+                let has_free_command_buffers = guard.free_command_buffers
+                    .iter()
+                    .any(|((pool, _), buffers)| *pool == command_pool && !buffers.is_empty());
+                if !pool_in_use && !has_free_command_buffers {
+                    guard.allocated_command_buffers.remove(&command_pool);
+                    guard.free_command_buffers.retain(|(pool, _), _| *pool != command_pool);
+                    guard.command_pools.retain(|_, pool| *pool != command_pool);
+                    unsafe {
+                        guard.device.destroy_command_pool(command_pool, None);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl CommandEncoderAllocator {
+    pub fn set_recycle_policy(&self, recycle_policy: RecyclePolicy) -> Result<()> {
+        let mut guard = self.0
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        guard.recycle_policy = recycle_policy;
+        Ok(())
+    }
+
+    fn allocate_with_level(
+        &mut self,
+        queue: Arc<Queue>,
+        level: vk::CommandBufferLevel,
+    ) -> Result<CommandEncoder> {
+        let (command_buffer, command_pool, device) = {
             let mut guard = self.0
                 .lock()
                 .map_err(|e| eyre!(e.to_string()))?;
 
             let device = guard.device.clone();
 
-            let command_pool = match guard.command_pools.entry(queue.family.clone()) {
+            let key = (queue.family.clone(), thread::current().id());
+            let command_pool = *match guard.command_pools.entry(key) {
                 hash_map::Entry::Vacant(entry) => {
                     let pool_info = vk::CommandPoolCreateInfo::default()
                         .queue_family_index(queue.family.index)
@@ -64,24 +164,34 @@ impl CommandEncoderAllocatorExt<CommandEncoderAllocator> for CommandEncoderAlloc
                 }
             };
 
-            let command_buffer_info = vk::CommandBufferAllocateInfo::default()
-                .command_pool(*command_pool)
-                .command_buffer_count(1)
-                .level(vk::CommandBufferLevel::PRIMARY);
-            let command_buffer = unsafe {
-                guard.device.allocate_command_buffers(&command_buffer_info)?[0]
+            let recycled = guard.free_command_buffers
+                .get_mut(&(command_pool, level))
+                .and_then(|buffers| buffers.pop());
+            let command_buffer = match recycled {
+                Some(command_buffer) => command_buffer,
+                None => {
+                    let command_buffer_info = vk::CommandBufferAllocateInfo::default()
+                        .command_pool(command_pool)
+                        .command_buffer_count(1)
+                        .level(level);
+                    unsafe {
+                        guard.device.allocate_command_buffers(&command_buffer_info)?[0]
+                    }
+                }
             };
 
             guard.allocated_command_buffers
-                .entry(queue.family.clone())
+                .entry(command_pool)
                 .or_insert_with(Vec::new)
                 .push(command_buffer);
 
-            (command_buffer, device)
+            (command_buffer, command_pool, device)
         };
 
         let command_encoder = CommandEncoder::new(
             command_buffer,
+            command_pool,
+            level,
             queue,
             device,
             self.clone(),
@@ -89,46 +199,19 @@ impl CommandEncoderAllocatorExt<CommandEncoderAllocator> for CommandEncoderAlloc
 
         Ok(command_encoder)
     }
-
-    fn free(&mut self, command_encoder: &CommandEncoder) -> Result<()> {
-        let mut guard = self.0
-            .lock()
-            .map_err(|e| eyre!(e.to_string()))?;
-            
-        let command_pool = guard.command_pools.get(&command_encoder.queue.family).unwrap();
-        let command_buffer = command_encoder.command_buffer;
-        unsafe {
-            guard.device.free_command_buffers(*command_pool, &[command_buffer]);
-        }
-        let command_buffers = guard.allocated_command_buffers
-            .get_mut(&command_encoder.queue.family)
-            .ok_or_eyre(format!("Failed to get command buffers for queue family: {}", command_encoder.queue.family.index))?;
-        let index = command_buffers
-            .iter()
-            .position(|&cb| cb == command_buffer)
-            .ok_or_eyre(format!("Failed to find command buffer in vec for queue family: {}", command_encoder.queue.family.index))?;
-        let _ = command_buffers.swap_remove(index);
-        Ok(())
-    }
 }
 
-impl Drop for CommandEncoderAllocator {
+// Implemented on the inner struct so the pools are only destroyed once the last clone of the
+// allocator is gone
+impl Drop for CommandEncoderAllocatorInner {
     fn drop(&mut self) {
-        let mut guard = self.0
-            .lock()
-            .map_err(|e| eyre!(e.to_string()))
-            .unwrap();
-
-        let command_pools = guard.command_pools
-            .drain()
-            .collect::<Vec<_>>();
-        
-        for (queue_family, command_pool) in command_pools {
-            let command_buffers = guard.allocated_command_buffers.remove(&queue_family).unwrap();
+        // Destroying a pool also frees every command buffer allocated from it
+        for (_, command_pool) in self.command_pools.drain() {
             unsafe {
-                guard.device.free_command_buffers(command_pool, &command_buffers);
-                guard.device.destroy_command_pool(command_pool, None);
+                self.device.destroy_command_pool(command_pool, None);
             }
         }
+        self.allocated_command_buffers.clear();
+        self.free_command_buffers.clear();
     }
 }
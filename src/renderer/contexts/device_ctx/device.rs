@@ -1,17 +1,33 @@
 use std::ffi::{c_char, c_void, CStr};
 use std::str::Utf8Error;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use ash::vk;
-use color_eyre::eyre::OptionExt;
+use color_eyre::eyre::{eyre, OptionExt};
 use color_eyre::owo_colors::OwoColorize;
 use color_eyre::Result;
 use gpu_descriptor::{CreatePoolError, DescriptorAllocator, DescriptorDevice, DescriptorPoolCreateFlags, DescriptorTotalCount, DeviceAllocationError};
-use crate::renderer::resources::image::Image;
+use crate::renderer::config::{BindlessLimits, RenderConfig};
+use crate::renderer::contexts::resource_ctx::resource_type::RenderResourceType;
+use crate::renderer::resources::buffer::Buffer;
+use crate::renderer::resources::image::{Image, Image3dDesc};
 use crate::renderer::resources::megabuffer::{Megabuffer, MegabufferExt};
+use crate::renderer::resources::texture::ArrayTexture;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
 use crate::renderer::contexts::device_ctx::command_encoder_allocator::{CommandEncoderAllocator, CommandEncoderAllocatorExt};
 use crate::renderer::contexts::device_ctx::instance::RenderInstance;
 use crate::renderer::contexts::device_ctx::queue::{Queue, QueueFamily};
-use crate::renderer::contexts::device_ctx::transfer_ctx::TransferContext;
+use crate::renderer::contexts::device_ctx::transfer_ctx::{SubmitHandle, TransferContext};
+use crate::renderer::contexts::device_ctx::sync_pool::SyncPool;
+use crate::renderer::contexts::resource_ctx::resource_id::ResourceIdAllocator;
+
+/// Queue family indices and handles of a device created outside the renderer
+pub struct ExistingQueues {
+    /// Must support presentation if the renderer draws to a window
+    pub graphics: (u32, vk::Queue),
+    pub compute: (u32, vk::Queue),
+    pub transfer: (u32, vk::Queue),
+}
 
 /// Main structure for the renderer
 pub struct RenderDevice {
@@ -23,17 +39,27 @@ pub struct RenderDevice {
     pub compute_queue: Arc<Queue>,
     pub transfer_queue: Arc<Queue>,
 
+    // Every core feature the device supports is enabled when the renderer creates the device
+    features: vk::PhysicalDeviceFeatures,
+    // Extensions from `get_optional_device_extensions` the device supports, which are enabled
+    // the same way
+    optional_extensions: Vec<&'static CStr>,
+    bindless_limits: BindlessLimits,
+    limits: vk::PhysicalDeviceLimits,
+
     memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
     command_encoder_allocator: CommandEncoderAllocator,
     pub descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
 
     transfer_context: Arc<TransferContext>,
+    sync_pool: Arc<SyncPool>,
 }
 
 impl RenderDevice {
     pub fn new(
         instance: &RenderInstance,
         surface: Option<&(vk::SurfaceKHR, ash::khr::surface::Instance)>,
+        config: &RenderConfig,
     ) -> Result<Self> {
         let (
             physical_device,
@@ -43,8 +69,23 @@ impl RenderDevice {
         ) = Self::select_physical_device(
             &instance.instance,
             surface,
+            config.allow_software_rendering,
         )?;
 
+        let properties = unsafe {
+            instance.instance.get_physical_device_properties(physical_device)
+        };
+        if properties.device_type == vk::PhysicalDeviceType::CPU {
+            let device_name = properties
+                .device_name_as_c_str()
+                .map_or("unknown".into(), |name| name.to_string_lossy());
+            log::warn!(
+                "No GPU found, falling back to software rendering on {}. Expect very low frame \
+                rates; set RenderConfig::allow_software_rendering to false to fail instead.",
+                device_name,
+            );
+        }
+
         let (
             logical_device,
             graphics_queue,
@@ -56,16 +97,187 @@ impl RenderDevice {
             graphics_queue_family,
             compute_queue_family,
             transfer_queue_family,
+            |device_create_info| Ok(unsafe {
+                instance.instance.create_device(physical_device, device_create_info, None)?
+            }),
         )?;
 
+        Self::from_logical_device(
+            instance,
+            physical_device,
+            logical_device,
+            graphics_queue,
+            compute_queue,
+            transfer_queue,
+            config,
+        )
+    }
+
+    /// Create the device on a physical device picked by someone else, with `create_device` making
+    /// the actual Vulkan call. This is for runtimes such as OpenXR that choose the physical device
+    /// and add their own extensions to the device.
+    pub fn new_with_device_creator<F>(
+        instance: &RenderInstance,
+        physical_device: vk::PhysicalDevice,
+        config: &RenderConfig,
+        create_device: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce(&vk::DeviceCreateInfo) -> Result<ash::Device>,
+    {
+        let (
+            graphics_queue_family_index,
+            compute_queue_family_index,
+            transfer_queue_family_index,
+        ) = Self::find_queue_family_indices(&instance.instance, physical_device, None)
+            .ok_or_eyre("Physical device does not have separate graphics, compute and transfer queue families")?;
+        let (
+            graphics_queue_family,
+            compute_queue_family,
+            transfer_queue_family,
+        ) = Self::get_queue_families(
+            &instance.instance,
+            physical_device,
+            graphics_queue_family_index,
+            compute_queue_family_index,
+            transfer_queue_family_index,
+        );
+
+        let (
+            logical_device,
+            graphics_queue,
+            compute_queue,
+            transfer_queue,
+        ) = Self::create_logical_device(
+            &instance.instance,
+            &physical_device,
+            graphics_queue_family,
+            compute_queue_family,
+            transfer_queue_family,
+            create_device,
+        )?;
+
+        Self::from_logical_device(
+            instance,
+            physical_device,
+            logical_device,
+            graphics_queue,
+            compute_queue,
+            transfer_queue,
+            config,
+        )
+    }
+
+    /// Whether `new` would find a device to render headless on with `config`: one with the
+    /// extensions, features, bindless limits and queue families the renderer needs
+    pub fn is_supported(instance: &RenderInstance, config: &RenderConfig) -> bool {
+        let Ok((physical_device, ..)) = Self::select_physical_device(
+            &instance.instance,
+            None,
+            config.allow_software_rendering,
+        ) else {
+            return false;
+        };
+        let api_version = unsafe {
+            instance.instance.get_physical_device_properties(physical_device).api_version
+        };
+        let (features11, features12, features13) = Self::get_required_device_features();
+        Self::check_required_device_features_supported(
+            &instance.instance,
+            &physical_device,
+            Self::supports_vulkan13(api_version),
+            &features11,
+            &features12,
+            &features13,
+        ).is_ok()
+            && Self::check_bindless_limits(&instance.instance, physical_device, config).is_ok()
+    }
+
+    /// Build the device on top of a Vulkan device created elsewhere, such as by an XR runtime or
+    /// a host application. The device must have been created with the extensions and features
+    /// that `new` would have enabled, and must outlive the renderer.
+    pub fn from_existing(
+        instance: &RenderInstance,
+        physical_device: vk::PhysicalDevice,
+        logical_device: ash::Device,
+        queues: ExistingQueues,
+        config: &RenderConfig,
+    ) -> Result<Self> {
+        let queue_family_props = unsafe {
+            instance.instance.get_physical_device_queue_family_properties(physical_device)
+        };
+        let queue = |(family_index, handle): (u32, vk::Queue), supports_present: bool| {
+            let props = queue_family_props
+                .get(family_index as usize)
+                .ok_or_eyre(format!("Queue family {} does not exist", family_index))?;
+            Ok::<_, color_eyre::Report>(Queue::new(
+                QueueFamily::new(family_index, *props, supports_present),
+                handle,
+            ))
+        };
+
+        Self::from_logical_device(
+            instance,
+            physical_device,
+            logical_device,
+            queue(queues.graphics, true)?,
+            queue(queues.compute, false)?,
+            queue(queues.transfer, false)?,
+            config,
+        )
+    }
+
+    fn from_logical_device(
+        instance: &RenderInstance,
+        physical_device: vk::PhysicalDevice,
+        logical_device: ash::Device,
+        graphics_queue: Queue,
+        compute_queue: Queue,
+        transfer_queue: Queue,
+        config: &RenderConfig,
+    ) -> Result<Self> {
+        let max_bindless_descriptors = Self::check_bindless_limits(
+            &instance.instance,
+            physical_device,
+            config,
+        )?;
+
+        // VMA copies the callbacks, so they only have to live until the allocator is created
+        let device_memory_callbacks = DeviceMemoryCallbacks {
+            allocate: Some(log_device_memory_allocation),
+            free: Some(log_device_memory_free),
+            user_data: std::ptr::null_mut(),
+        };
+        let mut allocator_info = vk_mem::AllocatorCreateInfo::new(
+            &instance.instance,
+            &logical_device,
+            physical_device,
+        );
+        if config.log_vulkan_allocations {
+            allocator_info.device_memory_callbacks = Some(unsafe {
+                &*(&device_memory_callbacks as *const DeviceMemoryCallbacks as *const _)
+            });
+        }
         let memory_allocator = unsafe {
-            vk_mem::Allocator::new(vk_mem::AllocatorCreateInfo::new(
-                &instance.instance,
-                &logical_device,
-                physical_device,
-            ))?
+            vk_mem::Allocator::new(allocator_info)?
         };
 
+        let features = unsafe {
+            instance.instance.get_physical_device_features(physical_device)
+        };
+        let optional_extensions = Self::get_supported_optional_device_extensions(
+            &instance.instance,
+            physical_device,
+        )?;
+        let limits = unsafe {
+            instance.instance.get_physical_device_properties(physical_device)
+        }.limits;
+
+        let logical_device = Self::load_extension_commands(
+            &instance.instance,
+            physical_device,
+            logical_device,
+        );
         let logical_device = Arc::new(logical_device);
         let graphics_queue = Arc::new(graphics_queue);
         let compute_queue = Arc::new(compute_queue);
@@ -75,10 +287,12 @@ impl RenderDevice {
             logical_device.clone(),
         )?;
         let descriptor_allocator: DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>
-            = DescriptorAllocator::new(1024);
+            = DescriptorAllocator::new(max_bindless_descriptors);
 
+        let sync_pool = Arc::new(SyncPool::new(logical_device.clone()));
         let transfer_context = TransferContext::new(
             transfer_queue.clone(),
+            sync_pool.clone(),
             logical_device.clone(),
         )?;
 
@@ -90,11 +304,17 @@ impl RenderDevice {
             compute_queue,
             transfer_queue,
 
+            features,
+            optional_extensions,
+            bindless_limits: config.bindless_limits,
+            limits,
+
             memory_allocator: Arc::new(Mutex::new(memory_allocator)),
             command_encoder_allocator,
             descriptor_allocator: Arc::new(Mutex::new(descriptor_allocator)),
 
             transfer_context: Arc::new(transfer_context),
+            sync_pool,
         };
 
         Ok(dev)
@@ -110,11 +330,165 @@ impl RenderDevice {
         self.transfer_context.immediate_submit(func)
     }
 
+    /// Like `immediate_submit`, but on the compute queue so it can run alongside graphics work
+    pub fn compute_immediate_submit<F>(
+        &self,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.immediate_submit_on(&self.compute_queue, func)
+    }
+
+    /// Submit one-off work to any queue and wait for it. A command pool and fence are created for
+    /// each queue family the first time it is used and reused after that.
+    pub fn immediate_submit_on<F>(
+        &self,
+        queue: &Arc<Queue>,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.transfer_context.immediate_submit_on(queue, func)
+    }
+
+    /// Like `immediate_submit_on`, but returns a `SubmitTimeoutError` if the work doesn't finish
+    /// within `timeout`
+    pub fn immediate_submit_on_with_timeout<F>(
+        &self,
+        queue: &Arc<Queue>,
+        timeout: Duration,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.transfer_context.immediate_submit_on_with_timeout(queue, timeout, func)
+    }
+
+    /// Submit one-off work to any queue without waiting for it. Poll the returned handle with
+    /// `SubmitHandle::is_done` or block on it with `SubmitHandle::wait`.
+    pub fn submit_polled<F>(
+        &self,
+        queue: &Arc<Queue>,
+        func: F,
+    ) -> Result<SubmitHandle>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.transfer_context.submit_polled(queue, func)
+    }
+
+    /// Like `submit_polled`, but the work also signals a semaphore that a later submission can
+    /// wait on. See `SubmitHandle::take_semaphore`.
+    pub fn submit_polled_signaling<F>(
+        &self,
+        queue: &Arc<Queue>,
+        func: F,
+    ) -> Result<SubmitHandle>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.transfer_context.submit_polled_signaling(queue, func)
+    }
+
+    pub fn allocate_command_encoder(
+        &self,
+        queue: Arc<Queue>,
+    ) -> Result<CommandEncoder> {
+        self.command_encoder_allocator
+            .clone()
+            .allocate(queue)
+    }
+
+    /// Core features of the physical device, such as `logic_op`, to check before building a
+    /// material that relies on an optional one. A device passed to `from_existing` only has the
+    /// features its creator enabled.
+    pub fn get_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.features
+    }
+
+    /// Optional extensions that are enabled, such as `VK_EXT_conservative_rasterization`, to pass
+    /// to `GraphicsMaterialFactoryBuilder::with_supported_extensions`. Like `get_features`, a
+    /// device passed to `from_existing` only has the extensions its creator enabled.
+    pub fn get_optional_extensions(&self) -> &[&'static CStr] {
+        &self.optional_extensions
+    }
+
+    pub fn get_memory_allocator(&self) -> &Arc<Mutex<vk_mem::Allocator>> {
+        &self.memory_allocator
+    }
+
+    /// Sizes of the bindless descriptor tables, already checked against the device limits
+    pub fn get_bindless_limits(&self) -> &BindlessLimits {
+        &self.bindless_limits
+    }
+
+    /// Limits of the physical device, queried once when the device is created
+    pub fn get_limits(&self) -> &vk::PhysicalDeviceLimits {
+        &self.limits
+    }
+
+    /// Upper bound for `SamplerDesc::max_anisotropy`
+    pub fn get_max_sampler_anisotropy(&self) -> f32 {
+        self.limits.max_sampler_anisotropy
+    }
+
+    /// Largest width or height of a 2D image the device can create
+    pub fn get_max_image_dimension_2d(&self) -> u32 {
+        self.limits.max_image_dimension2_d
+    }
+
+    /// Fail with a clear message if a 2D image of this size is too large for the device, instead
+    /// of the opaque error image creation would give
+    pub fn check_image_dimensions_2d(&self, width: u32, height: u32) -> Result<()> {
+        let max = self.get_max_image_dimension_2d();
+        if width > max || height > max {
+            return Err(eyre!(
+                "Texture {}x{} exceeds the device's maximum dimension of {}",
+                width,
+                height,
+                max,
+            ));
+        }
+        Ok(())
+    }
+
+    /// For uploads that need the transfer queue, like `ArrayTexture::upload_layer`
+    pub fn get_transfer_context(&self) -> &TransferContext {
+        &self.transfer_context
+    }
+
+    /// Fences and semaphores for short-lived submissions, recycled instead of recreated
+    pub fn get_sync_pool(&self) -> &SyncPool {
+        &self.sync_pool
+    }
+
+    /// Command pools are per thread, so threads that record commands should allocate their
+    /// encoders themselves through a clone of the allocator
+    pub fn get_command_encoder_allocator(&self) -> CommandEncoderAllocator {
+        self.command_encoder_allocator.clone()
+    }
+
+    /// Allocate a secondary command encoder, which is recorded separately and executed from a
+    /// primary one
+    pub fn allocate_secondary_command_encoder(
+        &self,
+        queue: Arc<Queue>,
+    ) -> Result<CommandEncoder> {
+        self.command_encoder_allocator
+            .clone()
+            .allocate_secondary(queue)
+    }
+
     pub fn create_megabuffer(
         &self,
         size: u64,
         alignment: u64,
         buf_usage: vk::BufferUsageFlags,
+        id_allocator: Arc<ResourceIdAllocator>,
     ) -> Result<Megabuffer> {
         Megabuffer::new(
             size,
@@ -123,6 +497,35 @@ impl RenderDevice {
             self.memory_allocator.clone(),
             self.logical.clone(),
             self.transfer_context.clone(),
+            id_allocator,
+        )
+    }
+
+    pub fn create_buffer(
+        &self,
+        size: u64,
+        alignment: u64,
+        buf_usage: vk::BufferUsageFlags,
+        mem_usage: vk_mem::MemoryUsage,
+        mapped: bool,
+    ) -> Result<Buffer> {
+        Buffer::new(
+            size,
+            alignment,
+            buf_usage,
+            mem_usage,
+            mapped,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+        )
+    }
+
+    /// Host-visible buffer for reading GPU results, see `ReadbackBuffer`
+    pub fn create_readback_buffer(&self, size: u64) -> Result<Buffer> {
+        Buffer::new_readback(
+            size,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
         )
     }
 
@@ -133,6 +536,7 @@ impl RenderDevice {
         data: Option<&[u8]>,
         use_dedicated_memory: bool,
     ) -> Result<Image> {
+        self.check_image_dimensions_2d(width, height)?;
         Image::new_color_image(
             width,
             height,
@@ -144,6 +548,63 @@ impl RenderDevice {
         )
     }
 
+    pub fn create_3d_image(&self, desc: &Image3dDesc, data: Option<&[u8]>) -> Result<Image> {
+        Image::new_3d(
+            desc,
+            data,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+            &self.transfer_context,
+        )
+    }
+
+    pub fn create_array_texture(
+        &self,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: vk::Format,
+    ) -> Result<ArrayTexture> {
+        self.check_image_dimensions_2d(width, height)?;
+        ArrayTexture::new(
+            width,
+            height,
+            layers,
+            format,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+            &self.transfer_context,
+        )
+    }
+
+    pub fn create_draw_image(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<Image> {
+        Image::new_draw_image(
+            width,
+            height,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+        )
+    }
+
+    /// See `Image::new_offscreen_image`
+    pub fn create_offscreen_image(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<Image> {
+        self.check_image_dimensions_2d(width, height)?;
+        Image::new_offscreen_image(
+            width,
+            height,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+        )
+    }
+
     pub fn create_depth_image(
         &self,
         width: u32,
@@ -156,23 +617,70 @@ impl RenderDevice {
             self.logical.clone()
         )
     }
+
+    /// Draw image with `view_count` layers for a multiview pass
+    pub fn create_layered_draw_image(
+        &self,
+        width: u32,
+        height: u32,
+        view_count: u32,
+    ) -> Result<Image> {
+        Image::new_layered_draw_image(
+            width,
+            height,
+            view_count,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+        )
+    }
+
+    /// Depth image with `view_count` layers for a multiview pass
+    pub fn create_layered_depth_image(
+        &self,
+        width: u32,
+        height: u32,
+        view_count: u32,
+    ) -> Result<Image> {
+        Image::new_layered_depth_image(
+            width,
+            height,
+            view_count,
+            self.memory_allocator.clone(),
+            self.logical.clone(),
+        )
+    }
     
     fn select_physical_device(
         instance: &ash::Instance,
         surface: Option<&(vk::SurfaceKHR, ash::khr::surface::Instance)>,
+        allow_software_rendering: bool,
     ) -> Result<(vk::PhysicalDevice, QueueFamily, QueueFamily, QueueFamily)> {
-        let req_device_exts = Self::get_required_device_extensions();
-        let req_device_exts = req_device_exts
-            .iter()
-            .map(|ext| ext.to_str())
-            .collect::<std::result::Result<Vec<&str>, Utf8Error>>()?;
-
+        let no_device_message = if allow_software_rendering {
+            "No suitable physical device found"
+        } else {
+            "No suitable physical device found (CPU devices are skipped because software rendering is not allowed)"
+        };
         Ok(unsafe {
             instance
                 .enumerate_physical_devices()?
                 .into_iter()
+                // Filter out software implementations like llvmpipe unless they are allowed
+                .filter(|device| {
+                    allow_software_rendering
+                        || instance.get_physical_device_properties(*device).device_type
+                            != vk::PhysicalDeviceType::CPU
+                })
                 // Filter out devices that do not contain the required device extensions
                 .filter(|device| {
+                    let api_version = instance
+                        .get_physical_device_properties(*device)
+                        .api_version;
+                    let req_device_exts = Self::get_required_device_extensions(api_version)
+                        .iter()
+                        .map(|ext| ext.to_str())
+                        .collect::<std::result::Result<Vec<&str>, Utf8Error>>()
+                        .unwrap_or_default();
+
                     let supported_extensions = instance
                         .enumerate_device_extension_properties(*device)
                         .map_or(Vec::new(), |exts| exts);
@@ -197,65 +705,17 @@ impl RenderDevice {
                 })
                 // Filter out devices that do not contain the required queues
                 .filter_map(|device| {
-                    let props = instance
-                        .get_physical_device_queue_family_properties(device);
-
-                    let graphics_queue_family_index = props
-                        .iter()
-                        .enumerate()
-                        .position(|(i, q)| {
-                            let supports_graphics = q.queue_flags.contains(vk::QueueFlags::GRAPHICS);
-                            if let Some((surface, surface_loader)) = surface {
-                                let supports_present = {
-                                    surface_loader.get_physical_device_surface_support(
-                                        device,
-                                        i as u32,
-                                        *surface,
-                                    ).map_or(false, |b| b)
-                                };
-                                supports_graphics && supports_present
-                            } else {
-                                supports_graphics
-                            }
-                        });
-
-                    let compute_queue_family_index = props
-                        .iter()
-                        .enumerate()
-                        .position(|(i, q)| {
-                            let supports_compute = q.queue_flags.contains(vk::QueueFlags::COMPUTE);
-                            let same_as_graphics = graphics_queue_family_index == Some(i);
-                            supports_compute && !same_as_graphics
-                        });
-
-                    let transfer_queue_family_index = props
-                        .iter()
-                        .enumerate()
-                        .position(|(i, q)| {
-                            let supports_transfer = q.queue_flags.contains(vk::QueueFlags::TRANSFER);
-                            let same_as_graphics = graphics_queue_family_index == Some(i);
-                            let same_as_compute = compute_queue_family_index == Some(i);
-                            supports_transfer && !same_as_graphics && !same_as_compute
-                        });
-
-                    if let (
-                        Some(graphics_queue_family_index),
-                        Some(compute_queue_family_index),
-                        Some(transfer_queue_family_index)
-                    ) = (
+                    let (
                         graphics_queue_family_index,
                         compute_queue_family_index,
-                        transfer_queue_family_index
-                    ) {
-                        Some((
-                            device,
-                            graphics_queue_family_index as u32,
-                            compute_queue_family_index as u32,
-                            transfer_queue_family_index as u32
-                        ))
-                    } else {
-                        None
-                    }
+                        transfer_queue_family_index,
+                    ) = Self::find_queue_family_indices(instance, device, surface)?;
+                    Some((
+                        device,
+                        graphics_queue_family_index,
+                        compute_queue_family_index,
+                        transfer_queue_family_index,
+                    ))
                 })
                 .min_by_key(|(device, _, _, _)| {
                     let props = instance.get_physical_device_properties(*device);
@@ -274,28 +734,110 @@ impl RenderDevice {
                           compute_queue_family_index,
                           transfer_queue_family_index,
                       )| {
-                    let queue_family_props = instance.get_physical_device_queue_family_properties(device);
-                    let graphics_props = queue_family_props.get(graphics_queue_family_index as usize).unwrap();
-                    let compute_props = queue_family_props.get(compute_queue_family_index as usize).unwrap();
-                    let transfer_props = queue_family_props.get(transfer_queue_family_index as usize).unwrap();
-                    (
+                    let (
+                        graphics_queue_family,
+                        compute_queue_family,
+                        transfer_queue_family,
+                    ) = Self::get_queue_families(
+                        instance,
                         device,
-                        QueueFamily::new(graphics_queue_family_index, *graphics_props, true),
-                        QueueFamily::new(compute_queue_family_index, *compute_props, false),
-                        QueueFamily::new(transfer_queue_family_index, *transfer_props, false),
-                    )
+                        graphics_queue_family_index,
+                        compute_queue_family_index,
+                        transfer_queue_family_index,
+                    );
+                    (device, graphics_queue_family, compute_queue_family, transfer_queue_family)
                 })
-                .ok_or_eyre("No suitable physical device found")?
+                .ok_or_eyre(no_device_message)?
         })
     }
 
-    fn create_logical_device(
+    /// Find separate graphics, compute and transfer queue families. The graphics family also has to
+    /// support presenting to the surface, if there is one.
+    fn find_queue_family_indices(
+        instance: &ash::Instance,
+        device: vk::PhysicalDevice,
+        surface: Option<&(vk::SurfaceKHR, ash::khr::surface::Instance)>,
+    ) -> Option<(u32, u32, u32)> {
+        let props = unsafe {
+            instance.get_physical_device_queue_family_properties(device)
+        };
+
+        let graphics_queue_family_index = props
+            .iter()
+            .enumerate()
+            .position(|(i, q)| {
+                let supports_graphics = q.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+                if let Some((surface, surface_loader)) = surface {
+                    let supports_present = unsafe {
+                        surface_loader.get_physical_device_surface_support(
+                            device,
+                            i as u32,
+                            *surface,
+                        ).is_ok_and(|b| b)
+                    };
+                    supports_graphics && supports_present
+                } else {
+                    supports_graphics
+                }
+            });
+
+        let compute_queue_family_index = props
+            .iter()
+            .enumerate()
+            .position(|(i, q)| {
+                let supports_compute = q.queue_flags.contains(vk::QueueFlags::COMPUTE);
+                let same_as_graphics = graphics_queue_family_index == Some(i);
+                supports_compute && !same_as_graphics
+            });
+
+        let transfer_queue_family_index = props
+            .iter()
+            .enumerate()
+            .position(|(i, q)| {
+                let supports_transfer = q.queue_flags.contains(vk::QueueFlags::TRANSFER);
+                let same_as_graphics = graphics_queue_family_index == Some(i);
+                let same_as_compute = compute_queue_family_index == Some(i);
+                supports_transfer && !same_as_graphics && !same_as_compute
+            });
+
+        Some((
+            graphics_queue_family_index? as u32,
+            compute_queue_family_index? as u32,
+            transfer_queue_family_index? as u32,
+        ))
+    }
+
+    fn get_queue_families(
+        instance: &ash::Instance,
+        device: vk::PhysicalDevice,
+        graphics_queue_family_index: u32,
+        compute_queue_family_index: u32,
+        transfer_queue_family_index: u32,
+    ) -> (QueueFamily, QueueFamily, QueueFamily) {
+        let queue_family_props = unsafe {
+            instance.get_physical_device_queue_family_properties(device)
+        };
+        let graphics_props = queue_family_props.get(graphics_queue_family_index as usize).unwrap();
+        let compute_props = queue_family_props.get(compute_queue_family_index as usize).unwrap();
+        let transfer_props = queue_family_props.get(transfer_queue_family_index as usize).unwrap();
+        (
+            QueueFamily::new(graphics_queue_family_index, *graphics_props, true),
+            QueueFamily::new(compute_queue_family_index, *compute_props, false),
+            QueueFamily::new(transfer_queue_family_index, *transfer_props, false),
+        )
+    }
+
+    fn create_logical_device<F>(
         instance: &ash::Instance,
         physical_device: &vk::PhysicalDevice,
         graphics_queue_family: QueueFamily,
         compute_queue_family: QueueFamily,
         transfer_queue_family: QueueFamily,
-    ) -> Result<(ash::Device, Queue, Queue, Queue)> {
+        create_device: F,
+    ) -> Result<(ash::Device, Queue, Queue, Queue)>
+    where
+        F: FnOnce(&vk::DeviceCreateInfo) -> Result<ash::Device>,
+    {
         let queue_priorities = [1.0];
         let queue_create_infos = [
             vk::DeviceQueueCreateInfo::default()
@@ -311,8 +853,14 @@ impl RenderDevice {
 
         // Create device
         let device = {
-            let enabled_extension_names = Self::get_required_device_extensions()
-                .iter()
+            let api_version = unsafe {
+                instance.get_physical_device_properties(*physical_device).api_version
+            };
+            let use_vulkan13_features = Self::supports_vulkan13(api_version);
+
+            let enabled_extension_names = Self::get_required_device_extensions(api_version)
+                .into_iter()
+                .chain(Self::get_supported_optional_device_extensions(instance, *physical_device)?)
                 .map(|ext| ext.as_ptr())
                 .collect::<Vec<*const c_char>>();
 
@@ -320,48 +868,52 @@ impl RenderDevice {
             unsafe {
                 instance.get_physical_device_features2(*physical_device, &mut features2);
             }
-            let mut features11 = vk::PhysicalDeviceVulkan11Features::default()
-                .shader_draw_parameters(true);
-            let mut features12 = vk::PhysicalDeviceVulkan12Features::default()
-                .runtime_descriptor_array(true)
-                .buffer_device_address(true)
-                .descriptor_indexing(true)
-                .descriptor_binding_partially_bound(true)
-                .descriptor_binding_variable_descriptor_count(true)
-                // Dynamic indexing
-                .shader_input_attachment_array_dynamic_indexing(true)
-                .shader_uniform_texel_buffer_array_dynamic_indexing(true)
-                .shader_storage_texel_buffer_array_dynamic_indexing(true)
-                // Non-uniform indexing
-                .shader_uniform_buffer_array_non_uniform_indexing(true)
-                .shader_sampled_image_array_non_uniform_indexing(true)
-                .shader_storage_buffer_array_non_uniform_indexing(true)
-                .shader_storage_image_array_non_uniform_indexing(true)
-                .shader_input_attachment_array_non_uniform_indexing(true)
-                .shader_uniform_texel_buffer_array_non_uniform_indexing(true)
-                .shader_storage_texel_buffer_array_non_uniform_indexing(true)
-                // Update after bind
-                .descriptor_binding_uniform_buffer_update_after_bind(true)
-                .descriptor_binding_sampled_image_update_after_bind(true)
-                .descriptor_binding_storage_image_update_after_bind(true)
-                .descriptor_binding_storage_buffer_update_after_bind(true)
-                .descriptor_binding_uniform_texel_buffer_update_after_bind(true)
-                .descriptor_binding_storage_texel_buffer_update_after_bind(true);
-            let mut features13 = vk::PhysicalDeviceVulkan13Features::default()
-                .synchronization2(true)
-                .dynamic_rendering(true);
-
-            let device_create_info = vk::DeviceCreateInfo::default()//enabled_features.device_create_info()
+            let (
+                mut features11,
+                mut features12,
+                mut features13,
+            ) = Self::get_required_device_features();
+            Self::check_required_device_features_supported(
+                instance,
+                physical_device,
+                use_vulkan13_features,
+                &features11,
+                &features12,
+                &features13,
+            )?;
+
+            // Before 1.3, dynamic rendering, synchronization2 and the dynamic depth compare op are
+            // only available through their extensions, which have their own feature structs
+            let mut dynamic_rendering_features =
+                vk::PhysicalDeviceDynamicRenderingFeatures::default()
+                    .dynamic_rendering(features13.dynamic_rendering == vk::TRUE);
+            let mut synchronization2_features =
+                vk::PhysicalDeviceSynchronization2Features::default()
+                    .synchronization2(features13.synchronization2 == vk::TRUE);
+            let mut extended_dynamic_state_features =
+                vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::default()
+                    .extended_dynamic_state(true);
+
+            // The feature structs are chained with `push_next` rather than by assigning `p_next`
+            // by hand. They are locals borrowed by the create info, so the borrow checker keeps the
+            // chain valid until `create_device` returns.
+            let device_create_info = vk::DeviceCreateInfo::default()
                 .push_next(&mut features2)
                 .push_next(&mut features11)
                 .push_next(&mut features12)
-                .push_next(&mut features13)
                 .queue_create_infos(&queue_create_infos)
                 .enabled_extension_names(&enabled_extension_names);
+            let device_create_info = if use_vulkan13_features {
+                device_create_info
+                    .push_next(&mut features13)
+            } else {
+                device_create_info
+                    .push_next(&mut dynamic_rendering_features)
+                    .push_next(&mut synchronization2_features)
+                    .push_next(&mut extended_dynamic_state_features)
+            };
 
-            unsafe {
-                instance.create_device(*physical_device, &device_create_info, None)?
-            }
+            create_device(&device_create_info)?
         };
 
         let graphics_queue = unsafe {
@@ -380,19 +932,366 @@ impl RenderDevice {
         Ok((device, graphics_queue, compute_queue, transfer_queue))
     }
 
-    fn get_required_device_extensions() -> Vec<&'static CStr> {
-        vec![
+    fn get_required_device_features() -> (
+        vk::PhysicalDeviceVulkan11Features<'static>,
+        vk::PhysicalDeviceVulkan12Features<'static>,
+        vk::PhysicalDeviceVulkan13Features<'static>,
+    ) {
+        let features11 = vk::PhysicalDeviceVulkan11Features::default()
+            .shader_draw_parameters(true)
+            // Rendering several views in one pass, see `GraphicsMaterialFactoryBuilder::with_view_mask`
+            .multiview(true);
+        let features12 = vk::PhysicalDeviceVulkan12Features::default()
+            .runtime_descriptor_array(true)
+            .buffer_device_address(true)
+            .descriptor_indexing(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_variable_descriptor_count(true)
+            // Dynamic indexing
+            .shader_input_attachment_array_dynamic_indexing(true)
+            .shader_uniform_texel_buffer_array_dynamic_indexing(true)
+            .shader_storage_texel_buffer_array_dynamic_indexing(true)
+            // Non-uniform indexing
+            .shader_uniform_buffer_array_non_uniform_indexing(true)
+            .shader_sampled_image_array_non_uniform_indexing(true)
+            .shader_storage_buffer_array_non_uniform_indexing(true)
+            .shader_storage_image_array_non_uniform_indexing(true)
+            .shader_input_attachment_array_non_uniform_indexing(true)
+            .shader_uniform_texel_buffer_array_non_uniform_indexing(true)
+            .shader_storage_texel_buffer_array_non_uniform_indexing(true)
+            // Update after bind
+            .descriptor_binding_uniform_buffer_update_after_bind(true)
+            .descriptor_binding_sampled_image_update_after_bind(true)
+            .descriptor_binding_storage_image_update_after_bind(true)
+            .descriptor_binding_storage_buffer_update_after_bind(true)
+            .descriptor_binding_uniform_texel_buffer_update_after_bind(true)
+            .descriptor_binding_storage_texel_buffer_update_after_bind(true);
+        let features13 = vk::PhysicalDeviceVulkan13Features::default()
+            .synchronization2(true)
+            .dynamic_rendering(true);
+
+        (features11, features12, features13)
+    }
+
+    /// Query which of the required features the device supports, so a missing one is reported by
+    /// name instead of as an opaque device creation failure
+    fn check_required_device_features_supported(
+        instance: &ash::Instance,
+        physical_device: &vk::PhysicalDevice,
+        use_vulkan13_features: bool,
+        required11: &vk::PhysicalDeviceVulkan11Features,
+        required12: &vk::PhysicalDeviceVulkan12Features,
+        required13: &vk::PhysicalDeviceVulkan13Features,
+    ) -> Result<()> {
+        let mut supported11 = vk::PhysicalDeviceVulkan11Features::default();
+        let mut supported12 = vk::PhysicalDeviceVulkan12Features::default();
+        let mut supported13 = vk::PhysicalDeviceVulkan13Features::default();
+        let mut supported_dynamic_rendering = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut supported_synchronization2 = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut supported_extended_dynamic_state =
+            vk::PhysicalDeviceExtendedDynamicStateFeaturesEXT::default();
+        let supported = vk::PhysicalDeviceFeatures2::default()
+            .push_next(&mut supported11)
+            .push_next(&mut supported12);
+        let mut supported = if use_vulkan13_features {
+            supported
+                .push_next(&mut supported13)
+        } else {
+            supported
+                .push_next(&mut supported_dynamic_rendering)
+                .push_next(&mut supported_synchronization2)
+                .push_next(&mut supported_extended_dynamic_state)
+        };
+        unsafe {
+            instance.get_physical_device_features2(*physical_device, &mut supported);
+        }
+        if !use_vulkan13_features {
+            supported13.dynamic_rendering = supported_dynamic_rendering.dynamic_rendering;
+            supported13.synchronization2 = supported_synchronization2.synchronization2;
+        }
+
+        // Collects the names of the features that are required but not supported
+        macro_rules! missing_features {
+            ($required:expr, $supported:expr, [$($feature:ident),* $(,)?]) => {{
+                let mut missing: Vec<&'static str> = Vec::new();
+                $(
+                    if $required.$feature == vk::TRUE && $supported.$feature != vk::TRUE {
+                        missing.push(stringify!($feature));
+                    }
+                )*
+                missing
+            }};
+        }
+
+        let mut missing = missing_features!(required11, supported11, [
+            shader_draw_parameters,
+            multiview,
+        ]);
+        missing.extend(missing_features!(required12, supported12, [
+            runtime_descriptor_array,
+            buffer_device_address,
+            descriptor_indexing,
+            descriptor_binding_partially_bound,
+            descriptor_binding_variable_descriptor_count,
+            shader_input_attachment_array_dynamic_indexing,
+            shader_uniform_texel_buffer_array_dynamic_indexing,
+            shader_storage_texel_buffer_array_dynamic_indexing,
+            shader_uniform_buffer_array_non_uniform_indexing,
+            shader_sampled_image_array_non_uniform_indexing,
+            shader_storage_buffer_array_non_uniform_indexing,
+            shader_storage_image_array_non_uniform_indexing,
+            shader_input_attachment_array_non_uniform_indexing,
+            shader_uniform_texel_buffer_array_non_uniform_indexing,
+            shader_storage_texel_buffer_array_non_uniform_indexing,
+            descriptor_binding_uniform_buffer_update_after_bind,
+            descriptor_binding_sampled_image_update_after_bind,
+            descriptor_binding_storage_image_update_after_bind,
+            descriptor_binding_storage_buffer_update_after_bind,
+            descriptor_binding_uniform_texel_buffer_update_after_bind,
+            descriptor_binding_storage_texel_buffer_update_after_bind,
+        ]));
+        missing.extend(missing_features!(required13, supported13, [
+            synchronization2,
+            dynamic_rendering,
+        ]));
+        // Core in 1.3, where setting the depth compare op dynamically needs no feature
+        if !use_vulkan13_features
+            && supported_extended_dynamic_state.extended_dynamic_state != vk::TRUE
+        {
+            missing.push("extended_dynamic_state");
+        }
+
+        if !missing.is_empty() {
+            return Err(eyre!(
+                "Device does not support required features: {}",
+                missing.join(", ")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Whether dynamic rendering and synchronization2 are core, so `PhysicalDeviceVulkan13Features`
+    /// can be used instead of the extensions
+    fn supports_vulkan13(api_version: u32) -> bool {
+        vk::api_version_major(api_version) > 1 || vk::api_version_minor(api_version) >= 3
+    }
+
+    /// The renderer calls the commands that became core in 1.3 through `ash::Device`, whose 1.3
+    /// function pointers are null on older devices. Fill them in from the extension loaders, so
+    /// the same calls go to `vkCmdPipelineBarrier2KHR` and friends there.
+    fn load_extension_commands(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+    ) -> ash::Device {
+        let api_version = unsafe {
+            instance.get_physical_device_properties(physical_device).api_version
+        };
+        if Self::supports_vulkan13(api_version) {
+            return device;
+        }
+
+        let dynamic_rendering = ash::khr::dynamic_rendering::Device::new(instance, &device);
+        let synchronization2 = ash::khr::synchronization2::Device::new(instance, &device);
+        let copy_commands2 = ash::khr::copy_commands2::Device::new(instance, &device);
+        let extended_dynamic_state =
+            ash::ext::extended_dynamic_state::Device::new(instance, &device);
+
+        let mut fp_v1_3 = device.fp_v1_3().clone();
+        fp_v1_3.cmd_begin_rendering = dynamic_rendering.fp().cmd_begin_rendering_khr;
+        fp_v1_3.cmd_end_rendering = dynamic_rendering.fp().cmd_end_rendering_khr;
+        fp_v1_3.cmd_set_event2 = synchronization2.fp().cmd_set_event2_khr;
+        fp_v1_3.cmd_reset_event2 = synchronization2.fp().cmd_reset_event2_khr;
+        fp_v1_3.cmd_wait_events2 = synchronization2.fp().cmd_wait_events2_khr;
+        fp_v1_3.cmd_pipeline_barrier2 = synchronization2.fp().cmd_pipeline_barrier2_khr;
+        fp_v1_3.cmd_write_timestamp2 = synchronization2.fp().cmd_write_timestamp2_khr;
+        fp_v1_3.queue_submit2 = synchronization2.fp().queue_submit2_khr;
+        fp_v1_3.cmd_copy_buffer2 = copy_commands2.fp().cmd_copy_buffer2_khr;
+        fp_v1_3.cmd_copy_image2 = copy_commands2.fp().cmd_copy_image2_khr;
+        fp_v1_3.cmd_copy_buffer_to_image2 = copy_commands2.fp().cmd_copy_buffer_to_image2_khr;
+        fp_v1_3.cmd_copy_image_to_buffer2 = copy_commands2.fp().cmd_copy_image_to_buffer2_khr;
+        fp_v1_3.cmd_blit_image2 = copy_commands2.fp().cmd_blit_image2_khr;
+        fp_v1_3.cmd_resolve_image2 = copy_commands2.fp().cmd_resolve_image2_khr;
+        fp_v1_3.cmd_set_depth_compare_op = extended_dynamic_state.fp().cmd_set_depth_compare_op_ext;
+
+        ash::Device::from_parts_1_3(
+            device.handle(),
+            device.fp_v1_0().clone(),
+            device.fp_v1_1().clone(),
+            device.fp_v1_2().clone(),
+            fp_v1_3,
+        )
+    }
+
+    fn get_required_device_extensions(api_version: u32) -> Vec<&'static CStr> {
+        let mut exts = vec![
             ash::khr::swapchain::NAME,
-            ash::khr::dynamic_rendering::NAME,
             ash::khr::buffer_device_address::NAME,
-            ash::khr::synchronization2::NAME,
             ash::khr::maintenance3::NAME,
             ash::ext::descriptor_indexing::NAME,
 
             #[cfg(target_os = "macos")]
             ash::khr::portability_subset::NAME,
+        ];
+
+        if !Self::supports_vulkan13(api_version) {
+            exts.push(ash::khr::dynamic_rendering::NAME);
+            exts.push(ash::khr::synchronization2::NAME);
+            exts.push(ash::khr::copy_commands2::NAME);
+            exts.push(ash::ext::extended_dynamic_state::NAME);
+        }
+
+        exts
+    }
+
+    /// Extensions that are enabled when available but not needed by the renderer itself
+    fn get_optional_device_extensions() -> Vec<&'static CStr> {
+        vec![
+            ash::ext::conservative_rasterization::NAME,
         ]
     }
+
+    fn get_supported_optional_device_extensions(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<Vec<&'static CStr>> {
+        let supported_extensions = unsafe {
+            instance.enumerate_device_extension_properties(physical_device)?
+        };
+        Ok(Self::get_optional_device_extensions()
+            .into_iter()
+            .filter(|ext| {
+                supported_extensions
+                    .iter()
+                    .any(|sup_ext| sup_ext.extension_name_as_c_str() == Ok(*ext))
+            })
+            .collect())
+    }
+
+    /// Fail if the bindless tables are larger than the device allows for update-after-bind
+    /// descriptors, both per set and per shader stage, or if `max_bindless_descriptors` does not
+    /// fit a single set or is more than the device allows in all pools. Returns the descriptor
+    /// budget of all bindless sets together.
+    fn check_bindless_limits(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        config: &RenderConfig,
+    ) -> Result<u32> {
+        let limits = &config.bindless_limits;
+        let mut vulkan_12_properties = vk::PhysicalDeviceVulkan12Properties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default()
+            .push_next(&mut vulkan_12_properties);
+        unsafe {
+            instance.get_physical_device_properties2(physical_device, &mut properties);
+        }
+
+        let checks = [
+            (
+                "samplers",
+                limits.samplers,
+                vulkan_12_properties.max_descriptor_set_update_after_bind_samplers
+                    .min(vulkan_12_properties.max_per_stage_descriptor_update_after_bind_samplers),
+            ),
+            (
+                "sampled_images",
+                limits.sampled_images,
+                vulkan_12_properties.max_descriptor_set_update_after_bind_sampled_images
+                    .min(vulkan_12_properties.max_per_stage_descriptor_update_after_bind_sampled_images),
+            ),
+        ];
+        for (name, requested, max) in checks {
+            if requested == 0 {
+                return Err(eyre!("BindlessLimits::{} must be at least 1", name));
+            }
+            if requested > max {
+                return Err(eyre!(
+                    "BindlessLimits::{} is {}, but the device supports at most {}",
+                    name,
+                    requested,
+                    max,
+                ));
+            }
+        }
+
+        let device_max = vulkan_12_properties.max_update_after_bind_descriptors_in_all_pools;
+        let set_descriptor_count = RenderResourceType::total_descriptor_count(limits);
+        let max_bindless_descriptors = config.max_bindless_descriptors.unwrap_or(device_max);
+        if max_bindless_descriptors > device_max {
+            return Err(eyre!(
+                "RenderConfig::max_bindless_descriptors is {}, but the device supports at most {}",
+                max_bindless_descriptors,
+                device_max,
+            ));
+        }
+        if max_bindless_descriptors < set_descriptor_count {
+            return Err(eyre!(
+                "RenderConfig::max_bindless_descriptors is {}, but a single bindless set takes {} \
+                with the configured BindlessLimits",
+                max_bindless_descriptors,
+                set_descriptor_count,
+            ));
+        }
+        log::debug!(
+            "Room for {} bindless descriptor sets of {} descriptors",
+            max_bindless_descriptors / set_descriptor_count,
+            set_descriptor_count,
+        );
+
+        Ok(max_bindless_descriptors)
+    }
+}
+
+/// Allocation logs go to their own target so they can be filtered separately, e.g. with
+/// `RUST_LOG=debug,raxa::vulkan_allocations=off`
+const ALLOCATION_LOG_TARGET: &str = "raxa::vulkan_allocations";
+
+type DeviceMemoryCallback = unsafe extern "C" fn(
+    allocator: *mut c_void,
+    memory_type: u32,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    user_data: *mut c_void,
+);
+
+/// Same layout as `VmaDeviceMemoryCallbacks`, which vk-mem doesn't re-export
+#[repr(C)]
+struct DeviceMemoryCallbacks {
+    allocate: Option<DeviceMemoryCallback>,
+    free: Option<DeviceMemoryCallback>,
+    user_data: *mut c_void,
+}
+
+unsafe extern "C" fn log_device_memory_allocation(
+    _allocator: *mut c_void,
+    memory_type: u32,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    _user_data: *mut c_void,
+) {
+    log::debug!(
+        target: ALLOCATION_LOG_TARGET,
+        "Allocated {} bytes of device memory {:?} of type {}",
+        size,
+        memory,
+        memory_type,
+    );
+}
+
+unsafe extern "C" fn log_device_memory_free(
+    _allocator: *mut c_void,
+    memory_type: u32,
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    _user_data: *mut c_void,
+) {
+    log::debug!(
+        target: ALLOCATION_LOG_TARGET,
+        "Freeing {} bytes of device memory {:?} of type {}",
+        size,
+        memory,
+        memory_type,
+    );
 }
 
 pub struct DescriptorAshDevice(pub Arc<ash::Device>);
@@ -3,9 +3,10 @@ use ash::vk;
 use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use std::sync::Arc;
-use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 use winit::window::Window;
+use crate::renderer::config::RenderConfig;
 use crate::renderer::contexts::device_ctx::device::RenderDevice;
+use crate::renderer::contexts::device_ctx::surface::SurfaceProvider;
 use crate::renderer::contexts::device_ctx::target::RenderTarget;
 
 /// Initializes Vulkan and keeps the Vulkan instance alive
@@ -25,9 +26,27 @@ impl RenderInstance {
     pub fn new(
         window: Option<Arc<Window>>,
     ) -> Result<Self> {
+        Self::new_with_instance_creator(
+            window,
+            |entry, instance_info| Ok(unsafe {
+                entry.create_instance(instance_info, None)?
+            }),
+        )
+    }
+
+    /// Like `new`, but `create_instance` makes the actual Vulkan call. This is for runtimes such as
+    /// OpenXR that add their own extensions to the instance.
+    pub fn new_with_instance_creator<F>(
+        window: Option<Arc<Window>>,
+        create_instance: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce(&ash::Entry, &vk::InstanceCreateInfo) -> Result<ash::Instance>,
+    {
         let entry = ash::Entry::linked();
 
-        let instance = Self::create_instance(&entry, window.as_ref())?;
+        let surface_provider = window.as_deref().map(|window| window as &dyn SurfaceProvider);
+        let instance = Self::create_instance(&entry, surface_provider, create_instance)?;
 
         let (
             debug_utils_messenger,
@@ -42,29 +61,40 @@ impl RenderInstance {
         })
     }
 
+    /// Wrap a Vulkan instance created elsewhere. No debug messenger is created, since the instance
+    /// may not have the debug utils extension enabled. To draw to a window, the instance must have
+    /// the surface extensions for it enabled.
+    pub fn from_existing(
+        entry: ash::Entry,
+        instance: ash::Instance,
+    ) -> Self {
+        let debug_utils_loader = ash::ext::debug_utils::Instance::new(&entry, &instance);
+
+        Self {
+            instance,
+            entry,
+            debug_utils_messenger: vk::DebugUtilsMessengerEXT::null(),
+            debug_utils_loader,
+        }
+    }
+
     pub fn create_device(
         &self,
         surface: Option<&(vk::SurfaceKHR, ash::khr::surface::Instance)>,
+        config: &RenderConfig,
     ) -> Result<RenderDevice> {
         RenderDevice::new(
             self,
             surface,
+            config,
         )
     }
 
     pub fn create_surface(
         &self,
-        window: &Window,
+        surface_provider: &dyn SurfaceProvider,
     ) -> Result<(vk::SurfaceKHR, ash::khr::surface::Instance)> {
-        let surface = unsafe {
-            ash_window::create_surface(
-                &self.entry,
-                &self.instance,
-                window.display_handle()?.as_raw(),
-                window.window_handle()?.as_raw(),
-                None,
-            )?
-        };
+        let surface = surface_provider.create_surface(&self.entry, &self.instance)?;
         let surface_loader = ash::khr::surface::Instance::new(
             &self.entry,
             &self.instance,
@@ -76,20 +106,26 @@ impl RenderInstance {
         &self,
         window: Arc<Window>,
         surface: (vk::SurfaceKHR, ash::khr::surface::Instance),
+        preferred_present_mode: Option<vk::PresentModeKHR>,
         dev: &RenderDevice,
     ) -> Result<RenderTarget> {
         RenderTarget::new(
             window,
             surface,
+            preferred_present_mode,
             self,
             dev,
         )
     }
 
-    fn create_instance(
+    fn create_instance<F>(
         entry: &ash::Entry,
-        window: Option<&Arc<Window>>,
-    ) -> Result<ash::Instance> {
+        surface_provider: Option<&dyn SurfaceProvider>,
+        create_instance: F,
+    ) -> Result<ash::Instance>
+    where
+        F: FnOnce(&ash::Entry, &vk::InstanceCreateInfo) -> Result<ash::Instance>,
+    {
         if Self::ENABLE_VALIDATION_LAYERS {
             Self::check_validation_layers_supported(entry)?;
         }
@@ -104,7 +140,7 @@ impl RenderInstance {
         } else {
             Vec::new()
         };
-        let enabled_extension_names = Self::get_required_instance_extensions(window)?
+        let enabled_extension_names = Self::get_required_instance_extensions(surface_provider)?
             .iter()
             .map(|ext| ext.as_ptr())
             .collect::<Vec<*const c_char>>();
@@ -119,9 +155,7 @@ impl RenderInstance {
         let instance_info = instance_info
             .flags(vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR);
 
-        Ok(unsafe {
-            entry.create_instance(&instance_info, None)?
-        })
+        create_instance(entry, &instance_info)
     }
 
     fn create_debug_utils_messenger(
@@ -137,17 +171,10 @@ impl RenderInstance {
     }
 
     fn get_required_instance_extensions(
-        window: Option<&Arc<Window>>,
+        surface_provider: Option<&dyn SurfaceProvider>,
     ) -> Result<Vec<&'static CStr>> {
-        let mut exts = if let Some(window) = window {
-            ash_window::enumerate_required_extensions(
-                window.display_handle()?.as_raw()
-            )?
-                .iter()
-                .map(|ext| unsafe {
-                    CStr::from_ptr(*ext)
-                })
-                .collect::<Vec<_>>()
+        let mut exts = if let Some(surface_provider) = surface_provider {
+            surface_provider.get_required_instance_extensions()?
         } else {
             Vec::new()
         };
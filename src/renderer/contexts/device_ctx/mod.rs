@@ -3,13 +3,18 @@ pub mod device;
 pub mod target;
 pub mod queue;
 pub mod swapchain;
+pub mod surface;
 pub mod transfer_ctx;
 pub mod command_encoder_allocator;
 pub mod command_encoder;
+pub mod query;
+pub mod sync_pool;
 
 use std::sync::Arc;
 use color_eyre::Result;
-use crate::renderer::contexts::device_ctx::device::RenderDevice;
+use ash::vk;
+use crate::renderer::config::RenderConfig;
+use crate::renderer::contexts::device_ctx::device::{ExistingQueues, RenderDevice};
 use crate::renderer::contexts::device_ctx::instance::RenderInstance;
 use crate::renderer::contexts::device_ctx::target::RenderTarget;
 
@@ -21,24 +26,83 @@ pub struct RenderDeviceContext {
     pub instance: RenderInstance,
     pub device: RenderDevice,
     pub target: Option<RenderTarget>,
+
+    // Kept so recreated targets use the same present mode
+    preferred_present_mode: Option<vk::PresentModeKHR>,
 }
 
 impl RenderDeviceContext {
     pub fn new(
-        window: Option<Arc<winit::window::Window>>
+        window: Option<Arc<winit::window::Window>>,
+        config: &RenderConfig,
     ) -> Result<Self> {
         let instance = RenderInstance::new(window.clone())?;
         let surface = if let Some(window) = window.as_ref() {
-            Some(instance.create_surface(window)?)
+            Some(instance.create_surface(window.as_ref())?)
         } else {
             None
         };
-        let device = instance.create_device(surface.as_ref())?;
+        let device = instance.create_device(surface.as_ref(), config)?;
+
+        Self::from_parts(instance, device, window, surface, config.present_mode)
+    }
+
+    /// Whether `new` can create a context without a window. Tests that render use this to skip
+    /// themselves on machines without a suitable GPU or driver.
+    pub fn is_headless_supported(config: &RenderConfig) -> bool {
+        let Ok(instance) = RenderInstance::new(None) else {
+            return false;
+        };
+        let supported = RenderDevice::is_supported(&instance, config);
+        unsafe {
+            instance.debug_utils_loader
+                .destroy_debug_utils_messenger(instance.debug_utils_messenger, None);
+            instance.instance.destroy_instance(None);
+        }
+        supported
+    }
+
+    /// Use a Vulkan instance and device that are owned by someone else
+    pub fn from_existing(
+        entry: ash::Entry,
+        instance: ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        queues: ExistingQueues,
+        window: Option<Arc<winit::window::Window>>,
+        config: &RenderConfig,
+    ) -> Result<Self> {
+        let instance = RenderInstance::from_existing(entry, instance);
+        let surface = if let Some(window) = window.as_ref() {
+            Some(instance.create_surface(window.as_ref())?)
+        } else {
+            None
+        };
+        let device = RenderDevice::from_existing(
+            &instance,
+            physical_device,
+            device,
+            queues,
+            config,
+        )?;
+
+        Self::from_parts(instance, device, window, surface, config.present_mode)
+    }
+
+    /// Assemble the context from an instance and device created some other way. There is only a
+    /// presentation target if both a window and a surface are given.
+    pub fn from_parts(
+        instance: RenderInstance,
+        device: RenderDevice,
+        window: Option<Arc<winit::window::Window>>,
+        surface: Option<(vk::SurfaceKHR, ash::khr::surface::Instance)>,
+        preferred_present_mode: Option<vk::PresentModeKHR>,
+    ) -> Result<Self> {
         let target = if let (
             Some(window),
             Some(surface),
         ) = (window, surface) {
-            Some(instance.create_target(window, surface, &device)?)
+            Some(instance.create_target(window, surface, preferred_present_mode, &device)?)
         } else {
             None
         };
@@ -47,6 +111,136 @@ impl RenderDeviceContext {
             instance,
             device,
             target,
+            preferred_present_mode,
         })
     }
+
+    /// Present with `preferred_present_mode`, rebuilding the target's swapchain if there is one.
+    /// Targets recreated later use it too.
+    pub fn set_present_mode(
+        &mut self,
+        preferred_present_mode: Option<vk::PresentModeKHR>,
+    ) -> Result<()> {
+        self.preferred_present_mode = preferred_present_mode;
+        if let Some(target) = self.target.as_mut() {
+            target.set_present_mode(preferred_present_mode, &self.instance, &self.device)?;
+        }
+        Ok(())
+    }
+
+    /// Tear down the presentation target while keeping the device, for platforms like Android
+    /// that destroy the window's surface when the app is suspended
+    pub fn destroy_target(&mut self) -> Result<()> {
+        if let Some(target) = self.target.take() {
+            unsafe {
+                self.device.logical.device_wait_idle()?;
+            }
+            target.destroy(&self.device);
+        }
+        Ok(())
+    }
+
+    /// Create a new surface and presentation target for `window`, replacing the old one. The
+    /// graphics queue must be able to present to the new surface, which holds when it is on the
+    /// same display as the window the device was created for.
+    pub fn recreate_target(&mut self, window: Arc<winit::window::Window>) -> Result<()> {
+        self.destroy_target()?;
+        let surface = self.instance.create_surface(window.as_ref())?;
+        self.target = Some(self.instance.create_target(
+            window,
+            surface,
+            self.preferred_present_mode,
+            &self.device,
+        )?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::contexts::resource_ctx::resource_id::ResourceIdAllocator;
+    use super::command_encoder_allocator::CommandEncoderAllocatorExt;
+    use crate::renderer::resources::megabuffer::MegabufferExt;
+
+    // A headless context, or None when there is no device for one and the test should skip
+    fn test_context() -> Option<RenderDeviceContext> {
+        let config = RenderConfig::default();
+        if !RenderDeviceContext::is_headless_supported(&config) {
+            eprintln!("No Vulkan device found, skipping");
+            return None;
+        }
+        Some(RenderDeviceContext::new(None, &config).unwrap())
+    }
+
+    #[test]
+    fn headless_context_has_every_queue() {
+        let Some(dev_ctx) = test_context() else {
+            return;
+        };
+        assert!(dev_ctx.target.is_none());
+        let queues = [
+            (&dev_ctx.device.graphics_queue, vk::QueueFlags::GRAPHICS),
+            (&dev_ctx.device.compute_queue, vk::QueueFlags::COMPUTE),
+            // Graphics and compute queues support transfers without saying so
+            (
+                &dev_ctx.device.transfer_queue,
+                vk::QueueFlags::TRANSFER | vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+            ),
+        ];
+        for (queue, flags) in queues {
+            assert_ne!(queue.handle, vk::Queue::null());
+            assert!(queue.family.properties.queue_flags.intersects(flags));
+        }
+        drop(dev_ctx);
+    }
+
+    #[test]
+    fn megabuffer_regions_respect_the_device_offset_alignments() {
+        let Some(dev_ctx) = test_context() else {
+            return;
+        };
+        let limits = *dev_ctx.device.get_limits();
+        let id_allocator = Arc::new(ResourceIdAllocator::new());
+        let cases = [
+            (limits.min_uniform_buffer_offset_alignment, vk::BufferUsageFlags::UNIFORM_BUFFER),
+            (limits.min_storage_buffer_offset_alignment, vk::BufferUsageFlags::STORAGE_BUFFER),
+        ];
+        for (alignment, usage) in cases {
+            let megabuffer = dev_ctx.device
+                .create_megabuffer(64 * 1024, alignment, usage, id_allocator.clone())
+                .unwrap();
+            // Odd sizes, so a region only starts aligned if the allocator pads the previous one
+            let regions = [1, 3, 17, 100, 255]
+                .map(|size| megabuffer.allocate_region(size).unwrap());
+            for region in &regions {
+                assert!(
+                    region.get_offset().is_multiple_of(alignment),
+                    "offset {} is not a multiple of {}",
+                    region.get_offset(),
+                    alignment,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn freed_command_buffers_are_handed_out_again() {
+        let Some(dev_ctx) = test_context() else {
+            return;
+        };
+        let queue = dev_ctx.device.graphics_queue.clone();
+        let mut allocator = dev_ctx.device.get_command_encoder_allocator();
+
+        let first = allocator.allocate(queue.clone()).unwrap();
+        let handle = first.command_buffer;
+        drop(first);
+        let second = allocator.allocate(queue.clone()).unwrap();
+        assert_eq!(second.command_buffer, handle);
+
+        // Recycled buffers keep their level, so a secondary allocation doesn't take a primary one
+        drop(second);
+        let secondary = allocator.allocate_secondary(queue).unwrap();
+        assert_ne!(secondary.command_buffer, handle);
+    }
 }
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::stats::PipelineStatistics;
+
+/// A single `PIPELINE_STATISTICS` query, begun and ended around a pass and read back once the
+/// frame's fence has been waited on
+pub struct PipelineStatisticsQuery {
+    pool: vk::QueryPool,
+    // Whether the query has been ended since it was last read
+    is_pending: bool,
+    device: Arc<ash::Device>,
+}
+
+impl PipelineStatisticsQuery {
+    // Results are written in the order of the flag bits, matching the fields of
+    // `PipelineStatistics`
+    const FLAGS: vk::QueryPipelineStatisticFlags = vk::QueryPipelineStatisticFlags::from_raw(
+        vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES.as_raw()
+            | vk::QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS.as_raw()
+            | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES.as_raw()
+            | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS.as_raw(),
+    );
+
+    /// Requires the `pipeline_statistics_query` device feature
+    pub fn new(device: Arc<ash::Device>) -> Result<Self> {
+        let pool_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::PIPELINE_STATISTICS)
+            .query_count(1)
+            .pipeline_statistics(Self::FLAGS);
+        let pool = unsafe {
+            device.create_query_pool(&pool_info, None)?
+        };
+
+        Ok(Self {
+            pool,
+            is_pending: false,
+            device,
+        })
+    }
+
+    /// Reset and begin the query. Must be recorded outside of rendering, and the commands in
+    /// between must not execute secondary command buffers.
+    pub fn begin(&mut self, cmd: &CommandEncoder) {
+        unsafe {
+            self.device.cmd_reset_query_pool(cmd.command_buffer, self.pool, 0, 1);
+            self.device.cmd_begin_query(
+                cmd.command_buffer,
+                self.pool,
+                0,
+                vk::QueryControlFlags::empty(),
+            );
+        }
+    }
+
+    pub fn end(&mut self, cmd: &CommandEncoder) {
+        unsafe {
+            self.device.cmd_end_query(cmd.command_buffer, self.pool, 0);
+        }
+        self.is_pending = true;
+    }
+
+    /// Results of the last ended query, or `None` if it hasn't been used since the last read.
+    /// Waits for the results, so only call it once the submission containing the query has
+    /// finished.
+    pub fn take_results(&mut self) -> Result<Option<PipelineStatistics>> {
+        if !self.is_pending {
+            return Ok(None);
+        }
+
+        let mut results = [[0u64; 5]; 1];
+        unsafe {
+            self.device.get_query_pool_results(
+                self.pool,
+                0,
+                &mut results,
+                vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WAIT,
+            )?;
+        }
+        self.is_pending = false;
+
+        let [
+            input_assembly_vertices,
+            input_assembly_primitives,
+            vertex_shader_invocations,
+            clipping_primitives,
+            fragment_shader_invocations,
+        ] = results[0];
+        Ok(Some(PipelineStatistics {
+            input_assembly_vertices,
+            input_assembly_primitives,
+            vertex_shader_invocations,
+            clipping_primitives,
+            fragment_shader_invocations,
+        }))
+    }
+}
+
+impl Drop for PipelineStatisticsQuery {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_query_pool(self.pool, None);
+        }
+    }
+}
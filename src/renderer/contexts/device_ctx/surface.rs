@@ -0,0 +1,51 @@
+use std::ffi::CStr;
+use ash::vk;
+use color_eyre::Result;
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use winit::window::Window;
+
+/// Something a Vulkan surface can be presented to, so the instance and swapchain don't depend on
+/// how the surface is made. Implemented for winit windows through `ash_window`; other platforms
+/// or backends can implement it themselves.
+pub trait SurfaceProvider {
+    /// Instance extensions `create_surface` needs
+    fn get_required_instance_extensions(&self) -> Result<Vec<&'static CStr>>;
+
+    /// Create the surface on an instance with the required extensions enabled
+    fn create_surface(&self, entry: &ash::Entry, instance: &ash::Instance) -> Result<vk::SurfaceKHR>;
+
+    /// Size of the drawable area in physical pixels, used when the surface lets the swapchain pick
+    /// its extent
+    fn get_surface_size(&self) -> vk::Extent2D;
+}
+
+impl SurfaceProvider for Window {
+    fn get_required_instance_extensions(&self) -> Result<Vec<&'static CStr>> {
+        Ok(ash_window::enumerate_required_extensions(self.display_handle()?.as_raw())?
+            .iter()
+            .map(|ext| unsafe {
+                CStr::from_ptr(*ext)
+            })
+            .collect())
+    }
+
+    fn create_surface(&self, entry: &ash::Entry, instance: &ash::Instance) -> Result<vk::SurfaceKHR> {
+        Ok(unsafe {
+            ash_window::create_surface(
+                entry,
+                instance,
+                self.display_handle()?.as_raw(),
+                self.window_handle()?.as_raw(),
+                None,
+            )?
+        })
+    }
+
+    fn get_surface_size(&self) -> vk::Extent2D {
+        let size = self.inner_size();
+        vk::Extent2D {
+            width: size.width,
+            height: size.height,
+        }
+    }
+}
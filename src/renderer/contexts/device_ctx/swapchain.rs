@@ -1,9 +1,9 @@
 use ash::prelude::VkResult;
 use ash::vk;
 use color_eyre::Result;
-use winit::window::Window;
 use crate::renderer::contexts::device_ctx::device::RenderDevice;
 use crate::renderer::contexts::device_ctx::instance::RenderInstance;
+use crate::renderer::contexts::device_ctx::surface::SurfaceProvider;
 
 pub struct Swapchain {
     pub swapchain: vk::SwapchainKHR,
@@ -19,32 +19,44 @@ pub struct Swapchain {
     pub swapchain_image_sharing_mode: vk::SharingMode,
 }
 
+/// The surface a swapchain presents to and how, as chosen by the `RenderTarget` that owns it
+pub struct SwapchainDesc<'a> {
+    pub surface: vk::SurfaceKHR,
+    pub surface_loader: &'a ash::khr::surface::Instance,
+    pub surface_format: vk::SurfaceFormatKHR,
+    pub present_mode: vk::PresentModeKHR,
+    /// The swapchain being replaced, or null
+    pub old_swapchain: vk::SwapchainKHR,
+    /// Size to ask for if the surface leaves it to the swapchain, the window's with `None`
+    pub desired_extent: Option<vk::Extent2D>,
+    pub surface_provider: &'a dyn SurfaceProvider,
+}
+
 impl Swapchain {
     pub fn new(
-        surface: &vk::SurfaceKHR,
-        surface_loader: &ash::khr::surface::Instance,
-        surface_format: &vk::SurfaceFormatKHR,
-        surface_present_mode: &vk::PresentModeKHR,
-        window: &Window,
+        desc: &SwapchainDesc,
         ins: &RenderInstance,
         dev: &RenderDevice,
     ) -> Result<Self> {
         let surface_capabilities = unsafe {
-            surface_loader
-                .get_physical_device_surface_capabilities(dev.physical, *surface)?
+            desc.surface_loader
+                .get_physical_device_surface_capabilities(dev.physical, desc.surface)?
         };
 
         let image_extent = {
             if surface_capabilities.current_extent.width != u32::MAX {
                 surface_capabilities.current_extent
             } else {
-                let window_size = window.inner_size();
+                // The surface lets the swapchain pick its size, so use the requested one or the
+                // window's
+                let desired_extent = desc.desired_extent
+                    .unwrap_or_else(|| desc.surface_provider.get_surface_size());
                 vk::Extent2D {
-                    width: window_size.width.clamp(
+                    width: desired_extent.width.clamp(
                         surface_capabilities.min_image_extent.width,
                         surface_capabilities.max_image_extent.width,
                     ),
-                    height: window_size.height.clamp(
+                    height: desired_extent.height.clamp(
                         surface_capabilities.min_image_extent.height,
                         surface_capabilities.max_image_extent.height,
                     ),
@@ -81,17 +93,18 @@ impl Swapchain {
             &dev.logical,
         );
         let swapchain_info = vk::SwapchainCreateInfoKHR::default()
-            .surface(*surface)
+            .surface(desc.surface)
             .min_image_count(min_image_count)
-            .image_format(surface_format.format)
-            .image_color_space(surface_format.color_space)
+            .image_format(desc.surface_format.format)
+            .image_color_space(desc.surface_format.color_space)
             .image_extent(image_extent)
             .image_usage(image_usage)
             .image_sharing_mode(image_sharing_mode)
             .pre_transform(pre_transform)
             .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-            .present_mode(*surface_present_mode)
+            .present_mode(desc.present_mode)
             .clipped(true)
+            .old_swapchain(desc.old_swapchain)
             .image_array_layers(1);
 
         let swapchain = unsafe {
@@ -104,7 +117,7 @@ impl Swapchain {
         ) = Self::create_swapchain_images(
             &swapchain,
             &swapchain_loader,
-            &surface_format.format,
+            &desc.surface_format.format,
             dev,
         )?;
 
@@ -113,18 +126,64 @@ impl Swapchain {
         Ok(Self {
             swapchain,
             swapchain_loader,
-            swapchain_present_mode: *surface_present_mode,
+            swapchain_present_mode: desc.present_mode,
             swapchain_images,
             swapchain_image_count,
             swapchain_image_views,
             swapchain_image_extent: image_extent,
-            swapchain_image_format: surface_format.format,
-            swapchain_image_color_space: surface_format.color_space,
+            swapchain_image_format: desc.surface_format.format,
+            swapchain_image_color_space: desc.surface_format.color_space,
             swapchain_image_usage: image_usage,
             swapchain_image_sharing_mode: image_sharing_mode,
         })
     }
 
+    /// Acquire the next image to render into, signalling `semaphore` once it is available.
+    /// Returns the image index and whether the swapchain is suboptimal for the surface.
+    pub fn acquire_next_image(
+        &self,
+        semaphore: vk::Semaphore,
+    ) -> VkResult<(u32, bool)> {
+        unsafe {
+            self.swapchain_loader.acquire_next_image(
+                self.swapchain,
+                u64::MAX,
+                semaphore,
+                vk::Fence::null(),
+            )
+        }
+    }
+
+    /// Queue the image for presentation once `wait_semaphore` is signalled.
+    /// Returns whether the swapchain is suboptimal for the surface.
+    pub fn present(
+        &self,
+        queue: vk::Queue,
+        image_index: u32,
+        wait_semaphore: vk::Semaphore,
+    ) -> VkResult<bool> {
+        let swapchains = [self.swapchain];
+        let image_indices = [image_index];
+        let wait_semaphores = [wait_semaphore];
+        let present_info = vk::PresentInfoKHR::default()
+            .swapchains(&swapchains)
+            .image_indices(&image_indices)
+            .wait_semaphores(&wait_semaphores);
+        unsafe {
+            self.swapchain_loader.queue_present(queue, &present_info)
+        }
+    }
+
+    /// Destroy the image views and the swapchain. The device must be idle.
+    pub fn destroy(&self, dev: &RenderDevice) {
+        unsafe {
+            for view in &self.swapchain_image_views {
+                dev.logical.destroy_image_view(*view, None);
+            }
+            self.swapchain_loader.destroy_swapchain(self.swapchain, None);
+        }
+    }
+
     fn create_swapchain_images(
         swapchain: &vk::SwapchainKHR,
         swapchain_loader: &ash::khr::swapchain::Device,
@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex};
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+/// Recycles fences and semaphores so submissions that come and go, like polled transfers, don't
+/// create and destroy them every time
+pub struct SyncPool {
+    fences: Mutex<Vec<vk::Fence>>,
+    semaphores: Mutex<Vec<vk::Semaphore>>,
+    device: Arc<ash::Device>,
+}
+
+impl SyncPool {
+    pub fn new(device: Arc<ash::Device>) -> Self {
+        Self {
+            fences: Mutex::new(Vec::new()),
+            semaphores: Mutex::new(Vec::new()),
+            device,
+        }
+    }
+
+    /// An unsignaled fence, recycled if one is available
+    pub fn acquire_fence(&self) -> Result<vk::Fence> {
+        let recycled = self.fences
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .pop();
+        match recycled {
+            Some(fence) => Ok(fence),
+            None => Ok(unsafe {
+                self.device.create_fence(&vk::FenceCreateInfo::default(), None)?
+            }),
+        }
+    }
+
+    /// Hand a fence back for reuse. Any work it was submitted with must have finished; it is reset
+    /// here so the next `acquire_fence` gets it unsignaled.
+    pub fn release_fence(&self, fence: vk::Fence) -> Result<()> {
+        unsafe {
+            self.device.reset_fences(&[fence])?;
+        }
+        self.fences
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .push(fence);
+        Ok(())
+    }
+
+    /// An unsignaled binary semaphore, recycled if one is available
+    pub fn acquire_semaphore(&self) -> Result<vk::Semaphore> {
+        let recycled = self.semaphores
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .pop();
+        match recycled {
+            Some(semaphore) => Ok(semaphore),
+            None => Ok(unsafe {
+                self.device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
+            }),
+        }
+    }
+
+    /// Hand a semaphore back for reuse. It must be unsignaled, either because it was never
+    /// signaled or because a submission that waited on it has finished, since binary semaphores
+    /// can't be reset from the host.
+    pub fn release_semaphore(&self, semaphore: vk::Semaphore) -> Result<()> {
+        self.semaphores
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .push(semaphore);
+        Ok(())
+    }
+}
+
+impl Drop for SyncPool {
+    fn drop(&mut self) {
+        unsafe {
+            for fence in self.fences.get_mut().unwrap().drain(..) {
+                self.device.destroy_fence(fence, None);
+            }
+            for semaphore in self.semaphores.get_mut().unwrap().drain(..) {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+        }
+    }
+}
@@ -1,11 +1,12 @@
-use crate::renderer::contexts::device_ctx::swapchain::Swapchain;
+use crate::renderer::contexts::device_ctx::swapchain::{Swapchain, SwapchainDesc};
 use ash::vk;
 use color_eyre::eyre::OptionExt;
 use color_eyre::Result;
 use std::sync::Arc;
-use winit::window::Window;
+use winit::window::{Fullscreen, Window};
 use crate::renderer::contexts::device_ctx::device::RenderDevice;
 use crate::renderer::contexts::device_ctx::instance::RenderInstance;
+use crate::renderer::config::FullscreenMode;
 
 /// Presentation target of the renderer, encapsulating the window, surface, and swapchain
 pub struct RenderTarget {
@@ -15,6 +16,8 @@ pub struct RenderTarget {
     pub surface_loader: ash::khr::surface::Instance,
     pub surface_format: vk::SurfaceFormatKHR,
     pub surface_present_mode: vk::PresentModeKHR,
+    // Asked for in the config; `surface_present_mode` is what the surface currently supports
+    preferred_present_mode: Option<vk::PresentModeKHR>,
 
     pub swapchain: Swapchain,
 }
@@ -23,6 +26,7 @@ impl RenderTarget {
     pub fn new(
         window: Arc<Window>,
         surface: (vk::SurfaceKHR, ash::khr::surface::Instance),
+        preferred_present_mode: Option<vk::PresentModeKHR>,
         ins: &RenderInstance,
         dev: &RenderDevice,
     ) -> Result<Self> {
@@ -34,11 +38,6 @@ impl RenderTarget {
                 .get_physical_device_surface_formats(dev.physical, surface)?
         };
 
-        let surface_present_modes = unsafe {
-            surface_loader
-                .get_physical_device_surface_present_modes(dev.physical, surface)?
-        };
-
         let surface_format = surface_formats
             .iter()
             .find(|format| {
@@ -47,17 +46,28 @@ impl RenderTarget {
             })
             .ok_or_eyre("No suitable surface format found")?;
 
-        let surface_present_mode = surface_present_modes
-            .iter()
-            .find(|mode| **mode == vk::PresentModeKHR::MAILBOX)
-            .unwrap_or(&vk::PresentModeKHR::FIFO);
+        let surface_present_mode = select_present_mode(
+            preferred_present_mode,
+            surface,
+            &surface_loader,
+            dev,
+        )?;
+        if let Some(mode) = preferred_present_mode
+            && mode != surface_present_mode
+        {
+            log::warn!("Present mode {:?} is not supported by the surface", mode);
+        }
 
         let swapchain = Swapchain::new(
-            &surface,
-            &surface_loader,
-            surface_format,
-            surface_present_mode,
-            &window,
+            &SwapchainDesc {
+                surface,
+                surface_loader: &surface_loader,
+                surface_format: *surface_format,
+                present_mode: surface_present_mode,
+                old_swapchain: vk::SwapchainKHR::null(),
+                desired_extent: None,
+                surface_provider: window.as_ref(),
+            },
             ins,
             dev,
         )?;
@@ -67,7 +77,8 @@ impl RenderTarget {
             surface,
             surface_loader,
             surface_format: *surface_format,
-            surface_present_mode: *surface_present_mode,
+            surface_present_mode,
+            preferred_present_mode,
             swapchain,
         })
     }
@@ -76,27 +87,173 @@ impl RenderTarget {
         &mut self,
         ins: &RenderInstance,
         dev: &RenderDevice,
+    ) -> Result<()> {
+        self.recreate(ins, dev)
+    }
+
+    /// Recreate the swapchain for the current surface extent. The surface format chosen in `new` is
+    /// reused rather than selected again, so pipelines built against the swapchain format stay
+    /// compatible. The present mode is selected again, since the modes a surface supports can
+    /// change with the window, e.g. when it enters exclusive fullscreen.
+    pub fn recreate(
+        &mut self,
+        ins: &RenderInstance,
+        dev: &RenderDevice,
+    ) -> Result<()> {
+        self.recreate_with_extent(None, ins, dev)
+    }
+
+    /// Like `recreate`, but asks for `extent` instead of the window size. Many surfaces dictate
+    /// their extent, in which case theirs is used; otherwise it is clamped to what the surface
+    /// supports. Check `swapchain.swapchain_image_extent` for the result.
+    pub fn recreate_with_extent(
+        &mut self,
+        extent: Option<vk::Extent2D>,
+        ins: &RenderInstance,
+        dev: &RenderDevice,
     ) -> Result<()> {
         unsafe {
             dev.logical.device_wait_idle()?;
         }
 
-        self.swapchain = Swapchain::new(
-            &self.surface,
+        let present_mode = select_present_mode(
+            self.preferred_present_mode,
+            self.surface,
             &self.surface_loader,
-            &self.surface_format,
-            &self.surface_present_mode,
-            &self.window,
+            dev,
+        )?;
+        if present_mode != self.surface_present_mode {
+            log::info!(
+                "Switching present mode from {:?} to {:?}",
+                self.surface_present_mode,
+                present_mode,
+            );
+            self.surface_present_mode = present_mode;
+        }
+
+        let swapchain = Swapchain::new(
+            &SwapchainDesc {
+                surface: self.surface,
+                surface_loader: &self.surface_loader,
+                surface_format: self.surface_format,
+                present_mode: self.surface_present_mode,
+                old_swapchain: self.swapchain.swapchain,
+                desired_extent: extent,
+                surface_provider: self.window.as_ref(),
+            },
             ins,
             dev,
         )?;
+        let old_swapchain = std::mem::replace(&mut self.swapchain, swapchain);
+        old_swapchain.destroy(dev);
 
         Ok(())
     }
 
+    /// Present with `preferred_present_mode` from now on, or the default with `None`, and rebuild
+    /// the swapchain with it. Falls back to `FIFO` with a warning if the surface doesn't support
+    /// the mode.
+    pub fn set_present_mode(
+        &mut self,
+        preferred_present_mode: Option<vk::PresentModeKHR>,
+        ins: &RenderInstance,
+        dev: &RenderDevice,
+    ) -> Result<()> {
+        self.preferred_present_mode = preferred_present_mode;
+        self.recreate(ins, dev)?;
+        if let Some(mode) = preferred_present_mode
+            && mode != self.surface_present_mode
+        {
+            log::warn!("Present mode {:?} is not supported by the surface", mode);
+        }
+        Ok(())
+    }
+
+    pub fn get_preferred_present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.preferred_present_mode
+    }
+
+    /// Destroy the swapchain and surface, e.g. when the platform takes the window's surface away
+    /// on suspend. The device must be idle.
+    pub fn destroy(self, dev: &RenderDevice) {
+        self.swapchain.destroy(dev);
+        unsafe {
+            self.surface_loader.destroy_surface(self.surface, None);
+        }
+    }
+
     pub fn get_size(&self) -> winit::dpi::PhysicalSize<u32> {
         self.window.inner_size()
     }
 
+    /// Ratio of physical to logical pixels, e.g. 2.0 on many HiDPI displays
+    pub fn get_scale_factor(&self) -> f64 {
+        self.window.scale_factor()
+    }
+
+    /// Size in logical pixels, for laying out UI and text independently of the display density.
+    /// The swapchain always uses the physical size.
+    pub fn get_logical_size(&self) -> winit::dpi::LogicalSize<f64> {
+        self.get_size().to_logical(self.get_scale_factor())
+    }
+
+    /// Make the window fullscreen on its current monitor, or windowed again with `None`. The
+    /// window is resized by the platform, so the swapchain has to be recreated afterwards.
+    ///
+    /// Exclusive fullscreen on Windows is only truly exclusive with `VK_EXT_full_screen_exclusive`,
+    /// which is not enabled yet, so the driver may treat it like borderless.
+    pub fn set_fullscreen(&self, mode: Option<FullscreenMode>) -> Result<()> {
+        let fullscreen = match mode {
+            None => None,
+            Some(FullscreenMode::Borderless) => Some(Fullscreen::Borderless(None)),
+            Some(FullscreenMode::Exclusive) => {
+                let video_mode = self.window
+                    .current_monitor()
+                    .ok_or_eyre("Window is not on a monitor")?
+                    .video_modes()
+                    .max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                    .ok_or_eyre("Monitor has no video modes")?;
+                Some(Fullscreen::Exclusive(video_mode))
+            }
+        };
+        self.window.set_fullscreen(fullscreen);
+
+        Ok(())
+    }
+
+    pub fn get_fullscreen(&self) -> Option<FullscreenMode> {
+        match self.window.fullscreen()? {
+            Fullscreen::Borderless(_) => Some(FullscreenMode::Borderless),
+            Fullscreen::Exclusive(_) => Some(FullscreenMode::Exclusive),
+        }
+    }
+
 }
 
+/// The preferred mode if the surface supports it, otherwise `MAILBOX` if supported, otherwise
+/// `FIFO`, which every surface supports. Some drivers drop `MAILBOX` in exclusive fullscreen, so
+/// this has to be asked again whenever the swapchain is recreated.
+fn select_present_mode(
+    preferred_present_mode: Option<vk::PresentModeKHR>,
+    surface: vk::SurfaceKHR,
+    surface_loader: &ash::khr::surface::Instance,
+    dev: &RenderDevice,
+) -> Result<vk::PresentModeKHR> {
+    let surface_present_modes = unsafe {
+        surface_loader
+            .get_physical_device_surface_present_modes(dev.physical, surface)?
+    };
+    // A mode asked for explicitly goes straight to `FIFO` when it isn't supported, so asking
+    // for e.g. `IMMEDIATE` never silently ends up as `MAILBOX`
+    let wanted = preferred_present_mode.unwrap_or(vk::PresentModeKHR::MAILBOX);
+    let present_mode = if surface_present_modes.contains(&wanted) {
+        wanted
+    } else {
+        vk::PresentModeKHR::FIFO
+    };
+
+    Ok(present_mode)
+}
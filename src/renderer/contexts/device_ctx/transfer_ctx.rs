@@ -1,50 +1,238 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use ash::vk;
-use color_eyre::eyre::Result;
-use crate::renderer::contexts::device_ctx::queue::Queue;
+use color_eyre::eyre::{eyre, Result};
+use crate::renderer::contexts::device_ctx::queue::{Queue, QueueFamily};
+use crate::renderer::contexts::device_ctx::sync_pool::SyncPool;
 
-pub struct TransferContext {
-    transfer_fence: vk::Fence,
+/// Command pool, command buffer and fence used to submit one-off work to a single queue family.
+/// Polled submissions to the family allocate their command buffers from the same pool.
+struct ImmediateSubmitResources {
+    fence: vk::Fence,
     command_pool: vk::CommandPool,
     command_buffer: vk::CommandBuffer,
+    // Set while submitted work hasn't been waited on, which stays the case after a bounded wait
+    // times out
+    pending: bool,
+    // Command buffers of finished polled submissions, reset and ready for the next ones
+    free_command_buffers: Vec<vk::CommandBuffer>,
+
+    device: Arc<ash::Device>,
+}
+
+impl ImmediateSubmitResources {
+    fn new(
+        queue_family: &QueueFamily,
+        sync_pool: &SyncPool,
+        device: &Arc<ash::Device>,
+    ) -> Result<Self> {
+        let fence = sync_pool.acquire_fence()?;
+        let command_pool = match Self::create_command_pool(queue_family, device) {
+            Ok(command_pool) => command_pool,
+            Err(e) => {
+                // The fence was never submitted, so it can go straight back
+                sync_pool.release_fence(fence)?;
+                return Err(e);
+            }
+        };
+        let command_buffer = match allocate_command_buffer(command_pool, device) {
+            Ok(command_buffer) => command_buffer,
+            Err(e) => {
+                unsafe {
+                    device.destroy_command_pool(command_pool, None);
+                }
+                sync_pool.release_fence(fence)?;
+                return Err(e);
+            }
+        };
+
+        Ok(Self {
+            fence,
+            command_pool,
+            command_buffer,
+            pending: false,
+            free_command_buffers: Vec::new(),
+            device: device.clone(),
+        })
+    }
+
+    fn create_command_pool(
+        queue_family: &QueueFamily,
+        device: &ash::Device,
+    ) -> Result<vk::CommandPool> {
+        let command_pool_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family.index)
+            // Allow the pool to reset individual command buffers
+            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
+        Ok(unsafe { device.create_command_pool(&command_pool_info, None)? })
+    }
+
+    /// A command buffer for a polled submission, recycled if one is available
+    fn acquire_polled_command_buffer(&mut self) -> Result<vk::CommandBuffer> {
+        match self.free_command_buffers.pop() {
+            Some(command_buffer) => Ok(command_buffer),
+            None => allocate_command_buffer(self.command_pool, &self.device),
+        }
+    }
+
+    /// Hand back the command buffer of a polled submission whose work has finished
+    fn release_polled_command_buffer(&mut self, command_buffer: vk::CommandBuffer) -> Result<()> {
+        unsafe {
+            self.device.reset_command_buffer(
+                command_buffer,
+                vk::CommandBufferResetFlags::empty(),
+            )?;
+        }
+        self.free_command_buffers.push(command_buffer);
+        Ok(())
+    }
+
+    /// Record commands with `func` into `cmd`, a command buffer from this pool. If recording
+    /// fails, `cmd` is reset so it isn't left half recorded for the next submission.
+    fn record<F>(&self, cmd: vk::CommandBuffer, func: F) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        // This command buffer will be used exactly once before resetting
+        let cmd_begin_info = vk::CommandBufferBeginInfo::default()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        unsafe {
+            self.device.begin_command_buffer(cmd, &cmd_begin_info)?;
+        }
+
+        let recorded = func(cmd, &self.device).and_then(|()| unsafe {
+            self.device.end_command_buffer(cmd).map_err(Into::into)
+        });
+        if recorded.is_err() {
+            unsafe {
+                self.device.reset_command_buffer(cmd, vk::CommandBufferResetFlags::empty())?;
+            }
+        }
+        recorded
+    }
+
+    /// Wait for the submitted work, then reset the fence and command buffer for the next
+    /// submission. Command buffers of polled submissions are left alone, since they may still be
+    /// in flight.
+    fn wait_and_reset(&mut self, timeout: Option<Duration>) -> Result<()> {
+        if !self.pending {
+            return Ok(());
+        }
+
+        wait_for_fence(self.fence, timeout, &self.device)?;
+        unsafe {
+            self.device.reset_fences(&[self.fence])?;
+            self.device.reset_command_buffer(
+                self.command_buffer,
+                vk::CommandBufferResetFlags::empty(),
+            )?;
+        }
+        self.pending = false;
+
+        Ok(())
+    }
+}
+
+impl Drop for ImmediateSubmitResources {
+    fn drop(&mut self) {
+        if self.pending && let Err(e) = wait_for_fence(self.fence, None, &self.device) {
+            log::error!("Failed to wait for submitted work before destroying it: {}", e);
+        }
+        // Polled submissions hold on to these resources, so none of their command buffers are
+        // still in flight by now
+        unsafe {
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.destroy_fence(self.fence, None);
+        }
+    }
+}
+
+fn allocate_command_buffer(
+    command_pool: vk::CommandPool,
+    device: &ash::Device,
+) -> Result<vk::CommandBuffer> {
+    let command_buffer_info = vk::CommandBufferAllocateInfo::default()
+        .command_pool(command_pool)
+        .command_buffer_count(1)
+        .level(vk::CommandBufferLevel::PRIMARY);
+    Ok(unsafe { device.allocate_command_buffers(&command_buffer_info)? }[0])
+}
+
+/// Returned (inside the `Report`) when a bounded wait for submitted work runs out of time. The
+/// work is still in flight; downcast to this to tell it apart from a device error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubmitTimeoutError {
+    pub timeout: Duration,
+}
+
+impl fmt::Display for SubmitTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Submitted work did not finish within {:?}", self.timeout)
+    }
+}
+
+impl std::error::Error for SubmitTimeoutError {}
+
+// `None` waits forever
+fn wait_for_fence(
+    fence: vk::Fence,
+    timeout: Option<Duration>,
+    device: &ash::Device,
+) -> Result<()> {
+    let timeout_ns = timeout
+        .map(|timeout| u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX))
+        .unwrap_or(u64::MAX);
+    match unsafe { device.wait_for_fences(&[fence], true, timeout_ns) } {
+        Ok(()) => Ok(()),
+        Err(vk::Result::TIMEOUT) => Err(SubmitTimeoutError {
+            timeout: timeout.unwrap_or(Duration::MAX),
+        }.into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub struct TransferContext {
+    // Created lazily the first time a queue family is submitted to. Each entry has its own mutex
+    // so submissions to different queue families don't wait on each other.
+    resources: Mutex<HashMap<QueueFamily, Arc<Mutex<ImmediateSubmitResources>>>>,
 
     transfer_queue: Arc<Queue>,
+    sync_pool: Arc<SyncPool>,
     device: Arc<ash::Device>,
 }
 
 impl TransferContext {
     pub fn new(
         transfer_queue: Arc<Queue>,
+        sync_pool: Arc<SyncPool>,
         device: Arc<ash::Device>,
     ) -> Result<Self> {
-        let transfer_fence_info = vk::FenceCreateInfo::default();
-        let transfer_fence =
-            unsafe { device.create_fence(&transfer_fence_info, None)? };
-
-        let command_pool_info = vk::CommandPoolCreateInfo::default()
-            .queue_family_index(transfer_queue.family.index)
-            // Allow the pool to reset individual command buffers
-            .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER);
-        let command_pool =
-            unsafe { device.create_command_pool(&command_pool_info, None)? };
-
-        let command_buffer_info = vk::CommandBufferAllocateInfo::default()
-            .command_pool(command_pool)
-            .command_buffer_count(1)
-            .level(vk::CommandBufferLevel::PRIMARY);
-        let command_buffer = unsafe {
-            device.allocate_command_buffers(&command_buffer_info)?[0]
-        };
+        let transfer_resources = ImmediateSubmitResources::new(
+            &transfer_queue.family,
+            &sync_pool,
+            &device,
+        )?;
+        let mut resources = HashMap::new();
+        resources.insert(
+            transfer_queue.family.clone(),
+            Arc::new(Mutex::new(transfer_resources)),
+        );
 
         Ok(Self {
-            transfer_fence,
-            command_pool,
-            command_buffer,
+            resources: Mutex::new(resources),
             transfer_queue,
+            sync_pool,
             device,
         })
     }
 
+    /// Queue that `immediate_submit` submits to
+    pub fn get_transfer_queue(&self) -> &Arc<Queue> {
+        &self.transfer_queue
+    }
+
     // Instantly execute some commands to the GPU without dealing with the render loop and other synchronization
     // This is great for compute calculations and can be used from a background thread separated from the render loop
     pub fn immediate_submit<F>(
@@ -54,25 +242,59 @@ impl TransferContext {
     where
         F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
     {
-        let cmd = self.command_buffer;
+        self.immediate_submit_on(&self.transfer_queue, func)
+    }
 
-        // This command buffer will be used exactly once before resetting
-        let cmd_begin_info = vk::CommandBufferBeginInfo::default()
-            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
-        // Begin the command buffer recording
-        unsafe {
-            self.device.begin_command_buffer(cmd, &cmd_begin_info)?;
-        }
+    /// Record commands with `func`, submit them to `queue` and wait for them to finish, however
+    /// long that takes
+    pub fn immediate_submit_on<F>(
+        &self,
+        queue: &Arc<Queue>,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.submit_and_wait(queue, None, func)
+    }
 
-        func(cmd, &*self.device)?;
+    /// Like `immediate_submit_on`, but gives up waiting after `timeout` with a
+    /// `SubmitTimeoutError`. The work keeps running; the next submission to the same queue family
+    /// waits for it before reusing the command buffer.
+    pub fn immediate_submit_on_with_timeout<F>(
+        &self,
+        queue: &Arc<Queue>,
+        timeout: Duration,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.submit_and_wait(queue, Some(timeout), func)
+    }
 
-        // End the command buffer recording
-        unsafe {
-            self.device.end_command_buffer(cmd)?;
-        }
+    fn submit_and_wait<F>(
+        &self,
+        queue: &Arc<Queue>,
+        timeout: Option<Duration>,
+        func: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        let resources = self.get_resources(&queue.family)?;
+        let mut resources = resources
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+
+        // Work from an earlier submission that timed out has to finish before the command buffer
+        // can be recorded again
+        resources.wait_and_reset(timeout)?;
+
+        resources.record(resources.command_buffer, func)?;
 
         // Submit command buffer to the queue and execute it
-        let cmd = [cmd];
+        let cmd = [resources.command_buffer];
         let submit = vk::SubmitInfo::default()
             .wait_semaphores(&[])
             .wait_dst_stage_mask(&[])
@@ -80,32 +302,190 @@ impl TransferContext {
             .signal_semaphores(&[]);
         unsafe {
             self.device.queue_submit(
-                self.transfer_queue.handle,
+                queue.handle,
                 &[submit],
-                self.transfer_fence
+                resources.fence,
             )?;
         }
+        resources.pending = true;
+
+        // The fence will now block until the commands finish execution
+        resources.wait_and_reset(timeout)
+    }
+
+    /// Record commands with `func` and submit them to `queue` without waiting. The returned handle
+    /// can be polled for completion, so the caller can keep doing work (like drawing a loading
+    /// screen) while the GPU finishes.
+    pub fn submit_polled<F>(
+        &self,
+        queue: &Arc<Queue>,
+        func: F,
+    ) -> Result<SubmitHandle>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.submit_polled_inner(queue, false, func)
+    }
+
+    /// Like `submit_polled`, but the work also signals a semaphore from the sync pool. Take it
+    /// with `SubmitHandle::take_semaphore` to make a later submission wait for the work on the
+    /// GPU instead of polling for it.
+    pub fn submit_polled_signaling<F>(
+        &self,
+        queue: &Arc<Queue>,
+        func: F,
+    ) -> Result<SubmitHandle>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        self.submit_polled_inner(queue, true, func)
+    }
+
+    fn submit_polled_inner<F>(
+        &self,
+        queue: &Arc<Queue>,
+        signal_semaphore: bool,
+        func: F,
+    ) -> Result<SubmitHandle>
+    where
+        F: FnOnce(vk::CommandBuffer, &ash::Device) -> Result<()>,
+    {
+        // Several polled submissions can be in flight at once, so each one gets its own command
+        // buffer from the queue family's pool, and its own fence and semaphore from the sync pool.
+        // The handle gives them back once the work has finished.
+        let resources = self.get_resources(&queue.family)?;
+        let mut handle = SubmitHandle {
+            fence: self.sync_pool.acquire_fence()?,
+            command_buffer: vk::CommandBuffer::null(),
+            semaphore: None,
+            submitted: false,
+            resources: resources.clone(),
+            sync_pool: self.sync_pool.clone(),
+            device: self.device.clone(),
+        };
+        // Locked after the handle exists, so that on an error the lock is released before the
+        // handle recycles its command buffer
+        let mut guard = resources
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        handle.command_buffer = guard.acquire_polled_command_buffer()?;
+        if signal_semaphore {
+            handle.semaphore = Some(self.sync_pool.acquire_semaphore()?);
+        }
+        guard.record(handle.command_buffer, func)?;
 
+        let cmd = [handle.command_buffer];
+        let signal_semaphores = handle.semaphore.as_slice();
+        let submit = vk::SubmitInfo::default()
+            .command_buffers(&cmd)
+            .signal_semaphores(signal_semaphores);
         unsafe {
-            // `transfer_fence` will now block until the commands finish execution
-            self.device.wait_for_fences(&[self.transfer_fence], true, 9999999999)?;
-            self.device.reset_fences(&[self.transfer_fence])?;
-            // Reset command buffers inside command pool
-            self.device.reset_command_pool(
-                self.command_pool,
-                vk::CommandPoolResetFlags::empty(),
-            )?;
+            self.device.queue_submit(queue.handle, &[submit], handle.fence)?;
         }
+        handle.submitted = true;
 
-        Ok(())
+        Ok(handle)
+    }
+
+    fn get_resources(
+        &self,
+        queue_family: &QueueFamily,
+    ) -> Result<Arc<Mutex<ImmediateSubmitResources>>> {
+        let mut guard = self.resources
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+
+        if let Some(resources) = guard.get(queue_family) {
+            return Ok(resources.clone());
+        }
+
+        let resources = Arc::new(Mutex::new(
+            ImmediateSubmitResources::new(queue_family, &self.sync_pool, &self.device)?
+        ));
+        guard.insert(queue_family.clone(), resources.clone());
+        Ok(resources)
     }
 }
 
-impl Drop for TransferContext {
+/// Work submitted with `TransferContext::submit_polled`. Dropping the handle waits for the work to
+/// finish, since its command buffer can't be reused while the GPU is still using it.
+pub struct SubmitHandle {
+    fence: vk::Fence,
+    // Null until one was acquired
+    command_buffer: vk::CommandBuffer,
+    // Signaled by the work, until the caller takes it
+    semaphore: Option<vk::Semaphore>,
+    // The fence is never signaled if recording or submission failed, so don't wait on it then
+    submitted: bool,
+    // The queue family's resources, whose pool the command buffer came from
+    resources: Arc<Mutex<ImmediateSubmitResources>>,
+    sync_pool: Arc<SyncPool>,
+    device: Arc<ash::Device>,
+}
+
+impl SubmitHandle {
+    /// Check whether the submitted work has finished without blocking
+    pub fn is_done(&self) -> Result<bool> {
+        Ok(unsafe {
+            self.device.get_fence_status(self.fence)?
+        })
+    }
+
+    /// Block until the submitted work has finished
+    pub fn wait(&self) -> Result<()> {
+        wait_for_fence(self.fence, None, &self.device)
+    }
+
+    /// Block until the submitted work has finished or `timeout` passes, in which case a
+    /// `SubmitTimeoutError` is returned
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<()> {
+        wait_for_fence(self.fence, Some(timeout), &self.device)
+    }
+
+    /// The semaphore signaled by work from `TransferContext::submit_polled_signaling`, for one
+    /// later submission to wait on. Once that submission has finished, hand the semaphore back
+    /// with `SyncPool::release_semaphore`. A semaphore that is never taken can't be reused, since
+    /// nothing waits on it, and is destroyed with the handle.
+    pub fn take_semaphore(&mut self) -> Option<vk::Semaphore> {
+        self.semaphore.take()
+    }
+
+    fn recycle(&mut self) -> Result<()> {
+        if self.command_buffer != vk::CommandBuffer::null() {
+            self.resources
+                .lock()
+                .map_err(|e| eyre!(e.to_string()))?
+                .release_polled_command_buffer(self.command_buffer)?;
+        }
+        if let Some(semaphore) = self.semaphore.take() {
+            if self.submitted {
+                unsafe {
+                    self.device.destroy_semaphore(semaphore, None);
+                }
+            } else {
+                self.sync_pool.release_semaphore(semaphore)?;
+            }
+        }
+        self.sync_pool.release_fence(self.fence)
+    }
+}
+
+impl Drop for SubmitHandle {
     fn drop(&mut self) {
-        unsafe {
-            self.device.destroy_command_pool(self.command_pool, None);
-            self.device.destroy_fence(self.transfer_fence, None);
+        if self.submitted && let Err(e) = self.wait() {
+            log::error!("Failed to wait for submitted work before recycling it: {}", e);
+            // The work may still be running, so only the fence and semaphore are destroyed. The
+            // command buffer is freed with its pool.
+            unsafe {
+                if let Some(semaphore) = self.semaphore.take() {
+                    self.device.destroy_semaphore(semaphore, None);
+                }
+                self.device.destroy_fence(self.fence, None);
+            }
+            return;
+        }
+        if let Err(e) = self.recycle() {
+            log::error!("Failed to recycle a polled submission: {}", e);
         }
     }
 }
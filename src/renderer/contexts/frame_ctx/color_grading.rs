@@ -0,0 +1,237 @@
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::contexts::resource_ctx::descriptor_set_layout_builder::DescriptorSetLayoutBuilder;
+use crate::renderer::resources::color_grading::ColorGradingLut;
+use crate::renderer::resources::image::Image;
+use crate::renderer::resources::material::{GraphicsMaterialFactoryBuilder, MaterialFactory};
+use crate::renderer::resources::sampler::SamplerDesc;
+use crate::renderer::resources::shader::GraphicsShader;
+use crate::renderer::resources::vertex::VertexInputDescription;
+
+/// Remaps the colors of the finished frame through a `ColorGradingLut` as a fullscreen pass,
+/// writing the result into an output image of the same size, one per frame in flight.
+///
+/// The input can differ between frames, e.g. with FXAA toggled, so each frame's descriptor set is
+/// rewritten when its input or LUT changes, once the frame's previous use has finished.
+pub struct ColorGradingPass {
+    factory: MaterialFactory,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+    // One per frame in flight, along with the input and LUT views it was last written with
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    written_views: Vec<Option<(vk::ImageView, vk::ImageView)>>,
+    output_images: Vec<Image>,
+
+    device: Arc<ash::Device>,
+}
+
+impl ColorGradingPass {
+    pub fn new(
+        dev_ctx: &RenderDeviceContext,
+        draw_images: &[&Image],
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<Self> {
+        let device = dev_ctx.device.logical.clone();
+
+        // The input image, then the LUT
+        let descriptor_set_layout = DescriptorSetLayoutBuilder::new()
+            .add_binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+                vk::ShaderStageFlags::FRAGMENT,
+                vk::DescriptorBindingFlags::empty(),
+                None,
+            )
+            .add_binding(
+                1,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+                vk::ShaderStageFlags::FRAGMENT,
+                vk::DescriptorBindingFlags::empty(),
+                None,
+            )
+            .build(vk::DescriptorSetLayoutCreateFlags::empty(), &device)?;
+
+        // Domain minimum and maximum padded to vec4s, then the number of entries per axis
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<[f32; 9]>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(&pipeline_layout_info, None)?
+        };
+
+        let shader = GraphicsShader::new("color_grading", device.clone())?;
+        let factory = GraphicsMaterialFactoryBuilder::new(
+            device.clone(),
+            dev_ctx.device.descriptor_allocator.clone(),
+        )
+            .with_pipeline_cache(pipeline_cache)
+            .with_shader(shader)
+            .with_pipeline_layout(pipeline_layout)
+            .with_descriptor_set_layout(descriptor_set_layout)
+            // The triangle is generated in the vertex shader
+            .with_vertex_input(VertexInputDescription {
+                bindings: Vec::new(),
+                attributes: Vec::new(),
+                flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+            })
+            .with_blending_disabled()
+            .with_depth_test(false, None)
+            .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+            .build()?;
+
+        let frame_count = draw_images.len() as u32;
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(frame_count * 2)];
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(frame_count)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&pool_info, None)?
+        };
+        let set_layouts = vec![descriptor_set_layout; draw_images.len()];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_sets = unsafe {
+            device.allocate_descriptor_sets(&allocate_info)?
+        };
+
+        // Linear filtering interpolates between the entries of the LUT
+        let sampler = SamplerDesc::linear_clamp().create(&dev_ctx.device)?;
+
+        let mut pass = Self {
+            factory,
+            descriptor_set_layout,
+            pipeline_layout,
+            descriptor_pool,
+            sampler,
+            written_views: vec![None; descriptor_sets.len()],
+            descriptor_sets,
+            output_images: Vec::new(),
+            device,
+        };
+        pass.resize(dev_ctx, draw_images)?;
+
+        Ok(pass)
+    }
+
+    /// Recreate the output images at the size of the new draw images. The device must be idle.
+    pub fn resize(&mut self, dev_ctx: &RenderDeviceContext, draw_images: &[&Image]) -> Result<()> {
+        self.output_images = draw_images
+            .iter()
+            .map(|draw_image| {
+                dev_ctx.device.create_draw_image(draw_image.extent.width, draw_image.extent.height)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // The old input images are gone
+        self.written_views.fill(None);
+        Ok(())
+    }
+
+    /// Record the pass for the frame at `frame_index`, whose fence must have been waited on.
+    /// `input_image` must be in `COLOR_ATTACHMENT_OPTIMAL`, and is left in
+    /// `SHADER_READ_ONLY_OPTIMAL`. Returns the output image, in `COLOR_ATTACHMENT_OPTIMAL`.
+    pub fn record(
+        &mut self,
+        cmd: &CommandEncoder,
+        frame_index: usize,
+        input_image: &mut Image,
+        lut: &ColorGradingLut,
+    ) -> &mut Image {
+        let descriptor_set = self.descriptor_sets[frame_index];
+        let views = (input_image.view, lut.image.view);
+        if self.written_views[frame_index] != Some(views) {
+            let image_infos = [input_image.view, lut.image.view].map(|view| {
+                [vk::DescriptorImageInfo::default()
+                    .sampler(self.sampler)
+                    .image_view(view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]
+            });
+            let writes = [0, 1].map(|binding| {
+                vk::WriteDescriptorSet::default()
+                    .dst_set(descriptor_set)
+                    .dst_binding(binding)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&image_infos[binding as usize])
+            });
+            unsafe {
+                self.device.update_descriptor_sets(&writes, &[]);
+            }
+            self.written_views[frame_index] = Some(views);
+        }
+
+        let output_image = &mut self.output_images[frame_index];
+        let extent = vk::Extent2D {
+            width: output_image.extent.width,
+            height: output_image.extent.height,
+        };
+
+        cmd.transition_image(input_image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        cmd.transition_image_layout(
+            output_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+
+        // Every pixel is overwritten, so loading the old contents costs nothing visible
+        cmd.begin_overlay_rendering(output_image);
+        cmd.set_viewport_and_scissor(extent);
+        self.factory.bind_pipeline(cmd.command_buffer);
+        let push_constants = [
+            lut.domain_min.x,
+            lut.domain_min.y,
+            lut.domain_min.z,
+            0.0,
+            lut.domain_max.x,
+            lut.domain_max.y,
+            lut.domain_max.z,
+            0.0,
+            lut.size as f32,
+        ];
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                cmd.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[descriptor_set],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                cmd.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::cast_slice(&push_constants),
+            );
+        }
+        cmd.draw(3, 0);
+        cmd.end_rendering();
+
+        output_image
+    }
+}
+
+impl Drop for ColorGradingPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
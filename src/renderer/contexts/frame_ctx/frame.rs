@@ -1,49 +1,118 @@
 use ash::vk;
 use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::contexts::device_ctx::query::PipelineStatisticsQuery;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
 use crate::renderer::contexts::resource_ctx::RenderResourceContext;
+use crate::renderer::resources::buffer::Buffer;
 use crate::renderer::resources::image::Image;
-use crate::renderer::resources::megabuffer::{Megabuffer, MegabufferExt};
+use crate::renderer::resources::megabuffer::{MegaSubbuffer, MegabufferExt};
+use crate::renderer::stats::RenderStats;
+
+/// The scene revision, viewport, scissor, depth direction and megabuffers a cached secondary
+/// command buffer was recorded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachedDrawsKey {
+    pub scene_revision: u64,
+    pub viewport: vk::Rect2D,
+    pub scissor: vk::Rect2D,
+    pub reverse_z: bool,
+    // Of the vertex and index megabuffers, which replace their buffers when they grow
+    pub megabuffer_generations: (u64, u64),
+}
 
 const FRAME_VERTEX_BUFFER_SIZE: u64 = 1024 * 1024; // 1 MB
 const FRAME_INDEX_BUFFER_SIZE: u64 = 1024 * 1024;  // 1 MB
+const FRAME_SPRITE_VERTEX_BUFFER_SIZE: u64 = 1024 * 1024; // 1 MB
+const FRAME_DEBUG_LINE_VERTEX_BUFFER_SIZE: u64 = 1024 * 1024 * 4; // 4 MB
 
 pub struct Frame {
-    draw_color_image: Image,
-    draw_depth_image: Image,
-    vertex_subbuffer: Megabuffer,
-    index_subbuffer: Megabuffer,
+    pub draw_color_image: Image,
+    pub draw_depth_image: Image,
+    // Color and depth images with a layer per view for `Renderer::draw_multiview_to_images`,
+    // created on first use at the size of the draw images
+    pub multiview_images: Option<(Image, Image)>,
+    // Per-frame space in the vertex and index megabuffers, held for the frame's lifetime
+    #[allow(dead_code)]
+    vertex_subbuffer: MegaSubbuffer,
+    #[allow(dead_code)]
+    index_subbuffer: MegaSubbuffer,
+
+    // Host-visible so the sprite batch and debug lines can be written into them directly every
+    // frame
+    pub sprite_vertex_buffer: Buffer,
+    pub debug_line_vertex_buffer: Buffer,
+
+    pub command_encoder: CommandEncoder,
+
+    // Secondary command buffer holding the scene draws when using `RecordStrategy::Cached`,
+    // allocated on first use. The key records what it was recorded against, and the stats what it
+    // contains.
+    pub cached_draws: Option<CommandEncoder>,
+    pub cached_draws_key: Option<CachedDrawsKey>,
+    pub cached_draws_stats: RenderStats,
+
+    // Secondary command buffers for `RecordStrategy::Parallel`, one per worker thread. Each is
+    // allocated by its worker on first use, so it comes from a command pool of its own.
+    pub parallel_draws: Vec<Option<CommandEncoder>>,
+
+    // Wraps the scene pass when pipeline statistics are enabled. `None` if the device doesn't
+    // support them.
+    pub pipeline_statistics_query: Option<PipelineStatisticsQuery>,
 
     // Signals when the swapchain is ready to present.
-    present_semaphore: vk::Semaphore,
+    pub present_semaphore: vk::Semaphore,
     
     // Signals when rendering commands have been submitted a queue.
-    render_semaphore: vk::Semaphore,
+    pub render_semaphore: vk::Semaphore,
 
     // Signals when all rendering commands have finished execution.
-    render_fence: vk::Fence,
+    pub render_fence: vk::Fence,
 }
 
 impl Frame {
     pub fn new(
         dev_ctx: &RenderDeviceContext,
         res_ctx: &RenderResourceContext,
+        draw_extent: vk::Extent2D,
     ) -> Result<Self> {
-        let target_size = dev_ctx.target.as_ref().unwrap().get_size();
-        
-        let draw_color_image = dev_ctx.device.create_color_image(
-            target_size.width,
-            target_size.height,
-            None,
-            true,
+        let draw_color_image = dev_ctx.device.create_draw_image(
+            draw_extent.width,
+            draw_extent.height,
         )?;
-        let draw_depth_image = dev_ctx.device.create_depth_image(target_size.width, target_size.height)?;
+        let draw_depth_image = dev_ctx.device.create_depth_image(draw_extent.width, draw_extent.height)?;
 
         let vertex_subbuffer = res_ctx.storage.vertex_megabuffer
             .allocate_subbuffer(FRAME_VERTEX_BUFFER_SIZE)?;
         let index_subbuffer = res_ctx.storage.index_megabuffer
             .allocate_subbuffer(FRAME_INDEX_BUFFER_SIZE)?;
 
+        let sprite_vertex_buffer = dev_ctx.device.create_buffer(
+            FRAME_SPRITE_VERTEX_BUFFER_SIZE,
+            16,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk_mem::MemoryUsage::AutoPreferHost,
+            true,
+        )?;
+        let debug_line_vertex_buffer = dev_ctx.device.create_buffer(
+            FRAME_DEBUG_LINE_VERTEX_BUFFER_SIZE,
+            16,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk_mem::MemoryUsage::AutoPreferHost,
+            true,
+        )?;
+
+        let command_encoder = dev_ctx.device.allocate_command_encoder(
+            dev_ctx.device.graphics_queue.clone(),
+        )?;
+
+        let features = dev_ctx.device.get_features();
+        let pipeline_statistics_query = if features.pipeline_statistics_query == vk::TRUE {
+            Some(PipelineStatisticsQuery::new(dev_ctx.device.logical.clone())?)
+        } else {
+            None
+        };
+
         let present_semaphore = unsafe {
             dev_ctx.device.logical.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?
         };
@@ -60,11 +129,38 @@ impl Frame {
         Ok(Self {
             draw_color_image,
             draw_depth_image,
+            multiview_images: None,
             vertex_subbuffer,
             index_subbuffer,
+            sprite_vertex_buffer,
+            debug_line_vertex_buffer,
+            command_encoder,
+            cached_draws: None,
+            cached_draws_key: None,
+            cached_draws_stats: RenderStats::default(),
+            parallel_draws: Vec::new(),
+            pipeline_statistics_query,
             present_semaphore,
             render_semaphore,
             render_fence,
         })
     }
+
+    /// Replace the draw images with ones of a new size. The frame must not be in use by the GPU.
+    pub fn resize_draw_images(
+        &mut self,
+        dev_ctx: &RenderDeviceContext,
+        draw_extent: vk::Extent2D,
+    ) -> Result<()> {
+        self.draw_color_image = dev_ctx.device.create_draw_image(
+            draw_extent.width,
+            draw_extent.height,
+        )?;
+        self.draw_depth_image = dev_ctx.device.create_depth_image(
+            draw_extent.width,
+            draw_extent.height,
+        )?;
+        self.multiview_images = None;
+        Ok(())
+    }
 }
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::resources::buffer::Buffer;
+use crate::renderer::resources::material::Material;
+use crate::renderer::shader_data::PerFrameData;
+
+// Binding of the per-frame uniform buffer in the bindless set
+const FRAME_DATA_BINDING: u32 = 0;
+
+/// The `PerFrameData` that shaders read from binding 0 of the bindless set, such as the camera's
+/// view-projection matrix. Multiview materials read a `PerFrameMultiviewData` from their own
+/// buffer instead.
+///
+/// It is small enough to be written inline in the frame's command buffer, so one device-local
+/// buffer serves every frame in flight without a staging buffer. It is only written when the data
+/// changed.
+pub struct FrameDataBuffer<T = PerFrameData> {
+    buffer: Buffer,
+    // What the buffer holds once the last submitted upload ran, `None` before the first one
+    uploaded: Option<T>,
+    // Data of the upload recorded last, which counts once its commands are submitted
+    pending: Option<T>,
+
+    device: Arc<ash::Device>,
+}
+
+impl<T: bytemuck::Pod> FrameDataBuffer<T> {
+    pub fn new(dev_ctx: &RenderDeviceContext) -> Result<Self> {
+        let buffer = dev_ctx.device.create_buffer(
+            size_of::<T>() as u64,
+            dev_ctx.device.get_limits().min_uniform_buffer_offset_alignment,
+            vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::AutoPreferDevice,
+            false,
+        )?;
+
+        Ok(Self {
+            buffer,
+            uploaded: None,
+            pending: None,
+            device: dev_ctx.device.logical.clone(),
+        })
+    }
+
+    /// Point binding 0 of `material`'s descriptor set at the buffer
+    pub fn write_descriptor(&self, material: &mut Material) {
+        material.write_uniform_buffer(
+            FRAME_DATA_BINDING,
+            self.buffer.buffer,
+            0,
+            size_of::<T>() as u64,
+        );
+    }
+
+    /// Record writing `data` into the buffer into `cmd` unless it already holds it. `cmd` must be
+    /// recording and outside of rendering. Returns whether anything was recorded. Call
+    /// `mark_submitted` once `cmd` is submitted.
+    pub fn record_upload(&mut self, cmd: &CommandEncoder, data: &T) -> bool {
+        self.pending = None;
+        if self.uploaded.is_some_and(|uploaded| {
+            bytemuck::bytes_of(&uploaded) == bytemuck::bytes_of(data)
+        }) {
+            return false;
+        }
+
+        let shader_stages = vk::PipelineStageFlags2::VERTEX_SHADER
+            | vk::PipelineStageFlags2::FRAGMENT_SHADER;
+        let buffer_barrier = vk::BufferMemoryBarrier2::default()
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        // The previous frame's draws may still be reading it
+        let before_update = [buffer_barrier
+            .src_stage_mask(shader_stages)
+            .src_access_mask(vk::AccessFlags2::UNIFORM_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)];
+        let after_update = [buffer_barrier
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(shader_stages)
+            .dst_access_mask(vk::AccessFlags2::UNIFORM_READ)];
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&before_update),
+            );
+            self.device.cmd_update_buffer(
+                cmd.command_buffer,
+                self.buffer.buffer,
+                0,
+                bytemuck::bytes_of(data),
+            );
+            self.device.cmd_pipeline_barrier2(
+                cmd.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&after_update),
+            );
+        }
+
+        self.pending = Some(*data);
+        true
+    }
+
+    /// Count the upload recorded by the last `record_upload` as done, after its command buffer was
+    /// submitted
+    pub fn mark_submitted(&mut self) {
+        if let Some(data) = self.pending.take() {
+            self.uploaded = Some(data);
+        }
+    }
+}
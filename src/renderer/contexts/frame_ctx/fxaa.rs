@@ -0,0 +1,215 @@
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::contexts::resource_ctx::descriptor_set_layout_builder::DescriptorSetLayoutBuilder;
+use crate::renderer::resources::image::Image;
+use crate::renderer::resources::material::{GraphicsMaterialFactoryBuilder, MaterialFactory};
+use crate::renderer::resources::sampler::SamplerDesc;
+use crate::renderer::resources::shader::GraphicsShader;
+use crate::renderer::resources::vertex::VertexInputDescription;
+
+/// Fast approximate anti-aliasing as a fullscreen pass. Reads a frame's color draw image and
+/// writes the result into an output image of the same size, one per frame in flight.
+///
+/// The input is expected to be the sRGB draw image in its final, display-referred colors, since
+/// edges are detected on gamma encoded luma. Sampling it returns linear colors and the shader
+/// converts them for the edge search only.
+pub struct FxaaPass {
+    factory: MaterialFactory,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+    // One per frame in flight, pointing at that frame's draw image
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    output_images: Vec<Image>,
+
+    device: Arc<ash::Device>,
+}
+
+impl FxaaPass {
+    pub fn new(
+        dev_ctx: &RenderDeviceContext,
+        draw_images: &[&Image],
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<Self> {
+        let device = dev_ctx.device.logical.clone();
+
+        let descriptor_set_layout = DescriptorSetLayoutBuilder::new()
+            .add_binding(
+                0,
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+                vk::ShaderStageFlags::FRAGMENT,
+                vk::DescriptorBindingFlags::empty(),
+                None,
+            )
+            .build(vk::DescriptorSetLayoutCreateFlags::empty(), &device)?;
+
+        // Inverse size of the input in pixels
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<[f32; 2]>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(&pipeline_layout_info, None)?
+        };
+
+        let shader = GraphicsShader::new("fxaa", device.clone())?;
+        let factory = GraphicsMaterialFactoryBuilder::new(
+            device.clone(),
+            dev_ctx.device.descriptor_allocator.clone(),
+        )
+            .with_pipeline_cache(pipeline_cache)
+            .with_shader(shader)
+            .with_pipeline_layout(pipeline_layout)
+            .with_descriptor_set_layout(descriptor_set_layout)
+            // The triangle is generated in the vertex shader
+            .with_vertex_input(VertexInputDescription {
+                bindings: Vec::new(),
+                attributes: Vec::new(),
+                flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+            })
+            .with_blending_disabled()
+            .with_depth_test(false, None)
+            .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+            .build()?;
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(draw_images.len() as u32)];
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(draw_images.len() as u32)
+            .pool_sizes(&pool_sizes);
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&pool_info, None)?
+        };
+
+        // Linear filtering lets the shader blend between neighbors with fractional offsets, and
+        // clamping keeps the edges of the screen from wrapping around
+        let sampler = SamplerDesc::linear_clamp().create(&dev_ctx.device)?;
+
+        let mut pass = Self {
+            factory,
+            descriptor_set_layout,
+            pipeline_layout,
+            descriptor_pool,
+            sampler,
+            descriptor_sets: Vec::new(),
+            output_images: Vec::new(),
+            device,
+        };
+        pass.resize(dev_ctx, draw_images)?;
+
+        Ok(pass)
+    }
+
+    /// Recreate the output images and point the descriptor sets at the new draw images. The device
+    /// must be idle.
+    pub fn resize(&mut self, dev_ctx: &RenderDeviceContext, draw_images: &[&Image]) -> Result<()> {
+        unsafe {
+            self.device.reset_descriptor_pool(
+                self.descriptor_pool,
+                vk::DescriptorPoolResetFlags::empty(),
+            )?;
+        }
+
+        let set_layouts = vec![self.descriptor_set_layout; draw_images.len()];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        self.descriptor_sets = unsafe {
+            self.device.allocate_descriptor_sets(&allocate_info)?
+        };
+
+        self.output_images.clear();
+        for (draw_image, descriptor_set) in draw_images.iter().zip(&self.descriptor_sets) {
+            let image_info = [vk::DescriptorImageInfo::default()
+                .sampler(self.sampler)
+                .image_view(draw_image.view)
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(*descriptor_set)
+                .dst_binding(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(&image_info);
+            unsafe {
+                self.device.update_descriptor_sets(&[write], &[]);
+            }
+
+            self.output_images.push(dev_ctx.device.create_draw_image(
+                draw_image.extent.width,
+                draw_image.extent.height,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Record the pass for the frame at `frame_index`. `draw_image` must be that frame's draw image
+    /// in `COLOR_ATTACHMENT_OPTIMAL`, and is left in `SHADER_READ_ONLY_OPTIMAL`. Returns the output
+    /// image, in `COLOR_ATTACHMENT_OPTIMAL`.
+    pub fn record(
+        &mut self,
+        cmd: &CommandEncoder,
+        frame_index: usize,
+        draw_image: &mut Image,
+    ) -> &mut Image {
+        let output_image = &mut self.output_images[frame_index];
+        let extent = vk::Extent2D {
+            width: output_image.extent.width,
+            height: output_image.extent.height,
+        };
+
+        cmd.transition_image(draw_image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        cmd.transition_image_layout(
+            output_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+
+        // Every pixel is overwritten, so loading the old contents costs nothing visible
+        cmd.begin_overlay_rendering(output_image);
+        cmd.set_viewport_and_scissor(extent);
+        self.factory.bind_pipeline(cmd.command_buffer);
+        let inverse_size = [1.0 / extent.width as f32, 1.0 / extent.height as f32];
+        unsafe {
+            self.device.cmd_bind_descriptor_sets(
+                cmd.command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[self.descriptor_sets[frame_index]],
+                &[],
+            );
+            self.device.cmd_push_constants(
+                cmd.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::cast_slice(&inverse_size),
+            );
+        }
+        cmd.draw(3, 0);
+        cmd.end_rendering();
+
+        output_image
+    }
+}
+
+impl Drop for FxaaPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
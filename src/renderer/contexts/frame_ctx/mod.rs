@@ -1,8 +1,15 @@
-mod frame;
+pub mod color_grading;
+pub mod frame;
+pub mod frame_data;
+pub mod fxaa;
+pub mod object_data;
+pub mod wireframe_overlay;
 
+use ash::vk;
 use color_eyre::Result;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
 use crate::renderer::contexts::frame_ctx::frame::Frame;
+use crate::renderer::resources::image::Image;
 
 use super::resource_ctx::RenderResourceContext;
 
@@ -14,20 +21,74 @@ const MAX_FRAMES_IN_FLIGHT: usize = 2;
 /// - Manage synchronization between frames
 pub struct RenderFrameContext {
     frames: Vec<Frame>,
+    frame_index: usize,
 }
 
 impl RenderFrameContext {
     pub fn new(
         dev_ctx: &RenderDeviceContext,
         res_ctx: &RenderResourceContext,
+        draw_extent: vk::Extent2D,
     ) -> Result<Self> {
         let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT);
         for _ in 0..MAX_FRAMES_IN_FLIGHT {
-            frames.push(Frame::new(dev_ctx, res_ctx)?);
+            frames.push(Frame::new(dev_ctx, res_ctx, draw_extent)?);
         }
         
         Ok(Self {
             frames,
+            frame_index: 0,
         })
     }
+
+    pub fn current_frame_mut(&mut self) -> &mut Frame {
+        &mut self.frames[self.frame_index]
+    }
+
+    /// Index of the current frame in flight
+    pub fn get_frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    /// Color draw image of every frame in flight, in frame index order
+    pub fn get_draw_color_images(&self) -> Vec<&Image> {
+        self.frames
+            .iter()
+            .map(|frame| &frame.draw_color_image)
+            .collect()
+    }
+
+    /// Size of the draw images, which all frames share
+    pub fn get_draw_extent(&self) -> vk::Extent2D {
+        let extent = self.frames[0].draw_color_image.extent;
+        vk::Extent2D {
+            width: extent.width,
+            height: extent.height,
+        }
+    }
+
+    /// Recreate the draw images of every frame. The device must be idle.
+    pub fn resize(
+        &mut self,
+        dev_ctx: &RenderDeviceContext,
+        draw_extent: vk::Extent2D,
+    ) -> Result<()> {
+        for frame in self.frames.iter_mut() {
+            frame.resize_draw_images(dev_ctx, draw_extent)?;
+        }
+        Ok(())
+    }
+
+    /// Make every frame record its cached draws again, e.g. after the pipelines they bind changed
+    #[cfg(feature = "hot-reload")]
+    pub fn invalidate_cached_draws(&mut self) {
+        for frame in self.frames.iter_mut() {
+            frame.cached_draws_key = None;
+        }
+    }
+
+    /// Move on to the next frame in flight
+    pub fn advance(&mut self) {
+        self.frame_index = (self.frame_index + 1) % self.frames.len();
+    }
 }
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::resources::buffer::Buffer;
+use crate::renderer::resources::material::Material;
+use crate::renderer::scene::Scene;
+use crate::renderer::shader_data::PerObjectData;
+
+// Binding of the per-object storage buffer in the bindless set
+const OBJECT_DATA_BINDING: u32 = 2;
+// Instances the buffer has room for until `reserve` grows it
+const INITIAL_CAPACITY: usize = 16384;
+
+/// Per-object data of every instance, which shaders read from binding 2 of the bindless set
+/// through the instance handle.
+///
+/// The data lives in one device-local buffer shared by all frames. Each frame copies in only the
+/// instances that changed since the last upload, from a host-visible staging buffer of its own, so
+/// a scene of mostly static instances costs next to nothing per frame.
+pub struct ObjectDataBuffer {
+    buffer: Buffer,
+    // One per frame in flight, so a frame can fill its own while the others are in use
+    staging_buffers: Vec<Buffer>,
+    // Number of instances the buffers have room for
+    capacity: usize,
+    // Scene revision the buffer was last brought up to date with, `None` before the first upload
+    synced_revision: Option<u64>,
+    // Scene revision of the upload recorded last, which counts once its commands are submitted
+    pending_revision: Option<u64>,
+
+    device: Arc<ash::Device>,
+}
+
+impl ObjectDataBuffer {
+    pub fn new(dev_ctx: &RenderDeviceContext, frame_count: usize) -> Result<Self> {
+        let (buffer, staging_buffers) = Self::create_buffers(dev_ctx, frame_count, INITIAL_CAPACITY)?;
+
+        Ok(Self {
+            buffer,
+            staging_buffers,
+            capacity: INITIAL_CAPACITY,
+            synced_revision: None,
+            pending_revision: None,
+            device: dev_ctx.device.logical.clone(),
+        })
+    }
+
+    fn create_buffers(
+        dev_ctx: &RenderDeviceContext,
+        frame_count: usize,
+        capacity: usize,
+    ) -> Result<(Buffer, Vec<Buffer>)> {
+        let size = (capacity * size_of::<PerObjectData>()) as u64;
+        let alignment = dev_ctx.device.get_limits().min_storage_buffer_offset_alignment;
+        let buffer = dev_ctx.device.create_buffer(
+            size,
+            alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::AutoPreferDevice,
+            false,
+        )?;
+        let staging_buffers = (0..frame_count)
+            .map(|_| {
+                dev_ctx.device.create_buffer(
+                    size,
+                    alignment,
+                    vk::BufferUsageFlags::TRANSFER_SRC,
+                    vk_mem::MemoryUsage::AutoPreferHost,
+                    true,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok((buffer, staging_buffers))
+    }
+
+    /// Make room for the per-object data of instances with handles below `instance_count`. When
+    /// the buffer has to grow, this waits for the device to be idle, and returns true because the
+    /// descriptors written by `write_descriptor` point at the old buffer. Everything is uploaded
+    /// again by the next `record_upload`.
+    pub fn reserve(&mut self, dev_ctx: &RenderDeviceContext, instance_count: usize) -> Result<bool> {
+        if instance_count <= self.capacity {
+            return Ok(false);
+        }
+        let capacity = instance_count.next_power_of_two();
+        log::debug!("Growing the per-object buffer to {} instances", capacity);
+        // Frames in flight may still read the old buffer
+        unsafe {
+            self.device.device_wait_idle()?;
+        }
+        let (buffer, staging_buffers) =
+            Self::create_buffers(dev_ctx, self.staging_buffers.len(), capacity)?;
+        self.buffer = buffer;
+        self.staging_buffers = staging_buffers;
+        self.capacity = capacity;
+        self.synced_revision = None;
+        self.pending_revision = None;
+        Ok(true)
+    }
+
+    /// Point binding 2 of `material`'s descriptor set at the buffer
+    pub fn write_descriptor(&self, material: &mut Material) {
+        material.write_storage_buffer(OBJECT_DATA_BINDING, self.buffer.buffer, 0, vk::WHOLE_SIZE);
+    }
+
+    /// Record copies of the per-object data of the instances changed since the last upload into
+    /// `cmd`, the command encoder of the frame at `frame_index`, whose fence must have been waited
+    /// on. It must be recording and outside of rendering. Returns how many instances were copied.
+    /// Call `mark_submitted` once `cmd` is submitted, or the same instances are copied again.
+    pub fn record_upload(
+        &mut self,
+        cmd: &CommandEncoder,
+        frame_index: usize,
+        scene: &Scene,
+    ) -> Result<u32> {
+        let object_size = size_of::<PerObjectData>() as u64;
+        let mut objects = Vec::new();
+        let mut regions: Vec<vk::BufferCopy> = Vec::new();
+        for (handle, instance) in scene.instances_changed_since(self.synced_revision) {
+            if handle.0 >= self.capacity {
+                return Err(eyre!(
+                    "Instance {:?} is past the {} instances the per-object buffer has room for; \
+                    call reserve first",
+                    handle,
+                    self.capacity,
+                ));
+            }
+            let src_offset = objects.len() as u64 * object_size;
+            let dst_offset = handle.0 as u64 * object_size;
+            objects.push(PerObjectData {
+                model: instance.transform.get_matrix(),
+            });
+            // Runs of neighboring instances, like a whole scene on the first upload, are one copy
+            match regions.last_mut() {
+                Some(region) if region.dst_offset + region.size == dst_offset => {
+                    region.size += object_size;
+                }
+                _ => regions.push(vk::BufferCopy {
+                    src_offset,
+                    dst_offset,
+                    size: object_size,
+                }),
+            }
+        }
+        self.pending_revision = None;
+        if objects.is_empty() {
+            self.pending_revision = Some(scene.get_revision());
+            return Ok(0);
+        }
+
+        let staging_buffer = &mut self.staging_buffers[frame_index];
+        staging_buffer.write(&objects, 0)?;
+
+        let shader_stages = vk::PipelineStageFlags2::VERTEX_SHADER
+            | vk::PipelineStageFlags2::FRAGMENT_SHADER;
+        let buffer_barrier = vk::BufferMemoryBarrier2::default()
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(self.buffer.buffer)
+            .offset(0)
+            .size(vk::WHOLE_SIZE);
+        // The previous frame's draws may still be reading the slots about to be overwritten
+        let before_copy = [buffer_barrier
+            .src_stage_mask(shader_stages)
+            .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)];
+        let after_copy = [buffer_barrier
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(shader_stages)
+            .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)];
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cmd.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&before_copy),
+            );
+            self.device.cmd_copy_buffer(
+                cmd.command_buffer,
+                staging_buffer.buffer,
+                self.buffer.buffer,
+                &regions,
+            );
+            self.device.cmd_pipeline_barrier2(
+                cmd.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&after_copy),
+            );
+        }
+
+        self.pending_revision = Some(scene.get_revision());
+        Ok(objects.len() as u32)
+    }
+
+    /// Count the upload recorded by the last `record_upload` as done, after its command buffer was
+    /// submitted
+    pub fn mark_submitted(&mut self) {
+        if let Some(revision) = self.pending_revision.take() {
+            self.synced_revision = Some(revision);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::mem::offset_of;
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::Result;
+use glam::{Mat4, Vec3};
+use crate::renderer::config::WireframeOverlay;
+use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::contexts::frame_ctx::frame::Frame;
+use crate::renderer::contexts::resource_ctx::descriptor_set_layout_builder::DescriptorSetLayoutBuilder;
+use crate::renderer::resources::buffer::Buffer;
+use crate::renderer::resources::material::{GraphicsMaterialFactoryBuilder, MaterialFactory};
+use crate::renderer::resources::shader::GraphicsShader;
+use crate::renderer::resources::vertex::VertexInputDescription;
+use crate::renderer::scene::Scene;
+use crate::renderer::shader_data::PerWireframeVertexData;
+use crate::renderer::stats::RenderStats;
+
+const VERTEX_BUFFER_SIZE: u64 = 1024 * 1024 * 8; // 8 MB
+// Pulls the edges slightly towards the camera, in clip space units of w, so they win the depth
+// test against the triangles they were rasterized from
+const DEPTH_OFFSET: f32 = 0.0005;
+
+/// Draws the edges of every triangle in the scene over the shaded geometry, depth tested against
+/// it so hidden edges stay hidden.
+///
+/// The triangles are expanded on the CPU into vertices that each carry one corner of the
+/// barycentric coordinates, which the fragment shader turns into the distance to the nearest edge.
+/// Like the debug lines, they are transformed with a view-projection matrix given by the caller.
+pub struct WireframeOverlayPass {
+    factory: MaterialFactory,
+    descriptor_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
+    // One per frame in flight, host-visible so they can be written directly every frame
+    vertex_buffers: Vec<Buffer>,
+    settings: WireframeOverlay,
+
+    device: Arc<ash::Device>,
+}
+
+impl WireframeOverlayPass {
+    pub fn new(
+        dev_ctx: &RenderDeviceContext,
+        frame_count: usize,
+        settings: WireframeOverlay,
+        pipeline_cache: vk::PipelineCache,
+    ) -> Result<Self> {
+        let device = dev_ctx.device.logical.clone();
+
+        // Everything comes in through the vertices and push constants
+        let descriptor_set_layout = DescriptorSetLayoutBuilder::new()
+            .build(vk::DescriptorSetLayoutCreateFlags::empty(), &device)?;
+
+        // Edge color and thickness
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<[f32; 5]>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(&pipeline_layout_info, None)?
+        };
+
+        let shader = GraphicsShader::new("wireframe_overlay", device.clone())?;
+        let factory = GraphicsMaterialFactoryBuilder::new(
+            device.clone(),
+            dev_ctx.device.descriptor_allocator.clone(),
+        )
+            .with_pipeline_cache(pipeline_cache)
+            .with_shader(shader)
+            .with_pipeline_layout(pipeline_layout)
+            .with_descriptor_set_layout(descriptor_set_layout)
+            .with_vertex_input(Self::get_input_description())
+            .with_alpha_blending_enabled()
+            .with_depth_test(true, None)
+            .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+            .with_depth_attachment_format(vk::Format::D32_SFLOAT)
+            .build()?;
+
+        let vertex_buffers = (0..frame_count)
+            .map(|_| {
+                dev_ctx.device.create_buffer(
+                    VERTEX_BUFFER_SIZE,
+                    16,
+                    vk::BufferUsageFlags::VERTEX_BUFFER,
+                    vk_mem::MemoryUsage::AutoPreferHost,
+                    true,
+                )
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            factory,
+            descriptor_set_layout,
+            pipeline_layout,
+            vertex_buffers,
+            settings,
+            device,
+        })
+    }
+
+    pub fn set_settings(&mut self, settings: WireframeOverlay) {
+        self.settings = settings;
+    }
+
+    pub fn get_settings(&self) -> WireframeOverlay {
+        self.settings
+    }
+
+    /// Record the pass into `frame`, the frame at `frame_index`, whose fence must have been waited
+    /// on. Its command encoder must be recording and its draw images must hold the drawn scene, in
+    /// `COLOR_ATTACHMENT_OPTIMAL` and `DEPTH_ATTACHMENT_OPTIMAL`. Triangles that don't fit in the
+    /// vertex buffer are dropped with a warning.
+    pub fn record(
+        &mut self,
+        frame: &Frame,
+        frame_index: usize,
+        scene: &Scene,
+        viewproj: Mat4,
+        viewport: vk::Rect2D,
+        scissor: vk::Rect2D,
+    ) -> Result<RenderStats> {
+        let vertex_buffer = &mut self.vertex_buffers[frame_index];
+        let mut vertices = build_vertices(scene, viewproj);
+        // Whole triangles only
+        let triangle_size = size_of::<PerWireframeVertexData>() * 3;
+        let capacity = vertex_buffer.size as usize / triangle_size * 3;
+        if vertices.len() > capacity {
+            log::warn!(
+                "Drawing the wireframe of {} of {} triangles; the rest do not fit in its vertex \
+                buffer",
+                capacity / 3,
+                vertices.len() / 3,
+            );
+            vertices.truncate(capacity);
+        }
+        if vertices.is_empty() {
+            return Ok(RenderStats::default());
+        }
+        vertex_buffer.write(&vertices, 0)?;
+
+        let WireframeOverlay { color, thickness } = self.settings;
+        let push_constants = [color[0], color[1], color[2], color[3], thickness];
+
+        let cmd = &frame.command_encoder;
+        cmd.begin_overlay_rendering_with_depth(&frame.draw_color_image, &frame.draw_depth_image);
+        cmd.set_viewport_and_scissor_rects(viewport, scissor);
+        self.factory.bind_pipeline(cmd.command_buffer);
+        cmd.apply_depth_compare_op();
+        unsafe {
+            self.device.cmd_push_constants(
+                cmd.command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                bytemuck::cast_slice(&push_constants),
+            );
+        }
+        cmd.bind_vertex_buffer(vertex_buffer.buffer, 0);
+        cmd.draw(vertices.len() as u32, 0);
+        cmd.end_rendering();
+
+        Ok(RenderStats {
+            draw_calls: 1,
+            triangles: vertices.len() as u64 / 3,
+            pipeline_binds: 1,
+            ..Default::default()
+        })
+    }
+
+    /// Describes the layout of `PerWireframeVertexData` for the wireframe overlay pipeline
+    fn get_input_description() -> VertexInputDescription {
+        let bindings = vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<PerWireframeVertexData>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+
+        let attributes = vec![
+            // Position
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(PerWireframeVertexData, position) as u32,
+            },
+            // Barycentric coordinates
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(PerWireframeVertexData, barycentric) as u32,
+            },
+        ];
+
+        VertexInputDescription {
+            bindings,
+            attributes,
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+        }
+    }
+}
+
+impl Drop for WireframeOverlayPass {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+        }
+    }
+}
+
+/// Three vertices for every triangle of every instance in `scene`, in clip space
+fn build_vertices(scene: &Scene, viewproj: Mat4) -> Vec<PerWireframeVertexData> {
+    let mut vertices = Vec::new();
+    for (_, instance) in scene.instances() {
+        let Some(model) = scene.get_model(instance.model) else {
+            continue;
+        };
+        let mvp = viewproj * instance.transform.get_matrix();
+        for mesh in model.get_meshes() {
+            let corner_count = mesh.indices
+                .as_ref()
+                .map_or(mesh.vertices.len(), |indices| indices.len());
+            let corner_count = corner_count - corner_count % 3;
+            for corner in 0..corner_count {
+                let index = match mesh.indices.as_ref() {
+                    Some(indices) => indices[corner] as usize,
+                    None => corner,
+                };
+                let mut position = mvp * mesh.vertices[index].position.extend(1.0);
+                position.z -= DEPTH_OFFSET * position.w;
+                let mut barycentric = Vec3::ZERO;
+                barycentric[corner % 3] = 1.0;
+                vertices.push(PerWireframeVertexData {
+                    position,
+                    barycentric,
+                    _padding: 0.0,
+                });
+            }
+        }
+    }
+    vertices
+}
@@ -1,2 +1,561 @@
+use ash::vk;
+use color_eyre::eyre::{eyre, OptionExt};
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::resources::image::Image;
+
+/// Handle of an image or buffer imported into a `RenderGraph`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+impl ResourceId {
+    /// Use the image in `layout` instead of the one its usage implies, e.g. `GENERAL` for a
+    /// storage image
+    pub fn in_layout(self, layout: vk::ImageLayout) -> ResourceAccess {
+        ResourceAccess {
+            id: self,
+            layout: Some(layout),
+        }
+    }
+}
+
+/// A resource that a pass reads or writes. A `ResourceId` converts into one that uses the default
+/// layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceAccess {
+    id: ResourceId,
+    layout: Option<vk::ImageLayout>,
+}
+
+impl From<ResourceId> for ResourceAccess {
+    fn from(id: ResourceId) -> Self {
+        Self {
+            id,
+            layout: None,
+        }
+    }
+}
+
+/// Records the commands of a pass. The graph has already recorded the barriers the pass needs.
+pub type RecordFn = Box<dyn FnMut(&CommandEncoder) -> Result<()>>;
+
+enum Resource {
+    Image {
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        initial_layout: vk::ImageLayout,
+    },
+    Buffer {
+        buffer: vk::Buffer,
+    },
+}
+
+struct Pass {
+    name: String,
+    reads: Vec<ResourceAccess>,
+    writes: Vec<ResourceAccess>,
+    record_fn: RecordFn,
+}
+
+// How a pass uses a resource, which is what barriers are made from
+#[derive(Clone, Copy)]
+struct Usage {
+    stage: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    layout: vk::ImageLayout,
+}
+
+// What the commands before a pass did to a resource
+#[derive(Clone, Copy)]
+struct ResourceState {
+    stage: vk::PipelineStageFlags2,
+    access: vk::AccessFlags2,
+    layout: vk::ImageLayout,
+    // Whether the last use wrote to it, so later uses have to wait on that write
+    written: bool,
+}
+
+/// Barriers to record at once
+#[derive(Default)]
+struct Barriers {
+    image_barriers: Vec<vk::ImageMemoryBarrier2<'static>>,
+    buffer_barriers: Vec<vk::BufferMemoryBarrier2<'static>>,
+}
+
+impl Barriers {
+    fn record(&self, cmd: &CommandEncoder) {
+        if self.image_barriers.is_empty() && self.buffer_barriers.is_empty() {
+            return;
+        }
+        cmd.pipeline_barrier(
+            &vk::DependencyInfo::default()
+                .image_memory_barriers(&self.image_barriers)
+                .buffer_memory_barriers(&self.buffer_barriers),
+        );
+    }
+}
+
+struct Schedule {
+    // Indices into `RenderGraph::passes` in execution order, with the barriers before each one
+    passes: Vec<(usize, Barriers)>,
+    // Return images to the layout they were imported with
+    final_barriers: Barriers,
+}
+
+/// Passes and the images and buffers they read and write. `compile` orders the passes so every
+/// pass runs after the ones writing what it reads, and works out the barriers between them.
+/// `execute` records the passes in that order into one command buffer.
+///
+/// The usage of a resource follows from whether it is read or written:
+/// - Images are written as color or depth attachments, depending on their aspect, and read as
+///   sampled images. Passes can ask for another layout with `ResourceId::in_layout`.
+/// - Buffers are read and written as storage buffers by any shader stage
+///
+/// Layouts are only transitioned between passes that need different ones.
+///
+/// Images are expected in the layout they were imported with and are returned to it at the end,
+/// unless it is `UNDEFINED`, so the graph can be executed again. The first use of every resource
+/// waits on all earlier commands.
+#[derive(Default)]
 pub struct RenderGraph {
-}
\ No newline at end of file
+    resources: Vec<Resource>,
+    passes: Vec<Pass>,
+    // `None` when passes or resources were added since the last compile
+    schedule: Option<Schedule>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn import_image(
+        &mut self,
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        initial_layout: vk::ImageLayout,
+    ) -> ResourceId {
+        self.add_resource(Resource::Image {
+            image,
+            aspect,
+            initial_layout,
+        })
+    }
+
+    /// Import `image` in the layout it was last transitioned to. It is back in that layout after
+    /// each execution, so its tracked layout stays right; if it is `UNDEFINED`, the contents are
+    /// discarded at the start of each execution instead.
+    pub fn import_tracked_image(&mut self, image: &Image) -> ResourceId {
+        self.import_image(image.image, image.aspect, image.get_layout())
+    }
+
+    pub fn import_buffer(&mut self, buffer: vk::Buffer) -> ResourceId {
+        self.add_resource(Resource::Buffer {
+            buffer,
+        })
+    }
+
+    /// Add a pass that reads `reads` and writes `writes`. It runs after every pass writing what it
+    /// reads; passes writing the same resource run in the order they were added.
+    pub fn add_pass(
+        &mut self,
+        name: &str,
+        reads: &[ResourceAccess],
+        writes: &[ResourceAccess],
+        record_fn: RecordFn,
+    ) -> Result<()> {
+        let unknown = reads.iter().chain(writes).find(|access| access.id.0 >= self.resources.len());
+        if let Some(access) = unknown {
+            return Err(eyre!(
+                "Pass \"{}\" uses resource {} that was never imported",
+                name,
+                access.id.0,
+            ));
+        }
+
+        self.passes.push(Pass {
+            name: name.to_string(),
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+            record_fn,
+        });
+        self.schedule = None;
+        Ok(())
+    }
+
+    /// Order the passes by their dependencies and work out the barriers between them. Passes
+    /// that don't depend on each other keep the order they were added in. Fails with the names of
+    /// the passes involved if the dependencies form a cycle.
+    pub fn compile(&mut self) -> Result<()> {
+        let order = self.sort_passes()?;
+
+        // Anything before the graph, like its previous execution, may have written any resource
+        let mut states = self.resources
+            .iter()
+            .map(|resource| ResourceState {
+                stage: vk::PipelineStageFlags2::ALL_COMMANDS,
+                access: vk::AccessFlags2::MEMORY_WRITE,
+                layout: match resource {
+                    Resource::Image { initial_layout, .. } => *initial_layout,
+                    Resource::Buffer { .. } => vk::ImageLayout::UNDEFINED,
+                },
+                written: true,
+            })
+            .collect::<Vec<_>>();
+
+        let mut passes = Vec::with_capacity(order.len());
+        for pass_index in order {
+            let pass = &self.passes[pass_index];
+            let mut barriers = Barriers::default();
+            // A resource that is both read and written is used the way writing it needs
+            let uses = pass.writes
+                .iter()
+                .map(|access| (*access, true))
+                .chain(
+                    pass.reads
+                        .iter()
+                        .filter(|read| !pass.writes.iter().any(|write| write.id == read.id))
+                        .map(|access| (*access, false)),
+                );
+            for (access, is_write) in uses {
+                let resource = &self.resources[access.id.0];
+                let mut usage = Self::get_usage(resource, is_write);
+                if let Some(layout) = access.layout {
+                    usage.layout = layout;
+                }
+                let state = &mut states[access.id.0];
+                let layout_changes = matches!(resource, Resource::Image { .. })
+                    && state.layout != usage.layout;
+                // Reads after reads in the same layout need no barrier
+                let needs_barrier = layout_changes || state.written || is_write;
+
+                if needs_barrier {
+                    // Earlier reads only need to finish, their results need not be made visible
+                    let src_access = if state.written {
+                        state.access
+                    } else {
+                        vk::AccessFlags2::NONE
+                    };
+                    match resource {
+                        Resource::Image { image, aspect, .. } => {
+                            barriers.image_barriers.push(
+                                Self::get_image_barrier(*image, *aspect, usage.layout)
+                                    .src_stage_mask(state.stage)
+                                    .src_access_mask(src_access)
+                                    .dst_stage_mask(usage.stage)
+                                    .dst_access_mask(usage.access)
+                                    .old_layout(state.layout),
+                            );
+                        }
+                        Resource::Buffer { buffer } => {
+                            barriers.buffer_barriers.push(
+                                vk::BufferMemoryBarrier2::default()
+                                    .src_stage_mask(state.stage)
+                                    .src_access_mask(src_access)
+                                    .dst_stage_mask(usage.stage)
+                                    .dst_access_mask(usage.access)
+                                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                                    .buffer(*buffer)
+                                    .offset(0)
+                                    .size(vk::WHOLE_SIZE),
+                            );
+                        }
+                    }
+                    *state = ResourceState {
+                        stage: usage.stage,
+                        access: usage.access,
+                        layout: usage.layout,
+                        written: is_write,
+                    };
+                } else {
+                    // A later write has to wait on this read as well as the earlier ones
+                    state.stage |= usage.stage;
+                    state.access |= usage.access;
+                }
+            }
+            passes.push((pass_index, barriers));
+        }
+
+        let mut final_barriers = Barriers::default();
+        for (resource, state) in self.resources.iter().zip(states.iter()) {
+            let Resource::Image { image, aspect, initial_layout } = resource else {
+                continue;
+            };
+            if *initial_layout == vk::ImageLayout::UNDEFINED || state.layout == *initial_layout {
+                continue;
+            }
+            let src_access = if state.written {
+                state.access
+            } else {
+                vk::AccessFlags2::NONE
+            };
+            // The first barrier of the next execution waits on everything, so this only has to
+            // change the layout
+            final_barriers.image_barriers.push(
+                Self::get_image_barrier(*image, *aspect, *initial_layout)
+                    .src_stage_mask(state.stage)
+                    .src_access_mask(src_access)
+                    .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .dst_access_mask(vk::AccessFlags2::NONE)
+                    .old_layout(state.layout),
+            );
+        }
+
+        self.schedule = Some(Schedule {
+            passes,
+            final_barriers,
+        });
+        Ok(())
+    }
+
+    /// Record the passes and their barriers into `cmd`, compiling first if passes were added since
+    /// the last compile. `cmd` must be recording.
+    pub fn execute(&mut self, cmd: &CommandEncoder) -> Result<()> {
+        if self.schedule.is_none() {
+            self.compile()?;
+        }
+        let schedule = self.schedule
+            .as_ref()
+            .ok_or_eyre("Render graph was not compiled")?;
+
+        for (pass_index, barriers) in schedule.passes.iter() {
+            barriers.record(cmd);
+            let pass = &mut self.passes[*pass_index];
+            (pass.record_fn)(cmd)
+                .map_err(|e| eyre!("Pass \"{}\" failed to record: {}", pass.name, e))?;
+        }
+        schedule.final_barriers.record(cmd);
+
+        Ok(())
+    }
+
+    fn add_resource(&mut self, resource: Resource) -> ResourceId {
+        self.resources.push(resource);
+        self.schedule = None;
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Barrier of the whole image into `new_layout`, with the rest left to the caller
+    fn get_image_barrier(
+        image: vk::Image,
+        aspect: vk::ImageAspectFlags,
+        new_layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier2<'static> {
+        vk::ImageMemoryBarrier2::default()
+            .new_layout(new_layout)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .image(image)
+            .subresource_range(vk::ImageSubresourceRange {
+                aspect_mask: aspect,
+                base_mip_level: 0,
+                level_count: vk::REMAINING_MIP_LEVELS,
+                base_array_layer: 0,
+                layer_count: vk::REMAINING_ARRAY_LAYERS,
+            })
+    }
+
+    fn get_usage(resource: &Resource, is_write: bool) -> Usage {
+        match (resource, is_write) {
+            (Resource::Image { aspect, .. }, true)
+                if aspect.contains(vk::ImageAspectFlags::DEPTH) => Usage {
+                stage: vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                access: vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                layout: vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            },
+            (Resource::Image { .. }, true) => Usage {
+                stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                access: vk::AccessFlags2::COLOR_ATTACHMENT_READ
+                    | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            },
+            (Resource::Image { .. }, false) => Usage {
+                stage: vk::PipelineStageFlags2::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                access: vk::AccessFlags2::SHADER_SAMPLED_READ,
+                layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+            (Resource::Buffer { .. }, is_write) => Usage {
+                stage: vk::PipelineStageFlags2::ALL_GRAPHICS
+                    | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                access: if is_write {
+                    vk::AccessFlags2::SHADER_STORAGE_READ | vk::AccessFlags2::SHADER_STORAGE_WRITE
+                } else {
+                    vk::AccessFlags2::SHADER_STORAGE_READ
+                },
+                layout: vk::ImageLayout::UNDEFINED,
+            },
+        }
+    }
+
+    /// Indices of the passes in an order where every pass comes after the passes it depends on
+    fn sort_passes(&self) -> Result<Vec<usize>> {
+        let pass_count = self.passes.len();
+        // dependents[i] are the passes that have to wait for pass i
+        let mut dependents = vec![Vec::new(); pass_count];
+        let mut dependency_counts = vec![0usize; pass_count];
+        let mut add_dependency = |from: usize, to: usize| {
+            if from != to && !dependents[from].contains(&to) {
+                dependents[from].push(to);
+                dependency_counts[to] += 1;
+            }
+        };
+
+        for resource_index in 0..self.resources.len() {
+            let id = ResourceId(resource_index);
+            let uses = |accesses: &[ResourceAccess]| accesses.iter().any(|access| access.id == id);
+            let writers = (0..pass_count)
+                .filter(|i| uses(&self.passes[*i].writes))
+                .collect::<Vec<_>>();
+            // Writers of the same resource keep the order they were added in
+            for pair in writers.windows(2) {
+                add_dependency(pair[0], pair[1]);
+            }
+            for reader in (0..pass_count).filter(|i| uses(&self.passes[*i].reads)) {
+                // A pass that also writes it is ordered among the writers, after the earlier ones
+                let is_writer = writers.contains(&reader);
+                for writer in writers.iter().filter(|writer| !is_writer || **writer < reader) {
+                    add_dependency(*writer, reader);
+                }
+            }
+        }
+
+        // Kahn's algorithm, always taking the earliest added pass that is ready
+        let mut order = Vec::with_capacity(pass_count);
+        let mut scheduled = vec![false; pass_count];
+        while let Some(next) = (0..pass_count)
+            .find(|i| !scheduled[*i] && dependency_counts[*i] == 0)
+        {
+            scheduled[next] = true;
+            order.push(next);
+            for dependent in dependents[next].iter() {
+                dependency_counts[*dependent] -= 1;
+            }
+        }
+
+        if order.len() < pass_count {
+            // The passes in the cycle, along with any that wait on them
+            let unscheduled = (0..pass_count)
+                .filter(|i| !scheduled[*i])
+                .map(|i| format!("\"{}\"", self.passes[i].name))
+                .collect::<Vec<_>>();
+            return Err(eyre!(
+                "Render graph has a dependency cycle among passes {}",
+                unscheduled.join(", "),
+            ));
+        }
+        Ok(order)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn no_op() -> RecordFn {
+        Box::new(|_| Ok(()))
+    }
+
+    fn color_image(graph: &mut RenderGraph, raw: u64, layout: vk::ImageLayout) -> ResourceId {
+        graph.import_image(vk::Image::from_raw(raw), vk::ImageAspectFlags::COLOR, layout)
+    }
+
+    #[test]
+    fn passes_run_after_the_writers_of_what_they_read() {
+        let mut graph = RenderGraph::new();
+        let a = color_image(&mut graph, 1, vk::ImageLayout::UNDEFINED);
+        let b = color_image(&mut graph, 2, vk::ImageLayout::UNDEFINED);
+        let c = color_image(&mut graph, 3, vk::ImageLayout::UNDEFINED);
+        graph.add_pass("composite", &[a.into(), b.into()], &[c.into()], no_op()).unwrap();
+        graph.add_pass("draw_a", &[], &[a.into()], no_op()).unwrap();
+        graph.add_pass("draw_b", &[], &[b.into()], no_op()).unwrap();
+
+        // The independent draws keep the order they were added in
+        assert_eq!(graph.sort_passes().unwrap(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn writers_of_the_same_resource_keep_their_order() {
+        let mut graph = RenderGraph::new();
+        let image = color_image(&mut graph, 1, vk::ImageLayout::UNDEFINED);
+        graph.add_pass("first", &[], &[image.into()], no_op()).unwrap();
+        graph.add_pass("second", &[image.into()], &[image.into()], no_op()).unwrap();
+        graph.add_pass("read", &[image.into()], &[], no_op()).unwrap();
+
+        assert_eq!(graph.sort_passes().unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn cycle_is_an_error_naming_its_passes() {
+        let mut graph = RenderGraph::new();
+        let a = color_image(&mut graph, 1, vk::ImageLayout::UNDEFINED);
+        let b = color_image(&mut graph, 2, vk::ImageLayout::UNDEFINED);
+        let c = color_image(&mut graph, 3, vk::ImageLayout::UNDEFINED);
+        graph.add_pass("ping", &[b.into()], &[a.into()], no_op()).unwrap();
+        graph.add_pass("pong", &[a.into()], &[b.into()], no_op()).unwrap();
+        graph.add_pass("unrelated", &[], &[c.into()], no_op()).unwrap();
+
+        let error = graph.compile().unwrap_err().to_string();
+        assert!(error.contains("cycle"), "{}", error);
+        assert!(error.contains("\"ping\"") && error.contains("\"pong\""), "{}", error);
+        assert!(!error.contains("unrelated"), "{}", error);
+    }
+
+    #[test]
+    fn read_after_write_chain_gets_barriers_and_restores_layouts() {
+        let mut graph = RenderGraph::new();
+        let scene = color_image(&mut graph, 1, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let output = color_image(&mut graph, 2, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        // Added in reverse, so compiling has to reorder them
+        graph.add_pass("post", &[scene.into()], &[output.into()], no_op()).unwrap();
+        graph.add_pass("draw", &[], &[scene.into()], no_op()).unwrap();
+        graph.compile().unwrap();
+
+        let schedule = graph.schedule.as_ref().unwrap();
+        let order = schedule.passes.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+        assert_eq!(order, vec![1, 0]);
+
+        // The draw waits on everything before the graph and moves the scene into an attachment
+        let draw_barriers = &schedule.passes[0].1;
+        assert!(draw_barriers.buffer_barriers.is_empty());
+        assert_eq!(draw_barriers.image_barriers.len(), 1);
+        let barrier = &draw_barriers.image_barriers[0];
+        assert_eq!(barrier.image, vk::Image::from_raw(1));
+        assert_eq!(barrier.old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::ALL_COMMANDS);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::MEMORY_WRITE);
+
+        // Post processing writes the output and reads what the draw wrote
+        let post_barriers = &schedule.passes[1].1;
+        assert_eq!(post_barriers.image_barriers.len(), 2);
+        let output_barrier = &post_barriers.image_barriers[0];
+        assert_eq!(output_barrier.image, vk::Image::from_raw(2));
+        assert_eq!(output_barrier.old_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(output_barrier.new_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        let scene_barrier = &post_barriers.image_barriers[1];
+        assert_eq!(scene_barrier.image, vk::Image::from_raw(1));
+        assert_eq!(scene_barrier.old_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(scene_barrier.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(
+            scene_barrier.src_stage_mask,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        );
+        assert_eq!(
+            scene_barrier.src_access_mask,
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+        );
+        assert_eq!(scene_barrier.dst_access_mask, vk::AccessFlags2::SHADER_SAMPLED_READ);
+
+        // The scene already ends up in its imported layout, the output has to be moved back
+        let final_barriers = &schedule.final_barriers.image_barriers;
+        assert_eq!(final_barriers.len(), 1);
+        assert_eq!(final_barriers[0].image, vk::Image::from_raw(2));
+        assert_eq!(final_barriers[0].old_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(final_barriers[0].new_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+    }
+}
@@ -1,5 +1,7 @@
 use color_eyre::Result;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::contexts::graph_ctx::graph::RenderGraph;
 
 pub mod graph;
 
@@ -7,10 +9,24 @@ pub mod graph;
 /// - Manage the RenderGraph object
 /// - Build and schedule passes based on dependencies
 /// - Record command buffers in the correct order
-pub struct RenderGraphContext;
+pub struct RenderGraphContext {
+    graph: RenderGraph,
+}
 
 impl RenderGraphContext {
     pub fn new(_dev_ctx: &RenderDeviceContext) -> Result<Self> {
-        Ok(Self)
+        Ok(Self {
+            graph: RenderGraph::new(),
+        })
     }
-}
\ No newline at end of file
+
+    /// The graph to import resources and add passes to
+    pub fn get_graph_mut(&mut self) -> &mut RenderGraph {
+        &mut self.graph
+    }
+
+    /// Record the graph's passes into `cmd` in dependency order, with the barriers between them
+    pub fn execute(&mut self, cmd: &CommandEncoder) -> Result<()> {
+        self.graph.execute(cmd)
+    }
+}
@@ -1,16 +1,175 @@
+#[cfg(feature = "hot-reload")]
+pub mod shader_watcher;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+#[cfg(feature = "hot-reload")]
+use crate::renderer::contexts::pipeline_ctx::shader_watcher::ShaderWatcher;
+#[cfg(feature = "hot-reload")]
+use crate::renderer::resources::shader::find_shaders_dir;
+
+// Size of `VkPipelineCacheHeaderVersionOne`, which starts every pipeline cache blob
+const CACHE_HEADER_SIZE: usize = 32;
 
 /// Responsibilities:
 /// - Manage graphics and compute pipelines
 /// - Shader reflection and pipeline layouts
 /// - Pipeline state management and caching
-pub struct RenderPipelineContext;
+pub struct RenderPipelineContext {
+    // Passed to every pipeline the renderer creates
+    cache: vk::PipelineCache,
+    // Where the cache is loaded from and saved to on drop, see `RenderConfig::pipeline_cache_path`
+    cache_path: Option<PathBuf>,
+    // Recompiles shaders as their sources change, see `poll_reloaded_shaders`
+    #[cfg(feature = "hot-reload")]
+    shader_watcher: Option<ShaderWatcher>,
+
+    device: Arc<ash::Device>,
+}
 
 impl RenderPipelineContext {
+    /// Start the pipeline cache from the file at `cache_path` if there is one. A file that is
+    /// unreadable or was written by another device or driver is ignored with a warning, and the
+    /// cache starts out empty.
     pub fn new(
-        _dev_ctx: &RenderDeviceContext,
+        dev_ctx: &RenderDeviceContext,
+        cache_path: Option<&Path>,
     ) -> Result<Self> {
-        Ok(Self)
+        let properties = unsafe {
+            dev_ctx.instance.instance.get_physical_device_properties(dev_ctx.device.physical)
+        };
+        let initial_data = cache_path
+            .and_then(|path| match std::fs::read(path) {
+                Ok(data) => Some((path, data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => {
+                    log::warn!("Failed to read pipeline cache {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .and_then(|(path, data)| match Self::validate_cache_header(&data, &properties) {
+                Ok(()) => Some(data),
+                Err(e) => {
+                    log::warn!("Ignoring pipeline cache {}: {}", path.display(), e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+
+        let create_info = vk::PipelineCacheCreateInfo::default().initial_data(&initial_data);
+        let cache = unsafe {
+            dev_ctx.device.logical.create_pipeline_cache(&create_info, None)?
+        };
+        if !initial_data.is_empty() {
+            log::info!("Loaded {} bytes of pipeline cache", initial_data.len());
+        }
+
+        Ok(Self {
+            cache,
+            cache_path: cache_path.map(Path::to_path_buf),
+            #[cfg(feature = "hot-reload")]
+            shader_watcher: Self::create_shader_watcher(),
+            device: dev_ctx.device.logical.clone(),
+        })
+    }
+
+    pub fn get_cache(&self) -> vk::PipelineCache {
+        self.cache
+    }
+
+    /// Write the pipelines created so far to `path`, to be loaded by a later run. This happens on
+    /// drop as well when the cache has a path.
+    pub fn save_cache(&self, path: &Path) -> Result<()> {
+        let data = unsafe {
+            self.device.get_pipeline_cache_data(self.cache)?
+        };
+        std::fs::write(path, &data)
+            .map_err(|e| eyre!("Failed to write pipeline cache {}: {}", path.display(), e))?;
+        log::info!("Saved {} bytes of pipeline cache to {}", data.len(), path.display());
+        Ok(())
+    }
+
+    /// Recompile the shaders whose sources changed since the last call and return their names.
+    /// The pipelines created from them still use the old code until they are rebuilt.
+    #[cfg(feature = "hot-reload")]
+    pub fn poll_reloaded_shaders(&mut self) -> Vec<String> {
+        self.shader_watcher
+            .as_mut()
+            .map(ShaderWatcher::poll)
+            .unwrap_or_default()
+    }
+
+    /// Watch the shader sources next to the crate's manifest, which the build script compiles.
+    /// Hot reloading is off with a warning when they can't be watched, e.g. when the app runs
+    /// away from the source tree.
+    #[cfg(feature = "hot-reload")]
+    fn create_shader_watcher() -> Option<ShaderWatcher> {
+        let source_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("shaders");
+        let watcher = find_shaders_dir()
+            .and_then(|output_dir| ShaderWatcher::new(&source_dir, &output_dir));
+        match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                log::warn!("Shader hot reloading is off: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Check that `data` starts with a header the driver of `properties` would accept. A driver
+    /// should reject a foreign blob on its own, but not all of them do so gracefully.
+    fn validate_cache_header(
+        data: &[u8],
+        properties: &vk::PhysicalDeviceProperties,
+    ) -> Result<()> {
+        if data.len() < CACHE_HEADER_SIZE {
+            return Err(eyre!("{} bytes is too short for a pipeline cache header", data.len()));
+        }
+        let read_u32 = |offset: usize| {
+            u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap())
+        };
+
+        let header_size = read_u32(0);
+        let header_version = read_u32(4);
+        let vendor_id = read_u32(8);
+        let device_id = read_u32(12);
+        let uuid = &data[16..32];
+
+        if (header_size as usize) < CACHE_HEADER_SIZE || header_size as usize > data.len() {
+            return Err(eyre!("Header size {} is invalid", header_size));
+        }
+        if header_version != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+            return Err(eyre!("Header version {} is not supported", header_version));
+        }
+        if vendor_id != properties.vendor_id || device_id != properties.device_id {
+            return Err(eyre!(
+                "It was written for device {:04x}:{:04x}, not {:04x}:{:04x}",
+                vendor_id,
+                device_id,
+                properties.vendor_id,
+                properties.device_id,
+            ));
+        }
+        if uuid != properties.pipeline_cache_uuid {
+            return Err(eyre!("It was written by another driver version"));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RenderPipelineContext {
+    fn drop(&mut self) {
+        if let Some(path) = self.cache_path.as_ref()
+            && let Err(e) = self.save_cache(path)
+        {
+            log::error!("{}", e);
+        }
+        unsafe {
+            self.device.destroy_pipeline_cache(self.cache, None);
+        }
     }
-}
\ No newline at end of file
+}
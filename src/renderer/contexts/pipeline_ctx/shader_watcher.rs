@@ -0,0 +1,84 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use color_eyre::eyre::OptionExt;
+use color_eyre::Result;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use crate::renderer::resources::shader_compiler::{
+    compile_shader,
+    compiled_file_name,
+    is_shader_source,
+};
+
+/// Watches the shader sources and recompiles the ones that change into the directory compiled
+/// shaders are loaded from, the same way the build script does. Rebuilding the pipelines that
+/// use them is up to the caller of `poll`.
+pub struct ShaderWatcher {
+    output_dir: PathBuf,
+    events: Receiver<notify::Result<Event>>,
+    // Watching stops when it is dropped
+    _watcher: RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    pub fn new(source_dir: &Path, output_dir: &Path) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(source_dir, RecursiveMode::NonRecursive)?;
+        log::info!("Watching {} for shader changes", source_dir.display());
+
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Recompile the shaders whose sources changed since the last call and return their names,
+    /// e.g. `default` for `default.frag`, without duplicates. A shader that fails to compile is
+    /// logged and left out, so its previously compiled file stays in place.
+    pub fn poll(&mut self) -> Vec<String> {
+        let changed_paths = self.events
+            .try_iter()
+            .filter_map(|event| match event {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    log::warn!("Error while watching shaders: {}", e);
+                    None
+                }
+            })
+            .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)))
+            .flat_map(|event| event.paths)
+            // Editors write temporary and backup files next to the sources
+            .filter(|path| is_shader_source(path) && path.is_file())
+            .collect::<BTreeSet<_>>();
+
+        let mut shader_names = BTreeSet::new();
+        for path in changed_paths {
+            match self.recompile(&path) {
+                Ok(shader_name) => {
+                    shader_names.insert(shader_name);
+                }
+                Err(e) => log::error!(
+                    "Failed to recompile {}, keeping the previous version: {:?}",
+                    path.display(),
+                    e,
+                ),
+            }
+        }
+        shader_names.into_iter().collect()
+    }
+
+    fn recompile(&self, path: &Path) -> Result<String> {
+        let spv_binary = compile_shader(path)?;
+        let output_path = self.output_dir.join(compiled_file_name(path)?);
+        std::fs::write(&output_path, bytemuck::cast_slice(&spv_binary))?;
+        log::info!("Recompiled {} into {}", path.display(), output_path.display());
+
+        let shader_name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or_eyre("Shader file name is not valid UTF-8")?;
+        Ok(shader_name.to_string())
+    }
+}
@@ -1,7 +1,9 @@
 pub mod descriptor_set_layout_builder;
+pub mod resource_id;
 pub mod resource_storage;
 pub mod resource_type;
 
+use ash::vk;
 use color_eyre::Result;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
 use crate::renderer::contexts::resource_ctx::resource_storage::RenderResourceStorage;
@@ -17,8 +19,9 @@ pub struct RenderResourceContext {
 impl RenderResourceContext {
     pub fn new(
         dev_ctx: &RenderDeviceContext,
+        pipeline_cache: vk::PipelineCache,
     ) -> Result<Self> {
-        let storage = RenderResourceStorage::new(dev_ctx)?;
+        let storage = RenderResourceStorage::new(dev_ctx, pipeline_cache)?;
 
         Ok(Self {
             storage,
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Hands out ids for resources that are compared by identity, like meshes and megabuffers.
+/// Each `RenderResourceStorage` owns one, so ids start at 0 for every renderer and don't depend
+/// on what other renderers in the process have created.
+#[derive(Debug, Default)]
+pub struct ResourceIdAllocator {
+    next_id: AtomicUsize,
+}
+
+impl ResourceIdAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allocate(&self) -> usize {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
@@ -1,102 +1,429 @@
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use ash::vk;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 use gpu_descriptor::DescriptorAllocator;
+use crate::renderer::config::BindlessLimits;
+use crate::renderer::contexts::device_ctx::device::RenderDevice;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
 use crate::renderer::contexts::resource_ctx::descriptor_set_layout_builder::DescriptorSetLayoutBuilder;
+use crate::renderer::contexts::resource_ctx::resource_id::ResourceIdAllocator;
 use crate::renderer::contexts::resource_ctx::resource_type::RenderResourceType;
-use crate::renderer::resources::buffer::Buffer;
-use crate::renderer::resources::material::{GraphicsMaterialFactoryBuilder, MaterialFactory};
+use crate::renderer::debug_lines::DebugLines;
+use crate::renderer::resources::color_grading::ColorGradingLut;
+use crate::renderer::resources::material::{GraphicsMaterialFactoryBuilder, Material, MaterialFactory};
 use crate::renderer::resources::megabuffer::Megabuffer;
-use crate::renderer::resources::shader::GraphicsShader;
-use crate::renderer::resources::texture::{ColorTexture, StorageTexture};
-use crate::renderer::shader_data::PerDrawData;
+use crate::renderer::resources::sampler::SamplerDesc;
+use crate::renderer::resources::shader::{check_graphics_shaders_built, GraphicsShader};
+use crate::renderer::resources::texture::{ArrayTexture, ColorTexture, StorageTexture};
+use crate::renderer::scene::{MaterialHandle, TextureHandle};
+use crate::renderer::sprite::SpriteBatch;
+use crate::renderer::shader_data::{PerDrawData, MULTIVIEW_VIEW_COUNT};
 
+// Initial sizes of the megabuffers, which grow when an allocation doesn't fit
 const VERTEX_BUFFER_SIZE: u64 = 1024 * 1024 * 256; // 256 MB
 const INDEX_BUFFER_SIZE: u64 = 1024 * 1024 * 64; // 64 MB
 const VERTEX_BUFFER_ALIGNMENT: u64 = 16;
+// Index regions hold either 16-bit or 32-bit indices, and `cmd_bind_index_buffer` requires the
+// offset to be a multiple of the index size, so align to the larger of the two
 const INDEX_BUFFER_ALIGNMENT: u64 = 4;
-const STORAGE_BUFFER_ALIGNMENT: u64 = 16;
-const UNIFORM_BUFFER_ALIGNMENT: u64 = 256;
+// Size of each material's slot in the material data buffers. 256 is the largest
+// `minStorageBufferOffsetAlignment` a device may have, so every slot starts aligned.
+const MATERIAL_DATA_SLOT_SIZE: u64 = 256;
+// Materials each factory with a material data buffer can have at once
+const MATERIAL_DATA_SLOT_COUNT: u32 = 1024;
+// Bindings of the bindless layout holding the sampler and texture tables
+const SAMPLERS_BINDING: u32 = 3;
+const TEXTURES_BINDING: u32 = 4;
+// Graphics shaders the built-in material factories are created from
+const BUILTIN_GRAPHICS_SHADERS: [&str; 7] = [
+    "color_grading",
+    "debug_line",
+    "default",
+    "fxaa",
+    "multiview",
+    "sprite",
+    "wireframe_overlay",
+];
 
 pub struct RenderResourceStorage {
-    pub uniform_buffers: Vec<Buffer>,
-    pub storage_buffers: Vec<Megabuffer>,
     pub storage_images: Vec<StorageTexture>,
     pub sampled_images: Vec<ColorTexture>,
+    pub array_textures: Vec<ArrayTexture>,
+    pub color_grading_luts: Vec<ColorGradingLut>,
     pub samplers: Vec<vk::Sampler>,
+    // Index into `samplers` for each description, so textures that filter alike share a sampler
+    sampler_indices: HashMap<SamplerDesc, u32>,
 
     pub vertex_megabuffer: Megabuffer,
     pub index_megabuffer: Megabuffer,
     pub bindless_material_factory: MaterialFactory,
+    pub multiview_material_factory: MaterialFactory,
+    pub sprite_material_factory: MaterialFactory,
+    pub debug_line_material_factory: MaterialFactory,
+    pub materials: Vec<Material>,
+    // Shared by the built-in material factories, which only borrow them, so they are destroyed
+    // here rather than by any one factory
+    bindless_descriptor_set_layout: vk::DescriptorSetLayout,
+    bindless_pipeline_layout: vk::PipelineLayout,
+
+    /// Ids of the meshes and megabuffers created through this storage
+    pub id_allocator: Arc<ResourceIdAllocator>,
+    device: Arc<ash::Device>,
 }
 
 impl RenderResourceStorage {
     pub fn new(
         dev_ctx: &RenderDeviceContext,
+        pipeline_cache: vk::PipelineCache,
     ) -> Result<Self> {
         let device = &dev_ctx.device;
+        check_graphics_shaders_built(&BUILTIN_GRAPHICS_SHADERS)?;
+        let id_allocator = Arc::new(ResourceIdAllocator::new());
 
         let vertex_megabuffer = device.create_megabuffer(
             VERTEX_BUFFER_SIZE,
             VERTEX_BUFFER_ALIGNMENT,
             vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            id_allocator.clone(),
         )?;
 
         let index_megabuffer = device.create_megabuffer(
             INDEX_BUFFER_SIZE,
             INDEX_BUFFER_ALIGNMENT,
             vk::BufferUsageFlags::INDEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            id_allocator.clone(),
         )?;
 
+        let bindless_limits = *device.get_bindless_limits();
+        let bindless_descriptor_set_layout = Self::create_bindless_descriptor_set_layout(
+            &bindless_limits,
+            &device.logical,
+        )?;
+        let bindless_pipeline_layout = Self::create_bindless_pipeline_layout(
+            bindless_descriptor_set_layout,
+            &device.logical,
+        )?;
         let bindless_material_factory = Self::create_bindless_material_factory(
+            bindless_descriptor_set_layout,
+            bindless_pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            device.get_memory_allocator().clone(),
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        )?;
+        let multiview_material_factory = Self::create_multiview_material_factory(
+            bindless_descriptor_set_layout,
+            bindless_pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            device.get_memory_allocator().clone(),
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        )?;
+        let sprite_material_factory = Self::create_sprite_material_factory(
+            bindless_descriptor_set_layout,
+            bindless_pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            device.get_memory_allocator().clone(),
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        )?;
+        let debug_line_material_factory = Self::create_debug_line_material_factory(
+            bindless_descriptor_set_layout,
+            bindless_pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
             device.logical.clone(),
             device.descriptor_allocator.clone(),
         )?;
 
         Ok(Self {
-            uniform_buffers: Vec::new(),
-            storage_buffers: Vec::new(),
             storage_images: Vec::new(),
             samplers: Vec::new(),
+            sampler_indices: HashMap::new(),
             sampled_images: Vec::new(),
+            array_textures: Vec::new(),
+            color_grading_luts: Vec::new(),
 
             vertex_megabuffer,
             index_megabuffer,
 
             bindless_material_factory,
+            multiview_material_factory,
+            sprite_material_factory,
+            debug_line_material_factory,
+            materials: Vec::new(),
+            bindless_descriptor_set_layout,
+            bindless_pipeline_layout,
+
+            id_allocator,
+            device: device.logical.clone(),
         })
     }
 
+    /// Index of the sampler for `desc` in the bindless sampler table, creating it on first use. A
+    /// new sampler is written into the descriptor set of every material.
+    pub fn get_or_create_sampler(
+        &mut self,
+        desc: &SamplerDesc,
+        device: &RenderDevice,
+    ) -> Result<u32> {
+        if let Some(index) = self.sampler_indices.get(desc) {
+            return Ok(*index);
+        }
+
+        let max_samplers = device.get_bindless_limits().samplers;
+        if self.samplers.len() as u32 >= max_samplers {
+            return Err(eyre!(
+                "All {} bindless samplers are in use; raise BindlessLimits::samplers",
+                max_samplers,
+            ));
+        }
+
+        let index = self.samplers.len() as u32;
+        let sampler = desc.create(device)?;
+        for material in self.materials.iter_mut() {
+            material.write_sampler(SAMPLERS_BINDING, index, sampler);
+        }
+        self.samplers.push(sampler);
+        self.sampler_indices.insert(*desc, index);
+        Ok(index)
+    }
+
+    /// Add a texture to the bindless texture table, writing it into the descriptor set of every
+    /// material, and record which sampler it is read with
+    pub fn register_texture(
+        &mut self,
+        mut texture: ColorTexture,
+        sampler: &SamplerDesc,
+        device: &RenderDevice,
+    ) -> Result<TextureHandle> {
+        let max_sampled_images = device.get_bindless_limits().sampled_images;
+        if self.sampled_images.len() as u32 >= max_sampled_images {
+            return Err(eyre!(
+                "All {} bindless textures are in use; raise BindlessLimits::sampled_images",
+                max_sampled_images,
+            ));
+        }
+
+        texture.sampler_index = self.get_or_create_sampler(sampler, device)?;
+        let index = self.sampled_images.len() as u32;
+        for material in self.materials.iter_mut() {
+            material.write_sampled_image(TEXTURES_BINDING, index, texture.image.view, None);
+        }
+        self.sampled_images.push(texture);
+        Ok(TextureHandle(index as usize))
+    }
+
+    /// Write every registered texture and sampler into the material's descriptor set and add it
+    /// to `materials`. The material must use the bindless layout.
+    pub fn add_material(&mut self, mut material: Material) -> MaterialHandle {
+        for (index, sampler) in self.samplers.iter().enumerate() {
+            material.write_sampler(SAMPLERS_BINDING, index as u32, *sampler);
+        }
+        for (index, texture) in self.sampled_images.iter().enumerate() {
+            material.write_sampled_image(TEXTURES_BINDING, index as u32, texture.image.view, None);
+        }
+        self.materials.push(material);
+        MaterialHandle(self.materials.len() - 1)
+    }
+
+    /// Rebuild the pipelines of the built-in material factories whose shader is in
+    /// `shader_names`. Their materials keep their descriptor sets and bind the new pipeline from
+    /// then on. A factory that fails to rebuild keeps its pipeline. The device must be idle.
+    #[cfg(feature = "hot-reload")]
+    pub fn reload_shaders(
+        &mut self,
+        shader_names: &[String],
+        device: &RenderDevice,
+        pipeline_cache: vk::PipelineCache,
+    ) {
+        let descriptor_set_layout = self.bindless_descriptor_set_layout;
+        let pipeline_layout = self.bindless_pipeline_layout;
+        let bindless_limits = *device.get_bindless_limits();
+        let memory_allocator = device.get_memory_allocator();
+        let rebuild_bindless = || Self::create_bindless_material_factory(
+            descriptor_set_layout,
+            pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            memory_allocator.clone(),
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        );
+        let rebuild_multiview = || Self::create_multiview_material_factory(
+            descriptor_set_layout,
+            pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            memory_allocator.clone(),
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        );
+        let rebuild_sprite = || Self::create_sprite_material_factory(
+            descriptor_set_layout,
+            pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            memory_allocator.clone(),
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        );
+        let rebuild_debug_line = || Self::create_debug_line_material_factory(
+            descriptor_set_layout,
+            pipeline_layout,
+            bindless_limits,
+            pipeline_cache,
+            device.logical.clone(),
+            device.descriptor_allocator.clone(),
+        );
+        let factories: [(&mut MaterialFactory, &dyn Fn() -> Result<MaterialFactory>); 4] = [
+            (&mut self.bindless_material_factory, &rebuild_bindless),
+            (&mut self.multiview_material_factory, &rebuild_multiview),
+            (&mut self.sprite_material_factory, &rebuild_sprite),
+            (&mut self.debug_line_material_factory, &rebuild_debug_line),
+        ];
+
+        for (factory, rebuild) in factories {
+            if !shader_names.iter().any(|name| name == factory.get_shader_name()) {
+                continue;
+            }
+            match rebuild() {
+                Ok(rebuilt) => {
+                    factory.replace_pipeline(rebuilt);
+                    log::info!("Rebuilt the {} pipeline", factory.get_shader_name());
+                }
+                Err(e) => log::error!(
+                    "Failed to rebuild the {} pipeline, keeping the old one: {:?}",
+                    factory.get_shader_name(),
+                    e,
+                ),
+            }
+        }
+    }
+
     fn create_bindless_material_factory(
+        bindless_descriptor_set_layout: vk::DescriptorSetLayout,
+        bindless_pipeline_layout: vk::PipelineLayout,
+        bindless_limits: BindlessLimits,
+        pipeline_cache: vk::PipelineCache,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
         device: Arc<ash::Device>,
         descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
     ) -> Result<MaterialFactory> {
-        let bindless_descriptor_set_layout = Self::create_bindless_descriptor_set_layout(
-            &device
-        )?;
-        let bindless_pipeline_layout = Self::create_bindless_pipeline_layout(
-            bindless_descriptor_set_layout,
-            &device,
-        )?;
         let default_shader = GraphicsShader::new("default", device.clone())?;
         GraphicsMaterialFactoryBuilder::new(device, descriptor_allocator)
+            .with_pipeline_cache(pipeline_cache)
             .with_shader(default_shader)
             .with_pipeline_layout(bindless_pipeline_layout)
             .with_descriptor_set_layout(bindless_descriptor_set_layout)
+            .with_bindless_limits(bindless_limits)
+            .with_material_data(
+                MATERIAL_DATA_SLOT_SIZE,
+                MATERIAL_DATA_SLOT_COUNT,
+                memory_allocator,
+            )
+            .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+            .with_depth_attachment_format(vk::Format::D32_SFLOAT)
+            .build()
+    }
+
+    /// Same bindless layouts as the default factory, but renders `MULTIVIEW_VIEW_COUNT` views at
+    /// once into layered attachments
+    fn create_multiview_material_factory(
+        bindless_descriptor_set_layout: vk::DescriptorSetLayout,
+        bindless_pipeline_layout: vk::PipelineLayout,
+        bindless_limits: BindlessLimits,
+        pipeline_cache: vk::PipelineCache,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+        descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
+    ) -> Result<MaterialFactory> {
+        let multiview_shader = GraphicsShader::new("multiview", device.clone())?;
+        let view_mask = (1u32 << MULTIVIEW_VIEW_COUNT) - 1;
+        GraphicsMaterialFactoryBuilder::new(device, descriptor_allocator)
+            .with_pipeline_cache(pipeline_cache)
+            .with_shader(multiview_shader)
+            .with_pipeline_layout(bindless_pipeline_layout)
+            .with_descriptor_set_layout(bindless_descriptor_set_layout)
+            .with_bindless_limits(bindless_limits)
+            .with_material_data(
+                MATERIAL_DATA_SLOT_SIZE,
+                MATERIAL_DATA_SLOT_COUNT,
+                memory_allocator,
+            )
             .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
             .with_depth_attachment_format(vk::Format::D32_SFLOAT)
+            .with_view_mask(view_mask)
             .build()
     }
     
+    /// Alpha blended textured quads drawn over the scene, without depth
+    fn create_sprite_material_factory(
+        bindless_descriptor_set_layout: vk::DescriptorSetLayout,
+        bindless_pipeline_layout: vk::PipelineLayout,
+        bindless_limits: BindlessLimits,
+        pipeline_cache: vk::PipelineCache,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+        descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
+    ) -> Result<MaterialFactory> {
+        let sprite_shader = GraphicsShader::new("sprite", device.clone())?;
+        GraphicsMaterialFactoryBuilder::new(device, descriptor_allocator)
+            .with_pipeline_cache(pipeline_cache)
+            .with_shader(sprite_shader)
+            .with_pipeline_layout(bindless_pipeline_layout)
+            .with_descriptor_set_layout(bindless_descriptor_set_layout)
+            .with_bindless_limits(bindless_limits)
+            .with_material_data(
+                MATERIAL_DATA_SLOT_SIZE,
+                MATERIAL_DATA_SLOT_COUNT,
+                memory_allocator,
+            )
+            .with_vertex_input(SpriteBatch::get_input_description())
+            .with_alpha_blending_enabled()
+            .with_depth_test(false, None)
+            .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+            .build()
+    }
+
+    /// Opaque colored lines drawn over the scene, without depth
+    fn create_debug_line_material_factory(
+        bindless_descriptor_set_layout: vk::DescriptorSetLayout,
+        bindless_pipeline_layout: vk::PipelineLayout,
+        bindless_limits: BindlessLimits,
+        pipeline_cache: vk::PipelineCache,
+        device: Arc<ash::Device>,
+        descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
+    ) -> Result<MaterialFactory> {
+        let debug_line_shader = GraphicsShader::new("debug_line", device.clone())?;
+        GraphicsMaterialFactoryBuilder::new(device, descriptor_allocator)
+            .with_pipeline_cache(pipeline_cache)
+            .with_shader(debug_line_shader)
+            .with_pipeline_layout(bindless_pipeline_layout)
+            .with_descriptor_set_layout(bindless_descriptor_set_layout)
+            .with_bindless_limits(bindless_limits)
+            .with_input_topology(vk::PrimitiveTopology::LINE_LIST)
+            .with_vertex_input(DebugLines::get_input_description())
+            .with_blending_disabled()
+            .with_depth_test(false, None)
+            .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+            .build()
+    }
+
     fn create_bindless_descriptor_set_layout(
+        bindless_limits: &BindlessLimits,
         device: &ash::Device,
     ) -> Result<vk::DescriptorSetLayout> {
         DescriptorSetLayoutBuilder::new()
             .add_binding( // Per-frame
                 0,
                 RenderResourceType::UniformBuffer.descriptor_type(),
-                RenderResourceType::UniformBuffer.descriptor_count(),
+                RenderResourceType::UniformBuffer.descriptor_count(bindless_limits),
                 vk::ShaderStageFlags::ALL,
                 RenderResourceType::UniformBuffer.descriptor_binding_flags(),
                 None,
@@ -104,7 +431,7 @@ impl RenderResourceStorage {
             .add_binding( // Per-material
                 1,
                 RenderResourceType::StorageBuffer.descriptor_type(),
-                RenderResourceType::StorageBuffer.descriptor_count(),
+                RenderResourceType::StorageBuffer.descriptor_count(bindless_limits),
                 vk::ShaderStageFlags::ALL,
                 RenderResourceType::StorageBuffer.descriptor_binding_flags(),
                 None,
@@ -112,7 +439,7 @@ impl RenderResourceStorage {
             .add_binding( // Per-material
                 2,
                 RenderResourceType::StorageBuffer.descriptor_type(),
-                RenderResourceType::StorageBuffer.descriptor_count(),
+                RenderResourceType::StorageBuffer.descriptor_count(bindless_limits),
                 vk::ShaderStageFlags::ALL,
                 RenderResourceType::StorageBuffer.descriptor_binding_flags(),
                 None,
@@ -120,7 +447,7 @@ impl RenderResourceStorage {
             .add_binding( // Samplers
                 3,
                 RenderResourceType::Sampler.descriptor_type(),
-                RenderResourceType::Sampler.descriptor_count(),
+                RenderResourceType::Sampler.descriptor_count(bindless_limits),
                 vk::ShaderStageFlags::ALL,
                 RenderResourceType::Sampler.descriptor_binding_flags(),
                 None,
@@ -128,7 +455,7 @@ impl RenderResourceStorage {
             .add_binding( // Textures
                 4,
                 RenderResourceType::SampledImage.descriptor_type(),
-                RenderResourceType::SampledImage.descriptor_count(),
+                RenderResourceType::SampledImage.descriptor_count(bindless_limits),
                 vk::ShaderStageFlags::ALL,
                 RenderResourceType::SampledImage.descriptor_binding_flags(),
                 None,
@@ -163,3 +490,14 @@ impl RenderResourceStorage {
         Ok(pipeline_layout)
     }
 }
+
+impl Drop for RenderResourceStorage {
+    fn drop(&mut self) {
+        // Pipelines don't need their layouts after creation, and nothing updates the materials'
+        // descriptor sets once the storage is gone, so the factories can be dropped after this
+        unsafe {
+            self.device.destroy_pipeline_layout(self.bindless_pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.bindless_descriptor_set_layout, None);
+        }
+    }
+}
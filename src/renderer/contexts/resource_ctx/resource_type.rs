@@ -1,10 +1,9 @@
 use ash::vk;
+use crate::renderer::config::BindlessLimits;
 
 const UNIFORM_BUFFER_DESCRIPTOR_COUNT: u32 = 1;
 const STORAGE_BUFFER_DESCRIPTOR_COUNT: u32 = 1;
 const STORAGE_IMAGE_DESCRIPTOR_COUNT: u32 = 1;
-const SAMPLER_DESCRIPTOR_COUNT: u32 = 16;
-const SAMPLED_IMAGE_DESCRIPTOR_COUNT: u32 = 1024;
 
 #[derive(PartialEq)]
 pub enum RenderResourceType {
@@ -34,16 +33,25 @@ impl RenderResourceType {
         }
     }
 
-    pub fn descriptor_count(&self) -> u32 {
+    /// The bindless tables are sized by `limits`, the other bindings hold a single descriptor
+    pub fn descriptor_count(&self, limits: &BindlessLimits) -> u32 {
         match self {
             Self::UniformBuffer => UNIFORM_BUFFER_DESCRIPTOR_COUNT,
             Self::StorageBuffer => STORAGE_BUFFER_DESCRIPTOR_COUNT,
             Self::StorageImage => STORAGE_IMAGE_DESCRIPTOR_COUNT,
-            Self::Sampler => SAMPLER_DESCRIPTOR_COUNT,
-            Self::SampledImage => SAMPLED_IMAGE_DESCRIPTOR_COUNT,
+            Self::Sampler => limits.samplers,
+            Self::SampledImage => limits.sampled_images,
         }
     }
 
+    /// Descriptors in one bindless set, i.e. what each material's descriptor set takes
+    pub fn total_descriptor_count(limits: &BindlessLimits) -> u32 {
+        Self::ALL
+            .iter()
+            .map(|resource_type| resource_type.descriptor_count(limits))
+            .sum()
+    }
+
     pub fn descriptor_binding_flags(&self) -> vk::DescriptorBindingFlags {
         match self {
             Self::UniformBuffer => vk::DescriptorBindingFlags::PARTIALLY_BOUND
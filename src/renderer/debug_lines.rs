@@ -0,0 +1,153 @@
+use std::mem::offset_of;
+use ash::vk;
+use glam::{Mat3, Mat4, Vec3};
+use crate::renderer::resources::vertex::VertexInputDescription;
+use crate::renderer::scene::Scene;
+use crate::renderer::shader_data::PerDebugLineVertexData;
+
+const NORMAL_COLOR: [f32; 4] = [0.0, 0.0, 1.0, 1.0];
+const TANGENT_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+const BITANGENT_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    color: [f32; 4],
+}
+
+/// Collects world-space lines and turns them into vertices for the debug line pass, which is drawn
+/// over the scene without depth testing. Lines are kept between frames; call `clear` to rebuild
+/// them.
+///
+/// The vertices are transformed on the CPU with the view-projection matrix the scene is drawn
+/// with, so the lines follow the camera without being told about it.
+pub struct DebugLines {
+    lines: Vec<DebugLine>,
+    // Length of the normal, tangent and bitangent lines drawn at every vertex in the scene.
+    // `None` when the visualization is off.
+    normal_length: Option<f32>,
+}
+
+impl Default for DebugLines {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DebugLines {
+    pub fn new() -> Self {
+        Self {
+            lines: Vec::new(),
+            normal_length: None,
+        }
+    }
+
+    pub fn add_line(&mut self, start: Vec3, end: Vec3, color: [f32; 4]) {
+        self.lines.push(DebugLine {
+            start,
+            end,
+            color,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    /// Whether there is nothing to draw, counting the vertex visualization
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty() && self.normal_length.is_none()
+    }
+
+    /// Draw the normal (blue), tangent (red) and bitangent (green) of every vertex in the scene
+    /// as lines of `length` world units. `None` turns it off.
+    pub fn set_normal_length(&mut self, length: Option<f32>) {
+        self.normal_length = length;
+    }
+
+    pub fn get_normal_length(&self) -> Option<f32> {
+        self.normal_length
+    }
+
+    /// Two vertices per line in the clip space of `viewproj`: the added lines, then the vertex
+    /// visualization of `scene`
+    pub fn build_vertices(&self, scene: &Scene, viewproj: Mat4) -> Vec<PerDebugLineVertexData> {
+        let mut vertices = Vec::with_capacity(self.lines.len() * 2);
+        let mut push_line = |start: Vec3, end: Vec3, color: [f32; 4]| {
+            for point in [start, end] {
+                vertices.push(PerDebugLineVertexData {
+                    position: viewproj * point.extend(1.0),
+                    color,
+                });
+            }
+        };
+
+        for line in &self.lines {
+            push_line(line.start, line.end, line.color);
+        }
+
+        let Some(length) = self.normal_length else {
+            return vertices;
+        };
+        for (_, instance) in scene.instances() {
+            let Some(model) = scene.get_model(instance.model) else {
+                continue;
+            };
+            let model_matrix = instance.transform.get_matrix();
+            // Keeps normals perpendicular to the surface under non-uniform scale
+            let normal_matrix = Mat3::from_mat4(model_matrix).inverse().transpose();
+            for mesh in model.get_meshes() {
+                let tangents = mesh.compute_tangents();
+                for (vertex, tangent) in mesh.vertices.iter().zip(tangents) {
+                    let position = model_matrix.transform_point3(vertex.position);
+                    let normal = (normal_matrix * vertex.normal).normalize_or_zero();
+                    let bitangent = vertex.normal.cross(tangent.truncate()) * tangent.w;
+                    let tangent = model_matrix
+                        .transform_vector3(tangent.truncate())
+                        .normalize_or_zero();
+                    let bitangent = model_matrix
+                        .transform_vector3(bitangent)
+                        .normalize_or_zero();
+                    push_line(position, position + normal * length, NORMAL_COLOR);
+                    push_line(position, position + tangent * length, TANGENT_COLOR);
+                    push_line(position, position + bitangent * length, BITANGENT_COLOR);
+                }
+            }
+        }
+
+        vertices
+    }
+
+    /// Describes the layout of `PerDebugLineVertexData` for the debug line pipeline
+    pub fn get_input_description() -> VertexInputDescription {
+        let bindings = vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<PerDebugLineVertexData>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+
+        let attributes = vec![
+            // Position
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(PerDebugLineVertexData, position) as u32,
+            },
+            // Color
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(PerDebugLineVertexData, color) as u32,
+            },
+        ];
+
+        VertexInputDescription {
+            bindings,
+            attributes,
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+        }
+    }
+}
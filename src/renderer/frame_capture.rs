@@ -0,0 +1,59 @@
+use renderdoc::{RenderDoc, V110};
+
+/// Triggers RenderDoc captures of single frames from code. Does nothing unless the app was
+/// launched from RenderDoc, since the library is only used when it is already loaded.
+pub struct FrameCapture {
+    renderdoc: Option<RenderDoc<V110>>,
+    capture_requested: bool,
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        let renderdoc = match RenderDoc::<V110>::new() {
+            Ok(renderdoc) => {
+                log::info!("RenderDoc is loaded; frames can be captured with capture_next_frame");
+                Some(renderdoc)
+            }
+            Err(e) => {
+                log::debug!("RenderDoc is not loaded: {}", e);
+                None
+            }
+        };
+
+        Self {
+            renderdoc,
+            capture_requested: false,
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.renderdoc.is_some()
+    }
+
+    pub fn request_capture(&mut self) {
+        if self.renderdoc.is_none() {
+            log::warn!("Cannot capture a frame; RenderDoc is not loaded");
+            return;
+        }
+        self.capture_requested = true;
+    }
+
+    /// Start capturing if a capture was requested. Returns whether one was started.
+    pub fn begin_frame(&mut self) -> bool {
+        let Some(renderdoc) = self.renderdoc.as_mut() else {
+            return false;
+        };
+        if !std::mem::take(&mut self.capture_requested) {
+            return false;
+        }
+        // Null device and window handles capture whichever the application is using
+        renderdoc.start_frame_capture(std::ptr::null(), std::ptr::null());
+        true
+    }
+
+    pub fn end_frame(&mut self) {
+        if let Some(renderdoc) = self.renderdoc.as_mut() {
+            renderdoc.end_frame_capture(std::ptr::null(), std::ptr::null());
+        }
+    }
+}
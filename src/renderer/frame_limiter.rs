@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+/// Caps the frame rate by waiting until the next frame is due. Sleeping alone overshoots by up to
+/// the scheduler's granularity, so it sleeps until shortly before the deadline and spins the rest.
+#[derive(Debug, Default)]
+pub struct FrameLimiter {
+    frame_time: Option<Duration>,
+    next_deadline: Option<Instant>,
+}
+
+impl FrameLimiter {
+    // Left to spin instead of sleep, enough to cover typical sleep overshoot
+    const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `None` or 0 removes the cap
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.frame_time = fps
+            .filter(|fps| *fps > 0)
+            .map(|fps| Duration::from_secs_f64(1.0 / fps as f64));
+        self.next_deadline = None;
+    }
+
+    pub fn get_target_fps(&self) -> Option<u32> {
+        self.frame_time
+            .map(|frame_time| (1.0 / frame_time.as_secs_f64()).round() as u32)
+    }
+
+    /// Block until the next frame may start. Returns immediately without a cap.
+    pub fn wait(&mut self) {
+        let Some(frame_time) = self.frame_time else {
+            return;
+        };
+
+        if let Some(deadline) = self.next_deadline {
+            let now = Instant::now();
+            if deadline > now + Self::SPIN_MARGIN {
+                std::thread::sleep(deadline - now - Self::SPIN_MARGIN);
+            }
+            while Instant::now() < deadline {
+                std::hint::spin_loop();
+            }
+        }
+
+        // After a slow frame, start counting from now instead of rushing to catch up
+        let now = Instant::now();
+        let frame_start = match self.next_deadline {
+            Some(deadline) if now.duration_since(deadline) < frame_time => deadline,
+            _ => now,
+        };
+        let next_deadline = frame_start + frame_time;
+        self.next_deadline = Some(next_deadline);
+    }
+}
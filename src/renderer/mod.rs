@@ -1,17 +1,105 @@
+pub mod builder;
 pub mod camera;
+pub mod config;
+pub mod debug_lines;
+#[cfg(feature = "renderdoc")]
+pub mod frame_capture;
+pub mod frame_limiter;
+pub mod scene;
+pub mod sprite;
+pub mod stats;
+#[cfg(feature = "openxr")]
+pub mod xr;
 
 mod contexts;
-mod shader_data;
-mod resources;
+pub mod shader_data;
+pub mod resources;
 
+// The contexts are internal, but these types show up in the public API
+pub use contexts::device_ctx::command_encoder::{AttachmentOps, CommandEncoder};
+pub use contexts::device_ctx::device::{ExistingQueues, RenderDevice};
+pub use contexts::device_ctx::queue::{Queue, QueueFamily};
+pub use contexts::device_ctx::sync_pool::SyncPool;
+pub use contexts::device_ctx::transfer_ctx::{SubmitHandle, SubmitTimeoutError};
+pub use contexts::graph_ctx::graph::{RecordFn, RenderGraph, ResourceAccess, ResourceId};
+pub use contexts::resource_ctx::resource_id::ResourceIdAllocator;
+
+use ash::vk;
+use color_eyre::eyre::{eyre, OptionExt};
 use color_eyre::Result;
+use image::imageops::FilterType;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Instant;
+use crate::renderer::builder::RendererBuilder;
+use crate::renderer::camera::Camera;
+use crate::renderer::config::{
+    AntiAliasingMode,
+    CoordinateSystem,
+    PresentModePreference,
+    RenderConfig,
+    WireframeOverlay,
+};
+use crate::renderer::contexts::device_ctx::command_encoder_allocator::CommandEncoderAllocatorExt;
 use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::config::FullscreenMode;
+#[cfg(feature = "renderdoc")]
+use crate::renderer::frame_capture::FrameCapture;
+use crate::renderer::frame_limiter::FrameLimiter;
 use crate::renderer::contexts::resource_ctx::RenderResourceContext;
 use crate::renderer::contexts::graph_ctx::RenderGraphContext;
 use crate::renderer::contexts::frame_ctx::RenderFrameContext;
+use crate::renderer::contexts::frame_ctx::color_grading::ColorGradingPass;
+use crate::renderer::contexts::frame_ctx::frame::{CachedDrawsKey, Frame};
+use crate::renderer::contexts::frame_ctx::frame_data::FrameDataBuffer;
+use crate::renderer::contexts::frame_ctx::fxaa::FxaaPass;
+use crate::renderer::contexts::frame_ctx::object_data::ObjectDataBuffer;
+use crate::renderer::contexts::frame_ctx::wireframe_overlay::WireframeOverlayPass;
+use crate::renderer::contexts::resource_ctx::resource_storage::RenderResourceStorage;
 use crate::renderer::contexts::pipeline_ctx::RenderPipelineContext;
+use crate::renderer::resources::color_grading::ColorGradingLut;
+use crate::renderer::resources::material::{Material, MaterialFactory};
+use crate::renderer::resources::megabuffer::MegabufferExt;
+use crate::renderer::resources::mesh::Mesh;
+use crate::renderer::resources::model::Model;
+use crate::renderer::resources::sampler::SamplerDesc;
+use crate::renderer::resources::texture::ColorTexture;
+use crate::renderer::scene::{ArrayTextureHandle, InstanceHandle, LutHandle, MaterialHandle, ModelHandle, Scene, TextureHandle, Transform};
+use crate::renderer::shader_data::{
+    MULTIVIEW_VIEW_COUNT,
+    PerDebugLineVertexData,
+    PerDrawData,
+    PerFrameData,
+    PerFrameMultiviewData,
+    PerMaterialData,
+};
+use crate::renderer::sprite::SpriteBatch;
+use crate::renderer::debug_lines::DebugLines;
+use crate::renderer::stats::{PipelineStatistics, RenderStats};
+
+/// How the scene draws are recorded each frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RecordStrategy {
+    /// Record the draws directly into the frame's command buffer every frame
+    #[default]
+    PerFrame,
+    /// Record the draws once into a secondary command buffer per frame in flight and replay it
+    /// until the scene changes or the draw extent changes
+    Cached,
+    /// Split the scene into chunks and record each into a secondary command buffer on its own
+    /// worker thread every frame
+    Parallel {
+        thread_count: usize,
+    },
+}
 
+/// Draws the scene either to a window or headless.
+///
+/// - Windowed: created with `new` or `RendererBuilder::with_window`. `draw` renders and presents to
+///   the window's swapchain.
+/// - Headless: created with `new_headless`, `RendererBuilder::headless`, or by an XR runtime,
+///   without a presentation target.
+///   `draw` fails; render with `draw_to_image` into an image owned by the caller instead.
 pub struct Renderer {
     dev_ctx: RenderDeviceContext,
     res_ctx: RenderResourceContext,
@@ -19,18 +107,177 @@ pub struct Renderer {
     frm_ctx: RenderFrameContext,
     pip_ctx: RenderPipelineContext,
 
+    scene: Scene,
+    sprite_batch: SpriteBatch,
+    sprite_material: MaterialHandle,
+    debug_lines: DebugLines,
+    debug_line_material: MaterialHandle,
+    // Cover the whole draw image when not set
+    viewport: Option<vk::Rect2D>,
+    scissor: Option<vk::Rect2D>,
+    record_strategy: RecordStrategy,
+    last_frame_stats: RenderStats,
+    pipeline_statistics_enabled: bool,
+    color_grading_lut: Option<LutHandle>,
+    // Created when a LUT is first set, and kept for when one is set again
+    color_grading: Option<ColorGradingPass>,
+    // Set when `RenderConfig::anti_aliasing` is `Fxaa`
+    fxaa: Option<FxaaPass>,
+    // Set while `RenderConfig::wireframe_overlay` or `set_wireframe_overlay` asks for it
+    wireframe_overlay: Option<WireframeOverlayPass>,
+    // Per-object data of the scene's instances, read by materials from binding 2
+    object_data: ObjectDataBuffer,
+    // Camera matrices and the like, read by materials from binding 0
+    frame_data: PerFrameData,
+    frame_data_buffer: FrameDataBuffer,
+    // Whether the frame data comes from a camera using reverse-Z, which flips the depth test
+    reverse_z: bool,
+    // One view-projection matrix per view, read by multiview materials from binding 0
+    multiview_frame_data: PerFrameMultiviewData,
+    multiview_frame_data_buffer: FrameDataBuffer<PerFrameMultiviewData>,
+    downscale_oversized_textures: bool,
+    import_coordinate_system: CoordinateSystem,
+    frame_limiter: FrameLimiter,
+    #[cfg(feature = "renderdoc")]
+    frame_capture: FrameCapture,
     resize_requested: bool,
 }
 
 impl Renderer {
+    const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+    // The scene pass starts from scratch. Its depth is kept for the wireframe overlay, which tests
+    // against it.
+    const MAIN_PASS_COLOR_OPS: AttachmentOps = AttachmentOps::clear();
+    const MAIN_PASS_DEPTH_OPS: AttachmentOps = AttachmentOps::clear();
+
+    /// Start configuring a renderer, see `RendererBuilder`
+    pub fn builder() -> RendererBuilder {
+        RendererBuilder::new()
+    }
+
+    /// Windowed renderer with the default config. Without a window, use `new_headless` or
+    /// `RendererBuilder::headless` instead.
     pub fn new(
-        window: Option<Arc<winit::window::Window>>
+        window: Arc<winit::window::Window>
+    ) -> Result<Self> {
+        RendererBuilder::new()
+            .with_window(window)
+            .build()
+    }
+
+    pub fn new_with_config(
+        window: Arc<winit::window::Window>,
+        config: &RenderConfig,
+    ) -> Result<Self> {
+        let dev_ctx = RenderDeviceContext::new(Some(window), config)?;
+        Self::from_device_context(dev_ctx, None, config)
+    }
+
+    /// Whether `new_headless` can find a device to render on with `config`, without creating one
+    pub fn is_headless_supported(config: &RenderConfig) -> bool {
+        RenderDeviceContext::is_headless_supported(config)
+    }
+
+    /// Create a renderer without a window, for offscreen rendering with `draw_to_image`. The draw
+    /// images are `draw_extent` in size.
+    pub fn new_headless(draw_extent: vk::Extent2D, config: &RenderConfig) -> Result<Self> {
+        let dev_ctx = RenderDeviceContext::new(None, config)?;
+        Self::from_device_context(dev_ctx, Some(draw_extent), config)
+    }
+
+    /// Create the renderer on top of a Vulkan instance and device that something else owns, such
+    /// as an XR runtime or a host application. The device must have been created with the
+    /// extensions and features the renderer needs, and both must outlive the renderer.
+    pub fn from_existing(
+        entry: ash::Entry,
+        instance: ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        queues: ExistingQueues,
+        window: Option<Arc<winit::window::Window>>,
+        config: &RenderConfig,
     ) -> Result<Self> {
-        let dev_ctx = RenderDeviceContext::new(window)?;
-        let res_ctx = RenderResourceContext::new(&dev_ctx)?;
-        let frm_ctx = RenderFrameContext::new(&dev_ctx, &res_ctx)?;
+        let dev_ctx = RenderDeviceContext::from_existing(
+            entry,
+            instance,
+            physical_device,
+            device,
+            queues,
+            window,
+            config,
+        )?;
+        Self::from_device_context(dev_ctx, None, config)
+    }
+
+    /// `draw_extent` sizes the draw images. It defaults to the size of the presentation target and
+    /// is required when there is none.
+    fn from_device_context(
+        dev_ctx: RenderDeviceContext,
+        draw_extent: Option<vk::Extent2D>,
+        config: &RenderConfig,
+    ) -> Result<Self> {
+        let draw_extent = match (draw_extent, dev_ctx.target.as_ref()) {
+            (Some(draw_extent), _) => draw_extent,
+            (None, Some(target)) => {
+                let size = target.get_size();
+                vk::Extent2D {
+                    width: size.width,
+                    height: size.height,
+                }
+            }
+            (None, None) => return Err(eyre!(
+                "A draw extent is required without a presentation target; use Renderer::new_headless to render without a window"
+            )),
+        };
+
+        let pip_ctx = RenderPipelineContext::new(&dev_ctx, config.pipeline_cache_path.as_deref())?;
+        let pipeline_cache = pip_ctx.get_cache();
+        let mut res_ctx = RenderResourceContext::new(&dev_ctx, pipeline_cache)?;
+        let sprite_material = {
+            let storage = &mut res_ctx.storage;
+            // Sprites pick their texture per vertex, but all of them are read with the sampler
+            // in the material data
+            let sampler_index = storage.get_or_create_sampler(
+                &SamplerDesc::default(),
+                &dev_ctx.device,
+            )?;
+            let mut material = storage.sprite_material_factory.create_material()?;
+            material.set_material_data(&PerMaterialData {
+                texture_index: 0,
+                sampler_index,
+            })?;
+            storage.add_material(material)
+        };
+        let debug_line_material = {
+            let storage = &mut res_ctx.storage;
+            let material = storage.debug_line_material_factory.create_material()?;
+            storage.add_material(material)
+        };
+        let frm_ctx = RenderFrameContext::new(&dev_ctx, &res_ctx, draw_extent)?;
+        let fxaa = match config.anti_aliasing {
+            AntiAliasingMode::None => None,
+            AntiAliasingMode::Fxaa => Some(FxaaPass::new(
+                &dev_ctx,
+                &frm_ctx.get_draw_color_images(),
+                pipeline_cache,
+            )?),
+            AntiAliasingMode::Msaa(samples) => return Err(eyre!(
+                "MSAA with {} samples is not supported yet; use AntiAliasingMode::Fxaa instead",
+                samples,
+            )),
+        };
+        let frame_count = frm_ctx.get_draw_color_images().len();
+        let wireframe_overlay = config.wireframe_overlay
+            .map(|settings| {
+                WireframeOverlayPass::new(&dev_ctx, frame_count, settings, pipeline_cache)
+            })
+            .transpose()?;
+        let object_data = ObjectDataBuffer::new(&dev_ctx, frame_count)?;
+        let frame_data_buffer = FrameDataBuffer::new(&dev_ctx)?;
+        let multiview_frame_data_buffer = FrameDataBuffer::new(&dev_ctx)?;
+        let frame_data = Camera::new()
+            .get_frame_data(draw_extent.width as f32, draw_extent.height as f32);
         let grp_ctx = RenderGraphContext::new(&dev_ctx)?;
-        let pip_ctx = RenderPipelineContext::new(&dev_ctx)?;
 
         Ok(Self {
             dev_ctx,
@@ -39,15 +286,1506 @@ impl Renderer {
             frm_ctx,
             pip_ctx,
 
+            scene: Scene::new(),
+            sprite_batch: SpriteBatch::new(),
+            sprite_material,
+            debug_lines: DebugLines::new(),
+            debug_line_material,
+            viewport: None,
+            scissor: None,
+            record_strategy: RecordStrategy::default(),
+            last_frame_stats: RenderStats::default(),
+            pipeline_statistics_enabled: false,
+            color_grading_lut: None,
+            color_grading: None,
+            fxaa,
+            wireframe_overlay,
+            object_data,
+            frame_data,
+            frame_data_buffer,
+            reverse_z: false,
+            multiview_frame_data: PerFrameMultiviewData::new(
+                [frame_data.viewproj; MULTIVIEW_VIEW_COUNT],
+                frame_data.near,
+                frame_data.far,
+            ),
+            multiview_frame_data_buffer,
+            downscale_oversized_textures: config.downscale_oversized_textures,
+            import_coordinate_system: config.import_coordinate_system,
+            frame_limiter: FrameLimiter::new(),
+            #[cfg(feature = "renderdoc")]
+            frame_capture: FrameCapture::new(),
             resize_requested: false,
         })
     }
 
+    /// Resize the swapchain and draw images to the window's size before the next `draw`, e.g.
+    /// after a winit resize event
     pub fn request_resize(&mut self) {
         self.resize_requested = true;
     }
 
+    /// Carry out a resize requested with `request_resize` or by the swapchain, sizing everything
+    /// to the window. `draw` calls this itself. Returns false while the window has no area, e.g.
+    /// when minimized, in which case the resize stays pending and nothing should be drawn.
+    pub fn handle_resize(&mut self) -> Result<bool> {
+        if !self.resize_requested {
+            return Ok(true);
+        }
+        let Some(target) = self.dev_ctx.target.as_ref() else {
+            // Headless draw images only change size through `resize`
+            self.resize_requested = false;
+            return Ok(true);
+        };
+
+        let size = target.window.inner_size();
+        if size.width == 0 || size.height == 0 {
+            return Ok(false);
+        }
+        self.resize(size.width, size.height)?;
+        Ok(true)
+    }
+
+    /// Release the window's surface and swapchain when the app is suspended. The device and all
+    /// resources stay alive; call `resume` with the new window afterwards. Drawing with `draw`
+    /// fails in between.
+    pub fn suspend(&mut self) -> Result<()> {
+        self.dev_ctx.destroy_target()
+    }
+
+    /// Recreate the surface and swapchain for `window` after `suspend`, and resize the draw images
+    /// to match it
+    pub fn resume(&mut self, window: Arc<winit::window::Window>) -> Result<()> {
+        let size = window.inner_size();
+        self.dev_ctx.recreate_target(window)?;
+        // The window may not have its final size yet, in which case a resize event follows
+        if size.width == 0 || size.height == 0 {
+            self.resize_requested = true;
+            return Ok(());
+        }
+        self.resize(size.width, size.height)
+    }
+
+    /// Resize to `width` x `height` without going through winit, for embedders that run their own
+    /// event loop. With a window, the swapchain is recreated at that size, clamped to what the
+    /// surface supports, and the draw images follow the swapchain. Headless, only the draw images
+    /// are recreated.
+    pub fn resize(&mut self, width: u32, height: u32) -> Result<()> {
+        if width == 0 || height == 0 {
+            return Err(eyre!("Cannot resize to {}x{}", width, height));
+        }
+
+        unsafe {
+            self.dev_ctx.device.logical.device_wait_idle()?;
+        }
+
+        let requested_extent = vk::Extent2D { width, height };
+        let draw_extent = match self.dev_ctx.target.as_mut() {
+            Some(target) => {
+                target.recreate_with_extent(
+                    Some(requested_extent),
+                    &self.dev_ctx.instance,
+                    &self.dev_ctx.device,
+                )?;
+                target.swapchain.swapchain_image_extent
+            }
+            None => requested_extent,
+        };
+        self.frm_ctx.resize(&self.dev_ctx, draw_extent)?;
+        if let Some(fxaa) = self.fxaa.as_mut() {
+            fxaa.resize(&self.dev_ctx, &self.frm_ctx.get_draw_color_images())?;
+        }
+        if let Some(color_grading) = self.color_grading.as_mut() {
+            color_grading.resize(&self.dev_ctx, &self.frm_ctx.get_draw_color_images())?;
+        }
+        self.resize_requested = false;
+
+        Ok(())
+    }
+
+    /// Draw the edges of every triangle in the scene over the shaded geometry, or stop with
+    /// `None`. The triangles are transformed with the same view-projection matrix as the scene.
+    pub fn set_wireframe_overlay(
+        &mut self,
+        wireframe_overlay: Option<WireframeOverlay>,
+    ) -> Result<()> {
+        match (wireframe_overlay, self.wireframe_overlay.as_mut()) {
+            (Some(settings), Some(pass)) => pass.set_settings(settings),
+            (Some(settings), None) => {
+                let frame_count = self.frm_ctx.get_draw_color_images().len();
+                let pass = WireframeOverlayPass::new(
+                    &self.dev_ctx,
+                    frame_count,
+                    settings,
+                    self.pip_ctx.get_cache(),
+                )?;
+                self.wireframe_overlay = Some(pass);
+            }
+            (None, Some(_)) => {
+                // Frames in flight may still read the pass's vertex buffers
+                unsafe {
+                    self.dev_ctx.device.logical.device_wait_idle()?;
+                }
+                self.wireframe_overlay = None;
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    pub fn get_wireframe_overlay(&self) -> Option<WireframeOverlay> {
+        self.wireframe_overlay.as_ref().map(|pass| pass.get_settings())
+    }
+
+    /// Switch the swapchain to the present mode for `preference`, e.g. to turn vsync off for
+    /// benchmarking. Falls back to `FIFO` with a warning if the surface doesn't support it.
+    pub fn set_present_mode(&mut self, preference: PresentModePreference) -> Result<()> {
+        self.dev_ctx.set_present_mode(Some(preference.into()))?;
+        // The rebuilt swapchain is sized to the window, which the draw images may not match yet
+        self.resize_requested = true;
+        Ok(())
+    }
+
+    /// Present mode the swapchain uses, or `None` without a presentation target
+    pub fn get_present_mode(&self) -> Option<vk::PresentModeKHR> {
+        self.dev_ctx.target.as_ref().map(|target| target.surface_present_mode)
+    }
+
+    /// Cap the frame rate of `draw` and `draw_to_image` by waiting before each frame, or remove
+    /// the cap with `None`. Mostly useful without vsync, e.g. with the `IMMEDIATE` present mode,
+    /// to save power in menus.
+    pub fn set_target_fps(&mut self, fps: Option<u32>) {
+        self.frame_limiter.set_target_fps(fps);
+    }
+
+    pub fn get_target_fps(&self) -> Option<u32> {
+        self.frame_limiter.get_target_fps()
+    }
+
+    /// Switch the window between windowed and fullscreen and request a resize for the new size
+    pub fn set_fullscreen(&mut self, mode: Option<FullscreenMode>) -> Result<()> {
+        self.dev_ctx.target
+            .as_ref()
+            .ok_or_eyre("Cannot go fullscreen without a presentation target")?
+            .set_fullscreen(mode)?;
+        self.resize_requested = true;
+        Ok(())
+    }
+
+    /// Scale factor of the window, or 1.0 without a presentation target
+    pub fn get_scale_factor(&self) -> f64 {
+        self.dev_ctx.target
+            .as_ref()
+            .map_or(1.0, |target| target.get_scale_factor())
+    }
+
+    /// `None` when windowed or without a presentation target
+    pub fn get_fullscreen(&self) -> Option<FullscreenMode> {
+        self.dev_ctx.target.as_ref()?.get_fullscreen()
+    }
+
+    /// Create a material from the bindless material factory. It can sample every texture added
+    /// before or after it.
+    pub fn create_material(&mut self) -> Result<MaterialHandle> {
+        let storage = &mut self.res_ctx.storage;
+        let mut material = storage.bindless_material_factory.create_material()?;
+        self.frame_data_buffer.write_descriptor(&mut material);
+        self.object_data.write_descriptor(&mut material);
+        Ok(storage.add_material(material))
+    }
+
+    /// Set the per-material data that the material's shaders read from binding 1, e.g. a
+    /// `PerMaterialData` for materials from `create_material`. It is uploaded when the next frame
+    /// is recorded, so frames in flight keep drawing with the old data.
+    pub fn set_material_data<T: bytemuck::Pod>(
+        &mut self,
+        material: MaterialHandle,
+        data: &T,
+    ) -> Result<()> {
+        self.res_ctx.storage.materials
+            .get_mut(material.0)
+            .ok_or_eyre(format!("Material {:?} does not exist", material))?
+            .set_material_data(data)
+    }
+
+    /// Add a material built by the caller, e.g. from a `GraphicsMaterialFactoryBuilder` using the
+    /// layouts of `get_material_factory` so it stays compatible with the bindless resources
+    pub fn add_material(&mut self, mut material: Material) -> MaterialHandle {
+        self.frame_data_buffer.write_descriptor(&mut material);
+        self.object_data.write_descriptor(&mut material);
+        self.res_ctx.storage.add_material(material)
+    }
+
+    /// The bindless material factory that `create_material` uses
+    pub fn get_material_factory(&self) -> &MaterialFactory {
+        &self.res_ctx.storage.bindless_material_factory
+    }
+
+    /// Create a material that renders every view in a single multiview pass, reading its matrices
+    /// from `set_multiview_frame_data`. Instances using it are drawn by
+    /// `draw_multiview_to_images`.
+    pub fn create_multiview_material(&mut self) -> Result<MaterialHandle> {
+        let storage = &mut self.res_ctx.storage;
+        let mut material = storage.multiview_material_factory.create_material()?;
+        self.multiview_frame_data_buffer.write_descriptor(&mut material);
+        self.object_data.write_descriptor(&mut material);
+        Ok(storage.add_material(material))
+    }
+
+    /// Upload an image as a texture read with the default linear, repeating sampler. The handle is
+    /// also the texture's index into the bindless texture array, which is what sprites refer to.
+    ///
+    /// Fails if the image is larger than the device's maximum image dimension, unless
+    /// `RenderConfig::downscale_oversized_textures` is set.
+    pub fn add_texture(&mut self, image: &image::DynamicImage) -> Result<TextureHandle> {
+        self.add_texture_with_sampler(image, &SamplerDesc::default())
+    }
+
+    /// Like `add_texture`, but read with the sampler described by `sampler`. Its index in the
+    /// bindless sampler table is available from `get_texture_sampler_index`.
+    pub fn add_texture_with_sampler(
+        &mut self,
+        image: &image::DynamicImage,
+        sampler: &SamplerDesc,
+    ) -> Result<TextureHandle> {
+        let (width, height) = (image.width(), image.height());
+        let max_dimension = self.dev_ctx.device.get_max_image_dimension_2d();
+        let downscaled;
+        let image = if self.downscale_oversized_textures
+            && (width > max_dimension || height > max_dimension)
+        {
+            // Keeps the aspect ratio
+            downscaled = image.resize(max_dimension, max_dimension, FilterType::Triangle);
+            log::warn!(
+                "Downscaled texture from {}x{} to {}x{} to fit the device's maximum dimension",
+                width,
+                height,
+                downscaled.width(),
+                downscaled.height(),
+            );
+            &downscaled
+        } else {
+            image
+        };
+        let data = image.to_rgba8().into_raw();
+        let image = self.dev_ctx.device.create_color_image(
+            image.width(),
+            image.height(),
+            Some(&data),
+            false,
+        )?;
+        let texture = ColorTexture {
+            image,
+            sampler_index: 0,
+        };
+        let handle = self.res_ctx.storage.register_texture(texture, sampler, &self.dev_ctx.device)?;
+        // Lets sprites refer to atlas regions in pixels, of the image as given even if it was
+        // downscaled
+        self.sprite_batch.set_texture_size(handle, width, height);
+        Ok(handle)
+    }
+
+    /// Shrink textures added from now on that are too large for the device, see
+    /// `RenderConfig::downscale_oversized_textures`
+    pub fn set_downscale_oversized_textures(&mut self, downscale: bool) {
+        self.downscale_oversized_textures = downscale;
+    }
+
+    /// Coordinate system of the models added from now on, see
+    /// `RenderConfig::import_coordinate_system`
+    pub fn set_import_coordinate_system(&mut self, coordinate_system: CoordinateSystem) {
+        self.import_coordinate_system = coordinate_system;
+    }
+
+    pub fn get_import_coordinate_system(&self) -> CoordinateSystem {
+        self.import_coordinate_system
+    }
+
+    /// Index into the bindless sampler table of the sampler the texture was added with, for
+    /// `PerMaterialData::sampler_index`
+    pub fn get_texture_sampler_index(&self, handle: TextureHandle) -> Option<u32> {
+        self.res_ctx.storage.sampled_images
+            .get(handle.0)
+            .map(ColorTexture::get_sampler_index)
+    }
+
+    /// Create a layered texture with `layers` empty layers of the same size. Fill them with
+    /// `upload_array_texture_layer`, and bind it to a material with `write_array_texture`.
+    pub fn add_array_texture(
+        &mut self,
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: vk::Format,
+    ) -> Result<ArrayTextureHandle> {
+        let texture = self.dev_ctx.device.create_array_texture(width, height, layers, format)?;
+        let storage = &mut self.res_ctx.storage;
+        storage.array_textures.push(texture);
+        Ok(ArrayTextureHandle(storage.array_textures.len() - 1))
+    }
+
+    /// Replace the contents of one layer with `data`, which must hold exactly one layer of
+    /// tightly packed texels. Waits for the device to be idle first, since frames in flight may
+    /// still be sampling the texture.
+    pub fn upload_array_texture_layer(
+        &mut self,
+        texture: ArrayTextureHandle,
+        index: u32,
+        data: &[u8],
+    ) -> Result<()> {
+        let texture = self.res_ctx.storage.array_textures
+            .get_mut(texture.0)
+            .ok_or_eyre(format!("Array texture {:?} does not exist", texture))?;
+        let device = &self.dev_ctx.device;
+        unsafe {
+            device.logical.device_wait_idle()?;
+        }
+        texture.upload_layer(index, data, device.get_transfer_context())
+    }
+
+    /// Point `binding` of `material`'s descriptor set, a `texture2DArray` read with a separate
+    /// sampler, at the whole array texture. The bindless layout of `create_material` has no such
+    /// binding, so this is for materials added with `add_material` from a layout that does.
+    pub fn write_array_texture(
+        &mut self,
+        material: MaterialHandle,
+        binding: u32,
+        texture: ArrayTextureHandle,
+    ) -> Result<()> {
+        let storage = &mut self.res_ctx.storage;
+        let texture = storage.array_textures
+            .get(texture.0)
+            .ok_or_eyre(format!("Array texture {:?} does not exist", texture))?;
+        storage.materials
+            .get_mut(material.0)
+            .ok_or_eyre(format!("Material {:?} does not exist", material))?
+            .write_sampled_image(binding, 0, texture.image.view, None);
+        Ok(())
+    }
+
+    /// Load a color grading LUT from a `.cube` file into a 3D texture
+    pub fn add_color_grading_lut(&mut self, path: impl AsRef<Path>) -> Result<LutHandle> {
+        let lut = ColorGradingLut::load(path.as_ref(), &self.dev_ctx.device)?;
+        let storage = &mut self.res_ctx.storage;
+        storage.color_grading_luts.push(lut);
+        Ok(LutHandle(storage.color_grading_luts.len() - 1))
+    }
+
+    /// LUT to grade the final image with, after anti-aliasing, or `None` to leave colors as they
+    /// are
+    pub fn set_color_grading_lut(&mut self, lut: Option<LutHandle>) -> Result<()> {
+        if let Some(lut) = lut {
+            if lut.0 >= self.res_ctx.storage.color_grading_luts.len() {
+                return Err(eyre!("Color grading LUT {:?} does not exist", lut));
+            }
+            if self.color_grading.is_none() {
+                self.color_grading = Some(ColorGradingPass::new(
+                    &self.dev_ctx,
+                    &self.frm_ctx.get_draw_color_images(),
+                    self.pip_ctx.get_cache(),
+                )?);
+            }
+        }
+        self.color_grading_lut = lut;
+        Ok(())
+    }
+
+    pub fn get_color_grading_lut(&self) -> Option<LutHandle> {
+        self.color_grading_lut
+    }
+
+    /// Upload `meshes` into the vertex and index megabuffers and add them to the scene as a model,
+    /// converted from `RenderConfig::import_coordinate_system`
+    pub fn add_model(&mut self, mut meshes: Vec<Mesh>) -> Result<ModelHandle> {
+        if self.import_coordinate_system != CoordinateSystem::default() {
+            let conversion = self.import_coordinate_system.get_conversion_matrix();
+            for mesh in meshes.iter_mut() {
+                mesh.transform(conversion);
+            }
+        }
+        let storage = &self.res_ctx.storage;
+        let model = Model::new(
+            meshes,
+            &storage.vertex_megabuffer,
+            &storage.index_megabuffer,
+            &storage.id_allocator,
+        )?;
+        storage.vertex_megabuffer.upload()?;
+        storage.index_megabuffer.upload()?;
+        Ok(self.scene.add_model(model))
+    }
+
+    /// Close the gaps that removed and rewritten models left in the vertex and index megabuffers,
+    /// see `Megabuffer::compact`. Waits for the device to be idle.
+    pub fn compact_megabuffers(&mut self) -> Result<()> {
+        let storage = &self.res_ctx.storage;
+        for megabuffer in [&storage.vertex_megabuffer, &storage.index_megabuffer] {
+            let remap = megabuffer.compact()?;
+            self.scene.apply_megabuffer_remap(&remap);
+        }
+        Ok(())
+    }
+
+    pub fn add_instance(
+        &mut self,
+        model: ModelHandle,
+        material: MaterialHandle,
+        transform: Transform,
+    ) -> InstanceHandle {
+        self.scene.add_instance(model, material, transform)
+    }
+
+    /// Passes recorded every frame before the scene is drawn, outside of rendering, in the order
+    /// their dependencies need and with the barriers between them
+    pub fn get_render_graph_mut(&mut self) -> &mut RenderGraph {
+        self.grp_ctx.get_graph_mut()
+    }
+
+    /// Access to the device for work outside the frame loop, such as async compute through
+    /// `RenderDevice::compute_immediate_submit` or submitting to the raw queues directly
+    pub fn get_device(&self) -> &RenderDevice {
+        &self.dev_ctx.device
+    }
+
+    /// Draw into part of the draw image from the next frame on, e.g. one half for split-screen.
+    /// `None` covers the whole image again.
+    pub fn set_viewport(&mut self, viewport: Option<vk::Rect2D>) -> Result<()> {
+        if let Some(viewport) = viewport {
+            check_rect_fits(viewport, self.frm_ctx.get_draw_extent())?;
+        }
+        self.viewport = viewport;
+        Ok(())
+    }
+
+    /// Only touch the pixels inside the scissor rect from the next frame on. `None` covers the
+    /// whole draw image again.
+    pub fn set_scissor(&mut self, scissor: Option<vk::Rect2D>) -> Result<()> {
+        if let Some(scissor) = scissor {
+            check_rect_fits(scissor, self.frm_ctx.get_draw_extent())?;
+        }
+        self.scissor = scissor;
+        Ok(())
+    }
+
+    pub fn get_viewport(&self) -> Option<vk::Rect2D> {
+        self.viewport
+    }
+
+    pub fn get_scissor(&self) -> Option<vk::Rect2D> {
+        self.scissor
+    }
+
+    /// The viewport and scissor to record with. A rect that no longer fits the draw images, for
+    /// example after they were resized, falls back to covering them entirely.
+    fn get_viewport_and_scissor(&self) -> (vk::Rect2D, vk::Rect2D) {
+        let extent = self.frm_ctx.get_draw_extent();
+        let full = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        let fit = |rect: Option<vk::Rect2D>| {
+            rect
+                .filter(|rect| check_rect_fits(*rect, extent).is_ok())
+                .unwrap_or(full)
+        };
+        (fit(self.viewport), fit(self.scissor))
+    }
+
+    /// View the scene through `camera` from the next frame on. The aspect ratio is taken from the
+    /// viewport, so call it again after resizing or changing the viewport.
+    pub fn set_camera(&mut self, camera: &Camera) {
+        let (viewport, _) = self.get_viewport_and_scissor();
+        self.frame_data = camera.get_frame_data(
+            viewport.extent.width as f32,
+            viewport.extent.height as f32,
+        );
+        // The 2D projection keeps depth running from 0 at the near plane
+        self.reverse_z = camera.get_reverse_z() && camera.get_2d_mode().is_none();
+    }
+
+    /// What materials read from the per-frame uniform buffer at binding 0
+    pub fn set_frame_data(&mut self, frame_data: PerFrameData) {
+        self.frame_data = frame_data;
+    }
+
+    pub fn get_frame_data(&self) -> PerFrameData {
+        self.frame_data
+    }
+
+    /// Clear depth to 0 and keep the fragments with the greatest depth, for matrices that map the
+    /// near plane to depth 1. `set_camera` takes it from `Camera::get_reverse_z`, so it only has
+    /// to be set along with `set_frame_data`.
+    pub fn set_reverse_z(&mut self, reverse_z: bool) {
+        self.reverse_z = reverse_z;
+    }
+
+    pub fn get_reverse_z(&self) -> bool {
+        self.reverse_z
+    }
+
+    /// What multiview materials read from their per-frame uniform buffer at binding 0, e.g. the
+    /// view-projection matrix of each eye
+    pub fn set_multiview_frame_data(&mut self, frame_data: PerFrameMultiviewData) {
+        self.multiview_frame_data = frame_data;
+    }
+
+    pub fn get_multiview_frame_data(&self) -> PerFrameMultiviewData {
+        self.multiview_frame_data
+    }
+
+    pub fn set_record_strategy(&mut self, record_strategy: RecordStrategy) {
+        self.record_strategy = record_strategy;
+    }
+
+    pub fn get_record_strategy(&self) -> RecordStrategy {
+        self.record_strategy
+    }
+
+    /// Sprites drawn over the scene every frame until the batch is cleared
+    pub fn get_sprite_batch_mut(&mut self) -> &mut SpriteBatch {
+        &mut self.sprite_batch
+    }
+
+    /// Lines drawn over the scene every frame until they are cleared
+    pub fn get_debug_lines_mut(&mut self) -> &mut DebugLines {
+        &mut self.debug_lines
+    }
+
+    /// Draw the normal (blue), tangent (red) and bitangent (green) of every vertex in the scene as
+    /// lines of `length` world units, over the scene
+    pub fn set_normal_debug(&mut self, enabled: bool, length: f32) {
+        self.debug_lines.set_normal_length(enabled.then_some(length));
+    }
+
+    /// Counters for the work submitted by the last `draw` or `draw_to_image`
+    pub fn get_last_frame_stats(&self) -> RenderStats {
+        self.last_frame_stats
+    }
+
+    /// Measure the scene pass with a pipeline statistics query, reported in
+    /// `RenderStats::pipeline_statistics`. Only draws recorded with `RecordStrategy::PerFrame` are
+    /// measured, since a query can't stay active across secondary command buffers without the
+    /// `inherited_queries` feature.
+    pub fn set_pipeline_statistics_enabled(&mut self, enabled: bool) -> Result<()> {
+        if enabled && self.dev_ctx.device.get_features().pipeline_statistics_query != vk::TRUE {
+            return Err(eyre!("Pipeline statistics queries are not supported by the device"));
+        }
+        self.pipeline_statistics_enabled = enabled;
+        Ok(())
+    }
+
+    pub fn get_pipeline_statistics_enabled(&self) -> bool {
+        self.pipeline_statistics_enabled
+    }
+
+    /// Limits of the device, like the maximum push constant size, bound descriptor sets, texture
+    /// size, and buffer offset alignments
+    pub fn get_device_limits(&self) -> &vk::PhysicalDeviceLimits {
+        self.dev_ctx.device.get_limits()
+    }
+
+    pub fn get_scene(&self) -> &Scene {
+        &self.scene
+    }
+
+    pub fn get_scene_mut(&mut self) -> &mut Scene {
+        &mut self.scene
+    }
+
+    /// Capture the next `draw` or `draw_to_image` in RenderDoc. Does nothing unless the app is
+    /// running under RenderDoc.
+    #[cfg(feature = "renderdoc")]
+    pub fn capture_next_frame(&mut self) {
+        self.frame_capture.request_capture();
+    }
+
+    /// Wrap one frame in a RenderDoc capture if `capture_next_frame` was called
+    fn with_frame_capture(&mut self, draw: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        #[cfg(feature = "renderdoc")]
+        let capturing = self.frame_capture.begin_frame();
+        let result = draw(self);
+        #[cfg(feature = "renderdoc")]
+        if capturing {
+            self.frame_capture.end_frame();
+        }
+        result
+    }
+
+    /// Draw the scene and copy it into an image owned by someone else, such as an XR swapchain
+    /// image, leaving it in `dst_final_layout`. Nothing is presented, and the work is submitted to
+    /// the graphics queue without semaphores.
+    pub fn draw_to_image(
+        &mut self,
+        dst_image: vk::Image,
+        dst_extent: vk::Extent2D,
+        dst_final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        self.with_frame_capture(|renderer| {
+            renderer.record_and_submit_to_image(dst_image, dst_extent, dst_final_layout)
+        })
+    }
+
+    fn record_and_submit_to_image(
+        &mut self,
+        dst_image: vk::Image,
+        dst_extent: vk::Extent2D,
+        dst_final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        #[cfg(feature = "hot-reload")]
+        self.reload_changed_shaders()?;
+        self.frame_limiter.wait();
+        self.reserve_object_data()?;
+        let device = self.dev_ctx.device.logical.clone();
+        let graphics_queue = self.dev_ctx.device.graphics_queue.clone();
+        let (viewport, scissor) = self.get_viewport_and_scissor();
+        let frame_index = self.frm_ctx.get_frame_index();
+        let frame = self.frm_ctx.current_frame_mut();
+
+        unsafe {
+            device.wait_for_fences(&[frame.render_fence], true, u64::MAX)?;
+        }
+        let pipeline_statistics = take_pipeline_statistics(frame)?;
+
+        let cmd = &mut frame.command_encoder;
+        cmd.set_reverse_z(self.reverse_z);
+        cmd.begin_recording()?;
+
+        cmd.transition_image_layout(
+            &mut frame.draw_color_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        cmd.transition_image_layout(
+            &mut frame.draw_depth_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+        );
+        let objects_uploaded = self.object_data.record_upload(cmd, frame_index, &self.scene)?;
+        self.frame_data_buffer.record_upload(cmd, &self.frame_data);
+        record_material_data_uploads(cmd, &mut self.res_ctx.storage.materials)?;
+        let storage = &self.res_ctx.storage;
+        self.grp_ctx.execute(cmd)?;
+
+        let mut stats = record_scene_pass(
+            frame,
+            &self.scene,
+            storage,
+            &self.dev_ctx.device,
+            ScenePassSettings {
+                record_strategy: self.record_strategy,
+                collect_pipeline_statistics: self.pipeline_statistics_enabled,
+                viewport,
+                scissor,
+            },
+        )?;
+        stats.pipeline_statistics = pipeline_statistics;
+        stats.objects_uploaded = objects_uploaded;
+        if let Some(wireframe_overlay) = self.wireframe_overlay.as_mut() {
+            stats += wireframe_overlay.record(
+                frame,
+                frame_index,
+                &self.scene,
+                self.frame_data.viewproj,
+                viewport,
+                scissor,
+            )?;
+        }
+        stats += record_sprite_pass(
+            frame,
+            &self.sprite_batch,
+            storage,
+            self.sprite_material,
+            viewport,
+            scissor,
+        )?;
+        stats += record_debug_line_pass(
+            frame,
+            self.debug_lines.build_vertices(&self.scene, self.frame_data.viewproj),
+            storage,
+            self.debug_line_material,
+            viewport,
+            scissor,
+        )?;
+        let cmd = &mut frame.command_encoder;
+        let output_image = match self.fxaa.as_mut() {
+            Some(fxaa) => fxaa.record(cmd, frame_index, &mut frame.draw_color_image),
+            None => &mut frame.draw_color_image,
+        };
+        let output_image = match (self.color_grading.as_mut(), self.color_grading_lut) {
+            (Some(color_grading), Some(lut)) => {
+                let lut = &storage.color_grading_luts[lut.0];
+                color_grading.record(cmd, frame_index, output_image, lut)
+            }
+            _ => output_image,
+        };
+
+        cmd.transition_image(output_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        cmd.transition_vkimage_layout(
+            dst_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        cmd.copy_image_to_vkimage(
+            output_image,
+            dst_image,
+            dst_extent,
+        );
+        cmd.transition_vkimage_layout(
+            dst_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            dst_final_layout,
+        );
+
+        cmd.end_recording()?;
+
+        let command_buffers = [cmd.command_buffer];
+        let submit = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers);
+        // Reset the fence right before submitting, so that an error while recording leaves it
+        // signaled and the next wait on it doesn't hang
+        unsafe {
+            device.reset_fences(&[frame.render_fence])?;
+            device.queue_submit(graphics_queue.handle, &[submit], frame.render_fence)?;
+        }
+        self.object_data.mark_submitted();
+        self.frame_data_buffer.mark_submitted();
+
+        self.last_frame_stats = stats;
+        self.frm_ctx.advance();
+
+        Ok(())
+    }
+
+    /// Draw the scene once for every view in a single multiview pass and copy each view into the
+    /// matching image of `dst_images`, such as the per-eye XR swapchain images, leaving them in
+    /// `dst_final_layout`. Every instance in the scene must use a material from
+    /// `create_multiview_material`, and sprites, debug lines and post-processing are skipped.
+    pub fn draw_multiview_to_images(
+        &mut self,
+        dst_images: [vk::Image; MULTIVIEW_VIEW_COUNT],
+        dst_extent: vk::Extent2D,
+        dst_final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        self.with_frame_capture(|renderer| {
+            renderer.record_and_submit_multiview(dst_images, dst_extent, dst_final_layout)
+        })
+    }
+
+    fn record_and_submit_multiview(
+        &mut self,
+        dst_images: [vk::Image; MULTIVIEW_VIEW_COUNT],
+        dst_extent: vk::Extent2D,
+        dst_final_layout: vk::ImageLayout,
+    ) -> Result<()> {
+        #[cfg(feature = "hot-reload")]
+        self.reload_changed_shaders()?;
+        self.frame_limiter.wait();
+        self.reserve_object_data()?;
+        let device = self.dev_ctx.device.logical.clone();
+        let graphics_queue = self.dev_ctx.device.graphics_queue.clone();
+        let (viewport, scissor) = self.get_viewport_and_scissor();
+        let frame_index = self.frm_ctx.get_frame_index();
+        let frame = self.frm_ctx.current_frame_mut();
+
+        unsafe {
+            device.wait_for_fences(&[frame.render_fence], true, u64::MAX)?;
+        }
+        let pipeline_statistics = take_pipeline_statistics(frame)?;
+        let (color_image, depth_image) = match frame.multiview_images.as_mut() {
+            Some(images) => images,
+            None => {
+                let extent = frame.draw_color_image.extent;
+                let view_count = MULTIVIEW_VIEW_COUNT as u32;
+                frame.multiview_images.insert((
+                    self.dev_ctx.device
+                        .create_layered_draw_image(extent.width, extent.height, view_count)?,
+                    self.dev_ctx.device
+                        .create_layered_depth_image(extent.width, extent.height, view_count)?,
+                ))
+            }
+        };
+        let cmd = &mut frame.command_encoder;
+        cmd.set_reverse_z(self.reverse_z);
+        cmd.begin_recording()?;
+
+        cmd.transition_image_layout(
+            color_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        cmd.transition_image_layout(
+            depth_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+        );
+        let objects_uploaded = self.object_data.record_upload(cmd, frame_index, &self.scene)?;
+        self.multiview_frame_data_buffer.record_upload(cmd, &self.multiview_frame_data);
+        record_material_data_uploads(cmd, &mut self.res_ctx.storage.materials)?;
+        let storage = &self.res_ctx.storage;
+        self.grp_ctx.execute(cmd)?;
+
+        cmd.begin_multiview_rendering(
+            (color_image, Self::MAIN_PASS_COLOR_OPS),
+            (depth_image, Self::MAIN_PASS_DEPTH_OPS),
+            Self::CLEAR_COLOR,
+            vk::RenderingFlags::empty(),
+            (1 << MULTIVIEW_VIEW_COUNT) - 1,
+        );
+        cmd.set_viewport_and_scissor_rects(viewport, scissor);
+        let mut stats = self.scene.record_draws(
+            cmd,
+            &storage.materials,
+            storage.vertex_megabuffer.get_buffer()?,
+            storage.index_megabuffer.get_buffer()?,
+        )?;
+        cmd.end_rendering();
+        stats.pipeline_statistics = pipeline_statistics;
+        stats.objects_uploaded = objects_uploaded;
+
+        cmd.transition_image(color_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        for (view, dst_image) in dst_images.into_iter().enumerate() {
+            cmd.transition_vkimage_layout(
+                dst_image,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            );
+            cmd.copy_image_layer_to_vkimage(color_image, view as u32, dst_image, dst_extent);
+            cmd.transition_vkimage_layout(
+                dst_image,
+                vk::ImageAspectFlags::COLOR,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                dst_final_layout,
+            );
+        }
+
+        cmd.end_recording()?;
+
+        let command_buffers = [cmd.command_buffer];
+        let submit = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers);
+        // Reset the fence right before submitting, so that an error while recording leaves it
+        // signaled and the next wait on it doesn't hang
+        unsafe {
+            device.reset_fences(&[frame.render_fence])?;
+            device.queue_submit(graphics_queue.handle, &[submit], frame.render_fence)?;
+        }
+        self.object_data.mark_submitted();
+        self.multiview_frame_data_buffer.mark_submitted();
+
+        self.last_frame_stats = stats;
+        self.frm_ctx.advance();
+
+        Ok(())
+    }
+
+    /// Draw the scene and present it to the window. Fails on a headless renderer, which has to use
+    /// `draw_to_image` instead.
     pub fn draw(&mut self) -> Result<()> {
+        self.with_frame_capture(Self::record_and_present)
+    }
+
+    fn record_and_present(&mut self) -> Result<()> {
+        if !self.handle_resize()? {
+            return Ok(());
+        }
+        #[cfg(feature = "hot-reload")]
+        self.reload_changed_shaders()?;
+        self.frame_limiter.wait();
+        self.reserve_object_data()?;
+        let device = self.dev_ctx.device.logical.clone();
+        let graphics_queue = self.dev_ctx.device.graphics_queue.clone();
+        let target = self.dev_ctx.target
+            .as_ref()
+            .ok_or_eyre("Renderer was created without a window; use draw_to_image for headless rendering")?;
+        let swapchain = &target.swapchain;
+        let (viewport, scissor) = self.get_viewport_and_scissor();
+        let frame_index = self.frm_ctx.get_frame_index();
+        let frame = self.frm_ctx.current_frame_mut();
+
+        unsafe {
+            device.wait_for_fences(&[frame.render_fence], true, u64::MAX)?;
+        }
+        let pipeline_statistics = take_pipeline_statistics(frame)?;
+
+        let image_index = match swapchain.acquire_next_image(frame.present_semaphore) {
+            Ok((image_index, _suboptimal)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.resize_requested = true;
+                return Ok(());
+            }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => return self.recreate_lost_surface(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let swapchain_image = swapchain.swapchain_images[image_index as usize];
+        let cmd = &mut frame.command_encoder;
+        cmd.set_reverse_z(self.reverse_z);
+        cmd.begin_recording()?;
+
+        cmd.transition_image_layout(
+            &mut frame.draw_color_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        );
+        cmd.transition_image_layout(
+            &mut frame.draw_depth_image,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+        );
+        let objects_uploaded = self.object_data.record_upload(cmd, frame_index, &self.scene)?;
+        self.frame_data_buffer.record_upload(cmd, &self.frame_data);
+        record_material_data_uploads(cmd, &mut self.res_ctx.storage.materials)?;
+        let storage = &self.res_ctx.storage;
+        self.grp_ctx.execute(cmd)?;
+
+        let mut stats = record_scene_pass(
+            frame,
+            &self.scene,
+            storage,
+            &self.dev_ctx.device,
+            ScenePassSettings {
+                record_strategy: self.record_strategy,
+                collect_pipeline_statistics: self.pipeline_statistics_enabled,
+                viewport,
+                scissor,
+            },
+        )?;
+        stats.pipeline_statistics = pipeline_statistics;
+        stats.objects_uploaded = objects_uploaded;
+        if let Some(wireframe_overlay) = self.wireframe_overlay.as_mut() {
+            stats += wireframe_overlay.record(
+                frame,
+                frame_index,
+                &self.scene,
+                self.frame_data.viewproj,
+                viewport,
+                scissor,
+            )?;
+        }
+        stats += record_sprite_pass(
+            frame,
+            &self.sprite_batch,
+            storage,
+            self.sprite_material,
+            viewport,
+            scissor,
+        )?;
+        stats += record_debug_line_pass(
+            frame,
+            self.debug_lines.build_vertices(&self.scene, self.frame_data.viewproj),
+            storage,
+            self.debug_line_material,
+            viewport,
+            scissor,
+        )?;
+        let cmd = &mut frame.command_encoder;
+        let output_image = match self.fxaa.as_mut() {
+            Some(fxaa) => fxaa.record(cmd, frame_index, &mut frame.draw_color_image),
+            None => &mut frame.draw_color_image,
+        };
+        let output_image = match (self.color_grading.as_mut(), self.color_grading_lut) {
+            (Some(color_grading), Some(lut)) => {
+                let lut = &storage.color_grading_luts[lut.0];
+                color_grading.record(cmd, frame_index, output_image, lut)
+            }
+            _ => output_image,
+        };
+
+        // Copy the finished image into the swapchain image
+        cmd.transition_image(output_image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        cmd.transition_vkimage_layout(
+            swapchain_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::UNDEFINED,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        );
+        cmd.copy_image_to_vkimage(
+            output_image,
+            swapchain_image,
+            swapchain.swapchain_image_extent,
+        );
+        cmd.transition_vkimage_layout(
+            swapchain_image,
+            vk::ImageAspectFlags::COLOR,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        );
+
+        cmd.end_recording()?;
+
+        let command_buffers = [cmd.command_buffer];
+        let wait_semaphores = [frame.present_semaphore];
+        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let signal_semaphores = [frame.render_semaphore];
+        let submit = vk::SubmitInfo::default()
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(&signal_semaphores);
+        // Reset the fence right before submitting, so that an error while recording leaves it
+        // signaled and the next wait on it doesn't hang
+        unsafe {
+            device.reset_fences(&[frame.render_fence])?;
+            device.queue_submit(graphics_queue.handle, &[submit], frame.render_fence)?;
+        }
+        self.object_data.mark_submitted();
+        self.frame_data_buffer.mark_submitted();
+
+        // The render semaphore is waited on even when presenting fails with one of these errors
+        let mut surface_lost = false;
+        match swapchain.present(graphics_queue.handle, image_index, frame.render_semaphore) {
+            Ok(false) => {}
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.resize_requested = true;
+            }
+            Err(vk::Result::ERROR_SURFACE_LOST_KHR) => surface_lost = true,
+            Err(e) => return Err(e.into()),
+        }
+
+        self.last_frame_stats = stats;
+        self.frm_ctx.advance();
+
+        if surface_lost {
+            self.recreate_lost_surface()?;
+        }
+
         Ok(())
     }
+
+    /// Rebuild the pipelines of the shaders the shader watcher recompiled. This runs between
+    /// frames, so every material switches to a new pipeline at the same frame. Pipelines that
+    /// fail to build keep the old ones, with an error logged. Only the built-in factories and
+    /// passes are rebuilt; materials from factories made outside the renderer keep their pipeline.
+    #[cfg(feature = "hot-reload")]
+    fn reload_changed_shaders(&mut self) -> Result<()> {
+        let shader_names = self.pip_ctx.poll_reloaded_shaders();
+        if shader_names.is_empty() {
+            return Ok(());
+        }
+        // Frames in flight may still use the old pipelines
+        unsafe {
+            self.dev_ctx.device.logical.device_wait_idle()?;
+        }
+
+        let pipeline_cache = self.pip_ctx.get_cache();
+        self.res_ctx.storage.reload_shaders(&shader_names, &self.dev_ctx.device, pipeline_cache);
+        let reloaded = |name: &str| shader_names.iter().any(|shader_name| shader_name == name);
+        // The passes own more than their pipeline, but recreating them whole is cheap
+        if self.fxaa.is_some() && reloaded("fxaa") {
+            let draw_images = self.frm_ctx.get_draw_color_images();
+            match FxaaPass::new(&self.dev_ctx, &draw_images, pipeline_cache) {
+                Ok(fxaa) => {
+                    self.fxaa = Some(fxaa);
+                }
+                Err(e) => log::error!(
+                    "Failed to rebuild the FXAA pass, keeping the old one: {:?}",
+                    e,
+                ),
+            }
+        }
+        if self.color_grading.is_some() && reloaded("color_grading") {
+            let draw_images = self.frm_ctx.get_draw_color_images();
+            match ColorGradingPass::new(&self.dev_ctx, &draw_images, pipeline_cache) {
+                Ok(color_grading) => self.color_grading = Some(color_grading),
+                Err(e) => log::error!(
+                    "Failed to rebuild the color grading pass, keeping the old one: {:?}",
+                    e,
+                ),
+            }
+        }
+        if let Some(wireframe_overlay) = self.wireframe_overlay.as_ref()
+            && reloaded("wireframe_overlay")
+        {
+            let frame_count = self.frm_ctx.get_draw_color_images().len();
+            let settings = wireframe_overlay.get_settings();
+            match WireframeOverlayPass::new(&self.dev_ctx, frame_count, settings, pipeline_cache) {
+                Ok(wireframe_overlay) => {
+                    self.wireframe_overlay = Some(wireframe_overlay);
+                }
+                Err(e) => log::error!(
+                    "Failed to rebuild the wireframe overlay pass, keeping the old one: {:?}",
+                    e,
+                ),
+            }
+        }
+        // Cached secondary command buffers still bind the old pipelines
+        self.frm_ctx.invalidate_cached_draws();
+        Ok(())
+    }
+
+    /// Grow the per-object buffer to fit every instance handle, pointing the materials at the new
+    /// buffer when it was replaced
+    fn reserve_object_data(&mut self) -> Result<()> {
+        let handle_count = self.scene.get_handle_count();
+        if self.object_data.reserve(&self.dev_ctx, handle_count)? {
+            for material in self.res_ctx.storage.materials.iter_mut() {
+                self.object_data.write_descriptor(material);
+            }
+            // Cached draws stay valid, since the bindless bindings are update-after-bind
+        }
+        Ok(())
+    }
+
+    /// Replace a surface the platform invalidated, e.g. after a display was unplugged or the
+    /// system slept, with a new one for the same window, along with its swapchain
+    fn recreate_lost_surface(&mut self) -> Result<()> {
+        log::warn!("Surface was lost; recreating it and the swapchain");
+        let window = self.dev_ctx.target
+            .as_ref()
+            .ok_or_eyre("Cannot recreate the surface without a presentation target")?
+            .window
+            .clone();
+        self.dev_ctx.recreate_target(window)?;
+        // The new swapchain may not match the draw images
+        self.resize_requested = true;
+        Ok(())
+    }
+}
+
+/// How `record_scene_pass` records the scene and where it draws it
+#[derive(Debug, Clone, Copy)]
+struct ScenePassSettings {
+    record_strategy: RecordStrategy,
+    collect_pipeline_statistics: bool,
+    viewport: vk::Rect2D,
+    scissor: vk::Rect2D,
+}
+
+/// Record the scene into the frame's draw images with the given strategy. The frame's command
+/// encoder must be recording and the draw images must be in attachment layouts. Returns the
+/// counters for the scene draws.
+fn record_scene_pass(
+    frame: &mut Frame,
+    scene: &Scene,
+    storage: &RenderResourceStorage,
+    device: &RenderDevice,
+    settings: ScenePassSettings,
+) -> Result<RenderStats> {
+    let ScenePassSettings {
+        record_strategy,
+        collect_pipeline_statistics,
+        viewport,
+        scissor,
+    } = settings;
+    let record_start = Instant::now();
+    let cmd = &mut frame.command_encoder;
+    // Secondary command buffers record with the same depth direction
+    let reverse_z = cmd.get_reverse_z();
+    let mut stats = match record_strategy {
+        RecordStrategy::PerFrame => {
+            let mut query = frame.pipeline_statistics_query
+                .as_mut()
+                .filter(|_| collect_pipeline_statistics);
+            if let Some(query) = query.as_deref_mut() {
+                query.begin(cmd);
+            }
+            cmd.begin_rendering(
+                (&frame.draw_color_image, Renderer::MAIN_PASS_COLOR_OPS),
+                (&frame.draw_depth_image, Renderer::MAIN_PASS_DEPTH_OPS),
+                Renderer::CLEAR_COLOR,
+                vk::RenderingFlags::empty(),
+            );
+            cmd.set_viewport_and_scissor_rects(viewport, scissor);
+            let stats = scene.record_draws(
+                cmd,
+                &storage.materials,
+                storage.vertex_megabuffer.get_buffer()?,
+                storage.index_megabuffer.get_buffer()?,
+            )?;
+            cmd.end_rendering();
+            if let Some(query) = query {
+                query.end(cmd);
+            }
+            stats
+        }
+        RecordStrategy::Cached => {
+            let key = CachedDrawsKey {
+                scene_revision: scene.get_revision(),
+                viewport,
+                scissor,
+                reverse_z,
+                megabuffer_generations: (
+                    storage.vertex_megabuffer.get_generation()?,
+                    storage.index_megabuffer.get_generation()?,
+                ),
+            };
+            let cached_draws = match frame.cached_draws.as_mut() {
+                Some(cached_draws) => cached_draws,
+                None => frame.cached_draws.insert(
+                    device.allocate_secondary_command_encoder(
+                        device.graphics_queue.clone(),
+                    )?
+                ),
+            };
+
+            // The frame's fence has been waited on, so the previous recording is no longer
+            // in use and can be overwritten
+            if frame.cached_draws_key != Some(key) {
+                frame.cached_draws_key = None;
+                cached_draws.set_reverse_z(reverse_z);
+                cached_draws.begin_secondary_recording(
+                    frame.draw_color_image.format,
+                    frame.draw_depth_image.format,
+                )?;
+                // Dynamic state is not inherited from the primary command buffer
+                cached_draws.set_viewport_and_scissor_rects(viewport, scissor);
+                frame.cached_draws_stats = scene.record_draws(
+                    cached_draws,
+                    &storage.materials,
+                    storage.vertex_megabuffer.get_buffer()?,
+                    storage.index_megabuffer.get_buffer()?,
+                )?;
+                cached_draws.end_recording()?;
+                frame.cached_draws_key = Some(key);
+            }
+
+            cmd.begin_rendering(
+                (&frame.draw_color_image, Renderer::MAIN_PASS_COLOR_OPS),
+                (&frame.draw_depth_image, Renderer::MAIN_PASS_DEPTH_OPS),
+                Renderer::CLEAR_COLOR,
+                vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS,
+            );
+            cmd.execute_commands(&[cached_draws]);
+            cmd.end_rendering();
+            frame.cached_draws_stats
+        }
+        RecordStrategy::Parallel { thread_count } => {
+            let thread_count = thread_count.max(1);
+            if frame.parallel_draws.len() < thread_count {
+                frame.parallel_draws.resize_with(thread_count, || None);
+            }
+
+            let chunks = scene.chunked_batches(thread_count);
+            let slots = &mut frame.parallel_draws[..chunks.len()];
+            let allocator = device.get_command_encoder_allocator();
+            let color_format = frame.draw_color_image.format;
+            let depth_format = frame.draw_depth_image.format;
+            let vertex_buffer = storage.vertex_megabuffer.get_buffer()?;
+            let index_buffer = storage.index_megabuffer.get_buffer()?;
+            let materials = &storage.materials;
+
+            let stats = std::thread::scope(|s| {
+                let workers = slots
+                    .iter_mut()
+                    .zip(chunks.iter())
+                    .map(|(slot, chunk)| {
+                        let mut allocator = allocator.clone();
+                        let queue = device.graphics_queue.clone();
+                        s.spawn(move || -> Result<RenderStats> {
+                            // Allocated here rather than on the main thread so the command
+                            // pool belongs to this worker
+                            let encoder = match slot {
+                                Some(encoder) => encoder,
+                                None => slot.insert(allocator.allocate_secondary(queue)?),
+                            };
+                            encoder.set_reverse_z(reverse_z);
+                            encoder.begin_secondary_recording(color_format, depth_format)?;
+                            encoder.set_viewport_and_scissor_rects(viewport, scissor);
+                            let stats = scene.record_batches(
+                                encoder,
+                                chunk,
+                                materials,
+                                vertex_buffer,
+                                index_buffer,
+                            )?;
+                            encoder.end_recording()?;
+                            Ok(stats)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                workers
+                    .into_iter()
+                    .try_fold(RenderStats::default(), |mut stats, worker| {
+                        stats += worker
+                            .join()
+                            .map_err(|_| eyre!("Draw recording thread panicked"))??;
+                        Ok::<_, color_eyre::Report>(stats)
+                    })
+            })?;
+
+            cmd.begin_rendering(
+                (&frame.draw_color_image, Renderer::MAIN_PASS_COLOR_OPS),
+                (&frame.draw_depth_image, Renderer::MAIN_PASS_DEPTH_OPS),
+                Renderer::CLEAR_COLOR,
+                vk::RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS,
+            );
+            cmd.execute_commands(&slots.iter().flatten().collect::<Vec<_>>());
+            cmd.end_rendering();
+            stats
+        }
+    };
+    stats.scene_record_time = record_start.elapsed();
+
+    Ok(stats)
+}
+
+/// Read back the pipeline statistics the frame collected the last time it was drawn. The frame's
+/// fence must have been waited on.
+fn take_pipeline_statistics(frame: &mut Frame) -> Result<Option<PipelineStatistics>> {
+    match frame.pipeline_statistics_query.as_mut() {
+        Some(query) => query.take_results(),
+        None => Ok(None),
+    }
+}
+
+/// Record the material data staged with `Material::set_material_data`. The previous frames'
+/// draws may still be reading the slots about to be overwritten.
+fn record_material_data_uploads(cmd: &CommandEncoder, materials: &mut [Material]) -> Result<()> {
+    if !materials.iter().any(Material::has_pending_data) {
+        return Ok(());
+    }
+
+    let shader_stages = vk::PipelineStageFlags2::VERTEX_SHADER
+        | vk::PipelineStageFlags2::FRAGMENT_SHADER;
+    let before_update = [vk::MemoryBarrier2::default()
+        .src_stage_mask(shader_stages)
+        .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)
+        .dst_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+        .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)];
+    let after_update = [vk::MemoryBarrier2::default()
+        .src_stage_mask(vk::PipelineStageFlags2::ALL_TRANSFER)
+        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+        .dst_stage_mask(shader_stages)
+        .dst_access_mask(vk::AccessFlags2::SHADER_STORAGE_READ)];
+    cmd.pipeline_barrier(&vk::DependencyInfo::default().memory_barriers(&before_update));
+    for material in materials.iter_mut() {
+        material.record_data_upload(cmd.command_buffer)?;
+    }
+    cmd.pipeline_barrier(&vk::DependencyInfo::default().memory_barriers(&after_update));
+    Ok(())
+}
+
+/// Draw the sprite batch over the frame's draw color image with a single draw call. The frame's
+/// command encoder must be recording and the image must be in the color attachment layout.
+fn record_sprite_pass(
+    frame: &mut Frame,
+    sprite_batch: &SpriteBatch,
+    storage: &RenderResourceStorage,
+    sprite_material: MaterialHandle,
+    viewport: vk::Rect2D,
+    scissor: vk::Rect2D,
+) -> Result<RenderStats> {
+    if sprite_batch.is_empty() {
+        return Ok(RenderStats::default());
+    }
+
+    let vertices = sprite_batch.build_vertices()?;
+    if size_of_val(vertices.as_slice()) as u64 > frame.sprite_vertex_buffer.size {
+        return Err(eyre!(
+            "Sprite batch of {} sprites does not fit in the sprite vertex buffer",
+            sprite_batch.len(),
+        ));
+    }
+    // The frame's fence has been waited on, so the GPU is done reading the previous contents
+    frame.sprite_vertex_buffer.write(&vertices, 0)?;
+
+    let material = storage.materials
+        .get(sprite_material.0)
+        .ok_or_eyre("Sprite material does not exist")?;
+    let per_draw_data = PerDrawData {
+        object_index: 0,
+        material_index: sprite_material.0 as u32,
+        vertex_offset: 0,
+    };
+
+    let cmd = &frame.command_encoder;
+    cmd.begin_overlay_rendering(&frame.draw_color_image);
+    cmd.set_viewport_and_scissor_rects(viewport, scissor);
+    material.bind_pipeline(cmd.command_buffer);
+    material.bind_descriptor_sets(cmd.command_buffer);
+    material.update_push_constants(
+        cmd.command_buffer,
+        bytemuck::bytes_of(&per_draw_data),
+    );
+    cmd.bind_vertex_buffer(frame.sprite_vertex_buffer.buffer, 0);
+    cmd.draw(vertices.len() as u32, 0);
+    cmd.end_rendering();
+
+    Ok(RenderStats {
+        draw_calls: 1,
+        triangles: vertices.len() as u64 / 3,
+        instances: sprite_batch.len() as u32,
+        pipeline_binds: 1,
+        descriptor_binds: 1,
+        ..Default::default()
+    })
+}
+
+/// Draw the debug line vertices over the frame's draw color image with a single draw call. Lines
+/// that don't fit in the frame's debug line vertex buffer are dropped with a warning. The frame's
+/// command encoder must be recording and the image must be in the color attachment layout.
+fn record_debug_line_pass(
+    frame: &mut Frame,
+    mut vertices: Vec<PerDebugLineVertexData>,
+    storage: &RenderResourceStorage,
+    debug_line_material: MaterialHandle,
+    viewport: vk::Rect2D,
+    scissor: vk::Rect2D,
+) -> Result<RenderStats> {
+    // Whole lines only
+    let line_size = size_of::<PerDebugLineVertexData>() * 2;
+    let capacity = frame.debug_line_vertex_buffer.size as usize / line_size * 2;
+    if vertices.len() > capacity {
+        log::warn!(
+            "Drawing {} of {} debug lines; the rest do not fit in the debug line vertex buffer",
+            capacity / 2,
+            vertices.len() / 2,
+        );
+        vertices.truncate(capacity);
+    }
+    if vertices.is_empty() {
+        return Ok(RenderStats::default());
+    }
+    // The frame's fence has been waited on, so the GPU is done reading the previous contents
+    frame.debug_line_vertex_buffer.write(&vertices, 0)?;
+
+    let material = storage.materials
+        .get(debug_line_material.0)
+        .ok_or_eyre("Debug line material does not exist")?;
+    let per_draw_data = PerDrawData {
+        object_index: 0,
+        material_index: debug_line_material.0 as u32,
+        vertex_offset: 0,
+    };
+
+    let cmd = &frame.command_encoder;
+    cmd.begin_overlay_rendering(&frame.draw_color_image);
+    cmd.set_viewport_and_scissor_rects(viewport, scissor);
+    material.bind_pipeline(cmd.command_buffer);
+    material.bind_descriptor_sets(cmd.command_buffer);
+    material.update_push_constants(
+        cmd.command_buffer,
+        bytemuck::bytes_of(&per_draw_data),
+    );
+    cmd.bind_vertex_buffer(frame.debug_line_vertex_buffer.buffer, 0);
+    cmd.draw(vertices.len() as u32, 0);
+    cmd.end_rendering();
+
+    Ok(RenderStats {
+        draw_calls: 1,
+        pipeline_binds: 1,
+        descriptor_binds: 1,
+        ..Default::default()
+    })
+}
+
+fn check_rect_fits(rect: vk::Rect2D, extent: vk::Extent2D) -> Result<()> {
+    let fits = rect.offset.x >= 0
+        && rect.offset.y >= 0
+        && rect.extent.width > 0
+        && rect.extent.height > 0
+        && rect.offset.x as u64 + rect.extent.width as u64 <= extent.width as u64
+        && rect.offset.y as u64 + rect.extent.height as u64 <= extent.height as u64;
+    if !fits {
+        return Err(eyre!(
+            "Rect {:?} does not fit in the {}x{} draw image",
+            rect,
+            extent.width,
+            extent.height,
+        ));
+    }
+    Ok(())
 }
@@ -1,13 +1,16 @@
 use std::sync::{Arc, Mutex};
 use ash::vk;
 use color_eyre::eyre::Result;
-use color_eyre::eyre::eyre;
+use color_eyre::eyre::{eyre, OptionExt};
 use vk_mem::Alloc;
 
 pub struct Buffer {
     pub buffer: vk::Buffer,
     pub size: u64,
     mapped: bool,
+    // Writes through the mapping have to be flushed before the GPU sees them unless the memory
+    // is host coherent
+    coherent: bool,
 
     allocation: Option<vk_mem::Allocation>,
     memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
@@ -25,6 +28,53 @@ impl Buffer {
         mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
         device: Arc<ash::Device>,
     ) -> Result<Self> {
+        let allocation_flags = if mapped {
+            vk_mem::AllocationCreateFlags::MAPPED | vk_mem::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE
+        } else {
+            vk_mem::AllocationCreateFlags::empty()
+        };
+        Self::new_with_allocation_flags(
+            size,
+            alignment,
+            buf_usage,
+            mem_usage,
+            allocation_flags,
+            mem_allocator,
+            device,
+        )
+    }
+
+    /// Mapped buffer the GPU copies into and the CPU reads from with `read`
+    pub fn new_readback(
+        size: u64,
+        mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        Self::new_with_allocation_flags(
+            size,
+            16,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::AutoPreferHost,
+            vk_mem::AllocationCreateFlags::MAPPED | vk_mem::AllocationCreateFlags::HOST_ACCESS_RANDOM,
+            mem_allocator,
+            device,
+        )
+    }
+
+    fn new_with_allocation_flags(
+        size: u64,
+        alignment: u64,
+        buf_usage: vk::BufferUsageFlags,
+        mem_usage: vk_mem::MemoryUsage,
+        allocation_flags: vk_mem::AllocationCreateFlags,
+
+        mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        let mapped = allocation_flags.contains(vk_mem::AllocationCreateFlags::MAPPED);
+        let mem_allocator_guard = mem_allocator
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
         let (buffer, allocation) = unsafe {
             let buffer_info = vk::BufferCreateInfo {
                 size,
@@ -34,27 +84,27 @@ impl Buffer {
             };
             let allocation_info = vk_mem::AllocationCreateInfo {
                 usage: mem_usage,
-                flags: if mapped {
-                    vk_mem::AllocationCreateFlags::MAPPED | vk_mem::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE
-                } else {
-                    vk_mem::AllocationCreateFlags::empty()
-                },
+                flags: allocation_flags,
                 ..Default::default()
             };
-            mem_allocator
-                .lock()
-                .map_err(|e| eyre!(e.to_string()))?
-                .create_buffer_with_alignment(
-                    &buffer_info,
-                    &allocation_info,
-                    alignment,
-                )?
+            mem_allocator_guard.create_buffer_with_alignment(
+                &buffer_info,
+                &allocation_info,
+                alignment,
+            )?
         };
+        let memory_type = mem_allocator_guard.get_allocation_info(&allocation).memory_type;
+        let coherent = unsafe { mem_allocator_guard.get_memory_properties() }
+            .memory_types[memory_type as usize]
+            .property_flags
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT);
+        drop(mem_allocator_guard);
 
         Ok(Self {
             buffer,
             size,
             mapped,
+            coherent,
 
             allocation: Some(allocation),
             memory_allocator: mem_allocator,
@@ -62,13 +112,15 @@ impl Buffer {
         })
     }
 
+    /// Copy `data` into mapped memory at `start_offset` or the next offset aligned for `T`,
+    /// flushing the written range if the memory isn't host coherent
     pub fn write<T>(
         &mut self,
         data: &[T],
         start_offset: usize,
     ) -> Result<presser::CopyRecord>
     where
-        T: Copy,
+        T: bytemuck::Pod,
     {
         if !self.mapped {
             return Err(eyre!("Cannot write to buffer that is not mapped"));
@@ -78,13 +130,23 @@ impl Buffer {
             .as_ref()
             .expect("Allocation does not exist");
 
-        let allocation_info = self.memory_allocator
+        let allocator = self.memory_allocator
             .lock()
-            .map_err(|e| eyre!(e.to_string()))?
-            .get_allocation_info(allocation);
+            .map_err(|e| eyre!(e.to_string()))?;
+        let allocation_info = allocator.get_allocation_info(allocation);
 
-        if std::mem::size_of_val(data) as u64 > allocation_info.size {
-            return Err(eyre!("Data too large to write into buffer"));
+        // presser moves the start up to the alignment of `T`
+        let end_offset = start_offset
+            .checked_next_multiple_of(align_of::<T>())
+            .and_then(|offset| offset.checked_add(size_of_val(data)))
+            .ok_or_eyre("Write offset overflowed")?;
+        if end_offset as u64 > self.size.min(allocation_info.size) {
+            return Err(eyre!(
+                "Cannot write {} bytes at offset {} into buffer of {} bytes",
+                size_of_val(data),
+                start_offset,
+                self.size,
+            ));
         }
 
         let mut raw_allocation = presser::RawAllocation::from_raw_parts(
@@ -99,8 +161,85 @@ impl Buffer {
             start_offset,
         )?;
 
+        if !self.coherent {
+            allocator.flush_allocation(
+                allocation,
+                copy_record.copy_start_offset as u64,
+                (copy_record.copy_end_offset - copy_record.copy_start_offset) as u64,
+            )?;
+        }
+
         Ok(copy_record)
     }
+
+    /// Move `size` bytes of mapped memory from `src_offset` to `dst_offset`. The ranges may
+    /// overlap.
+    pub fn copy_within(&mut self, src_offset: u64, dst_offset: u64, size: u64) -> Result<()> {
+        if !self.mapped {
+            return Err(eyre!("Cannot copy within buffer that is not mapped"));
+        }
+        let end_offset = src_offset.max(dst_offset).checked_add(size);
+        if end_offset.is_none_or(|end_offset| end_offset > self.size) {
+            return Err(eyre!(
+                "Cannot copy {} bytes from offset {} to {} within buffer of {} bytes",
+                size,
+                src_offset,
+                dst_offset,
+                self.size,
+            ));
+        }
+
+        let allocation = self.allocation
+            .as_ref()
+            .expect("Allocation does not exist");
+
+        let allocator = self.memory_allocator
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        if !self.coherent {
+            allocator.invalidate_allocation(allocation, src_offset, size)?;
+        }
+        let allocation_info = allocator.get_allocation_info(allocation);
+        let mapped_data = allocation_info.mapped_data as *mut u8;
+        unsafe {
+            std::ptr::copy(
+                mapped_data.add(src_offset as usize),
+                mapped_data.add(dst_offset as usize),
+                size as usize,
+            );
+        }
+        if !self.coherent {
+            allocator.flush_allocation(allocation, dst_offset, size)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copy the whole buffer out of mapped memory, after making the GPU's writes visible if the
+    /// memory isn't host coherent
+    pub fn read(&self) -> Result<Vec<u8>> {
+        if !self.mapped {
+            return Err(eyre!("Cannot read from buffer that is not mapped"));
+        }
+
+        let allocation = self.allocation
+            .as_ref()
+            .expect("Allocation does not exist");
+
+        let allocator = self.memory_allocator
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        allocator.invalidate_allocation(allocation, 0, vk::WHOLE_SIZE)?;
+        let allocation_info = allocator.get_allocation_info(allocation);
+
+        let data = unsafe {
+            std::slice::from_raw_parts(
+                allocation_info.mapped_data as *const u8,
+                self.size as usize,
+            )
+        };
+        Ok(data.to_vec())
+    }
 }
 
 impl Drop for Buffer {
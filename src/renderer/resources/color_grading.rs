@@ -0,0 +1,127 @@
+use std::path::Path;
+use ash::vk;
+use color_eyre::eyre::{eyre, OptionExt};
+use color_eyre::Result;
+use glam::Vec3;
+use crate::renderer::contexts::device_ctx::device::RenderDevice;
+use crate::renderer::resources::image::{Image, Image3dDesc};
+
+/// 3D lookup table that remaps colors after tonemapping, loaded from an Adobe/Resolve `.cube`
+/// file. Red varies fastest in the file, which matches the x axis of the 3D image.
+pub struct ColorGradingLut {
+    pub image: Image,
+    /// Number of entries along each axis
+    pub size: u32,
+    /// Input colors are mapped from this range onto the edges of the table
+    pub domain_min: Vec3,
+    pub domain_max: Vec3,
+}
+
+impl ColorGradingLut {
+    pub fn load(path: &Path, device: &RenderDevice) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| eyre!("Failed to read LUT {}: {}", path.display(), e))?;
+        let cube = parse_cube(&contents)
+            .map_err(|e| eyre!("Failed to parse LUT {}: {}", path.display(), e))?;
+        Self::from_cube(&cube, device)
+    }
+
+    fn from_cube(cube: &CubeLut, device: &RenderDevice) -> Result<Self> {
+        // RGBA, since three-component float formats are rarely sampleable
+        let texels = cube.entries
+            .iter()
+            .flat_map(|entry| [entry.x, entry.y, entry.z, 1.0])
+            .collect::<Vec<f32>>();
+        let desc = Image3dDesc {
+            width: cube.size,
+            height: cube.size,
+            depth: cube.size,
+            format: vk::Format::R32G32B32A32_SFLOAT,
+            usage: vk::ImageUsageFlags::SAMPLED,
+        };
+        let image = device.create_3d_image(&desc, Some(bytemuck::cast_slice(&texels)))?;
+
+        Ok(Self {
+            image,
+            size: cube.size,
+            domain_min: cube.domain_min,
+            domain_max: cube.domain_max,
+        })
+    }
+}
+
+struct CubeLut {
+    size: u32,
+    domain_min: Vec3,
+    domain_max: Vec3,
+    entries: Vec<Vec3>,
+}
+
+fn parse_cube(contents: &str) -> Result<CubeLut> {
+    let mut size = None;
+    let mut domain_min = Vec3::ZERO;
+    let mut domain_max = Vec3::ONE;
+    let mut entries = Vec::new();
+
+    for (line_index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let keyword = words.next().ok_or_eyre("Empty line")?;
+        let line_number = line_index + 1;
+        match keyword {
+            "TITLE" => {}
+            "LUT_1D_SIZE" => return Err(eyre!("1D LUTs are not supported")),
+            "LUT_3D_SIZE" => {
+                let value = words
+                    .next()
+                    .ok_or_eyre(format!("Line {}: LUT_3D_SIZE has no value", line_number))?
+                    .parse::<u32>()
+                    .map_err(|e| eyre!("Line {}: {}", line_number, e))?;
+                size = Some(value);
+            }
+            "DOMAIN_MIN" => domain_min = parse_vec3(words, line_number)?,
+            "DOMAIN_MAX" => domain_max = parse_vec3(words, line_number)?,
+            // Other keywords, like LUT_IN_VIDEO_RANGE, don't change how the table is sampled
+            _ if keyword.starts_with(|c: char| c.is_ascii_uppercase()) => {
+                log::warn!("Line {}: ignoring unsupported keyword {}", line_number, keyword);
+            }
+            _ => entries.push(parse_vec3(line.split_whitespace(), line_number)?),
+        }
+    }
+
+    let size = size.ok_or_eyre("Missing LUT_3D_SIZE")?;
+    if size < 2 {
+        return Err(eyre!("LUT_3D_SIZE must be at least 2, got {}", size));
+    }
+    let expected_entries = (size as usize).pow(3);
+    if entries.len() != expected_entries {
+        return Err(eyre!(
+            "Expected {} entries for LUT_3D_SIZE {}, found {}",
+            expected_entries,
+            size,
+            entries.len(),
+        ));
+    }
+
+    Ok(CubeLut {
+        size,
+        domain_min,
+        domain_max,
+        entries,
+    })
+}
+
+fn parse_vec3<'a>(mut words: impl Iterator<Item = &'a str>, line_number: usize) -> Result<Vec3> {
+    let mut component = || {
+        words
+            .next()
+            .ok_or_eyre(format!("Line {}: expected 3 values", line_number))?
+            .parse::<f32>()
+            .map_err(|e| eyre!("Line {}: {}", line_number, e))
+    };
+    Ok(Vec3::new(component()?, component()?, component()?))
+}
@@ -0,0 +1,619 @@
+use std::collections::{BTreeMap, BTreeSet};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+/// How a free list picks the free region to place an allocation in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    /// The first free region that fits, in order of offset. Fast, but leaves small holes behind
+    /// under allocations of mixed sizes.
+    #[default]
+    FirstFit,
+    /// The smallest free region that fits, which keeps the larger ones whole for later
+    BestFit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FreeRegion {
+    offset: u64,
+    size: u64,
+}
+
+/// Bookkeeping of which parts of a range of bytes are free, without any memory behind it. The
+/// megabuffers use it for their buffers, which keeps the allocation logic apart from Vulkan.
+///
+/// Sizes are rounded up to the alignment, so allocations stay aligned as long as the range starts
+/// aligned.
+#[derive(Debug, Clone)]
+pub struct FreeListAllocator {
+    start: u64,
+    end: u64,
+    alignment: u64,
+    strategy: AllocationStrategy,
+    free_regions: Vec<FreeRegion>,
+    // Offsets of the allocations made with a larger alignment than the allocator's, and that
+    // alignment, which `compact` has to keep
+    large_alignments: BTreeMap<u64, u64>,
+    // Offsets of the allocations `compact` must leave in place, see `pin`
+    pinned: BTreeSet<u64>,
+}
+
+impl FreeListAllocator {
+    /// Everything from `start` to `start + size` is free. `alignment` must be a power of two.
+    pub fn new(start: u64, size: u64, alignment: u64) -> Self {
+        let free_regions = if size > 0 {
+            vec![FreeRegion {
+                offset: start,
+                size,
+            }]
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            start,
+            end: start + size,
+            alignment,
+            strategy: AllocationStrategy::default(),
+            free_regions,
+            large_alignments: BTreeMap::new(),
+            pinned: BTreeSet::new(),
+        }
+    }
+
+    pub fn aligned_size(&self, size: u64) -> u64 {
+        (size + self.alignment - 1) & !(self.alignment - 1)
+    }
+
+    pub fn get_alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    pub fn set_strategy(&mut self, strategy: AllocationStrategy) {
+        self.strategy = strategy;
+    }
+
+    pub fn get_strategy(&self) -> AllocationStrategy {
+        self.strategy
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn get_free_region_count(&self) -> usize {
+        self.free_regions.len()
+    }
+
+    pub fn get_bytes_free(&self) -> u64 {
+        self.free_regions.iter().map(|region| region.size).sum()
+    }
+
+    /// Size of the largest allocation that fits, at the allocator's alignment
+    pub fn get_largest_free_block(&self) -> u64 {
+        self.free_regions
+            .iter()
+            .map(|region| region.size)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Take `size` bytes, rounded up to the allocator's alignment, from a free region picked by
+    /// the strategy. The offset is a multiple of `alignment` as well, a power of two; the free
+    /// space skipped in front to get there stays free. Returns the offset and the rounded size,
+    /// or `None` if no free region fits.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<(u64, u64)> {
+        let size = self.aligned_size(size);
+        let alignment = alignment.max(self.alignment);
+        let mut candidates = self.free_regions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, region)| {
+                let offset = region.offset.next_multiple_of(alignment);
+                let padding = offset - region.offset;
+                (region.size >= padding + size).then_some((i, *region, offset))
+            });
+        let (index, _, offset) = match self.strategy {
+            // The first free region that can fit the allocation
+            AllocationStrategy::FirstFit => candidates.next(),
+            // The smallest free region that can fit the allocation, the lowest of them on ties
+            AllocationStrategy::BestFit => {
+                candidates.min_by_key(|(_, region, _)| (region.size, region.offset))
+            }
+        }?;
+
+        let region = self.free_regions[index];
+        let padding = offset - region.offset;
+        let remainder = FreeRegion {
+            offset: offset + size,
+            size: region.size - padding - size,
+        };
+        match (padding > 0, remainder.size > 0) {
+            (true, true) => {
+                self.free_regions[index].size = padding;
+                self.free_regions.insert(index + 1, remainder);
+            }
+            (true, false) => self.free_regions[index].size = padding,
+            (false, true) => self.free_regions[index] = remainder,
+            (false, false) => {
+                self.free_regions.remove(index);
+            }
+        }
+        if alignment > self.alignment {
+            self.large_alignments.insert(offset, alignment);
+        }
+        Some((offset, size))
+    }
+
+    /// Return an allocation, merging it with the free regions on either side
+    pub fn free(&mut self, offset: u64, size: u64) -> Result<()> {
+        if size == 0 {
+            return Err(eyre!("Cannot free a region of size 0"));
+        }
+        let end = offset.checked_add(size).filter(|end| offset >= self.start && *end <= self.end);
+        let Some(end) = end else {
+            return Err(eyre!(
+                "Region at offset {} of {} bytes is outside of {}..{}",
+                offset,
+                size,
+                self.start,
+                self.end,
+            ));
+        };
+        let overlaps_free = self.free_regions
+            .iter()
+            .any(|region| offset < region.offset + region.size && region.offset < end);
+        if overlaps_free {
+            return Err(eyre!("Region at offset {} of {} bytes is already free", offset, size));
+        }
+        // The region may be several allocations merged together
+        self.large_alignments.retain(|allocation_offset, _| {
+            !(offset..end).contains(allocation_offset)
+        });
+        self.pinned.retain(|allocation_offset| !(offset..end).contains(allocation_offset));
+
+        let left_index = self.free_regions
+            .iter()
+            .position(|region| region.offset + region.size == offset);
+        let right_index = self.free_regions
+            .iter()
+            .position(|region| region.offset == end);
+
+        match (left_index, right_index) {
+            (Some(left), Some(right)) => {
+                self.free_regions[left].size += size + self.free_regions[right].size;
+                self.free_regions.remove(right);
+            }
+            (Some(left), None) => {
+                self.free_regions[left].size += size;
+            }
+            (None, Some(right)) => {
+                self.free_regions[right].offset = offset;
+                self.free_regions[right].size += size;
+            }
+            (None, None) => {
+                self.free_regions.push(FreeRegion {
+                    offset,
+                    size,
+                });
+                self.free_regions.sort_by_key(|region| region.offset);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sort the free regions and merge the ones that touch
+    pub fn defragment(&mut self) {
+        self.free_regions.sort_by_key(|region| region.offset);
+        let mut merged: Vec<FreeRegion> = Vec::with_capacity(self.free_regions.len());
+        for region in self.free_regions.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.offset + last.size == region.offset => last.size += region.size,
+                _ => merged.push(region),
+            }
+        }
+        self.free_regions = merged;
+    }
+
+    /// Extend the range by `additional_size` bytes at the end, which become free
+    pub fn grow(&mut self, additional_size: u64) {
+        if additional_size == 0 {
+            return;
+        }
+        let old_end = self.end;
+        self.end += additional_size;
+
+        // Extend the free region at the end, or add one if the end was allocated
+        match self.free_regions
+            .iter_mut()
+            .find(|region| region.offset + region.size == old_end)
+        {
+            Some(region) => region.size += additional_size,
+            None => self.free_regions.push(FreeRegion {
+                offset: old_end,
+                size: additional_size,
+            }),
+        }
+    }
+
+    /// Offsets and sizes of the parts of the range between the free regions, in order. Adjacent
+    /// allocations come out as one part.
+    pub fn allocated_regions(&self) -> Vec<(u64, u64)> {
+        let mut free_regions = self.free_regions
+            .iter()
+            .map(|region| (region.offset, region.size))
+            .collect::<Vec<_>>();
+        free_regions.sort_unstable();
+        // The end of the range closes off the last allocated region
+        free_regions.push((self.end, 0));
+
+        let mut allocated_regions = Vec::new();
+        let mut offset = self.start;
+        for (free_offset, free_size) in free_regions {
+            if free_offset > offset {
+                allocated_regions.push((offset, free_offset - offset));
+            }
+            offset = offset.max(free_offset + free_size);
+        }
+        allocated_regions
+    }
+
+    /// Keep the allocation at `offset` where it is when compacting, e.g. because something that
+    /// can't be remapped refers to it. It is unpinned when freed.
+    pub fn pin(&mut self, offset: u64) -> Result<()> {
+        let is_free = self.free_regions
+            .iter()
+            .any(|region| (region.offset..region.offset + region.size).contains(&offset));
+        if !(self.start..self.end).contains(&offset) || is_free {
+            return Err(eyre!("No allocation at offset {} to pin", offset));
+        }
+        self.pinned.insert(offset);
+        Ok(())
+    }
+
+    /// Pack the allocations at the start of the range, leaving the free space at the end.
+    /// Returns how the parts from `allocated_regions` moved, as (old offset, new offset, size) in
+    /// order of offset. Every part moves down, so moving them in that order never overwrites a
+    /// part that hasn't moved yet.
+    ///
+    /// A part holding an allocation made with a larger alignment than the allocator's only moves
+    /// by multiples of that alignment, and a part holding a pinned allocation doesn't move at all,
+    /// which can leave free regions between parts.
+    pub fn compact(&mut self) -> Vec<(u64, u64, u64)> {
+        let mut moves = Vec::new();
+        let mut free_regions = Vec::new();
+        let mut packed_offset = self.start;
+        for (offset, size) in self.allocated_regions() {
+            let alignment = self.large_alignments
+                .range(offset..offset + size)
+                .map(|(_, alignment)| *alignment)
+                .max()
+                .unwrap_or(self.alignment);
+            let new_offset = if self.pinned.range(offset..offset + size).next().is_some() {
+                offset
+            } else {
+                // The alignments are powers of two, so moving by a multiple of the largest keeps
+                // every allocation in the part aligned
+                packed_offset + (offset - packed_offset) % alignment
+            };
+            if new_offset > packed_offset {
+                free_regions.push(FreeRegion {
+                    offset: packed_offset,
+                    size: new_offset - packed_offset,
+                });
+            }
+            if new_offset != offset {
+                moves.push((offset, new_offset, size));
+            }
+            packed_offset = new_offset + size;
+        }
+        if packed_offset < self.end {
+            free_regions.push(FreeRegion {
+                offset: packed_offset,
+                size: self.end - packed_offset,
+            });
+        }
+        self.free_regions = free_regions;
+
+        let large_alignments = std::mem::take(&mut self.large_alignments);
+        self.large_alignments = large_alignments
+            .into_iter()
+            .map(|(offset, alignment)| {
+                let moved = moves.iter().find(|(old_offset, _, size)| {
+                    (*old_offset..old_offset + size).contains(&offset)
+                });
+                match moved {
+                    Some((old_offset, new_offset, _)) => {
+                        (offset - old_offset + new_offset, alignment)
+                    }
+                    None => (offset, alignment),
+                }
+            })
+            .collect();
+        moves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_regions(allocator: &FreeListAllocator) -> Vec<(u64, u64)> {
+        allocator.free_regions
+            .iter()
+            .map(|region| (region.offset, region.size))
+            .collect()
+    }
+
+    #[test]
+    fn allocate_rounds_up_to_the_alignment() {
+        let mut allocator = FreeListAllocator::new(0, 256, 16);
+        assert_eq!(allocator.allocate(10, 16), Some((0, 16)));
+        assert_eq!(allocator.allocate(17, 16), Some((16, 32)));
+        assert_eq!(free_regions(&allocator), vec![(48, 208)]);
+        assert_eq!(allocator.get_bytes_free(), 208);
+    }
+
+    #[test]
+    fn allocate_fails_when_nothing_fits() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        assert_eq!(allocator.allocate(64, 16), Some((0, 64)));
+        assert_eq!(allocator.allocate(16, 16), None);
+        assert!(free_regions(&allocator).is_empty());
+    }
+
+    #[test]
+    fn allocate_skips_padding_for_larger_alignments() {
+        let mut allocator = FreeListAllocator::new(0, 256, 16);
+        assert_eq!(allocator.allocate(16, 16), Some((0, 16)));
+        assert_eq!(allocator.allocate(16, 64), Some((64, 16)));
+        // The padding in front of the aligned allocation stays free
+        assert_eq!(free_regions(&allocator), vec![(16, 48), (80, 176)]);
+    }
+
+    #[test]
+    fn first_fit_takes_the_lowest_hole_that_fits() {
+        let mut allocator = FreeListAllocator::new(0, 160, 16);
+        for _ in 0..5 {
+            allocator.allocate(32, 16);
+        }
+        allocator.free(0, 32).unwrap();
+        allocator.free(64, 32).unwrap();
+        assert_eq!(allocator.get_strategy(), AllocationStrategy::FirstFit);
+        assert_eq!(allocator.allocate(16, 16), Some((0, 16)));
+    }
+
+    #[test]
+    fn best_fit_takes_the_smallest_hole_that_fits() {
+        let mut allocator = FreeListAllocator::new(0, 256, 16);
+        allocator.set_strategy(AllocationStrategy::BestFit);
+        // Holes of 64 bytes at 0 and 32 bytes at 96, with the rest free from 160
+        allocator.allocate(64, 16);
+        allocator.allocate(32, 16);
+        allocator.allocate(32, 16);
+        allocator.allocate(32, 16);
+        allocator.free(0, 64).unwrap();
+        allocator.free(96, 32).unwrap();
+        assert_eq!(allocator.allocate(32, 16), Some((96, 32)));
+        assert_eq!(free_regions(&allocator), vec![(0, 64), (160, 96)]);
+    }
+
+    #[test]
+    fn best_fit_reuses_the_tightest_hole_in_the_middle() {
+        let mut allocator = FreeListAllocator::new(0, 256, 16);
+        allocator.set_strategy(AllocationStrategy::BestFit);
+        for _ in 0..8 {
+            allocator.allocate(32, 16);
+        }
+        // Holes of 64 bytes at 32 and 32 bytes at 128, with everything around them allocated
+        allocator.free(32, 64).unwrap();
+        allocator.free(128, 32).unwrap();
+        assert_eq!(allocator.allocate(16, 16), Some((128, 16)));
+        assert_eq!(allocator.allocate(16, 16), Some((144, 16)));
+        assert_eq!(allocator.allocate(16, 16), Some((32, 16)));
+        assert_eq!(free_regions(&allocator), vec![(48, 48)]);
+    }
+
+    #[test]
+    fn best_fit_takes_the_lowest_of_equal_holes() {
+        let mut allocator = FreeListAllocator::new(0, 256, 16);
+        allocator.set_strategy(AllocationStrategy::BestFit);
+        for _ in 0..8 {
+            allocator.allocate(32, 16);
+        }
+        allocator.free(160, 32).unwrap();
+        allocator.free(64, 32).unwrap();
+        assert_eq!(allocator.allocate(32, 16), Some((64, 32)));
+        assert_eq!(allocator.allocate(32, 16), Some((160, 32)));
+        assert!(free_regions(&allocator).is_empty());
+    }
+
+    #[test]
+    fn free_merges_with_the_left_neighbor() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(16, 16);
+        allocator.allocate(16, 16);
+        allocator.allocate(32, 16);
+        allocator.free(0, 16).unwrap();
+        allocator.free(16, 16).unwrap();
+        assert_eq!(free_regions(&allocator), vec![(0, 32)]);
+    }
+
+    #[test]
+    fn free_merges_with_the_right_neighbor() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(16, 16);
+        allocator.allocate(16, 16);
+        allocator.free(16, 16).unwrap();
+        assert_eq!(free_regions(&allocator), vec![(16, 48)]);
+    }
+
+    #[test]
+    fn free_merges_with_both_neighbors() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(16, 16);
+        allocator.allocate(16, 16);
+        allocator.allocate(16, 16);
+        allocator.free(0, 16).unwrap();
+        allocator.free(16, 16).unwrap();
+        assert_eq!(free_regions(&allocator), vec![(0, 32), (48, 16)]);
+        allocator.free(32, 16).unwrap();
+        assert_eq!(free_regions(&allocator), vec![(0, 64)]);
+    }
+
+    #[test]
+    fn free_without_neighbors_adds_a_region_in_order() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        for _ in 0..4 {
+            allocator.allocate(16, 16);
+        }
+        allocator.free(32, 16).unwrap();
+        allocator.free(0, 16).unwrap();
+        assert_eq!(free_regions(&allocator), vec![(0, 16), (32, 16)]);
+    }
+
+    #[test]
+    fn double_free_is_an_error() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(32, 16);
+        allocator.free(0, 32).unwrap();
+        assert!(allocator.free(0, 32).is_err());
+        // Overlapping a free region only partly counts too
+        allocator.allocate(16, 16);
+        assert!(allocator.free(0, 32).is_err());
+        assert_eq!(free_regions(&allocator), vec![(16, 48)]);
+    }
+
+    #[test]
+    fn free_outside_the_range_is_an_error() {
+        let mut allocator = FreeListAllocator::new(64, 64, 16);
+        allocator.allocate(64, 16);
+        assert!(allocator.free(0, 16).is_err());
+        assert!(allocator.free(112, 32).is_err());
+        assert!(allocator.free(u64::MAX - 8, 16).is_err());
+        assert!(allocator.free(64, 0).is_err());
+        assert!(free_regions(&allocator).is_empty());
+    }
+
+    #[test]
+    fn defragment_merges_touching_regions() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.free_regions = vec![
+            FreeRegion { offset: 32, size: 16 },
+            FreeRegion { offset: 0, size: 16 },
+            FreeRegion { offset: 16, size: 16 },
+        ];
+        allocator.defragment();
+        assert_eq!(free_regions(&allocator), vec![(0, 48)]);
+    }
+
+    #[test]
+    fn defragment_with_a_single_free_region() {
+        // The old loop ran to `free_regions.len() - 1`, which underflowed without free regions
+        // and skipped the merge with exactly one
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(16, 16);
+        allocator.defragment();
+        assert_eq!(free_regions(&allocator), vec![(16, 48)]);
+    }
+
+    #[test]
+    fn defragment_when_fully_allocated() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        assert_eq!(allocator.allocate(64, 16), Some((0, 64)));
+        allocator.defragment();
+        assert!(free_regions(&allocator).is_empty());
+        assert_eq!(allocator.allocated_regions(), vec![(0, 64)]);
+    }
+
+    #[test]
+    fn grow_extends_a_free_tail() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(16, 16);
+        allocator.grow(64);
+        assert_eq!(allocator.get_size(), 128);
+        assert_eq!(free_regions(&allocator), vec![(16, 112)]);
+    }
+
+    #[test]
+    fn grow_after_an_allocated_tail_adds_a_region() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(64, 16);
+        allocator.grow(32);
+        assert_eq!(free_regions(&allocator), vec![(64, 32)]);
+        assert_eq!(allocator.allocate(32, 16), Some((64, 32)));
+    }
+
+    #[test]
+    fn allocated_regions_are_the_gaps_between_free_regions() {
+        let mut allocator = FreeListAllocator::new(16, 128, 16);
+        for _ in 0..6 {
+            allocator.allocate(16, 16);
+        }
+        allocator.free(32, 16).unwrap();
+        allocator.free(80, 16).unwrap();
+        // Adjacent allocations come out as one part
+        assert_eq!(allocator.allocated_regions(), vec![(16, 16), (48, 32), (96, 16)]);
+    }
+
+    #[test]
+    fn compact_packs_allocations_at_the_start() {
+        let mut allocator = FreeListAllocator::new(0, 128, 16);
+        for _ in 0..6 {
+            allocator.allocate(16, 16);
+        }
+        allocator.free(0, 16).unwrap();
+        allocator.free(48, 16).unwrap();
+        let moves = allocator.compact();
+        assert_eq!(moves, vec![(16, 0, 32), (64, 32, 32)]);
+        assert_eq!(free_regions(&allocator), vec![(64, 64)]);
+        assert_eq!(allocator.allocated_regions(), vec![(0, 64)]);
+    }
+
+    #[test]
+    fn compact_without_gaps_moves_nothing() {
+        let mut allocator = FreeListAllocator::new(0, 64, 16);
+        allocator.allocate(32, 16);
+        assert!(allocator.compact().is_empty());
+        assert_eq!(free_regions(&allocator), vec![(32, 32)]);
+    }
+
+    #[test]
+    fn compact_keeps_larger_alignments() {
+        let mut allocator = FreeListAllocator::new(0, 512, 16);
+        allocator.allocate(16, 16);
+        allocator.allocate(112, 16);
+        assert_eq!(allocator.allocate(64, 128), Some((128, 64)));
+        assert_eq!(allocator.allocate(16, 16), Some((192, 16)));
+        allocator.free(16, 112).unwrap();
+        // The aligned allocation can't move below 128 without losing its alignment
+        assert_eq!(allocator.compact(), vec![]);
+        assert_eq!(free_regions(&allocator), vec![(16, 112), (208, 304)]);
+
+        // Once freed, its alignment no longer holds the parts back
+        allocator.free(128, 64).unwrap();
+        assert_eq!(allocator.compact(), vec![(192, 16, 16)]);
+        assert_eq!(free_regions(&allocator), vec![(32, 480)]);
+    }
+
+    #[test]
+    fn compact_leaves_pinned_allocations_in_place() {
+        let mut allocator = FreeListAllocator::new(0, 256, 16);
+        for _ in 0..5 {
+            allocator.allocate(32, 16);
+        }
+        allocator.pin(32).unwrap();
+        allocator.free(0, 32).unwrap();
+        allocator.free(64, 32).unwrap();
+        // Parts after the pinned allocation still move down to it
+        assert_eq!(allocator.compact(), vec![(96, 64, 64)]);
+        assert_eq!(free_regions(&allocator), vec![(0, 32), (128, 128)]);
+
+        // Freeing it unpins it
+        allocator.free(32, 32).unwrap();
+        assert_eq!(allocator.compact(), vec![(64, 0, 64)]);
+        assert_eq!(free_regions(&allocator), vec![(64, 192)]);
+        assert!(allocator.pin(128).is_err());
+    }
+}
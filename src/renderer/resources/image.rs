@@ -6,11 +6,24 @@ use vk_mem::Alloc;
 use crate::renderer::resources::buffer::Buffer;
 use crate::renderer::contexts::device_ctx::transfer_ctx::TransferContext;
 
+/// Size, format and usage of an image made by `Image::new_3d`
+#[derive(Debug, Clone, Copy)]
+pub struct Image3dDesc {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+}
+
 pub struct ImageCreateInfo {
     pub format: vk::Format,
     pub extent: vk::Extent3D,
     pub usage: vk::ImageUsageFlags,
     pub aspect: vk::ImageAspectFlags,
+    pub array_layers: u32, // more than 1 makes the view a 2D array, e.g. for multiview targets
+    // Overrides the view type picked from array_layers. TYPE_3D also makes the image 3D.
+    pub view_type: Option<vk::ImageViewType>,
     pub use_dedicated_memory: bool, // true for larger images like fullscreen images
 }
 
@@ -20,7 +33,10 @@ pub struct Image {
     pub format: vk::Format,
     pub extent: vk::Extent3D,
     pub aspect: vk::ImageAspectFlags,
+    pub array_layers: u32,
 
+    // Layout the commands recorded so far leave it in, `UNDEFINED` before the first transition
+    layout: vk::ImageLayout,
     allocation: Option<vk_mem::Allocation>, // GPU-only memory block
     memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
     device: Arc<ash::Device>,
@@ -36,14 +52,19 @@ impl Image {
         memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
         device: Arc<ash::Device>,
     ) -> Result<Self> {
+        let image_type = if create_info.view_type == Some(vk::ImageViewType::TYPE_3D) {
+            vk::ImageType::TYPE_3D
+        } else {
+            vk::ImageType::TYPE_2D
+        };
         let (image, allocation) = unsafe {
             let image_info = vk::ImageCreateInfo::default()
                 .format(create_info.format)
                 .usage(create_info.usage)
                 .extent(create_info.extent)
-                .image_type(vk::ImageType::TYPE_2D)
+                .image_type(image_type)
                 .mip_levels(1)
-                .array_layers(1)
+                .array_layers(create_info.array_layers)
                 .samples(vk::SampleCountFlags::TYPE_1)
                 .tiling(vk::ImageTiling::OPTIMAL);
             let allocation_info = vk_mem::AllocationCreateInfo {
@@ -62,15 +83,22 @@ impl Image {
         };
             
         let view = {
+            let view_type = create_info.view_type.unwrap_or(
+                if create_info.array_layers > 1 {
+                    vk::ImageViewType::TYPE_2D_ARRAY
+                } else {
+                    vk::ImageViewType::TYPE_2D
+                }
+            );
             let info = vk::ImageViewCreateInfo::default()
-                .view_type(vk::ImageViewType::TYPE_2D)
+                .view_type(view_type)
                 .image(image)
                 .format(create_info.format)
                 .subresource_range(vk::ImageSubresourceRange {
                     base_mip_level: 0,
                     level_count: 1,
                     base_array_layer: 0,
-                    layer_count: 1,
+                    layer_count: create_info.array_layers,
                     aspect_mask: create_info.aspect,
                 });
             unsafe { device.create_image_view(&info, None)? }
@@ -82,7 +110,9 @@ impl Image {
             format: create_info.format,
             extent: create_info.extent,
             aspect: create_info.aspect,
+            array_layers: create_info.array_layers,
 
+            layout: vk::ImageLayout::UNDEFINED,
             allocation: Some(allocation),
             memory_allocator,
             device,
@@ -110,6 +140,8 @@ impl Image {
                 },
                 usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
                 aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                view_type: None,
                 use_dedicated_memory, 
             };
             let mut image = Self::new(&create_info, memory_allocator, device)?;
@@ -124,12 +156,201 @@ impl Image {
         Ok(image)
     }
 
+    /// Create a volume image with a 3D view, e.g. for a color grading LUT. If `data` is given it
+    /// must hold every texel, slice by slice, and the image ends up in `SHADER_READ_ONLY_OPTIMAL`.
+    pub fn new_3d(
+        desc: &Image3dDesc,
+        data: Option<&[u8]>,
+
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+        transfer_context: &TransferContext,
+    ) -> Result<Self> {
+        let extent = vk::Extent3D {
+            width: desc.width,
+            height: desc.height,
+            depth: desc.depth,
+        };
+        if let Some(data) = data {
+            check_upload_size(desc.format, extent, data.len())?;
+        }
+        let usage = if data.is_some() {
+            desc.usage | vk::ImageUsageFlags::TRANSFER_DST
+        } else {
+            desc.usage
+        };
+        let create_info = ImageCreateInfo {
+            format: desc.format,
+            extent,
+            usage,
+            aspect: vk::ImageAspectFlags::COLOR,
+            // 3D images can't have array layers
+            array_layers: 1,
+            view_type: Some(vk::ImageViewType::TYPE_3D),
+            use_dedicated_memory: false,
+        };
+        let mut image = Self::new(&create_info, memory_allocator, device)?;
+
+        if let Some(data) = data {
+            // The copy covers the whole extent, including its depth
+            image.upload(data, transfer_context)?;
+        }
+
+        Ok(image)
+    }
+
+    /// Create a shader-readable image with `layers` layers of the same size, sampled through a
+    /// single 2D array view. Every layer starts out in `SHADER_READ_ONLY_OPTIMAL` and is filled
+    /// with `upload_layer`.
+    pub fn new_array_image(
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: vk::Format,
+
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+        transfer_context: &TransferContext,
+    ) -> Result<Self> {
+        if layers == 0 {
+            return Err(eyre!("Array image must have at least one layer"));
+        }
+
+        let create_info = ImageCreateInfo {
+            format,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            usage: vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+            aspect: vk::ImageAspectFlags::COLOR,
+            array_layers: layers,
+            // Also an array view with a single layer, so shaders see the same type
+            view_type: Some(vk::ImageViewType::TYPE_2D_ARRAY),
+            use_dedicated_memory: false,
+        };
+        let mut image = Self::new(&create_info, memory_allocator, device)?;
+
+        // Layers that are never uploaded must still be in a readable layout
+        transfer_context.immediate_submit(|cmd, device| {
+            transition_image_layout(
+                cmd,
+                image.image,
+                image.aspect,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                device,
+            );
+            Ok(())
+        })?;
+        image.layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+
+        Ok(image)
+    }
+
+    /// Replace the contents of one layer of an image created with `new_array_image`
+    pub fn upload_layer(
+        &mut self,
+        layer: u32,
+        data: &[u8],
+        transfer_context: &TransferContext,
+    ) -> Result<()> {
+        if layer >= self.array_layers {
+            return Err(eyre!(
+                "Layer {} is out of range for an image with {} layers",
+                layer,
+                self.array_layers,
+            ));
+        }
+        check_upload_size(self.format, self.extent, data.len())?;
+        self.upload_to_layer(
+            data,
+            layer,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            transfer_context,
+        )
+    }
+
+    /// Create an image that is rendered into and then copied to the swapchain
+    pub fn new_draw_image(
+        width: u32,
+        height: u32,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        Self::new_layered_draw_image(width, height, 1, memory_allocator, device)
+    }
+
+    /// Create a draw image with one layer per view, for multiview rendering
+    pub fn new_layered_draw_image(
+        width: u32,
+        height: u32,
+        array_layers: u32,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        let create_info = ImageCreateInfo {
+            format: vk::Format::R8G8B8A8_SRGB,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::SAMPLED,
+            aspect: vk::ImageAspectFlags::COLOR,
+            array_layers,
+            view_type: None,
+            use_dedicated_memory: true, // Draw images are fullscreen attachments
+        };
+        Self::new(&create_info, memory_allocator, device)
+    }
+
+    /// Create a color image for `Renderer::draw_to_image` to copy into when rendering without a
+    /// window, which can be copied out of again, e.g. to read the result back
+    pub fn new_offscreen_image(
+        width: u32,
+        height: u32,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        let create_info = ImageCreateInfo {
+            format: vk::Format::R8G8B8A8_SRGB,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            },
+            usage: vk::ImageUsageFlags::TRANSFER_DST
+                | vk::ImageUsageFlags::TRANSFER_SRC
+                | vk::ImageUsageFlags::SAMPLED,
+            aspect: vk::ImageAspectFlags::COLOR,
+            array_layers: 1,
+            view_type: None,
+            use_dedicated_memory: true,
+        };
+        Self::new(&create_info, memory_allocator, device)
+    }
+
     /// Create a special type of image used for depth buffer
     pub fn new_depth_image(
         width: u32,
         height: u32,
         memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
         device: Arc<ash::Device>,
+    ) -> Result<Self> {
+        Self::new_layered_depth_image(width, height, 1, memory_allocator, device)
+    }
+
+    /// Create a depth image with one layer per view, for multiview rendering
+    pub fn new_layered_depth_image(
+        width: u32,
+        height: u32,
+        array_layers: u32,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
     ) -> Result<Self> {
         let create_info = ImageCreateInfo {
             format: vk::Format::D32_SFLOAT,
@@ -140,6 +361,8 @@ impl Image {
             },
             usage: vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
             aspect: vk::ImageAspectFlags::DEPTH,
+            array_layers,
+            view_type: None,
             use_dedicated_memory: true, // Assuming the depth image will be used as a fullscreen attachment
         };
         Self::new(&create_info, memory_allocator, device)
@@ -168,6 +391,8 @@ impl Image {
                 extent,
                 usage,
                 aspect: vk::ImageAspectFlags::COLOR,
+                array_layers: 1,
+                view_type: None,
                 use_dedicated_memory,
             };
             Image::new(&create_info, memory_allocator, device)?
@@ -176,6 +401,12 @@ impl Image {
         Ok(image)
     }
 
+    pub fn get_layout(&self) -> vk::ImageLayout {
+        self.layout
+    }
+
+    /// Transition from `old_layout` regardless of the tracked layout, e.g. from `UNDEFINED` to
+    /// discard the contents
     pub fn transition_layout(
         &mut self,
         cmd: vk::CommandBuffer,
@@ -190,6 +421,12 @@ impl Image {
             new_layout,
             self.device.as_ref(),
         );
+        self.layout = new_layout;
+    }
+
+    /// Transition from the tracked layout, which records nothing if it already is `new_layout`
+    pub fn transition_to(&mut self, cmd: vk::CommandBuffer, new_layout: vk::ImageLayout) {
+        self.transition_layout(cmd, self.layout, new_layout);
     }
 
     pub fn copy_to_vkimage(
@@ -197,10 +434,22 @@ impl Image {
         cmd: vk::CommandBuffer,
         dst_image: vk::Image,
         dst_image_extent: vk::Extent2D,
+    ) {
+        self.copy_layer_to_vkimage(cmd, 0, dst_image, dst_image_extent);
+    }
+
+    /// Blit one layer of a layered image, e.g. one view of a multiview target, into `dst_image`
+    pub fn copy_layer_to_vkimage(
+        &self,
+        cmd: vk::CommandBuffer,
+        layer: u32,
+        dst_image: vk::Image,
+        dst_image_extent: vk::Extent2D,
     ) {
         copy_image_to_image(
             cmd,
             self.image,
+            layer,
             dst_image,
             vk::Extent2D {
                 width: self.extent.width,
@@ -230,6 +479,18 @@ impl Image {
         &mut self,
         data: &[u8],
         transfer_context: &TransferContext,
+    ) -> Result<()> {
+        self.upload_to_layer(data, 0, vk::ImageLayout::UNDEFINED, transfer_context)
+    }
+
+    /// Copy `data` into `layer` and leave the layer in `SHADER_READ_ONLY_OPTIMAL`. `old_layout`
+    /// is the layer's current layout, or `UNDEFINED` if its contents can be discarded.
+    fn upload_to_layer(
+        &mut self,
+        data: &[u8],
+        layer: u32,
+        old_layout: vk::ImageLayout,
+        transfer_context: &TransferContext,
     ) -> Result<()> {
         let mut staging_buffer = Buffer::new(
             data.len() as u64,
@@ -247,12 +508,12 @@ impl Image {
                     aspect_mask: self.aspect,
                     base_mip_level: 0,
                     level_count: 1,
-                    base_array_layer: 0,
+                    base_array_layer: layer,
                     layer_count: 1,
                 };
 
                 let img_barrier_to_transfer = vk::ImageMemoryBarrier {
-                    old_layout: vk::ImageLayout::UNDEFINED,
+                    old_layout,
                     new_layout: vk::ImageLayout::TRANSFER_DST_OPTIMAL,
                     image: self.image,
                     subresource_range: range,
@@ -283,7 +544,7 @@ impl Image {
                     image_subresource: vk::ImageSubresourceLayers {
                         aspect_mask: self.aspect,
                         mip_level: 0,
-                        base_array_layer: 0,
+                        base_array_layer: layer,
                         layer_count: 1,
                     },
                     image_extent: self.extent,
@@ -323,6 +584,7 @@ impl Image {
                 Ok(())
             },
         )?;
+        self.layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
 
         Ok(())
     }
@@ -343,9 +605,63 @@ impl Drop for Image {
     }
 }
 
+/// Bytes per texel of the uncompressed color formats textures are uploaded in, `None` for others
+fn texel_size(format: vk::Format) -> Option<u64> {
+    match format {
+        vk::Format::R8_UNORM | vk::Format::R8_SNORM | vk::Format::R8_UINT | vk::Format::R8_SRGB => {
+            Some(1)
+        }
+        vk::Format::R8G8_UNORM
+        | vk::Format::R8G8_SNORM
+        | vk::Format::R8G8_UINT
+        | vk::Format::R8G8_SRGB
+        | vk::Format::R16_UNORM
+        | vk::Format::R16_UINT
+        | vk::Format::R16_SFLOAT => Some(2),
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::B10G11R11_UFLOAT_PACK32
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R32_UINT
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_UINT | vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+/// Fail unless `data_len` bytes are exactly one layer of `extent` texels in `format`
+fn check_upload_size(format: vk::Format, extent: vk::Extent3D, data_len: usize) -> Result<()> {
+    let texel_size = texel_size(format)
+        .ok_or_else(|| eyre!("Uploading to images in {:?} is not supported", format))?;
+    let expected = extent.width as u64 * extent.height as u64 * extent.depth as u64 * texel_size;
+    if data_len as u64 != expected {
+        return Err(eyre!(
+            "Expected {} bytes for {}x{}x{} texels of {:?}, got {}",
+            expected,
+            extent.width,
+            extent.height,
+            extent.depth,
+            format,
+            data_len,
+        ));
+    }
+    Ok(())
+}
+
 fn copy_image_to_image(
     cmd: vk::CommandBuffer,
     src: vk::Image,
+    src_layer: u32,
     dst: vk::Image,
     src_size: vk::Extent2D,
     dst_size: vk::Extent2D,
@@ -370,7 +686,7 @@ fn copy_image_to_image(
         ],
         src_subresource: vk::ImageSubresourceLayers {
             aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_array_layer: 0,
+            base_array_layer: src_layer,
             layer_count: 1,
             mip_level: 0,
         },
@@ -399,7 +715,7 @@ fn copy_image_to_image(
     }
 }
 
-fn transition_image_layout(
+pub fn transition_image_layout(
     cmd: vk::CommandBuffer,
     image: vk::Image,
     image_aspect: vk::ImageAspectFlags,
@@ -424,7 +740,8 @@ fn transition_image_layout(
             base_mip_level: 0,
             level_count: 1,
             base_array_layer: 0,
-            layer_count: 1,
+            // Covers every layer of layered images
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
         },
         image,
         ..Default::default()
@@ -440,3 +757,32 @@ fn transition_image_layout(
         device.cmd_pipeline_barrier2(cmd, &dep_info);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32, depth: u32) -> vk::Extent3D {
+        vk::Extent3D { width, height, depth }
+    }
+
+    #[test]
+    fn upload_size_must_match_one_layer() {
+        let format = vk::Format::R8G8B8A8_SRGB;
+        assert!(check_upload_size(format, extent(4, 2, 1), 32).is_ok());
+        assert!(check_upload_size(format, extent(4, 2, 1), 31).is_err());
+        assert!(check_upload_size(format, extent(4, 2, 1), 64).is_err());
+    }
+
+    #[test]
+    fn upload_size_covers_the_depth_of_3d_images() {
+        let format = vk::Format::R32G32B32A32_SFLOAT;
+        assert!(check_upload_size(format, extent(2, 2, 2), 128).is_ok());
+        assert!(check_upload_size(format, extent(2, 2, 2), 64).is_err());
+    }
+
+    #[test]
+    fn upload_size_rejects_unknown_formats() {
+        assert!(check_upload_size(vk::Format::BC1_RGB_UNORM_BLOCK, extent(4, 4, 1), 8).is_err());
+    }
+}
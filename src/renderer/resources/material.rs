@@ -2,32 +2,178 @@ use crate::renderer::contexts::device_ctx::device::DescriptorAshDevice;
 use crate::renderer::resources::shader::{ComputeShader, GraphicsShader};
 use crate::renderer::resources::vertex::VertexInputDescription;
 use ash::vk;
+use ash::vk::Handle;
 use color_eyre::eyre::{eyre, OptionExt};
 use color_eyre::Result;
 use gpu_descriptor::{DescriptorAllocator, DescriptorSetLayoutCreateFlags, DescriptorTotalCount};
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use crate::renderer::config::BindlessLimits;
 use crate::renderer::contexts::resource_ctx::resource_type::RenderResourceType;
+use crate::renderer::resources::buffer::Buffer;
 
-pub struct Material<'a> {
-    pipeline: &'a vk::Pipeline,
-    pipeline_layout: &'a vk::PipelineLayout,
-    pipeline_bind_point: &'a vk::PipelineBindPoint,
-    descriptor_set: gpu_descriptor::DescriptorSet<vk::DescriptorSet>,
+// Binding of the bindless layout that holds per-material data
+const MATERIAL_DATA_BINDING: u32 = 1;
+// Most bytes `vkCmdUpdateBuffer` writes at once
+const MAX_UPDATE_BUFFER_SIZE: usize = 65536;
+
+/// Pipeline shared by a `MaterialFactory` and the materials it creates, destroyed when the last
+/// of them is dropped. The pipeline layout is only borrowed; it belongs to whoever passed it to
+/// the builder and must outlive the pipeline.
+struct MaterialPipeline {
+    // Raw `vk::Pipeline`, swapped by `MaterialFactory::replace_pipeline` so that every material
+    // sharing it switches at once
+    pipeline: AtomicU64,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline_bind_point: vk::PipelineBindPoint,
+    // The depth test follows the encoder's depth direction, see `CommandEncoder::set_reverse_z`
+    dynamic_depth_compare_op: bool,
+
+    device: Arc<ash::Device>,
+}
+
+impl MaterialPipeline {
+    fn new(
+        pipeline: vk::Pipeline,
+        pipeline_layout: vk::PipelineLayout,
+        pipeline_bind_point: vk::PipelineBindPoint,
+        dynamic_depth_compare_op: bool,
+        device: Arc<ash::Device>,
+    ) -> Self {
+        Self {
+            pipeline: AtomicU64::new(pipeline.as_raw()),
+            pipeline_layout,
+            pipeline_bind_point,
+            dynamic_depth_compare_op,
+            device,
+        }
+    }
+
+    fn get_pipeline(&self) -> vk::Pipeline {
+        vk::Pipeline::from_raw(self.pipeline.load(Ordering::Acquire))
+    }
+}
+
+impl Drop for MaterialPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.get_pipeline(), None);
+        }
+    }
+}
+
+/// Host-visible storage buffer split into equally sized slots, one per material, shared by a
+/// `MaterialFactory` and its materials
+struct MaterialDataBuffer {
+    buffer: Buffer,
+    slot_size: u64,
+    slot_count: u32,
+    next_slot: u32,
+    // Slots of dropped materials, handed out again before new ones
+    free_slots: Vec<u32>,
+}
 
-    device: &'a ash::Device,
+impl MaterialDataBuffer {
+    fn allocate_slot(&mut self) -> Result<u32> {
+        if let Some(slot) = self.free_slots.pop() {
+            return Ok(slot);
+        }
+        if self.next_slot == self.slot_count {
+            return Err(eyre!(
+                "All {} material data slots of the factory are in use",
+                self.slot_count,
+            ));
+        }
+        self.next_slot += 1;
+        Ok(self.next_slot - 1)
+    }
 }
 
-impl<'a> Material<'a> {
+/// A material owns its descriptor set and shares the pipeline of the `MaterialFactory` that
+/// created it, so it can be stored on its own and outlive the factory. Dropping the last owner of
+/// the pipeline destroys it, so command buffers using it must have finished by then.
+pub struct Material {
+    pipeline: Arc<MaterialPipeline>,
+    descriptor_set: gpu_descriptor::DescriptorSet<vk::DescriptorSet>,
+    // The factory's data buffer and this material's slot in it, when the factory has one
+    data_slot: Option<(Arc<Mutex<MaterialDataBuffer>>, u32)>,
+    // Written into the slot by the next `record_data_upload`
+    pending_data: Option<Vec<u8>>,
+}
+
+impl Material {
+    /// Stage `data` for this material's slot of the factory's material data buffer, which
+    /// binding 1 of its descriptor set points at. `T` must match the shader's per-material
+    /// struct. The slot is written by `record_data_upload` in the next recorded frame, so draws
+    /// already submitted keep reading the old data.
+    pub fn set_material_data<T: bytemuck::Pod>(&mut self, data: &T) -> Result<()> {
+        let (data_buffer, _) = self.data_slot
+            .as_ref()
+            .ok_or_eyre("The material's factory was built without a material data buffer")?;
+        let slot_size = data_buffer
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .slot_size;
+        if size_of::<T>() as u64 > slot_size {
+            return Err(eyre!(
+                "Material data of {} bytes does not fit in a slot of {} bytes",
+                size_of::<T>(),
+                slot_size,
+            ));
+        }
+        if size_of::<T>() > MAX_UPDATE_BUFFER_SIZE {
+            return Err(eyre!(
+                "Material data of {} bytes is over the {} bytes a buffer update can write",
+                size_of::<T>(),
+                MAX_UPDATE_BUFFER_SIZE,
+            ));
+        }
+        // Buffer updates write whole words
+        let mut bytes = bytemuck::bytes_of(data).to_vec();
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        self.pending_data = Some(bytes);
+        Ok(())
+    }
+
+    /// Whether `set_material_data` staged data that has not been recorded yet
+    pub fn has_pending_data(&self) -> bool {
+        self.pending_data.is_some()
+    }
+
+    /// Record the write of the data staged by `set_material_data`, if any, into
+    /// `command_buffer`. The caller orders it after the reads of earlier frames and before the
+    /// reads of this one with barriers.
+    pub fn record_data_upload(&mut self, command_buffer: vk::CommandBuffer) -> Result<()> {
+        let (Some(bytes), Some((data_buffer, slot))) = (
+            self.pending_data.take(),
+            self.data_slot.as_ref(),
+        ) else {
+            return Ok(());
+        };
+        let data_buffer = data_buffer
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        unsafe {
+            self.pipeline.device.cmd_update_buffer(
+                command_buffer,
+                data_buffer.buffer.buffer,
+                *slot as u64 * data_buffer.slot_size,
+                &bytes,
+            );
+        }
+        Ok(())
+    }
+
     pub fn update_push_constants(
         &self,
         command_buffer: vk::CommandBuffer,
         data: &[u8],
     ) {
         unsafe {
-            self.device.cmd_push_constants(
+            self.pipeline.device.cmd_push_constants(
                 command_buffer,
-                *self.pipeline_layout,
+                self.pipeline.pipeline_layout,
                 vk::ShaderStageFlags::ALL,
                 0,
                 data,
@@ -40,52 +186,220 @@ impl<'a> Material<'a> {
         command_buffer: vk::CommandBuffer,
     ) {
         unsafe {
-            self.device.cmd_bind_pipeline(
+            self.pipeline.device.cmd_bind_pipeline(
                 command_buffer,
-                *self.pipeline_bind_point,
-                *self.pipeline,
+                self.pipeline.pipeline_bind_point,
+                self.pipeline.get_pipeline(),
             );
         }
     }
 
+    /// Whether the pipeline's depth compare op has to be set with
+    /// `CommandEncoder::apply_depth_compare_op` after binding it
+    pub fn has_dynamic_depth_compare_op(&self) -> bool {
+        self.pipeline.dynamic_depth_compare_op
+    }
+
     pub fn bind_descriptor_sets(
         &self,
         command_buffer: vk::CommandBuffer,
     ) {
         let descriptor_sets = [*self.descriptor_set.raw()];
         unsafe {
-            self.device.cmd_bind_descriptor_sets(
+            self.pipeline.device.cmd_bind_descriptor_sets(
                 command_buffer,
-                *self.pipeline_bind_point,
-                *self.pipeline_layout,
+                self.pipeline.pipeline_bind_point,
+                self.pipeline.pipeline_layout,
                 0,
                 &descriptor_sets,
                 &[],
             );
         }
     }
+
+    /// Point `binding` of the descriptor set at `range` bytes of `buffer` from `offset`
+    pub fn write_uniform_buffer(
+        &mut self,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    ) {
+        self.write_buffer(binding, vk::DescriptorType::UNIFORM_BUFFER, buffer, offset, range);
+    }
+
+    /// Point `binding` of the descriptor set at `range` bytes of `buffer` from `offset`
+    pub fn write_storage_buffer(
+        &mut self,
+        binding: u32,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    ) {
+        self.write_buffer(binding, vk::DescriptorType::STORAGE_BUFFER, buffer, offset, range);
+    }
+
+    /// Put `image_view`, in `SHADER_READ_ONLY_OPTIMAL`, at `array_index` of `binding`. With a
+    /// sampler the binding must be a combined image sampler; the bindless layout samples images
+    /// with separate samplers, written with `write_sampler`.
+    pub fn write_sampled_image(
+        &mut self,
+        binding: u32,
+        array_index: u32,
+        image_view: vk::ImageView,
+        sampler: Option<vk::Sampler>,
+    ) {
+        let descriptor_type = match sampler {
+            Some(_) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            None => vk::DescriptorType::SAMPLED_IMAGE,
+        };
+        let image_info = [vk::DescriptorImageInfo::default()
+            .sampler(sampler.unwrap_or_default())
+            .image_view(image_view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(*self.descriptor_set.raw())
+            .dst_binding(binding)
+            .dst_array_element(array_index)
+            .descriptor_type(descriptor_type)
+            .image_info(&image_info);
+        unsafe {
+            self.pipeline.device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    /// Put `sampler` at `array_index` of `binding`
+    pub fn write_sampler(&mut self, binding: u32, array_index: u32, sampler: vk::Sampler) {
+        let image_info = [vk::DescriptorImageInfo::default().sampler(sampler)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(*self.descriptor_set.raw())
+            .dst_binding(binding)
+            .dst_array_element(array_index)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .image_info(&image_info);
+        unsafe {
+            self.pipeline.device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+
+    fn write_buffer(
+        &mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        buffer: vk::Buffer,
+        offset: u64,
+        range: u64,
+    ) {
+        let buffer_info = [vk::DescriptorBufferInfo::default()
+            .buffer(buffer)
+            .offset(offset)
+            .range(range)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(*self.descriptor_set.raw())
+            .dst_binding(binding)
+            .descriptor_type(descriptor_type)
+            .buffer_info(&buffer_info);
+        unsafe {
+            self.pipeline.device.update_descriptor_sets(&[write], &[]);
+        }
+    }
+}
+
+impl Drop for Material {
+    fn drop(&mut self) {
+        if let Some((data_buffer, slot)) = self.data_slot.take()
+            && let Ok(mut data_buffer) = data_buffer.lock()
+        {
+            data_buffer.free_slots.push(slot);
+        }
+    }
 }
 
+/// Creates materials that share one pipeline. The pipeline is destroyed once the factory and all
+/// of its materials are dropped. The pipeline layout and descriptor set layout given to the
+/// builder are not owned by the factory, since several factories may share them.
 pub struct MaterialFactory {
-    pipeline: vk::Pipeline,
-    pipeline_layout: vk::PipelineLayout,
-    pipeline_bind_point: vk::PipelineBindPoint,
+    pipeline: Arc<MaterialPipeline>,
+    // Of the shader the pipeline was built from
+    shader_name: String,
+    material_data: Option<Arc<Mutex<MaterialDataBuffer>>>,
     descriptor_set_layout: vk::DescriptorSetLayout,
+    // Sizes the descriptor sets allocated for materials
+    bindless_limits: BindlessLimits,
     
     device: Arc<ash::Device>,
     descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
 }
 
 impl MaterialFactory {
+    pub fn get_pipeline_layout(&self) -> vk::PipelineLayout {
+        self.pipeline.pipeline_layout
+    }
+
+    pub fn get_descriptor_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.descriptor_set_layout
+    }
+
+    /// Name of the shader the pipeline was built from, see `GraphicsShader::get_name`
+    pub fn get_shader_name(&self) -> &str {
+        &self.shader_name
+    }
+
+    /// Switch this factory and all of its materials over to the pipeline of `rebuilt`, a factory
+    /// built the same way, e.g. after its shader was recompiled. The old pipeline is destroyed
+    /// along with `rebuilt`, so command buffers using it must have finished.
+    pub fn replace_pipeline(&mut self, rebuilt: MaterialFactory) {
+        let new_pipeline = rebuilt.pipeline.pipeline.load(Ordering::Acquire);
+        let old_pipeline = self.pipeline.pipeline.swap(new_pipeline, Ordering::AcqRel);
+        rebuilt.pipeline.pipeline.store(old_pipeline, Ordering::Release);
+    }
+
+    /// Bind the pipeline directly, for passes that manage their own descriptor sets instead of
+    /// creating materials
+    pub fn bind_pipeline(&self, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            self.device.cmd_bind_pipeline(
+                command_buffer,
+                self.pipeline.pipeline_bind_point,
+                self.pipeline.get_pipeline(),
+            );
+        }
+    }
+
     pub fn create_material(&mut self) -> Result<Material> {
         let descriptor_set = self.allocate_descriptor_sets()?;
-        Ok(Material {
-            pipeline: &self.pipeline,
-            pipeline_layout: &self.pipeline_layout,
-            pipeline_bind_point: &self.pipeline_bind_point,
+        let data_slot = match self.material_data.as_ref() {
+            Some(data_buffer) => {
+                let slot = data_buffer
+                    .lock()
+                    .map_err(|e| eyre!(e.to_string()))?
+                    .allocate_slot()?;
+                Some((data_buffer.clone(), slot))
+            }
+            None => None,
+        };
+        let mut material = Material {
+            pipeline: self.pipeline.clone(),
             descriptor_set,
-            device: &self.device,
-        })
+            data_slot,
+            pending_data: None,
+        };
+
+        if let Some((data_buffer, slot)) = material.data_slot.as_ref() {
+            let (buffer, slot_size) = {
+                let mut data_buffer = data_buffer
+                    .lock()
+                    .map_err(|e| eyre!(e.to_string()))?;
+                let slot_size = data_buffer.slot_size;
+                // Start from zeroes rather than whatever a dropped material left behind
+                let zeroes = vec![0u8; slot_size as usize];
+                data_buffer.buffer.write(&zeroes, (*slot as u64 * slot_size) as usize)?;
+                (data_buffer.buffer.buffer, slot_size)
+            };
+            let offset = *slot as u64 * slot_size;
+            material.write_storage_buffer(MATERIAL_DATA_BINDING, buffer, offset, slot_size);
+        }
+        Ok(material)
     }
 
     fn allocate_descriptor_sets(&mut self) -> Result<gpu_descriptor::DescriptorSet<vk::DescriptorSet>> {
@@ -98,14 +412,19 @@ impl MaterialFactory {
                     &self.descriptor_set_layout,
                     DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND,
                     &DescriptorTotalCount {
-                        sampler: RenderResourceType::Sampler.descriptor_count(),
+                        sampler: RenderResourceType::Sampler
+                            .descriptor_count(&self.bindless_limits),
                         combined_image_sampler: 0,
-                        sampled_image: RenderResourceType::SampledImage.descriptor_count(),
-                        storage_image: RenderResourceType::StorageImage.descriptor_count(),
+                        sampled_image: RenderResourceType::SampledImage
+                            .descriptor_count(&self.bindless_limits),
+                        storage_image: RenderResourceType::StorageImage
+                            .descriptor_count(&self.bindless_limits),
                         uniform_texel_buffer: 0,
                         storage_texel_buffer: 0,
-                        uniform_buffer: RenderResourceType::UniformBuffer.descriptor_count(),
-                        storage_buffer: RenderResourceType::StorageBuffer.descriptor_count(),
+                        uniform_buffer: RenderResourceType::UniformBuffer
+                            .descriptor_count(&self.bindless_limits),
+                        storage_buffer: RenderResourceType::StorageBuffer
+                            .descriptor_count(&self.bindless_limits),
                         uniform_buffer_dynamic: 0,
                         storage_buffer_dynamic: 0,
                         input_attachment: 0,
@@ -127,6 +446,13 @@ pub struct GraphicsMaterialFactoryBuilder<'a> {
     input_assembly: vk::PipelineInputAssemblyStateCreateInfo<'a>,
     rasterization: vk::PipelineRasterizationStateCreateInfo<'a>,
     color_blend_attachment: vk::PipelineColorBlendAttachmentState,
+    logic_op: Option<vk::LogicOp>,
+    dynamic_depth_bounds: bool,
+    dynamic_depth_compare_op: bool,
+    supported_features: Option<vk::PhysicalDeviceFeatures>,
+    supported_extensions: Vec<&'static CStr>,
+    // Mode and extra primitive overestimation size
+    conservative_rasterization: Option<(vk::ConservativeRasterizationModeEXT, f32)>,
     multisample: vk::PipelineMultisampleStateCreateInfo<'a>,
     depth_stencil: vk::PipelineDepthStencilStateCreateInfo<'a>,
     color_attachment_format: vk::Format,
@@ -134,6 +460,10 @@ pub struct GraphicsMaterialFactoryBuilder<'a> {
     shader: Option<GraphicsShader>,
     pipeline_layout: Option<vk::PipelineLayout>,
     descriptor_set_layout: Option<vk::DescriptorSetLayout>,
+    bindless_limits: BindlessLimits,
+    // Slot size, slot count and the allocator of the material data buffer
+    material_data: Option<(u64, u32, Arc<Mutex<vk_mem::Allocator>>)>,
+    pipeline_cache: vk::PipelineCache,
     
     device: Arc<ash::Device>,
     descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
@@ -148,6 +478,13 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         let input_assembly = Self::default_input_assembly_info();
         let rasterization = Self::default_rasterization_info();
         let color_blend_attachment = Self::default_color_blend_state();
+        let logic_op = None;
+        let dynamic_depth_bounds = false;
+        // The default depth test follows the encoder's depth direction
+        let dynamic_depth_compare_op = true;
+        let supported_features = None;
+        let supported_extensions = Vec::new();
+        let conservative_rasterization = None;
         let multisample = Self::default_multisample_info();
         let depth_stencil = Self::default_depth_stencil_info();
         let color_attachment_format = vk::Format::UNDEFINED;
@@ -155,12 +492,21 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         let shader = None;
         let pipeline_layout = None;
         let descriptor_set_layout = None;
+        let bindless_limits = BindlessLimits::default();
+        let material_data = None;
+        let pipeline_cache = vk::PipelineCache::null();
 
         Self {
             vertex_input_description,
             input_assembly,
             rasterization,
             color_blend_attachment,
+            logic_op,
+            dynamic_depth_bounds,
+            dynamic_depth_compare_op,
+            supported_features,
+            supported_extensions,
+            conservative_rasterization,
             multisample,
             depth_stencil,
             color_attachment_format,
@@ -168,6 +514,9 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
             shader,
             pipeline_layout,
             descriptor_set_layout,
+            bindless_limits,
+            material_data,
+            pipeline_cache,
             
             device,
             descriptor_allocator,
@@ -189,6 +538,27 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         self
     }
 
+    /// Must match the limits the descriptor set layout was created with, see
+    /// `RenderDevice::get_bindless_limits`
+    pub fn with_bindless_limits(mut self, limits: BindlessLimits) -> Self {
+        self.bindless_limits = limits;
+        self
+    }
+
+    /// Give the factory a storage buffer of `slot_count` slots of `slot_size` bytes, one for each
+    /// material, that binding 1 of the material's descriptor set points at and
+    /// `Material::set_material_data` writes to. `slot_size` must be a multiple of the device's
+    /// `minStorageBufferOffsetAlignment`.
+    pub fn with_material_data(
+        mut self,
+        slot_size: u64,
+        slot_count: u32,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+    ) -> Self {
+        self.material_data = Some((slot_size, slot_count, memory_allocator));
+        self
+    }
+
     pub fn with_input_topology(mut self, topology: vk::PrimitiveTopology) -> Self {
         self.input_assembly.topology = topology;
         self.input_assembly.primitive_restart_enable = vk::FALSE;
@@ -223,9 +593,6 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
     }
 
     pub fn with_blending_disabled(mut self) -> Self {
-        // Default RGBA write mask
-        self.color_blend_attachment.color_write_mask =
-            vk::ColorComponentFlags::RGBA;
         // No blending
         self.color_blend_attachment.blend_enable = vk::FALSE;
         self
@@ -234,7 +601,6 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
     // Make sure the transparent object is rendered AFTER the opaque ones
     pub fn with_alpha_blending_enabled(mut self) -> Self {
         let blend = &mut self.color_blend_attachment;
-        blend.color_write_mask = vk::ColorComponentFlags::RGBA;
         blend.blend_enable = vk::TRUE;
         blend.src_color_blend_factor = vk::BlendFactor::SRC_ALPHA;
         blend.dst_color_blend_factor = vk::BlendFactor::ONE_MINUS_SRC_ALPHA;
@@ -245,19 +611,42 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         self
     }
 
-    pub fn with_additive_blending_enabled(mut self) -> Self {
+    /// For textures whose color is already multiplied by their alpha
+    pub fn with_premultiplied_alpha_blending_enabled(mut self) -> Self {
         let blend = &mut self.color_blend_attachment;
-        blend.color_write_mask = vk::ColorComponentFlags::RGBA;
         blend.blend_enable = vk::TRUE;
         blend.src_color_blend_factor = vk::BlendFactor::ONE;
-        blend.dst_color_blend_factor = vk::BlendFactor::DST_ALPHA;
+        blend.dst_color_blend_factor = vk::BlendFactor::ONE_MINUS_SRC_ALPHA;
         blend.color_blend_op = vk::BlendOp::ADD;
         blend.src_alpha_blend_factor = vk::BlendFactor::ONE;
-        blend.dst_alpha_blend_factor = vk::BlendFactor::ZERO;
+        blend.dst_alpha_blend_factor = vk::BlendFactor::ONE_MINUS_SRC_ALPHA;
         blend.alpha_blend_op = vk::BlendOp::ADD;
         self
     }
 
+    // Adds the source color onto the destination (src * 1 + dst * 1), for glows and particles.
+    // Overlapping quads sum up, so the result can exceed 1 and clamp in UNORM targets.
+    pub fn with_additive_blending_enabled(mut self) -> Self {
+        Self::enable_additive_blending(&mut self.color_blend_attachment);
+        self
+    }
+
+    /// Combine the fragment color with the attachment using a bitwise operation like XOR instead
+    /// of blending. Blending is ignored while a logic op is enabled, and logic ops are not applied
+    /// to float or sRGB attachments. Requires the `logic_op` device feature, see
+    /// `RenderDevice::get_features` and `with_supported_features`.
+    pub fn with_logic_op(mut self, op: vk::LogicOp) -> Self {
+        self.logic_op = Some(op);
+        self
+    }
+
+    /// Only write the given channels of the color attachment, e.g. `ColorComponentFlags::A` to
+    /// write just alpha. Defaults to RGBA and is kept when the blending mode changes.
+    pub fn with_color_write_mask(mut self, mask: vk::ColorComponentFlags) -> Self {
+        self.color_blend_attachment.color_write_mask = mask;
+        self
+    }
+
     pub fn with_color_attachment_format(mut self, format: vk::Format) -> Self {
         self.color_attachment_format = format;
         // Connect the format to the rendering_info struct
@@ -272,6 +661,16 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         self
     }
 
+    /// Render to every view whose bit is set in `view_mask` in a single pass. The attachments must
+    /// have a layer per view and the shader picks its view with `gl_ViewIndex`.
+    pub fn with_view_mask(mut self, view_mask: u32) -> Self {
+        self.rendering_info.view_mask = view_mask;
+        self
+    }
+
+    /// Test and write depth when `enable` is set. Without a `compare` op, the test passes
+    /// fragments nearer to the camera whether or not it uses reverse-Z, picked with
+    /// `CommandEncoder::set_reverse_z` while recording.
     pub fn with_depth_test(
         mut self,
         enable: bool,
@@ -282,16 +681,57 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         self.depth_stencil.depth_write_enable =
             if enable { vk::TRUE } else { vk::FALSE };
         self.depth_stencil.depth_compare_op = if enable {
-            if let Some(compare) = compare {
-                compare
-            } else {
-                vk::CompareOp::LESS_OR_EQUAL
-            }
+            compare.unwrap_or(vk::CompareOp::LESS_OR_EQUAL)
         } else {
             vk::CompareOp::ALWAYS
         };
-        self.depth_stencil.min_depth_bounds = 0.0;
-        self.depth_stencil.max_depth_bounds = 1.0;
+        self.dynamic_depth_compare_op = enable && compare.is_none();
+        self
+    }
+
+    /// Discard fragments where the depth already in the attachment is outside `min..=max`, e.g.
+    /// to skip pixels outside a light volume. Requires the `depth_bounds` device feature; it is
+    /// turned off with a warning if `with_supported_features` says the device lacks it.
+    pub fn with_depth_bounds(mut self, min: f32, max: f32) -> Self {
+        self.depth_stencil.depth_bounds_test_enable = vk::TRUE;
+        self.depth_stencil.min_depth_bounds = min;
+        self.depth_stencil.max_depth_bounds = max;
+        self
+    }
+
+    /// Set the depth bounds while recording with `CommandEncoder::set_depth_bounds` instead of
+    /// fixing them in the pipeline
+    pub fn with_dynamic_depth_bounds(mut self) -> Self {
+        self.depth_stencil.depth_bounds_test_enable = vk::TRUE;
+        self.dynamic_depth_bounds = true;
+        self
+    }
+
+    /// Let `build` turn off optional features the device doesn't support instead of creating an
+    /// invalid pipeline
+    pub fn with_supported_features(mut self, features: &vk::PhysicalDeviceFeatures) -> Self {
+        self.supported_features = Some(*features);
+        self
+    }
+
+    /// Optional device extensions that are enabled, from `RenderDevice::get_optional_extensions`.
+    /// Options that rely on one fail to build unless it is listed here.
+    pub fn with_supported_extensions(mut self, extensions: &[&'static CStr]) -> Self {
+        self.supported_extensions = extensions.to_vec();
+        self
+    }
+
+    /// Rasterize every pixel a triangle touches (overestimate) or only pixels it fully covers
+    /// (underestimate), e.g. for voxelization. `extra_primitive_overestimation_size` grows
+    /// triangles by that many pixels in overestimate mode, up to the device's
+    /// `max_extra_primitive_overestimation_size`. Requires `VK_EXT_conservative_rasterization`,
+    /// see `with_supported_extensions`.
+    pub fn with_conservative_rasterization(
+        mut self,
+        mode: vk::ConservativeRasterizationModeEXT,
+        extra_primitive_overestimation_size: f32,
+    ) -> Self {
+        self.conservative_rasterization = Some((mode, extra_primitive_overestimation_size));
         self
     }
 
@@ -300,6 +740,12 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         self
     }
 
+    /// Cache to create the pipeline through, see `RenderConfig::pipeline_cache_path`
+    pub fn with_pipeline_cache(mut self, pipeline_cache: vk::PipelineCache) -> Self {
+        self.pipeline_cache = pipeline_cache;
+        self
+    }
+
     pub fn build(mut self) -> Result<MaterialFactory> {
         let device = self.device;
 
@@ -327,6 +773,29 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
             "No descriptor set layout provided for GraphicsMaterialBuilder",
         )?;
 
+        if let Some(features) = self.supported_features.as_ref() {
+            if self.logic_op.is_some() && features.logic_op != vk::TRUE {
+                log::warn!("Logic ops are not supported by the device, falling back to blending");
+                self.logic_op = None;
+            }
+            if self.depth_stencil.depth_bounds_test_enable == vk::TRUE
+                && features.depth_bounds != vk::TRUE
+            {
+                log::warn!("Depth bounds test is not supported by the device, disabling it");
+                self.depth_stencil.depth_bounds_test_enable = vk::FALSE;
+                self.dynamic_depth_bounds = false;
+            }
+        }
+
+        if self.conservative_rasterization.is_some()
+            && !self.supported_extensions.contains(&ash::ext::conservative_rasterization::NAME)
+        {
+            return Err(eyre!(
+                "Conservative rasterization requires {:?}, which is not enabled on the device",
+                ash::ext::conservative_rasterization::NAME,
+            ));
+        }
+
         let viewport_state = vk::PipelineViewportStateCreateInfo {
             viewport_count: 1,
             scissor_count: 1,
@@ -334,19 +803,35 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         };
 
         let color_blend_info = vk::PipelineColorBlendStateCreateInfo {
-            logic_op_enable: vk::FALSE,
-            logic_op: vk::LogicOp::COPY,
+            logic_op_enable: if self.logic_op.is_some() { vk::TRUE } else { vk::FALSE },
+            logic_op: self.logic_op.unwrap_or(vk::LogicOp::COPY),
             attachment_count: 1,
             p_attachments: &self.color_blend_attachment,
             ..Default::default()
         };
 
         // Use dynamic state for viewport and scissor configuration
-        let dynamic_states =
-            [vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        let mut dynamic_states =
+            vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if self.dynamic_depth_bounds {
+            dynamic_states.push(vk::DynamicState::DEPTH_BOUNDS);
+        }
+        if self.dynamic_depth_compare_op {
+            dynamic_states.push(vk::DynamicState::DEPTH_COMPARE_OP);
+        }
         let dynamic_info = vk::PipelineDynamicStateCreateInfo::default()
             .dynamic_states(&dynamic_states);
 
+        let mut conservative_rasterization_info =
+            vk::PipelineRasterizationConservativeStateCreateInfoEXT::default();
+        let mut rasterization = self.rasterization;
+        if let Some((mode, size)) = self.conservative_rasterization {
+            conservative_rasterization_info = conservative_rasterization_info
+                .conservative_rasterization_mode(mode)
+                .extra_primitive_overestimation_size(size);
+            rasterization = rasterization.push_next(&mut conservative_rasterization_info);
+        }
+
         let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
             .vertex_attribute_descriptions(&self.vertex_input_description.attributes)
             .vertex_binding_descriptions(&self.vertex_input_description.bindings)
@@ -359,7 +844,7 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
             .vertex_input_state(&vertex_input)
             .input_assembly_state(&self.input_assembly)
             .viewport_state(&viewport_state)
-            .rasterization_state(&self.rasterization)
+            .rasterization_state(&rasterization)
             .multisample_state(&self.multisample)
             .color_blend_state(&color_blend_info)
             .depth_stencil_state(&self.depth_stencil)
@@ -367,7 +852,7 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
         
         let pipeline = unsafe {
             match device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
+                self.pipeline_cache,
                 &[pipeline_info],
                 None,
             ) {
@@ -376,11 +861,40 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
             }
         }?[0];
 
+        let material_data = match self.material_data.take() {
+            Some((slot_size, slot_count, memory_allocator)) => {
+                let buffer = Buffer::new(
+                    slot_size * slot_count as u64,
+                    slot_size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                    vk_mem::MemoryUsage::AutoPreferHost,
+                    true,
+                    memory_allocator,
+                    device.clone(),
+                )?;
+                Some(Arc::new(Mutex::new(MaterialDataBuffer {
+                    buffer,
+                    slot_size,
+                    slot_count,
+                    next_slot: 0,
+                    free_slots: Vec::new(),
+                })))
+            }
+            None => None,
+        };
+
         Ok(MaterialFactory {
-            pipeline,
-            pipeline_layout,
-            pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+            pipeline: Arc::new(MaterialPipeline::new(
+                pipeline,
+                pipeline_layout,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.dynamic_depth_compare_op,
+                device.clone(),
+            )),
+            shader_name: shader.get_name().to_string(),
+            material_data,
             descriptor_set_layout,
+            bindless_limits: self.bindless_limits,
             device,
             descriptor_allocator: self.descriptor_allocator,
         })
@@ -424,6 +938,16 @@ impl<'a> GraphicsMaterialFactoryBuilder<'a> {
             .alpha_blend_op(vk::BlendOp::ADD)
     }
 
+    fn enable_additive_blending(blend: &mut vk::PipelineColorBlendAttachmentState) {
+        blend.blend_enable = vk::TRUE;
+        blend.src_color_blend_factor = vk::BlendFactor::ONE;
+        blend.dst_color_blend_factor = vk::BlendFactor::ONE;
+        blend.color_blend_op = vk::BlendOp::ADD;
+        blend.src_alpha_blend_factor = vk::BlendFactor::ONE;
+        blend.dst_alpha_blend_factor = vk::BlendFactor::ZERO;
+        blend.alpha_blend_op = vk::BlendOp::ADD;
+    }
+
     fn default_multisample_info() -> vk::PipelineMultisampleStateCreateInfo<'a> {
         vk::PipelineMultisampleStateCreateInfo::default()
             .sample_shading_enable(false)
@@ -450,6 +974,8 @@ pub struct ComputeMaterialFactoryBuilder {
     shader: Option<ComputeShader>,
     pipeline_layout: Option<vk::PipelineLayout>,
     descriptor_set_layout: Option<vk::DescriptorSetLayout>,
+    bindless_limits: BindlessLimits,
+    pipeline_cache: vk::PipelineCache,
 
     device: Arc<ash::Device>,
     descriptor_allocator: Arc<Mutex<DescriptorAllocator<vk::DescriptorPool, vk::DescriptorSet>>>,
@@ -464,6 +990,8 @@ impl ComputeMaterialFactoryBuilder {
             shader: None,
             pipeline_layout: None,
             descriptor_set_layout: None,
+            bindless_limits: BindlessLimits::default(),
+            pipeline_cache: vk::PipelineCache::null(),
             device,
             descriptor_allocator,
         }
@@ -484,6 +1012,19 @@ impl ComputeMaterialFactoryBuilder {
         self
     }
 
+    /// Must match the limits the descriptor set layout was created with, see
+    /// `RenderDevice::get_bindless_limits`
+    pub fn with_bindless_limits(mut self, limits: BindlessLimits) -> Self {
+        self.bindless_limits = limits;
+        self
+    }
+
+    /// Cache to create the pipeline through, see `RenderConfig::pipeline_cache_path`
+    pub fn with_pipeline_cache(mut self, pipeline_cache: vk::PipelineCache) -> Self {
+        self.pipeline_cache = pipeline_cache;
+        self
+    }
+
     pub fn build(mut self) -> Result<MaterialFactory> {
         let shader = self
             .shader
@@ -508,7 +1049,7 @@ impl ComputeMaterialFactoryBuilder {
             .stage(stage_info);
         let pipeline = unsafe {
             match self.device.create_compute_pipelines(
-                vk::PipelineCache::null(),
+                self.pipeline_cache,
                 &[pipeline_info],
                 None,
             ) {
@@ -518,12 +1059,36 @@ impl ComputeMaterialFactoryBuilder {
         }?[0];
 
         Ok(MaterialFactory {
-            pipeline,
-            pipeline_layout,
-            pipeline_bind_point: vk::PipelineBindPoint::COMPUTE,
+            pipeline: Arc::new(MaterialPipeline::new(
+                pipeline,
+                pipeline_layout,
+                vk::PipelineBindPoint::COMPUTE,
+                false,
+                self.device.clone(),
+            )),
+            shader_name: shader.get_name().to_string(),
+            material_data: None,
             descriptor_set_layout,
+            bindless_limits: self.bindless_limits,
             device: self.device,
             descriptor_allocator: self.descriptor_allocator,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn additive_blending_sums_source_and_destination_colors() {
+        let mut blend = GraphicsMaterialFactoryBuilder::default_color_blend_state();
+        GraphicsMaterialFactoryBuilder::enable_additive_blending(&mut blend);
+
+        assert_eq!(blend.blend_enable, vk::TRUE);
+        // src * 1 + dst * 1
+        assert_eq!(blend.src_color_blend_factor, vk::BlendFactor::ONE);
+        assert_eq!(blend.dst_color_blend_factor, vk::BlendFactor::ONE);
+        assert_eq!(blend.color_blend_op, vk::BlendOp::ADD);
+    }
+}
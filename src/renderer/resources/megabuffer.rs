@@ -1,19 +1,66 @@
 use crate::renderer::resources::buffer::Buffer;
+use crate::renderer::resources::free_list::AllocationStrategy;
+use crate::renderer::resources::free_list::FreeListAllocator;
 use crate::renderer::contexts::device_ctx::transfer_ctx::TransferContext;
+use crate::renderer::contexts::resource_ctx::resource_id::ResourceIdAllocator;
 use ash::vk;
 use color_eyre::eyre::{eyre, OptionExt};
 use color_eyre::Result;
-use std::mem::ManuallyDrop;
-use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
 
-static MEGABUFFER_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
-
+/// A region of a megabuffer with an allocator of its own, sharing the parent's buffers. The region
+/// is pinned, so compacting the parent never moves it, and is freed when the subbuffer is dropped.
+// Nothing allocates from the frames' subbuffers yet
+#[allow(dead_code)]
 pub struct MegaSubbuffer {
-    parent: Megabuffer,
+    megabuffer: Megabuffer,
+    // Dropped after `megabuffer`, which allocates within it
     allocation: AllocatedMegabufferRegion,
 }
 
+/// Occupancy of a megabuffer, see `Megabuffer::get_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MegabufferStats {
+    pub total_size: u64,
+    pub bytes_allocated: u64,
+    pub bytes_free: u64,
+    pub free_region_count: usize,
+    /// Size of the largest allocation that fits without growing. Much smaller than `bytes_free`
+    /// means the free space is fragmented, which `defragment` fixes.
+    pub largest_free_region: u64,
+}
+
+/// Where `Megabuffer::compact` moved the allocations of a megabuffer. Every region allocated from
+/// it has to be passed to `apply` before it is used again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MegabufferRemap {
+    megabuffer_id: usize,
+    // Runs of adjacent allocations that moved together, as (old offset, new offset, size)
+    moves: Vec<(u64, u64, u64)>,
+}
+
+impl MegabufferRemap {
+    /// Whether nothing moved
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    /// Move `region` to its offset after compaction. Regions of other megabuffers are left alone.
+    pub fn apply(&self, region: &mut AllocatedMegabufferRegion) {
+        if region.megabuffer_id != self.megabuffer_id {
+            return;
+        }
+        let moved = self.moves
+            .iter()
+            .find(|(old_offset, _, size)| {
+                (*old_offset..old_offset + size).contains(&region.offset)
+            });
+        if let Some((old_offset, new_offset, _)) = moved {
+            region.offset = region.offset - old_offset + new_offset;
+        }
+    }
+}
+
 pub struct Megabuffer {
     inner: Arc<Mutex<MegabufferInner>>,
     id: usize,
@@ -38,6 +85,94 @@ impl Megabuffer {
     pub fn get_id(&self) -> usize {
         self.id
     }
+
+    /// Handle of the device-local buffer that draws should bind
+    pub fn get_buffer(&self) -> Result<vk::Buffer> {
+        let guard = self.inner
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        let buffer = guard.buffer
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .buffer;
+        Ok(buffer)
+    }
+
+    /// Changes every time the buffer is replaced by a larger one, so whoever recorded draws that
+    /// bind `get_buffer` can tell when to record them again
+    pub fn get_generation(&self) -> Result<u64> {
+        let guard = self.inner
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        Ok(guard.generation)
+    }
+
+    /// Applies to the allocations made from now on. Subbuffers allocated later start out with the
+    /// same strategy.
+    pub fn set_allocation_strategy(&self, strategy: AllocationStrategy) -> Result<()> {
+        self.inner
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?
+            .free_list
+            .set_strategy(strategy);
+        Ok(())
+    }
+
+    pub fn get_stats(&self) -> Result<MegabufferStats> {
+        let guard = self.inner
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        guard.stats()
+    }
+
+    /// Move every allocation towards the start of the buffer, closing the gaps between them, so the
+    /// free space ends up at the end. Subbuffers stay where they are. The allocations are returned
+    /// to their owners with stale offsets; the returned remap has to be applied to every one of
+    /// them.
+    ///
+    /// This first waits for the device to be idle so no draw or upload uses the buffers while they
+    /// change. The data is moved within the staging buffer, where overlapping moves are fine since
+    /// they are done in order of offset, and then uploaded again. Command buffers that bound the
+    /// old offsets have to be recorded again.
+    pub fn compact(&self) -> Result<MegabufferRemap> {
+        let mut guard = self.inner
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        let remap = MegabufferRemap {
+            megabuffer_id: guard.id,
+            moves: guard.free_list.compact(),
+        };
+        if remap.is_empty() {
+            return Ok(remap);
+        }
+
+        unsafe {
+            guard.device.device_wait_idle()?;
+        }
+        {
+            let mut staging_buffer = guard.staging_buffer
+                .lock()
+                .map_err(|e| eyre!(e.to_string()))?;
+            // Every run only moves down, over space that is free or already moved out of
+            for &(old_offset, new_offset, size) in &remap.moves {
+                staging_buffer.copy_within(old_offset, new_offset, size)?;
+            }
+        }
+        for &(_, new_offset, size) in &remap.moves {
+            guard.dirty_ranges.push((new_offset, size));
+        }
+        drop(guard);
+        self.upload()?;
+
+        Ok(remap)
+    }
+
+    pub fn get_allocation_strategy(&self) -> Result<AllocationStrategy> {
+        let guard = self.inner
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        Ok(guard.free_list.get_strategy())
+    }
 }
 
 pub trait MegabufferExt {
@@ -48,11 +183,17 @@ pub trait MegabufferExt {
         memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
         device: Arc<ash::Device>,
         transfer_context: Arc<TransferContext>,
+        id_allocator: Arc<ResourceIdAllocator>,
     ) -> Result<Megabuffer>;
     fn allocate_subbuffer(&self, size: u64) -> Result<MegaSubbuffer>;
     fn allocate_region(&self, size: u64) -> Result<AllocatedMegabufferRegion>;
     fn deallocate_region(&self, region: &mut AllocatedMegabufferRegion) -> Result<()>;
     fn defragment(&self) -> Result<()>;
+    /// Copy the ranges written since the last upload from the staging buffer to the device buffer
+    /// and wait for it. Overlapping and adjacent ranges are copied together.
+    /// The copy is followed by a barrier, so draws submitted afterwards read the new data without
+    /// one of their own. Ownership stays with the transfer queue family; the buffer is never
+    /// released to the graphics queue.
     fn upload(&self) -> Result<()>;
     fn write<T>(
         &self,
@@ -60,7 +201,16 @@ pub trait MegabufferExt {
         region: &AllocatedMegabufferRegion,
     ) -> Result<presser::CopyRecord>
     where
-        T: Copy;
+        T: bytemuck::Pod;
+    /// Like `write`, but `byte_offset` bytes into the region, which must be aligned for `T`
+    fn write_at_offset<T>(
+        &self,
+        data: &[T],
+        region: &AllocatedMegabufferRegion,
+        byte_offset: u64,
+    ) -> Result<presser::CopyRecord>
+    where
+        T: bytemuck::Pod;
     fn aligned_size(&self, size: u64) -> Result<u64>;
 }
 
@@ -73,88 +223,92 @@ impl MegabufferExt for Megabuffer {
         memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
         device: Arc<ash::Device>,
         transfer_context: Arc<TransferContext>,
+        id_allocator: Arc<ResourceIdAllocator>,
     ) -> Result<Megabuffer> {
-        let mem_usage = vk_mem::MemoryUsage::AutoPreferDevice;
-        let buffer = Arc::new(Mutex::new(Buffer::new(
+        let buffer = Arc::new(Mutex::new(MegabufferInner::create_buffer(
             size,
             alignment,
             buf_usage,
-            mem_usage,
-            false,
             memory_allocator.clone(),
             device.clone(),
         )?));
-
-        let staging_buffer = Arc::new(Mutex::new(Buffer::new(
+        let staging_buffer = Arc::new(Mutex::new(MegabufferInner::create_staging_buffer(
             size,
             alignment,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk_mem::MemoryUsage::AutoPreferHost,
-            true,
             memory_allocator.clone(),
             device.clone(),
         )?));
 
-        let id = MEGABUFFER_ID_COUNTER
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let id = id_allocator.allocate();
 
         Ok(Megabuffer {
             inner: Arc::new(Mutex::new(MegabufferInner {
                 buffer,
                 staging_buffer,
-                free_regions: vec![FreeMegabufferRegion {
-                    offset: 0,
-                    size,
-                }],
-                alignment,
+                free_list: FreeListAllocator::new(0, size, alignment),
+                dirty_ranges: Vec::new(),
+                buf_usage,
+                growable: true,
+                generation: 0,
                 transfer_context,
                 id,
+                id_allocator,
                 mem_allocator: memory_allocator,
                 device,
             })),
-            parent: None,
             id,
         })
     }
 
-    fn allocate_subbuffer(&self, size: u64) -> Result<Self> {
+    fn allocate_subbuffer(&self, size: u64) -> Result<MegaSubbuffer> {
         let allocated_region = self.allocate_region(size)?;
-        let free_region = FreeMegabufferRegion {
-            offset: allocated_region.offset,
-            size: allocated_region.size,
-        };
 
-        let guard = self.inner
+        let mut guard = self.inner
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
+        // Regions allocated from the subbuffer can't be remapped when the parent compacts
+        guard.free_list.pin(allocated_region.offset)?;
 
         let buffer = guard.buffer.clone();
         let staging_buffer = guard.staging_buffer.clone();
-        let free_regions = vec![free_region];
 
-        let id = MEGABUFFER_ID_COUNTER
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let alignment = guard.alignment;
+        // Subbuffers draw from the same allocator as their parent
+        let id = guard.id_allocator.allocate();
+        let id_allocator = guard.id_allocator.clone();
+        let mut free_list = FreeListAllocator::new(
+            allocated_region.offset,
+            allocated_region.size,
+            guard.free_list.get_alignment(),
+        );
+        free_list.set_strategy(guard.free_list.get_strategy());
+        let buf_usage = guard.buf_usage;
         let mem_allocator = guard.mem_allocator.clone();
         let device = guard.device.clone();
         let transfer_context = guard.transfer_context.clone();
 
-        Ok(Megabuffer {
+        let megabuffer = Megabuffer {
             inner: Arc::new(Mutex::new(MegabufferInner {
                 id,
+                id_allocator,
                 
                 buffer,
                 staging_buffer,
-                free_regions,
-                alignment,
+                free_list,
+                dirty_ranges: Vec::new(),
+                buf_usage,
+                // Its region of the parent can't move, so it can't grow
+                growable: false,
+                generation: 0,
                 
                 mem_allocator,
                 device,
                 transfer_context,
             })),
-            parent: Some(self.inner.clone()),
-            parent_allocation: Some(ManuallyDrop::new(allocated_region)),
             id,
+        };
+        Ok(MegaSubbuffer {
+            megabuffer,
+            allocation: allocated_region,
         })
     }
     
@@ -163,17 +317,31 @@ impl MegabufferExt for Megabuffer {
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
 
-        let aligned_size = guard.aligned_size(size);
-        let free_region_index = guard
-            .find_free_region_for_allocation(aligned_size)
-            .ok_or_eyre("Failed to find free region for allocation")?;
+        let aligned_size = guard.free_list.aligned_size(size);
+        let alignment = guard.free_list.get_alignment();
+        let (offset, size) = match guard.free_list.allocate(aligned_size, alignment) {
+            Some(allocation) => allocation,
+            None if guard.growable => {
+                guard.grow(aligned_size)?;
+                guard.free_list
+                    .allocate(aligned_size, alignment)
+                    .ok_or_eyre("Failed to find free region for allocation after growing")?
+            }
+            None => {
+                let stats = guard.stats()?;
+                return Err(eyre!(
+                    "Failed to find free region for allocation of {} bytes: {} bytes are free, \
+                    but the largest free region is {} bytes",
+                    aligned_size,
+                    stats.bytes_free,
+                    stats.largest_free_region,
+                ));
+            }
+        };
         let megabuffer_id = guard.id;
-
-        // Remove the free region from the free regions vector
-        let free_region = guard.free_regions.remove(free_region_index);
         let allocated_region = AllocatedMegabufferRegion {
-            offset: free_region.offset,
-            size: free_region.size,
+            offset,
+            size,
             megabuffer: Some(self.clone()),
             megabuffer_id,
         };
@@ -193,38 +361,7 @@ impl MegabufferExt for Megabuffer {
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
 
-        let mut left_index = None; // Some if there is a free region to the left of the deallocated region
-        let mut right_index = None; // Some if there is a free region to the right of the deallocated region
-
-        for (i, free_region) in guard.free_regions.iter().enumerate() {
-            if free_region.offset + free_region.size == region.offset {
-                left_index = Some(i);
-            } else if region.offset + region.size == free_region.offset {
-                right_index = Some(i);
-            }
-        }
-
-        match (left_index, right_index) {
-            (Some(left), Some(right)) => {
-                guard.free_regions[left].size += region.size + guard.free_regions[right].size;
-                guard.free_regions.remove(right);
-            }
-            (Some(left), None) => {
-                guard.free_regions[left].size += region.size;
-            }
-            (None, Some(right)) => {
-                guard.free_regions[right].offset = region.offset;
-                guard.free_regions[right].size += region.size;
-            }
-            (None, None) => {
-                let region = FreeMegabufferRegion {
-                    offset: region.offset,
-                    size: region.size,
-                };
-                guard.free_regions.push(region);
-                guard.free_regions.sort_by_key(|r| r.offset);
-            }
-        }
+        guard.free_list.free(region.offset, region.size)?;
 
         region.size = 0;
 
@@ -236,40 +373,44 @@ impl MegabufferExt for Megabuffer {
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
 
-        guard.free_regions.sort_by_key(|r| r.offset);
-
-        // Merge adjacent free regions
-        let mut i = 0;
-        while i < guard.free_regions.len() - 1 {
-            if guard.free_regions[i].offset + guard.free_regions[i].size == guard.free_regions[i + 1].offset {
-                guard.free_regions[i].size += guard.free_regions[i + 1].size;
-                guard.free_regions.remove(i + 1);
-            } else {
-                i += 1;
-            }
-        }
+        guard.free_list.defragment();
 
         Ok(())
     }
 
     fn upload(&self) -> Result<()> {
-        let guard = self.inner
+        let mut guard = self.inner
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
 
+        let copy_regions = guard.take_dirty_ranges()
+            .into_iter()
+            .map(|(offset, size)| {
+                vk::BufferCopy {
+                    src_offset: offset,
+                    dst_offset: offset,
+                    size,
+                }
+            })
+            .collect::<Vec<vk::BufferCopy>>();
+        if copy_regions.is_empty() {
+            return Ok(());
+        }
+
+        // A dedicated transfer queue can't name the vertex input stages in a barrier
+        let (dst_stage, dst_access) =
+            if guard.transfer_context.get_transfer_queue().family.supports_graphics() {
+                (
+                    vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT
+                        | vk::PipelineStageFlags2::INDEX_INPUT,
+                    vk::AccessFlags2::VERTEX_ATTRIBUTE_READ | vk::AccessFlags2::INDEX_READ,
+                )
+            } else {
+                (vk::PipelineStageFlags2::ALL_COMMANDS, vk::AccessFlags2::MEMORY_READ)
+            };
+
         guard.transfer_context.immediate_submit(
             |cmd: vk::CommandBuffer, device: &ash::Device| {
-                let copy_regions = guard.free_regions
-                    .iter()
-                    .map(|region| {
-                        vk::BufferCopy {
-                            src_offset: region.offset,
-                            dst_offset: region.offset,
-                            size: region.size,
-                        }
-                    })
-                    .collect::<Vec<vk::BufferCopy>>();
-
                 let src_guard = guard.staging_buffer
                     .lock()
                     .map_err(|e| eyre!(e.to_string()))?;
@@ -277,6 +418,20 @@ impl MegabufferExt for Megabuffer {
                     .lock()
                     .map_err(|e| eyre!(e.to_string()))?;
 
+                // Make the copy available to the vertex and index reads of later submissions
+                let barriers = [vk::BufferMemoryBarrier2::default()
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(dst_stage)
+                    .dst_access_mask(dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(dst_guard.buffer)
+                    .offset(0)
+                    .size(vk::WHOLE_SIZE)];
+                let dependency_info = vk::DependencyInfo::default()
+                    .buffer_memory_barriers(&barriers);
+
                 unsafe {
                     device.cmd_copy_buffer(
                         cmd,
@@ -284,6 +439,7 @@ impl MegabufferExt for Megabuffer {
                         dst_guard.buffer,
                         &copy_regions,
                     );
+                    device.cmd_pipeline_barrier2(cmd, &dependency_info);
                 }
 
                 Ok(())
@@ -299,13 +455,35 @@ impl MegabufferExt for Megabuffer {
         region: &AllocatedMegabufferRegion,
     ) -> Result<presser::CopyRecord>
     where
-        T: Copy,
+        T: bytemuck::Pod,
+    {
+        self.write_at_offset(data, region, 0)
+    }
+
+    fn write_at_offset<T>(
+        &self,
+        data: &[T],
+        region: &AllocatedMegabufferRegion,
+        byte_offset: u64,
+    ) -> Result<presser::CopyRecord>
+    where
+        T: bytemuck::Pod,
     {
-        if std::mem::size_of_val(data) as u64 > region.size {
-            return Err(eyre!("Data too large for region"));
+        if region.megabuffer_id != self.id {
+            return Err(eyre!(
+                "Region belongs to megabuffer {}, not {}",
+                region.megabuffer_id,
+                self.id,
+            ));
         }
+        check_write_bounds(
+            byte_offset,
+            size_of_val(data) as u64,
+            align_of::<T>() as u64,
+            region.size,
+        )?;
 
-        let inner_guard = self.inner
+        let mut inner_guard = self.inner
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
         
@@ -313,7 +491,22 @@ impl MegabufferExt for Megabuffer {
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
 
-        staging_guard.write(data, region.offset as usize)
+        let region_end = region.offset
+            .checked_add(region.size)
+            .ok_or_eyre("Region end overflowed")?;
+        if region_end > staging_guard.size {
+            return Err(eyre!(
+                "Region at offset {} of {} bytes is outside the megabuffer of {} bytes",
+                region.offset,
+                region.size,
+                staging_guard.size,
+            ));
+        }
+
+        let record = staging_guard.write(data, (region.offset + byte_offset) as usize)?;
+        drop(staging_guard);
+        inner_guard.dirty_ranges.push((region.offset + byte_offset, size_of_val(data) as u64));
+        Ok(record)
     }
     
     fn aligned_size(&self, size: u64) -> Result<u64> {
@@ -321,17 +514,24 @@ impl MegabufferExt for Megabuffer {
             .lock()
             .map_err(|e| eyre!(e.to_string()))?;
         
-        Ok(guard.aligned_size(size))
+        Ok(guard.free_list.aligned_size(size))
     }
 }
 
 struct MegabufferInner {
     id: usize,
+    id_allocator: Arc<ResourceIdAllocator>,
 
     buffer: Arc<Mutex<Buffer>>,
     staging_buffer: Arc<Mutex<Buffer>>,
-    free_regions: Vec<FreeMegabufferRegion>,
-    alignment: u64,
+    free_list: FreeListAllocator,
+    // Ranges of the staging buffer written since the last upload, as (offset, size)
+    dirty_ranges: Vec<(u64, u64)>,
+    buf_usage: vk::BufferUsageFlags,
+    // Whether `allocate_region` may replace the buffers with larger ones when it runs out of space
+    growable: bool,
+    // Bumped every time `grow` replaces the buffers
+    generation: u64,
 
     mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
     device: Arc<ash::Device>,
@@ -339,51 +539,134 @@ struct MegabufferInner {
 }
 
 impl MegabufferInner {
-    fn aligned_size(&self, size: u64) -> u64 {
-        (size + self.alignment - 1) & !(self.alignment - 1)
+    fn stats(&self) -> Result<MegabufferStats> {
+        let total_size = self.free_list.get_size();
+        let bytes_free = self.free_list.get_bytes_free();
+
+        Ok(MegabufferStats {
+            total_size,
+            bytes_allocated: total_size.saturating_sub(bytes_free),
+            bytes_free,
+            free_region_count: self.free_list.get_free_region_count(),
+            largest_free_region: self.free_list.get_largest_free_block(),
+        })
     }
 
-    /// Find a free region that can fit the allocation and splits it into 2 free regions if possible
-    /// Returns the index of the free region that fits the allocation
-    fn find_free_region_for_allocation(
-        &mut self,
-        alloc_size: u64
-    ) -> Option<usize> {
-        let (
-            region_index,
-            new_region,
-        ) = self.free_regions.iter_mut()
-            .enumerate()
-            // Find the first free region that can fit the allocation
-            .find(|(_, region)| region.size >= alloc_size)
-            .map(|(i, region)| {
-                // Split the free region into 2 regions:
-                // 1. A free region that fits the allocation exactly
-                // 2. The remaining free region
-                let offset = region.offset;
-                region.offset += alloc_size;
-                region.size -= alloc_size;
-                (
-                    // Index of the remaining free region
-                    i,
-
-                    // The free region that fits the allocation exactly,
-                    // ready to be inserted into the free regions vector
-                    FreeMegabufferRegion {
-                        offset,
-                        size: alloc_size,
-                    },
-                )
-            })?;
+    /// Sort the dirty ranges, merge the ones that overlap or touch, and clear them
+    fn take_dirty_ranges(&mut self) -> Vec<(u64, u64)> {
+        let mut dirty_ranges = std::mem::take(&mut self.dirty_ranges);
+        dirty_ranges.retain(|(_, size)| *size > 0);
+        dirty_ranges.sort_unstable();
+
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(dirty_ranges.len());
+        for (offset, size) in dirty_ranges {
+            match merged.last_mut() {
+                Some((last_offset, last_size)) if *last_offset + *last_size >= offset => {
+                    *last_size = (*last_size).max(offset + size - *last_offset);
+                }
+                _ => merged.push((offset, size)),
+            }
+        }
+        merged
+    }
 
-        // Insert the new free region into the free regions vector
-        if self.free_regions[region_index].size == 0 {
-            self.free_regions[region_index] = new_region;
-        } else {
-            self.free_regions.insert(region_index, new_region);
+    /// Device-local buffer that draws bind. It can also be copied from, for growing.
+    fn create_buffer(
+        size: u64,
+        alignment: u64,
+        buf_usage: vk::BufferUsageFlags,
+        mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Buffer> {
+        Buffer::new(
+            size,
+            alignment,
+            buf_usage | vk::BufferUsageFlags::TRANSFER_SRC,
+            vk_mem::MemoryUsage::AutoPreferDevice,
+            false,
+            mem_allocator,
+            device,
+        )
+    }
+
+    /// Host-visible mirror of the buffer that writes go to before `upload`
+    fn create_staging_buffer(
+        size: u64,
+        alignment: u64,
+        mem_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+    ) -> Result<Buffer> {
+        Buffer::new(
+            size,
+            alignment,
+            vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+            vk_mem::MemoryUsage::AutoPreferHost,
+            true,
+            mem_allocator,
+            device,
+        )
+    }
+
+    /// Replace the buffer and staging buffer with ones at least `additional_size` bytes larger,
+    /// usually twice the size, and copy the contents over on the GPU. Only the tail is new, so
+    /// allocated regions keep their offsets; subbuffers share the buffers and see the new ones.
+    ///
+    /// This is a one-time stall: the device is waited on so no frame in flight still reads the old
+    /// buffer when it is destroyed, and the copy is waited on as well. Draws recorded earlier
+    /// refer to the old buffer, so cached command buffers must be recorded again; the bumped
+    /// generation tells them.
+    fn grow(&mut self, additional_size: u64) -> Result<()> {
+        let mut buffer = self.buffer
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        let mut staging_buffer = self.staging_buffer
+            .lock()
+            .map_err(|e| eyre!(e.to_string()))?;
+        let old_size = buffer.size;
+        let new_size = (old_size * 2).max(old_size + additional_size);
+        log::info!("Growing megabuffer {} from {} to {} bytes", self.id, old_size, new_size);
+
+        let new_buffer = Self::create_buffer(
+            new_size,
+            self.free_list.get_alignment(),
+            self.buf_usage,
+            self.mem_allocator.clone(),
+            self.device.clone(),
+        )?;
+        let new_staging_buffer = Self::create_staging_buffer(
+            new_size,
+            self.free_list.get_alignment(),
+            self.mem_allocator.clone(),
+            self.device.clone(),
+        )?;
+
+        unsafe {
+            self.device.device_wait_idle()?;
         }
+        let copy_regions = [vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: old_size,
+        }];
+        self.transfer_context.immediate_submit(|cmd, device| {
+            unsafe {
+                device.cmd_copy_buffer(cmd, buffer.buffer, new_buffer.buffer, &copy_regions);
+                device.cmd_copy_buffer(
+                    cmd,
+                    staging_buffer.buffer,
+                    new_staging_buffer.buffer,
+                    &copy_regions,
+                );
+            }
+            Ok(())
+        })?;
+        *buffer = new_buffer;
+        *staging_buffer = new_staging_buffer;
+        self.generation += 1;
 
-        Some(region_index)
+        self.free_list.grow(new_size - old_size);
+
+        Ok(())
     }
 }
 
@@ -392,11 +675,6 @@ impl PartialEq for MegabufferInner {
         self.id == other.id
     }
 }
-pub struct FreeMegabufferRegion {
-    offset: u64,
-    size: u64,
-}
-
 pub struct AllocatedMegabufferRegion {
     offset: u64,
     size: u64,
@@ -405,13 +683,31 @@ pub struct AllocatedMegabufferRegion {
 }
 
 impl AllocatedMegabufferRegion {
+    /// Byte offset of the region from the start of the megabuffer
+    pub fn get_offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
     pub fn write<T>(&mut self, data: &[T]) -> Result<presser::CopyRecord>
     where
-        T: Copy,
+        T: bytemuck::Pod,
     {
         self.megabuffer.as_ref().unwrap().write(data, self)
     }
 
+    /// Write `data` `byte_offset` bytes into the region, e.g. to update one element of an array
+    /// without rewriting the rest. `byte_offset` must be aligned for `T`.
+    pub fn write_at<T>(&mut self, data: &[T], byte_offset: u64) -> Result<presser::CopyRecord>
+    where
+        T: bytemuck::Pod,
+    {
+        self.megabuffer.as_ref().unwrap().write_at_offset(data, self, byte_offset)
+    }
+
     pub fn suballocate_region(&mut self, size: u64) -> Result<AllocatedMegabufferRegion> {
         let size = self.megabuffer.as_ref().unwrap().aligned_size(size)?;
         
@@ -504,3 +800,69 @@ impl Drop for AllocatedMegabufferRegion {
     }
 }
 
+/// Check that `write_size` bytes of data aligned to `type_alignment` can be written
+/// `byte_offset` bytes into a region of `region_size` bytes
+fn check_write_bounds(
+    byte_offset: u64,
+    write_size: u64,
+    type_alignment: u64,
+    region_size: u64,
+) -> Result<()> {
+    // The staging buffer would move an unaligned start forward, past what was checked
+    if !byte_offset.is_multiple_of(type_alignment) {
+        return Err(eyre!(
+            "Offset {} into the region is not aligned to the {} bytes of the data's type",
+            byte_offset,
+            type_alignment,
+        ));
+    }
+    let write_end = byte_offset
+        .checked_add(write_size)
+        .ok_or_eyre("Write end overflowed")?;
+    if write_end > region_size {
+        return Err(eyre!(
+            "Cannot write {} bytes at offset {} into region of {} bytes; {} bytes too many",
+            write_size,
+            byte_offset,
+            region_size,
+            write_end - region_size,
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_filling_the_region_is_in_bounds() {
+        assert!(check_write_bounds(0, 64, 4, 64).is_ok());
+        assert!(check_write_bounds(48, 16, 16, 64).is_ok());
+    }
+
+    #[test]
+    fn empty_write_at_the_end_is_in_bounds() {
+        assert!(check_write_bounds(64, 0, 4, 64).is_ok());
+    }
+
+    #[test]
+    fn write_one_byte_past_the_end_is_out_of_bounds() {
+        assert!(check_write_bounds(0, 65, 1, 64).is_err());
+        assert!(check_write_bounds(63, 2, 1, 64).is_err());
+    }
+
+    #[test]
+    fn unaligned_offset_is_rejected() {
+        assert!(check_write_bounds(2, 4, 4, 64).is_err());
+        assert!(check_write_bounds(4, 4, 8, 64).is_err());
+    }
+
+    #[test]
+    fn overflowing_write_end_is_rejected() {
+        let max_offset = u64::MAX - u64::MAX % 4;
+        assert!(check_write_bounds(max_offset, 4, 4, u64::MAX).is_err());
+        assert!(check_write_bounds(4, u64::MAX, 4, u64::MAX).is_err());
+    }
+}
+
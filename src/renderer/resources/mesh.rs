@@ -1,26 +1,171 @@
-use std::sync::atomic::AtomicU32;
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use glam::{Mat3, Mat4, Vec3, Vec4};
+use crate::renderer::contexts::resource_ctx::resource_id::ResourceIdAllocator;
 use crate::renderer::resources::vertex::Vertex;
 
-static MESH_ID_COUNTER: AtomicU32 = AtomicU32::new(0);
+/// Width of the indices stored in the index buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexType {
+    U16,
+    U32,
+}
+
+impl IndexType {
+    /// Pick the narrowest index type that can address `vertex_count` vertices
+    pub fn for_vertex_count(vertex_count: usize) -> Self {
+        if vertex_count <= u16::MAX as usize {
+            Self::U16
+        } else {
+            Self::U32
+        }
+    }
+
+    /// Size of a single index in bytes
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::U16 => size_of::<u16>() as u64,
+            Self::U32 => size_of::<u32>() as u64,
+        }
+    }
+
+    pub fn vk_index_type(&self) -> vk::IndexType {
+        match self {
+            Self::U16 => vk::IndexType::UINT16,
+            Self::U32 => vk::IndexType::UINT32,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Mesh {
     pub vertices: Vec<Vertex>,
     pub indices: Option<Vec<u32>>,
-    id: u32,
+    // Set with `set_index_type`; otherwise the type follows the vertex count
+    index_type: Option<IndexType>,
+    // Assigned by the resource storage when the mesh is uploaded as part of a model
+    id: Option<usize>,
 }
 
 impl Mesh {
     pub fn new(vertices: Vec<Vertex>, indices: Option<Vec<u32>>) -> Self {
-        let id = MESH_ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-
         Self {
             vertices,
             indices,
-            id,
+            index_type: None,
+            id: None,
+        }
+    }
+
+    pub fn get_id(&self) -> Option<usize> {
+        self.id
+    }
+
+    /// Give the mesh an id from `id_allocator` if it doesn't have one yet
+    pub fn assign_id(&mut self, id_allocator: &ResourceIdAllocator) {
+        if self.id.is_none() {
+            self.id = Some(id_allocator.allocate());
         }
     }
 
+    /// The narrowest index type for the current vertices, or 32 bits if `set_index_type` asked for
+    /// it. Worked out on every call, so it stays right when `vertices` changes.
+    pub fn get_index_type(&self) -> IndexType {
+        match (self.index_type, IndexType::for_vertex_count(self.vertices.len())) {
+            (Some(IndexType::U32), _) | (_, IndexType::U32) => IndexType::U32,
+            _ => IndexType::U16,
+        }
+    }
+
+    /// Override the automatically chosen index type.
+    /// Meshes with more than 65535 vertices cannot use 16-bit indices, and fall back to 32 bits if
+    /// they grow past that afterwards.
+    pub fn set_index_type(&mut self, index_type: IndexType) -> Result<()> {
+        if index_type == IndexType::U16 && self.vertices.len() > u16::MAX as usize {
+            return Err(eyre!(
+                "Mesh has {} vertices, which cannot be addressed with 16-bit indices",
+                self.vertices.len()
+            ));
+        }
+        self.index_type = Some(index_type);
+        Ok(())
+    }
+
+    /// Transform the vertices by `matrix`, e.g. a `CoordinateSystem` conversion. Normals are kept
+    /// perpendicular to the surface, and the winding of the triangles is reversed when the matrix
+    /// mirrors so they keep facing the same way.
+    pub fn transform(&mut self, matrix: Mat4) {
+        let normal_matrix = Mat3::from_mat4(matrix).inverse().transpose();
+        for vertex in self.vertices.iter_mut() {
+            vertex.position = matrix.transform_point3(vertex.position);
+            vertex.normal = (normal_matrix * vertex.normal).normalize_or_zero();
+        }
+
+        if matrix.determinant() >= 0.0 {
+            return;
+        }
+        match self.indices.as_mut() {
+            Some(indices) => {
+                for triangle in indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+            None => {
+                for triangle in self.vertices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+        }
+    }
+
+    /// Per-vertex tangents along the direction the u texture coordinate increases, averaged over
+    /// the adjacent triangles and made perpendicular to the normal. `w` is the handedness: the
+    /// bitangent is `normal.cross(tangent.xyz) * w`.
+    pub fn compute_tangents(&self) -> Vec<Vec4> {
+        let mut tangents = vec![Vec3::ZERO; self.vertices.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.vertices.len()];
+        let triangles: Vec<[usize; 3]> = match self.indices.as_ref() {
+            Some(indices) => indices
+                .chunks_exact(3)
+                .map(|triangle| [triangle[0], triangle[1], triangle[2]].map(|i| i as usize))
+                .collect(),
+            None => (0..self.vertices.len() / 3)
+                .map(|triangle| [triangle * 3, triangle * 3 + 1, triangle * 3 + 2])
+                .collect(),
+        };
+
+        for [a, b, c] in triangles {
+            let (v0, v1, v2) = (&self.vertices[a], &self.vertices[b], &self.vertices[c]);
+            let (edge1, edge2) = (v1.position - v0.position, v2.position - v0.position);
+            let (duv1, duv2) = (v1.texcoord - v0.texcoord, v2.texcoord - v0.texcoord);
+            let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+            // Triangles without a proper texture mapping don't define a direction
+            if determinant.abs() < f32::EPSILON {
+                continue;
+            }
+            let tangent = (edge1 * duv2.y - edge2 * duv1.y) / determinant;
+            let bitangent = (edge2 * duv1.x - edge1 * duv2.x) / determinant;
+            for index in [a, b, c] {
+                tangents[index] += tangent;
+                bitangents[index] += bitangent;
+            }
+        }
+
+        self.vertices
+            .iter()
+            .zip(tangents.iter().zip(bitangents.iter()))
+            .map(|(vertex, (&tangent, &bitangent))| {
+                let normal = vertex.normal;
+                let tangent = (tangent - normal * normal.dot(tangent))
+                    .try_normalize()
+                    .unwrap_or_else(|| normal.any_orthonormal_vector());
+                let mirrored = normal.cross(tangent).dot(bitangent) < 0.0;
+                tangent.extend(if mirrored { -1.0 } else { 1.0 })
+            })
+            .collect()
+    }
+
     pub fn new_triangle() -> Self {
         let vertices = vec![
             Vertex { // Bottom left
@@ -88,6 +233,41 @@ impl Mesh {
 
 impl PartialEq for Mesh {
     fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+        match (self.id, other.id) {
+            (Some(id), Some(other_id)) => id == other_id,
+            // A mesh without an id is only equal to itself
+            _ => std::ptr::eq(self, other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+    use super::*;
+
+    #[test]
+    fn index_type_follows_vertices_added_later() {
+        let mut mesh = Mesh::new_triangle();
+        mesh.set_index_type(IndexType::U16).unwrap();
+        assert_eq!(mesh.get_index_type(), IndexType::U16);
+
+        let extra_vertices = (mesh.vertices.len()..=u16::MAX as usize).map(|_| Vertex {
+            position: Vec3::ZERO,
+            normal: Vec3::Z,
+            color: Vec3::ONE,
+            texcoord: Vec2::ZERO,
+        });
+        mesh.vertices.extend(extra_vertices);
+        assert_eq!(mesh.get_index_type(), IndexType::U32);
+        assert!(mesh.set_index_type(IndexType::U16).is_err());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn forced_32_bit_indices_stay_32_bit() {
+        let mut mesh = Mesh::new_triangle();
+        assert_eq!(mesh.get_index_type(), IndexType::U16);
+        mesh.set_index_type(IndexType::U32).unwrap();
+        assert_eq!(mesh.get_index_type(), IndexType::U32);
+    }
+}
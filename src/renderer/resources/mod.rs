@@ -2,11 +2,19 @@
 /// They are relatively intuitive and managed by the user.
 
 pub mod mesh;
+// More `Mesh` constructors
+mod primitives;
 pub mod vertex;
 pub mod model;
 pub mod buffer;
 pub mod image;
 pub mod megabuffer;
+pub mod free_list;
 pub mod texture;
+pub mod color_grading;
+pub mod readback;
+pub mod sampler;
 pub mod material;
 pub mod shader;
+#[cfg(feature = "hot-reload")]
+pub mod shader_compiler;
@@ -1,9 +1,17 @@
-use super::mesh::Mesh;
+use super::mesh::{IndexType, Mesh};
 use super::vertex::Vertex;
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
 use crate::renderer::contexts::device_ctx::target::RenderTarget;
-use crate::renderer::resources::megabuffer::{AllocatedMegabufferRegion, Megabuffer, MegabufferExt};
+use crate::renderer::contexts::resource_ctx::resource_id::ResourceIdAllocator;
+use crate::renderer::resources::megabuffer::{
+    AllocatedMegabufferRegion,
+    Megabuffer,
+    MegabufferExt,
+    MegabufferRemap,
+};
 use crate::renderer::shader_data::PerVertexData;
-use color_eyre::eyre::{eyre, Result};
+use ash::vk;
+use color_eyre::eyre::{eyre, OptionExt, Result};
 use glam::Vec3;
 
 pub struct FullscreenQuad {
@@ -17,6 +25,7 @@ impl FullscreenQuad {
     pub fn new(
         vertex_megabuffer: &Megabuffer,
         index_megabuffer: &Megabuffer,
+        id_allocator: &ResourceIdAllocator,
         tgt: &RenderTarget,
     ) -> Result<Self> {
         let quad_mesh = Mesh::new_quad();
@@ -24,6 +33,7 @@ impl FullscreenQuad {
             vec![quad_mesh],
             vertex_megabuffer,
             index_megabuffer,
+            id_allocator,
         )?;
         let mut quad = Self {
             quad_model,
@@ -82,19 +92,24 @@ impl FullscreenQuad {
 
 pub struct Model {
     meshes: Vec<Mesh>,
+    index_type: IndexType,
     vertex_megabuffer_region: Option<AllocatedMegabufferRegion>,
     index_megabuffer_region: Option<AllocatedMegabufferRegion>,
 }
 
 impl Model {
     pub fn new(
-        meshes: Vec<Mesh>,
+        mut meshes: Vec<Mesh>,
         vertex_megabuffer: &Megabuffer,
         index_megabuffer: &Megabuffer,
+        id_allocator: &ResourceIdAllocator,
     ) -> Result<Self> {
         if meshes.is_empty() {
             return Err(eyre!("Model must have at least one mesh"));
         }
+        for mesh in meshes.iter_mut() {
+            mesh.assign_id(id_allocator);
+        }
 
         // Ensure that all meshes have either no indices or all indices
         let has_indices = meshes.first().unwrap().indices.is_some();
@@ -120,18 +135,32 @@ impl Model {
             .allocate_region(vertex_buffer_region_size)?;
         vertex_megabuffer.write(&vertices, &vertex_buffer_region)?;
 
+        // The merged index buffer can only be 16-bit if every mesh fits in 16-bit indices
+        let index_type = if meshes.iter().all(|m| m.get_index_type() == IndexType::U16) {
+            IndexType::U16
+        } else {
+            IndexType::U32
+        };
+
         // Upload all indices to the index buffer if the model has indices
         let index_buffer_region = if has_indices {
             // Collect all indices from all meshes
             let indices = meshes
                 .iter()
-                .flat_map(|m| m.indices.as_ref().unwrap().iter().cloned())
-                .collect::<Vec<u32>>();
+                .flat_map(|m| m.indices.as_ref().unwrap().iter().cloned());
 
-            let index_buffer_region_size = (indices.len() * size_of::<u32>()) as u64;
-            let index_buffer_region = index_megabuffer
-                .allocate_region(index_buffer_region_size)?;
-            index_megabuffer.write(&indices, &index_buffer_region)?;
+            let index_buffer_region = match index_type {
+                IndexType::U16 => {
+                    let indices = indices
+                        .map(u16::try_from)
+                        .collect::<std::result::Result<Vec<u16>, _>>()?;
+                    Self::write_indices(&indices, index_megabuffer)?
+                }
+                IndexType::U32 => {
+                    let indices = indices.collect::<Vec<u32>>();
+                    Self::write_indices(&indices, index_megabuffer)?
+                }
+            };
 
             Some(index_buffer_region)
         } else {
@@ -140,11 +169,23 @@ impl Model {
 
         Ok(Self {
             meshes,
+            index_type,
             vertex_megabuffer_region: Some(vertex_buffer_region),
             index_megabuffer_region: index_buffer_region,
         })
     }
 
+    fn write_indices<T: bytemuck::Pod>(
+        indices: &[T],
+        index_megabuffer: &Megabuffer,
+    ) -> Result<AllocatedMegabufferRegion> {
+        let index_buffer_region_size = std::mem::size_of_val(indices) as u64;
+        let index_buffer_region = index_megabuffer
+            .allocate_region(index_buffer_region_size)?;
+        index_megabuffer.write(indices, &index_buffer_region)?;
+        Ok(index_buffer_region)
+    }
+
     pub fn write_vertex_buffer(
         &mut self,
         vertices: &[PerVertexData],
@@ -182,6 +223,73 @@ impl Model {
         }
     }
 
+    /// Follow its regions to where `Megabuffer::compact` moved them
+    pub fn apply_megabuffer_remap(&mut self, remap: &MegabufferRemap) {
+        let regions = self.vertex_megabuffer_region
+            .iter_mut()
+            .chain(self.index_megabuffer_region.iter_mut());
+        for region in regions {
+            remap.apply(region);
+        }
+    }
+
+    /// Bind the model's regions of the vertex and index megabuffers
+    pub fn bind_buffers(
+        &self,
+        cmd: &CommandEncoder,
+        vertex_buffer: vk::Buffer,
+        index_buffer: vk::Buffer,
+    ) -> Result<()> {
+        let vertex_region = self.vertex_megabuffer_region
+            .as_ref()
+            .ok_or_eyre("Model does not have a vertex buffer region")?;
+        cmd.bind_vertex_buffer(vertex_buffer, vertex_region.get_offset());
+
+        if let Some(index_region) = self.index_megabuffer_region.as_ref() {
+            cmd.bind_index_buffer(index_buffer, index_region.get_offset(), self.index_type);
+        }
+
+        Ok(())
+    }
+
+    /// Record one draw per mesh. Expects the model's buffers to be bound with `bind_buffers`.
+    pub fn record_draws(&self, cmd: &CommandEncoder) {
+        // Indices of each mesh are relative to its own vertices,
+        // so offset them by the vertices of the meshes before it
+        let mut first_index = 0;
+        let mut vertex_offset = 0;
+        for mesh in self.meshes.iter() {
+            let vertex_count = mesh.vertices.len() as u32;
+            match mesh.indices.as_ref() {
+                Some(indices) => {
+                    let index_count = indices.len() as u32;
+                    cmd.draw_indexed(index_count, first_index, vertex_offset as i32);
+                    first_index += index_count;
+                }
+                None => {
+                    cmd.draw(vertex_count, vertex_offset);
+                }
+            }
+            vertex_offset += vertex_count;
+        }
+    }
+
+    /// Triangles drawn by `record_draws`, assuming a triangle list
+    pub fn get_triangle_count(&self) -> u64 {
+        self.meshes
+            .iter()
+            .map(|mesh| match mesh.indices.as_ref() {
+                Some(indices) => indices.len() as u64 / 3,
+                None => mesh.vertices.len() as u64 / 3,
+            })
+            .sum()
+    }
+
+    /// Index type of the model's index buffer region, to be passed to `cmd_bind_index_buffer`
+    pub fn get_index_type(&self) -> IndexType {
+        self.index_type
+    }
+
     pub fn get_meshes(&self) -> &Vec<Mesh> {
         &self.meshes
     }
@@ -0,0 +1,256 @@
+//! Procedural meshes for prototyping and debug gizmos. Every primitive fits in the unit cube
+//! centered on the origin, with +Y up and counter-clockwise front faces like `Mesh::new_quad`.
+
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+use glam::{Vec2, Vec3};
+use crate::renderer::resources::mesh::Mesh;
+use crate::renderer::resources::vertex::Vertex;
+
+impl Mesh {
+    /// Cube with an edge length of 1 and separate vertices per face, so the edges stay sharp. Each
+    /// face maps the whole texture.
+    pub fn new_cube() -> Self {
+        // Normal, then the face's right and up directions, with right x up = normal
+        let faces = [
+            (Vec3::Z, Vec3::X, Vec3::Y),
+            (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+            (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+            (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+            (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+            (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+        ];
+
+        let mut vertices = Vec::with_capacity(faces.len() * 4);
+        let mut indices = Vec::with_capacity(faces.len() * 6);
+        for (normal, right, up) in faces {
+            let first = vertices.len() as u32;
+            // Top left, bottom left, top right, bottom right, as in `new_quad`
+            for (x, y) in [(-1.0, 1.0), (-1.0, -1.0), (1.0, 1.0), (1.0, -1.0)] {
+                let position = (normal + right * x + up * y) * 0.5;
+                let texcoord = Vec2::new((x + 1.0) * 0.5, (1.0 - y) * 0.5);
+                vertices.push(vertex(position, normal, texcoord));
+            }
+            indices.extend([0, 1, 2, 2, 1, 3].map(|index| first + index));
+        }
+
+        Self::new(vertices, Some(indices))
+    }
+
+    /// Sphere with a radius of 0.5 made of `rings` bands of latitude and `sectors` of longitude,
+    /// at least 2 and 3. The texture wraps around once, like an equirectangular map.
+    pub fn new_uv_sphere(rings: u32, sectors: u32) -> Self {
+        let (vertices, indices) = grid(rings.max(2), sectors.max(3), |s, t| {
+            let (theta, phi) = (t * PI, s * TAU);
+            let normal = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                -theta.sin() * phi.sin(),
+            );
+            (normal * 0.5, normal)
+        });
+        Self::new(vertices, Some(indices))
+    }
+
+    /// Sphere with a radius of 0.5 from an icosahedron whose triangles are split in four
+    /// `subdivisions` times. The triangles are more even than a UV sphere's, without pinched poles.
+    pub fn new_ico_sphere(subdivisions: u32) -> Self {
+        let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+        let mut positions = [
+            [-1.0, t, 0.0], [1.0, t, 0.0], [-1.0, -t, 0.0], [1.0, -t, 0.0],
+            [0.0, -1.0, t], [0.0, 1.0, t], [0.0, -1.0, -t], [0.0, 1.0, -t],
+            [t, 0.0, -1.0], [t, 0.0, 1.0], [-t, 0.0, -1.0], [-t, 0.0, 1.0],
+        ]
+            .map(|position| Vec3::from(position).normalize())
+            .to_vec();
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+
+        for _ in 0..subdivisions {
+            // Neighboring triangles share their edge midpoints
+            let mut midpoints = HashMap::<(u32, u32), u32>::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    let position = (positions[a as usize] + positions[b as usize]).normalize();
+                    positions.push(position);
+                    positions.len() as u32 - 1
+                })
+            };
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // Same mapping as the UV sphere
+        let texcoord = |normal: Vec3| {
+            let u = (-normal.z).atan2(normal.x).rem_euclid(TAU) / TAU;
+            let v = normal.y.clamp(-1.0, 1.0).acos() / PI;
+            Vec2::new(u, v)
+        };
+        let mut vertices = positions
+            .iter()
+            .map(|&normal| vertex(normal * 0.5, normal, texcoord(normal)))
+            .collect::<Vec<_>>();
+
+        // Triangles that cross the seam would interpolate across the whole texture, so give
+        // them copies of their vertices on the far side of the seam
+        let mut seam_copies = HashMap::<u32, u32>::new();
+        for triangle in triangles.iter_mut() {
+            let us = triangle.map(|index| vertices[index as usize].texcoord.x);
+            let min_u = us.iter().copied().fold(f32::MAX, f32::min);
+            let max_u = us.iter().copied().fold(f32::MIN, f32::max);
+            if max_u - min_u <= 0.5 {
+                continue;
+            }
+            for index in triangle.iter_mut() {
+                if vertices[*index as usize].texcoord.x >= 0.5 {
+                    continue;
+                }
+                *index = *seam_copies.entry(*index).or_insert_with(|| {
+                    let original = &vertices[*index as usize];
+                    let copy = vertex(
+                        original.position,
+                        original.normal,
+                        original.texcoord + Vec2::X,
+                    );
+                    vertices.push(copy);
+                    vertices.len() as u32 - 1
+                });
+            }
+        }
+
+        let indices = triangles.into_iter().flatten().collect();
+        Self::new(vertices, Some(indices))
+    }
+
+    /// Cylinder along Y with a radius of 0.5, a height of 1, and `sectors` sides, at least 3.
+    /// The side wraps the texture around once and the caps map it as a disk.
+    pub fn new_cylinder(sectors: u32) -> Self {
+        let sectors = sectors.max(3);
+        let (mut vertices, mut indices) = grid(1, sectors, |s, t| {
+            let phi = s * TAU;
+            let normal = Vec3::new(phi.cos(), 0.0, -phi.sin());
+            (normal * 0.5 + Vec3::new(0.0, 0.5 - t, 0.0), normal)
+        });
+        add_cap(&mut vertices, &mut indices, sectors, 0.5, Vec3::Y);
+        add_cap(&mut vertices, &mut indices, sectors, -0.5, Vec3::NEG_Y);
+        Self::new(vertices, Some(indices))
+    }
+
+    /// Cone along Y with its tip at the top, a base radius of 0.5, a height of 1, and `sectors`
+    /// sides, at least 3
+    pub fn new_cone(sectors: u32) -> Self {
+        let sectors = sectors.max(3);
+        // The slope is the same everywhere: out by the height, up by the radius
+        let (mut vertices, mut indices) = grid(1, sectors, |s, t| {
+            let phi = s * TAU;
+            let outward = Vec3::new(phi.cos(), 0.0, -phi.sin());
+            let position = Vec3::new(0.0, 0.5, 0.0) + (outward * 0.5 - Vec3::Y) * t;
+            (position, (outward + Vec3::Y * 0.5).normalize())
+        });
+        add_cap(&mut vertices, &mut indices, sectors, -0.5, Vec3::NEG_Y);
+        Self::new(vertices, Some(indices))
+    }
+
+    /// Flat 1x1 square in the XZ plane facing +Y, split into `rows` along Z and `cols` along X,
+    /// each at least 1. The texture covers it once.
+    pub fn new_plane(rows: u32, cols: u32) -> Self {
+        let (vertices, indices) = grid(rows.max(1), cols.max(1), |s, t| {
+            (Vec3::new(s - 0.5, 0.0, t - 0.5), Vec3::Y)
+        });
+        Self::new(vertices, Some(indices))
+    }
+
+    /// Torus around Y with an outer radius of 0.5 and a tube radius of 0.15, made of `segments`
+    /// around the ring and `sides` around the tube, at least 3 each
+    pub fn new_torus(segments: u32, sides: u32) -> Self {
+        const TUBE_RADIUS: f32 = 0.15;
+        const RING_RADIUS: f32 = 0.5 - TUBE_RADIUS;
+        let (vertices, indices) = grid(sides.max(3), segments.max(3), |s, t| {
+            let (phi, theta) = (s * TAU, t * TAU);
+            let outward = Vec3::new(phi.cos(), 0.0, -phi.sin());
+            let normal = outward * theta.cos() - Vec3::Y * theta.sin();
+            (outward * RING_RADIUS + normal * TUBE_RADIUS, normal)
+        });
+        Self::new(vertices, Some(indices))
+    }
+}
+
+fn vertex(position: Vec3, normal: Vec3, texcoord: Vec2) -> Vertex {
+    Vertex {
+        position,
+        normal,
+        color: Vec3::ONE,
+        texcoord,
+    }
+}
+
+/// Build a surface from `rows` x `cols` quads. `surface` maps texture coordinates `s` (across the
+/// columns) and `t` (down the rows), both from 0 to 1, to a position and normal. Seen from the
+/// side the normals point to, `s` must run right and `t` down for the winding to come out
+/// counter-clockwise. The first and last column get separate vertices so textures wrap cleanly.
+fn grid(
+    rows: u32,
+    cols: u32,
+    surface: impl Fn(f32, f32) -> (Vec3, Vec3),
+) -> (Vec<Vertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(((rows + 1) * (cols + 1)) as usize);
+    for row in 0..=rows {
+        for col in 0..=cols {
+            let texcoord = Vec2::new(col as f32 / cols as f32, row as f32 / rows as f32);
+            let (position, normal) = surface(texcoord.x, texcoord.y);
+            vertices.push(vertex(position, normal, texcoord));
+        }
+    }
+
+    let mut indices = Vec::with_capacity((rows * cols * 6) as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let top_left = row * (cols + 1) + col;
+            let bottom_left = top_left + cols + 1;
+            indices.extend([
+                top_left, bottom_left, top_left + 1,
+                top_left + 1, bottom_left, bottom_left + 1,
+            ]);
+        }
+    }
+
+    (vertices, indices)
+}
+
+/// Add a disk with a radius of 0.5 at height `y`, facing `normal` (+Y or -Y), as a fan around its
+/// center
+fn add_cap(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u32>,
+    sectors: u32,
+    y: f32,
+    normal: Vec3,
+) {
+    let center = vertices.len() as u32;
+    vertices.push(vertex(Vec3::new(0.0, y, 0.0), normal, Vec2::splat(0.5)));
+    for sector in 0..=sectors {
+        let phi = sector as f32 / sectors as f32 * TAU;
+        let (x, z) = (phi.cos() * 0.5, -phi.sin() * 0.5);
+        let texcoord = Vec2::new(0.5 + x, 0.5 + z * normal.y);
+        vertices.push(vertex(Vec3::new(x, y, z), normal, texcoord));
+    }
+
+    for sector in 0..sectors {
+        let (current, next) = (center + 1 + sector, center + 2 + sector);
+        // The ring runs counter-clockwise seen from above
+        if normal.y > 0.0 {
+            indices.extend([center, current, next]);
+        } else {
+            indices.extend([center, next, current]);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::device::RenderDevice;
+use crate::renderer::contexts::device_ctx::queue::Queue;
+use crate::renderer::contexts::device_ctx::transfer_ctx::SubmitHandle;
+use crate::renderer::resources::buffer::Buffer;
+
+/// Reads GPU results back to the CPU a frame or more later, without waiting for the device.
+///
+/// Each `schedule_copy` copies a region of a device buffer into the next of several host-visible
+/// staging buffers, submitted on the queue that produced the data so it runs after that work.
+/// `try_read` returns the oldest copy once its fence has signaled and `None` until then. Use at
+/// least as many slots as there are frames in flight, so scheduling never has to wait.
+pub struct ReadbackBuffer {
+    slots: Vec<ReadbackSlot>,
+    // Slots with a copy in flight or finished but not read yet, oldest first
+    pending: VecDeque<usize>,
+    next_slot: usize,
+    size: u64,
+}
+
+struct ReadbackSlot {
+    buffer: Buffer,
+    submit: Option<SubmitHandle>,
+}
+
+impl ReadbackBuffer {
+    pub fn new(size: u64, slot_count: usize, device: &RenderDevice) -> Result<Self> {
+        if slot_count == 0 {
+            return Err(eyre!("A readback buffer needs at least one slot"));
+        }
+
+        let slots = (0..slot_count)
+            .map(|_| {
+                Ok(ReadbackSlot {
+                    buffer: device.create_readback_buffer(size)?,
+                    submit: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            slots,
+            pending: VecDeque::with_capacity(slot_count),
+            next_slot: 0,
+            size,
+        })
+    }
+
+    /// Copy `size` bytes of `src_buffer` from `src_offset` into the next slot. Submit it to the
+    /// same queue as the work that writes `src_buffer`, after that work, so queue submission order
+    /// and the barrier recorded here make the writes visible to the copy.
+    ///
+    /// If every slot still holds an unread copy, the oldest one is discarded, waiting for it first
+    /// if the GPU hasn't finished it.
+    pub fn schedule_copy(
+        &mut self,
+        src_buffer: vk::Buffer,
+        src_offset: u64,
+        queue: &Arc<Queue>,
+        device: &RenderDevice,
+    ) -> Result<()> {
+        let slot_index = self.next_slot;
+        if let Some(position) = self.pending.iter().position(|index| *index == slot_index) {
+            log::warn!("Discarding an unread readback; try_read isn't keeping up");
+            self.pending.remove(position);
+        }
+        // Dropping the handle waits for the old copy
+        let slot = &mut self.slots[slot_index];
+        slot.submit = None;
+
+        let dst_buffer = slot.buffer.buffer;
+        let size = self.size;
+        let submit = device.submit_polled(queue, |cmd, device| {
+            let barrier = vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_READ);
+            let barriers = [barrier];
+            let dependency_info = vk::DependencyInfo::default()
+                .memory_barriers(&barriers);
+            let region = vk::BufferCopy::default()
+                .src_offset(src_offset)
+                .dst_offset(0)
+                .size(size);
+            unsafe {
+                device.cmd_pipeline_barrier2(cmd, &dependency_info);
+                device.cmd_copy_buffer(cmd, src_buffer, dst_buffer, &[region]);
+            }
+            Ok(())
+        })?;
+        slot.submit = Some(submit);
+
+        self.pending.push_back(slot_index);
+        self.next_slot = (self.next_slot + 1) % self.slots.len();
+        Ok(())
+    }
+
+    /// Bytes of the oldest scheduled copy if the GPU has finished it, without blocking. Each copy
+    /// is returned once.
+    pub fn try_read(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(&slot_index) = self.pending.front() else {
+            return Ok(None);
+        };
+        let slot = &mut self.slots[slot_index];
+        if let Some(submit) = slot.submit.as_ref() && !submit.is_done()? {
+            return Ok(None);
+        }
+
+        self.pending.pop_front();
+        slot.submit = None;
+        slot.buffer.read().map(Some)
+    }
+
+    /// Like `try_read`, but reinterprets the bytes as a slice of `T`
+    pub fn try_read_as<T: bytemuck::Pod>(&mut self) -> Result<Option<Vec<T>>> {
+        Ok(self.try_read()?.map(|bytes| bytemuck::pod_collect_to_vec(&bytes)))
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+}
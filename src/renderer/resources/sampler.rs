@@ -0,0 +1,111 @@
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use crate::renderer::contexts::device_ctx::device::RenderDevice;
+
+/// How a texture is filtered and addressed. Textures with equal descriptions share one sampler in
+/// the bindless sampler table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    /// Used for all three texture coordinates
+    pub address_mode: vk::SamplerAddressMode,
+    /// Color outside the texture with `CLAMP_TO_BORDER`, which is the only mode it may be set
+    /// with. Transparent black when not set.
+    pub border_color: Option<vk::BorderColor>,
+    /// Anisotropic filtering with at most this many samples. Clamped to what the device supports,
+    /// and ignored without the `sampler_anisotropy` feature.
+    pub max_anisotropy: Option<u32>,
+}
+
+impl Default for SamplerDesc {
+    /// Linear filtering with repeating texture coordinates
+    fn default() -> Self {
+        Self {
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            border_color: None,
+            max_anisotropy: None,
+        }
+    }
+}
+
+impl SamplerDesc {
+    /// Sharp pixels without bleeding across the edges, e.g. for UI and pixel art
+    pub fn nearest_clamp() -> Self {
+        Self {
+            filter: vk::Filter::NEAREST,
+            mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            border_color: None,
+            max_anisotropy: None,
+        }
+    }
+
+    /// Linear filtering that stops at the edges, e.g. for reading fullscreen images in post
+    /// processing
+    pub fn linear_clamp() -> Self {
+        Self {
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+            ..Self::default()
+        }
+    }
+
+    /// Linear filtering with `border_color` outside the texture, e.g. for shadow maps
+    pub fn linear_border(border_color: vk::BorderColor) -> Self {
+        Self {
+            address_mode: vk::SamplerAddressMode::CLAMP_TO_BORDER,
+            border_color: Some(border_color),
+            ..Self::default()
+        }
+    }
+
+    /// For tiling surfaces seen at grazing angles, like terrain
+    pub fn anisotropic_repeat(max_anisotropy: u32) -> Self {
+        Self {
+            max_anisotropy: Some(max_anisotropy),
+            ..Self::default()
+        }
+    }
+
+    /// Check that the fields fit together. Called by `create`.
+    pub fn validate(&self) -> Result<()> {
+        let clamps_to_border = self.address_mode == vk::SamplerAddressMode::CLAMP_TO_BORDER;
+        if self.border_color.is_some() && !clamps_to_border {
+            return Err(eyre!(
+                "A border color only applies to CLAMP_TO_BORDER, but the address mode is {:?}",
+                self.address_mode,
+            ));
+        }
+        // Needs the sampler_mirror_clamp_to_edge feature, which the renderer doesn't enable
+        if self.address_mode == vk::SamplerAddressMode::MIRROR_CLAMP_TO_EDGE {
+            return Err(eyre!("MIRROR_CLAMP_TO_EDGE is not supported; use MIRRORED_REPEAT"));
+        }
+        Ok(())
+    }
+
+    pub fn create(&self, device: &RenderDevice) -> Result<vk::Sampler> {
+        self.validate()?;
+        let max_anisotropy = self.max_anisotropy
+            .filter(|_| device.get_features().sampler_anisotropy == vk::TRUE)
+            .map(|max| (max as f32).min(device.get_max_sampler_anisotropy()));
+
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(self.filter)
+            .min_filter(self.filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .border_color(self.border_color.unwrap_or(vk::BorderColor::FLOAT_TRANSPARENT_BLACK))
+            .anisotropy_enable(max_anisotropy.is_some())
+            .max_anisotropy(max_anisotropy.unwrap_or(1.0))
+            .max_lod(vk::LOD_CLAMP_NONE);
+
+        Ok(unsafe {
+            device.logical.create_sampler(&sampler_info, None)?
+        })
+    }
+}
@@ -1,6 +1,7 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use ash::vk;
+use color_eyre::eyre::eyre;
 use color_eyre::Result;
 
 const SHADERS_DIR: &str = "shaders-built";
@@ -8,35 +9,48 @@ const SHADERS_DIR: &str = "shaders-built";
 pub struct GraphicsShader {
     pub vert_mod: vk::ShaderModule,
     pub frag_mod: vk::ShaderModule,
+    name: String,
     device: Arc<ash::Device>,
 }
 
 pub struct ComputeShader {
     pub comp_mod: vk::ShaderModule,
+    name: String,
     device: Arc<ash::Device>,
 }
 
 impl GraphicsShader {
     pub fn new(shader_name: &str, device: Arc<ash::Device>) -> Result<Self> {
+        let shaders_dir = find_shaders_dir()?;
         let vert_mod = create_shader_module(
-            (&format!("{}/{}.vert.spv", SHADERS_DIR, shader_name)).as_ref(),
+            &shaders_dir.join(format!("{}.vert.spv", shader_name)),
             &device,
         )?;
         let frag_mod = create_shader_module(
-            (&format!("{}/{}.frag.spv", SHADERS_DIR, shader_name)).as_ref(),
+            &shaders_dir.join(format!("{}.frag.spv", shader_name)),
             &device,
         )?;
-        Ok(Self { vert_mod, frag_mod, device })
+        Ok(Self { vert_mod, frag_mod, name: shader_name.to_string(), device })
+    }
+
+    /// Name the shader was loaded by, which its source files are named after
+    pub fn get_name(&self) -> &str {
+        &self.name
     }
 }
 
 impl ComputeShader {
     pub fn new(shader_name: &str, device: Arc<ash::Device>) -> Result<Self> {
         let comp_mod = create_shader_module(
-            (&format!("{}/{}.comp.spv", SHADERS_DIR, shader_name)).as_ref(),
+            &find_shaders_dir()?.join(format!("{}.comp.spv", shader_name)),
             &device,
         )?;
-        Ok(Self { comp_mod, device })
+        Ok(Self { comp_mod, name: shader_name.to_string(), device })
+    }
+
+    /// Name the shader was loaded by, which its source file is named after
+    pub fn get_name(&self) -> &str {
+        &self.name
     }
 }
 
@@ -57,7 +71,59 @@ impl Drop for ComputeShader {
     }
 }
 
+/// Directory the build script compiles the shaders into. It is looked for next to the crate's
+/// manifest, next to the executable, and in the working directory, in that order, so running
+/// from another directory still finds it.
+pub fn find_shaders_dir() -> Result<PathBuf> {
+    let mut candidates = vec![Path::new(env!("CARGO_MANIFEST_DIR")).join(SHADERS_DIR)];
+    if let Ok(exe_path) = std::env::current_exe()
+        && let Some(exe_dir) = exe_path.parent()
+    {
+        candidates.push(exe_dir.join(SHADERS_DIR));
+    }
+    candidates.push(PathBuf::from(SHADERS_DIR));
+
+    candidates
+        .iter()
+        .find(|dir| dir.is_dir())
+        .cloned()
+        .ok_or_else(|| eyre!(
+            "Shader directory {} not found; did the build script run? Looked in:\n{}",
+            SHADERS_DIR,
+            candidates
+                .iter()
+                .map(|dir| format!("  {}", dir.display()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        ))
+}
+
+/// Fail early with every missing file listed if the compiled vertex and fragment shaders of
+/// `shader_names` are not in the shader directory
+pub fn check_graphics_shaders_built(shader_names: &[&str]) -> Result<()> {
+    let shaders_dir = find_shaders_dir()?;
+    let missing = shader_names
+        .iter()
+        .flat_map(|name| [format!("{}.vert.spv", name), format!("{}.frag.spv", name)])
+        .filter(|file_name| !shaders_dir.join(file_name).is_file())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(eyre!(
+            "Shaders missing from {}; did the build script run? Expected: {}",
+            shaders_dir.display(),
+            missing.join(", "),
+        ));
+    }
+    Ok(())
+}
+
 fn create_shader_module(filepath: &Path, device: &ash::Device) -> Result<vk::ShaderModule> {
+    if !filepath.is_file() {
+        return Err(eyre!(
+            "Shader {} not found; did the build script run?",
+            filepath.display(),
+        ));
+    }
     let code = std::fs::read(filepath)?;
 
     let shader_module_info = vk::ShaderModuleCreateInfo::default()
@@ -0,0 +1,100 @@
+// Compiles shader sources to SPIR-V. The build script includes this file to compile every shader
+// ahead of time, and the `hot-reload` feature uses it to recompile shaders as they change, so it
+// may only depend on crates that both have.
+
+use std::fs;
+use std::path::Path;
+use color_eyre::eyre::{eyre, OptionExt};
+use color_eyre::Result;
+use naga::back::spv;
+use naga::front::wgsl;
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use shaderc::ShaderKind;
+
+enum ShaderLanguage {
+    Glsl,
+    Wgsl,
+}
+
+/// Whether the file at `filepath` is a shader source by its extension, see `compile_shader`
+pub fn is_shader_source(filepath: &Path) -> bool {
+    shader_language(filepath).is_some()
+}
+
+/// Compile the GLSL (`.vert`, `.frag`, `.comp`) or WGSL (`.wgsl`) shader at `filepath` to SPIR-V
+pub fn compile_shader(filepath: &Path) -> Result<Vec<u32>> {
+    match shader_language(filepath) {
+        Some(ShaderLanguage::Glsl) => compile_glsl(filepath),
+        Some(ShaderLanguage::Wgsl) => compile_wgsl(filepath),
+        None => Err(eyre!("Shader language not recognized for file: {:#?}", filepath)),
+    }
+}
+
+/// Name of the file the SPIR-V of the shader at `filepath` is written to, e.g. `default.vert.spv`
+pub fn compiled_file_name(filepath: &Path) -> Result<String> {
+    let shader_name = filepath
+        .file_name()
+        .ok_or_eyre("Shader file has no name")?
+        .to_str()
+        .ok_or_eyre("Shader file name is not valid UTF-8")?;
+    Ok(format!("{}.spv", shader_name))
+}
+
+fn shader_language(filepath: &Path) -> Option<ShaderLanguage> {
+    match filepath.extension()?.to_str()? {
+        "vert" | "frag" | "comp" => Some(ShaderLanguage::Glsl),
+        "wgsl" => Some(ShaderLanguage::Wgsl),
+        _ => None,
+    }
+}
+
+fn compile_glsl(filepath: &Path) -> Result<Vec<u32>> {
+    let compiler = shaderc::Compiler::new()
+        .ok_or_eyre("Failed to create shaderc compiler")?;
+    let options = shaderc::CompileOptions::new()
+        .ok_or_eyre("Failed to create shaderc compile options")?;
+
+    let ext = filepath
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .ok_or_eyre(format!("Shader file has no extension: {:#?}", filepath))?;
+
+    let shader_kind = match ext {
+        "vert" => ShaderKind::Vertex,
+        "frag" => ShaderKind::Fragment,
+        "comp" => ShaderKind::Compute,
+        _ => {
+            return Err(eyre!("Shader kind not recognized for GLSL file: {:#?}", filepath));
+        }
+    };
+
+    let source = fs::read_to_string(filepath)?;
+    let filename = filepath
+        .file_name()
+        .ok_or_eyre(format!("No filename for filepath: {:#?}", filepath))?
+        .to_str()
+        .ok_or_eyre("Could not convert &OsStr to &str")?;
+    let artifact = compiler.compile_into_spirv(
+        &source,
+        shader_kind,
+        filename,
+        "main",
+        Some(&options),
+    )?;
+
+    Ok(artifact.as_binary().to_vec())
+}
+
+fn compile_wgsl(filepath: &Path) -> Result<Vec<u32>> {
+    // Read the WGSL file and parse into IR
+    let source = fs::read_to_string(filepath)?;
+    let module = wgsl::parse_str(&source)?;
+
+    // Validate the IR
+    let mut validator = Validator::new(ValidationFlags::all(), Capabilities::all());
+    let validation_info = validator.validate(&module)?;
+    log::debug!("{:#?}", validation_info);
+
+    // Generate the SPIR-V binary
+    Ok(spv::write_vec(&module, &validation_info, &spv::Options::default(), None)?)
+}
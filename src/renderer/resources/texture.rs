@@ -1,10 +1,13 @@
 use crate::renderer::contexts::device_ctx::transfer_ctx::TransferContext;
 use crate::renderer::resources::image::Image;
+use ash::vk;
 use color_eyre::Result;
 use std::sync::{Arc, Mutex};
 
 pub struct ColorTexture {
     pub image: Image,
+    /// Index into the bindless sampler table, set when the texture is registered
+    pub sampler_index: u32,
 }
 
 impl ColorTexture {
@@ -31,6 +34,7 @@ impl ColorTexture {
 
         Ok(Self {
             image,
+            sampler_index: 0,
         })
     }
 
@@ -54,6 +58,58 @@ impl ColorTexture {
             transfer_context,
         )
     }
+
+    pub fn get_sampler_index(&self) -> u32 {
+        self.sampler_index
+    }
+}
+
+/// A single layered texture sampled through one 2D array view, e.g. for terrain splat maps or
+/// sprite sheets with equally sized frames. Unlike the bindless texture table, the layers are
+/// picked in the shader with the third texture coordinate.
+pub struct ArrayTexture {
+    pub image: Image,
+}
+
+impl ArrayTexture {
+    pub fn new(
+        width: u32,
+        height: u32,
+        layers: u32,
+        format: vk::Format,
+        memory_allocator: Arc<Mutex<vk_mem::Allocator>>,
+        device: Arc<ash::Device>,
+        transfer_context: &TransferContext,
+    ) -> Result<Self> {
+        let image = Image::new_array_image(
+            width,
+            height,
+            layers,
+            format,
+            memory_allocator,
+            device,
+            transfer_context,
+        )?;
+
+        Ok(Self {
+            image,
+        })
+    }
+
+    /// The upload isn't synchronized with frames in flight, so layers that are being drawn with
+    /// should only be replaced while the device is idle
+    pub fn upload_layer(
+        &mut self,
+        index: u32,
+        data: &[u8],
+        transfer_context: &TransferContext,
+    ) -> Result<()> {
+        self.image.upload_layer(index, data, transfer_context)
+    }
+
+    pub fn get_layer_count(&self) -> u32 {
+        self.image.array_layers
+    }
 }
 
 pub struct StorageTexture {
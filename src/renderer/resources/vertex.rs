@@ -3,6 +3,13 @@ use glam::{Vec2, Vec3};
 use crate::renderer::shader_data::PerVertexData;
 use ash::vk;
 
+// The vertex buffer holds `PerVertexData`, not `Vertex`, so the input description below must
+// describe that layout. These checks make a layout change a compile error instead of silently
+// corrupted geometry.
+const _: () = assert!(size_of::<PerVertexData>() == 20);
+const _: () = assert!(offset_of!(PerVertexData, position) == 0);
+const _: () = assert!(offset_of!(PerVertexData, texcoord) == 12);
+
 #[derive(Debug)]
 pub struct Vertex {
     pub position: Vec3,
@@ -23,18 +30,26 @@ impl Default for VertexInputDescription {
     }
 }
 
+impl From<&Vertex> for PerVertexData {
+    fn from(vertex: &Vertex) -> Self {
+        Self {
+            position: vertex.position,
+            texcoord: vertex.texcoord,
+        }
+    }
+}
+
 impl Vertex {
+    /// Convert the vertex into the tightly packed form that is written into the vertex buffer
     pub fn as_shader_data(&self) -> PerVertexData {
-        PerVertexData {
-            position: self.position,
-            texcoord: self.texcoord,
-        }
+        PerVertexData::from(self)
     }
 
+    /// Describes the layout of `PerVertexData`, which is what actually lives in the vertex buffer
     pub fn get_input_description() -> VertexInputDescription {
         let bindings = vec![vk::VertexInputBindingDescription {
             binding: 0,
-            stride: size_of::<Vertex>() as u32,
+            stride: size_of::<PerVertexData>() as u32,
             input_rate: vk::VertexInputRate::VERTEX,
         }];
 
@@ -44,14 +59,14 @@ impl Vertex {
                 binding: 0,
                 location: 0,
                 format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Vertex, position) as u32,
+                offset: offset_of!(PerVertexData, position) as u32,
             },
             // Texcoord
             vk::VertexInputAttributeDescription {
                 binding: 0,
                 location: 1,
                 format: vk::Format::R32G32_SFLOAT,
-                offset: offset_of!(Vertex, texcoord) as u32,
+                offset: offset_of!(PerVertexData, texcoord) as u32,
             },
         ];
 
@@ -63,4 +78,47 @@ impl Vertex {
             flags,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read the floats an attribute of `format` would see at `offset` into `bytes`
+    fn read_attribute(bytes: &[u8], offset: u32, format: vk::Format) -> Vec<f32> {
+        let component_count = match format {
+            vk::Format::R32G32_SFLOAT => 2,
+            vk::Format::R32G32B32_SFLOAT => 3,
+            _ => panic!("Unexpected attribute format {:?}", format),
+        };
+        let start = offset as usize;
+        bytemuck::pod_collect_to_vec(&bytes[start..start + component_count * size_of::<f32>()])
+    }
+
+    #[test]
+    fn input_description_matches_shader_data_layout() {
+        let vertex = Vertex {
+            position: Vec3::new(1.0, 2.0, 3.0),
+            normal: Vec3::new(4.0, 5.0, 6.0),
+            color: Vec3::new(7.0, 8.0, 9.0),
+            texcoord: Vec2::new(10.0, 11.0),
+        };
+        let shader_data = vertex.as_shader_data();
+        let bytes = bytemuck::bytes_of(&shader_data);
+        let description = Vertex::get_input_description();
+
+        assert_eq!(description.bindings.len(), 1);
+        assert_eq!(description.bindings[0].stride as usize, bytes.len());
+
+        let expected: [&[f32]; 2] = [&vertex.position.to_array(), &vertex.texcoord.to_array()];
+        assert_eq!(description.attributes.len(), expected.len());
+        for attribute in &description.attributes {
+            assert_eq!(
+                read_attribute(bytes, attribute.offset, attribute.format),
+                expected[attribute.location as usize],
+                "attribute at location {}",
+                attribute.location,
+            );
+        }
+    }
+}
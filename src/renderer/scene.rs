@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use ash::vk;
+use color_eyre::eyre::OptionExt;
+use color_eyre::Result;
+use glam::{Mat4, Quat, Vec3};
+use crate::renderer::contexts::device_ctx::command_encoder::CommandEncoder;
+use crate::renderer::resources::material::Material;
+use crate::renderer::resources::megabuffer::MegabufferRemap;
+use crate::renderer::resources::model::Model;
+use crate::renderer::shader_data::PerDrawData;
+use crate::renderer::stats::RenderStats;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: Vec3::ONE,
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Default::default()
+        }
+    }
+
+    pub fn get_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelHandle(pub(crate) usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialHandle(pub(crate) usize);
+
+/// Also the texture's index into the bindless texture array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TextureHandle(pub(crate) usize);
+
+impl TextureHandle {
+    /// Index into the bindless texture array, for `PerMaterialData::texture_index`
+    pub fn get_index(&self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// Refers to an `ArrayTexture`, which is bound as a whole rather than per layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ArrayTextureHandle(pub(crate) usize);
+
+/// Refers to a `ColorGradingLut`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LutHandle(pub(crate) usize);
+
+/// Also used as the object index into the per-object data, so it stays stable for the lifetime
+/// of the instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InstanceHandle(pub(crate) usize);
+
+pub struct Instance {
+    pub model: ModelHandle,
+    pub material: MaterialHandle,
+    pub transform: Transform,
+}
+
+/// Instances that share a material and can be drawn with a single pipeline/descriptor set bind
+pub struct DrawBatch {
+    pub material: MaterialHandle,
+    /// Sorted by model so vertex/index buffers are only rebound when the model changes
+    pub instances: Vec<InstanceHandle>,
+}
+
+#[derive(Default)]
+pub struct Scene {
+    models: Vec<Model>,
+    instances: Vec<Option<Instance>>,
+    // Revision at which each instance slot last changed, so only the per-object data of changed
+    // instances has to be uploaded
+    object_revisions: Vec<u64>,
+
+    // Bumped whenever something that affects the recorded draws changes, so cached command
+    // buffers can tell whether they are stale. A counter rather than a bool because every frame
+    // in flight keeps its own cache.
+    revision: u64,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_model(&mut self, model: Model) -> ModelHandle {
+        self.models.push(model);
+        self.mark_dirty();
+        ModelHandle(self.models.len() - 1)
+    }
+
+    pub fn get_model(&self, handle: ModelHandle) -> Option<&Model> {
+        self.models.get(handle.0)
+    }
+
+    /// Follow the models' megabuffer regions to where `Megabuffer::compact` moved them
+    pub fn apply_megabuffer_remap(&mut self, remap: &MegabufferRemap) {
+        if remap.is_empty() {
+            return;
+        }
+        for model in self.models.iter_mut() {
+            model.apply_megabuffer_remap(remap);
+        }
+        // Cached draws bound the old offsets
+        self.mark_dirty();
+    }
+
+    pub fn add_instance(
+        &mut self,
+        model: ModelHandle,
+        material: MaterialHandle,
+        transform: Transform,
+    ) -> InstanceHandle {
+        let instance = Instance {
+            model,
+            material,
+            transform,
+        };
+
+        self.mark_dirty();
+
+        // Reuse the slot of a removed instance if there is one
+        if let Some(index) = self.instances.iter().position(|i| i.is_none()) {
+            self.instances[index] = Some(instance);
+            self.object_revisions[index] = self.revision;
+            InstanceHandle(index)
+        } else {
+            self.instances.push(Some(instance));
+            self.object_revisions.push(self.revision);
+            InstanceHandle(self.instances.len() - 1)
+        }
+    }
+
+    pub fn remove_instance(&mut self, handle: InstanceHandle) -> Option<Instance> {
+        let instance = self.instances.get_mut(handle.0)?.take()?;
+        self.mark_dirty();
+        Some(instance)
+    }
+
+    pub fn get_instance(&self, handle: InstanceHandle) -> Option<&Instance> {
+        self.instances.get(handle.0)?.as_ref()
+    }
+
+    /// Marks the scene dirty, since the caller may change the model, material or transform
+    pub fn get_instance_mut(&mut self, handle: InstanceHandle) -> Option<&mut Instance> {
+        let instance = self.instances.get_mut(handle.0)?.as_mut();
+        if instance.is_some() {
+            self.revision += 1;
+            self.object_revisions[handle.0] = self.revision;
+        }
+        instance
+    }
+
+    pub fn set_transform(&mut self, handle: InstanceHandle, transform: Transform) -> Result<()> {
+        let instance = self.get_instance_mut(handle)
+            .ok_or_eyre(format!("Instance {:?} does not exist", handle))?;
+        instance.transform = transform;
+        Ok(())
+    }
+
+    pub fn get_revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.revision += 1;
+    }
+
+    /// One more than the highest instance handle handed out, whether or not it was removed since
+    pub fn get_handle_count(&self) -> usize {
+        self.instances.len()
+    }
+
+    /// Every live instance with its handle
+    pub fn instances(&self) -> impl Iterator<Item = (InstanceHandle, &Instance)> {
+        self.instances
+            .iter()
+            .enumerate()
+            .filter_map(|(index, instance)| Some((InstanceHandle(index), instance.as_ref()?)))
+    }
+
+    /// Live instances that were added or changed after `revision`, or all of them with `None`
+    pub fn instances_changed_since(
+        &self,
+        revision: Option<u64>,
+    ) -> impl Iterator<Item = (InstanceHandle, &Instance)> {
+        self.instances()
+            .filter(move |(handle, _)| {
+                revision.is_none_or(|revision| self.object_revisions[handle.0] > revision)
+            })
+    }
+
+    /// Group the instances by material, in the order each material is first encountered, and by
+    /// model within each material
+    pub fn batches(&self) -> Vec<DrawBatch> {
+        // Order in which each material first shows up
+        let mut material_order: HashMap<MaterialHandle, usize> = HashMap::new();
+        let mut groups: HashMap<(MaterialHandle, ModelHandle), Vec<InstanceHandle>> =
+            HashMap::new();
+        for (handle, instance) in self.instances() {
+            let next_order = material_order.len();
+            material_order.entry(instance.material).or_insert(next_order);
+            groups
+                .entry((instance.material, instance.model))
+                .or_default()
+                .push(handle);
+        }
+
+        let mut groups = groups.into_iter().collect::<Vec<_>>();
+        groups.sort_unstable_by_key(|((material, model), _)| (material_order[material], model.0));
+
+        let mut batches: Vec<DrawBatch> = Vec::with_capacity(material_order.len());
+        for ((material, _), instances) in groups {
+            match batches.last_mut() {
+                Some(batch) if batch.material == material => batch.instances.extend(instances),
+                _ => batches.push(DrawBatch {
+                    material,
+                    instances,
+                }),
+            }
+        }
+        batches
+    }
+
+    /// Split the batches into at most `count` chunks with roughly equal numbers of instances, so
+    /// they can be recorded in parallel. Draw order is kept across the chunks.
+    pub fn chunked_batches(&self, count: usize) -> Vec<Vec<DrawBatch>> {
+        let draws = self.batches()
+            .into_iter()
+            .flat_map(|batch| {
+                batch.instances
+                    .into_iter()
+                    .map(move |handle| (batch.material, handle))
+            })
+            .collect::<Vec<_>>();
+        if draws.is_empty() {
+            return Vec::new();
+        }
+
+        let chunk_size = draws.len().div_ceil(count.max(1));
+        draws
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let mut batches: Vec<DrawBatch> = Vec::new();
+                for &(material, handle) in chunk {
+                    match batches.last_mut() {
+                        Some(batch) if batch.material == material => batch.instances.push(handle),
+                        _ => batches.push(DrawBatch {
+                            material,
+                            instances: vec![handle],
+                        }),
+                    }
+                }
+                batches
+            })
+            .collect()
+    }
+
+    /// Record the draws for every instance, binding each material's pipeline and descriptor set
+    /// only once per batch
+    pub fn record_draws(
+        &self,
+        cmd: &CommandEncoder,
+        materials: &[Material],
+        vertex_buffer: vk::Buffer,
+        index_buffer: vk::Buffer,
+    ) -> Result<RenderStats> {
+        self.record_batches(
+            cmd,
+            &self.batches(),
+            materials,
+            vertex_buffer,
+            index_buffer,
+        )
+    }
+
+    /// Record the draws for the given batches only
+    pub fn record_batches(
+        &self,
+        cmd: &CommandEncoder,
+        batches: &[DrawBatch],
+        materials: &[Material],
+        vertex_buffer: vk::Buffer,
+        index_buffer: vk::Buffer,
+    ) -> Result<RenderStats> {
+        let mut stats = RenderStats::default();
+        for batch in batches {
+            let material = materials
+                .get(batch.material.0)
+                .ok_or_eyre(format!("Material {:?} does not exist", batch.material))?;
+            material.bind_pipeline(cmd.command_buffer);
+            if material.has_dynamic_depth_compare_op() {
+                cmd.apply_depth_compare_op();
+            }
+            material.bind_descriptor_sets(cmd.command_buffer);
+            stats.pipeline_binds += 1;
+            stats.descriptor_binds += 1;
+
+            let mut bound_model = None;
+            for &handle in &batch.instances {
+                let instance = self.instances[handle.0]
+                    .as_ref()
+                    .ok_or_eyre("Batched instance was removed")?;
+                let model = self.models
+                    .get(instance.model.0)
+                    .ok_or_eyre(format!("Model {:?} does not exist", instance.model))?;
+
+                if bound_model != Some(instance.model) {
+                    model.bind_buffers(cmd, vertex_buffer, index_buffer)?;
+                    bound_model = Some(instance.model);
+                }
+
+                let per_draw_data = PerDrawData {
+                    object_index: handle.0 as u32,
+                    material_index: batch.material.0 as u32,
+                    vertex_offset: 0,
+                };
+                material.update_push_constants(
+                    cmd.command_buffer,
+                    bytemuck::bytes_of(&per_draw_data),
+                );
+                model.record_draws(cmd);
+                stats.draw_calls += model.get_meshes().len() as u32;
+                stats.triangles += model.get_triangle_count();
+                stats.instances += 1;
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removing_a_missing_instance_keeps_the_revision() {
+        let mut scene = Scene::new();
+        let instance = scene.add_instance(ModelHandle(0), MaterialHandle(0), Transform::default());
+        assert!(scene.remove_instance(instance).is_some());
+
+        let revision = scene.get_revision();
+        assert!(scene.remove_instance(instance).is_none());
+        assert!(scene.remove_instance(InstanceHandle(instance.0 + 1)).is_none());
+        assert_eq!(scene.get_revision(), revision);
+    }
+
+    #[test]
+    fn batches_group_by_material_then_model() {
+        let mut scene = Scene::new();
+        let (model_a, model_b) = (ModelHandle(0), ModelHandle(1));
+        let (material_a, material_b) = (MaterialHandle(5), MaterialHandle(2));
+        let add = |scene: &mut Scene, model, material| {
+            scene.add_instance(model, material, Transform::default())
+        };
+        let b_a = add(&mut scene, model_b, material_a);
+        let a_b = add(&mut scene, model_a, material_b);
+        let a_a = add(&mut scene, model_a, material_a);
+        let removed = add(&mut scene, model_a, material_a);
+        let b_a_2 = add(&mut scene, model_b, material_a);
+        scene.remove_instance(removed);
+
+        let batches = scene.batches();
+        let batches = batches
+            .iter()
+            .map(|batch| (batch.material, batch.instances.as_slice()))
+            .collect::<Vec<_>>();
+        assert_eq!(batches, [
+            (material_a, [a_a, b_a, b_a_2].as_slice()),
+            (material_b, [a_b].as_slice()),
+        ]);
+    }
+}
@@ -1,5 +1,5 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat4, Vec2, Vec3};
+use glam::{Mat4, Vec2, Vec3, Vec4};
 
 /// Data unique to each frame passed into uniform buffer
 #[repr(C)]
@@ -11,6 +11,41 @@ pub struct PerFrameData {
     _padding: [f32; 2],
 }
 
+impl PerFrameData {
+    pub fn new(viewproj: Mat4, near: f32, far: f32) -> Self {
+        Self {
+            viewproj,
+            near,
+            far,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Number of views rendered by multiview materials, e.g. one per eye
+pub const MULTIVIEW_VIEW_COUNT: usize = 2;
+
+/// Per-frame data for multiview materials, which select their matrix with `gl_ViewIndex`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
+pub struct PerFrameMultiviewData {
+    pub viewprojs: [Mat4; MULTIVIEW_VIEW_COUNT],
+    pub near: f32,
+    pub far: f32,
+    _padding: [f32; 2],
+}
+
+impl PerFrameMultiviewData {
+    pub fn new(viewprojs: [Mat4; MULTIVIEW_VIEW_COUNT], near: f32, far: f32) -> Self {
+        Self {
+            viewprojs,
+            near,
+            far,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
 /// Data unique to each material passed as elements into a storage buffer
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
@@ -34,6 +69,38 @@ pub struct PerVertexData {
     pub texcoord: Vec2,
 }
 
+/// Data unique to each sprite vertex passed as elements into a per-frame vertex buffer.
+/// Positions are already transformed into clip space by the `SpriteBatch`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
+pub struct PerSpriteVertexData {
+    pub position: Vec3,
+    pub texcoord: Vec2,
+    pub color: [f32; 4],
+    pub texture_index: u32,
+}
+
+/// Data unique to each debug line vertex passed as elements into a per-frame vertex buffer.
+/// Positions are already transformed into clip space by `DebugLines`, but not divided by w, so
+/// lines crossing the near plane are still clipped correctly.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
+pub struct PerDebugLineVertexData {
+    pub position: Vec4,
+    pub color: [f32; 4],
+}
+
+/// Data unique to each wireframe overlay vertex passed as elements into a per-frame vertex buffer.
+/// Every triangle gets its own three vertices, so each can carry a corner of the barycentric
+/// coordinates.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
+pub struct PerWireframeVertexData {
+    pub position: Vec4,
+    pub barycentric: Vec3,
+    pub _padding: f32,
+}
+
 /// Data unique to each draw call passed as a push constant
 #[repr(C)]
 #[derive(Debug, Default, Copy, Clone, Pod, Zeroable)]
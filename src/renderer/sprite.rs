@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::mem::offset_of;
+use ash::vk;
+use color_eyre::eyre::OptionExt;
+use color_eyre::Result;
+use glam::{Mat4, Vec2, Vec3};
+use crate::renderer::resources::vertex::VertexInputDescription;
+use crate::renderer::scene::TextureHandle;
+use crate::renderer::shader_data::PerSpriteVertexData;
+
+/// Two triangles per sprite, drawn without an index buffer
+const VERTICES_PER_SPRITE: usize = 6;
+
+/// Axis-aligned rectangle in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// Widths of the edges of a nine-slice sprite, in pixels of the source texture
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Borders {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+impl Borders {
+    pub fn uniform(width: f32) -> Self {
+        Self {
+            left: width,
+            right: width,
+            top: width,
+            bottom: width,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    pub texture: TextureHandle,
+    /// Region of the texture to draw in texture pixels, such as one image of an atlas. `None`
+    /// draws the whole texture.
+    pub src_rect: Option<Rect>,
+    /// Where the sprite is drawn, in the pixel coordinates of the 2D camera divided by the
+    /// batch's scale factor
+    pub dst_rect: Rect,
+    /// Multiplied with the texture color
+    pub color: [f32; 4],
+    /// Sprites on higher layers are drawn on top of lower ones
+    pub layer: i32,
+}
+
+/// Collects textured quads and turns them into vertices for the sprite pass, which is drawn over
+/// the scene with alpha blending. The batch is kept between frames; call `clear` to rebuild it.
+///
+/// The vertices are transformed on the CPU with `viewproj`, usually the view-projection matrix of a
+/// camera in 2D mode, so every sprite can be drawn with a single draw call.
+pub struct SpriteBatch {
+    sprites: Vec<Sprite>,
+    viewproj: Mat4,
+    // Layer given to sprites added with `draw_sprite` and `draw_nine_slice`
+    layer: i32,
+    // Destination rectangles are multiplied by this, so they can be given in logical pixels
+    scale_factor: f32,
+    // Needed to turn source rectangles in pixels into texture coordinates. Filled in by
+    // `Renderer::add_texture`.
+    texture_sizes: HashMap<TextureHandle, Vec2>,
+}
+
+impl Default for SpriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpriteBatch {
+    pub fn new() -> Self {
+        Self {
+            sprites: Vec::new(),
+            viewproj: Mat4::IDENTITY,
+            layer: 0,
+            scale_factor: 1.0,
+            texture_sizes: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite);
+    }
+
+    /// Draw the `src_rect` region of the texture into `dst_rect`, on the current layer.
+    /// Sprites from the same atlas share the texture, so they don't break up the batch.
+    pub fn draw_sprite(
+        &mut self,
+        texture: TextureHandle,
+        src_rect: Option<Rect>,
+        dst_rect: Rect,
+        color: [f32; 4],
+    ) {
+        self.push(Sprite {
+            texture,
+            src_rect,
+            dst_rect,
+            color,
+            layer: self.layer,
+        });
+    }
+
+    /// Draw a scalable panel from the `src_rect` region of the texture. The corners keep their
+    /// size, the edges stretch along one axis and the center stretches along both. If `dst_rect`
+    /// is smaller than the borders, they are scaled down to fit.
+    pub fn draw_nine_slice(
+        &mut self,
+        texture: TextureHandle,
+        src_rect: Rect,
+        dst_rect: Rect,
+        borders: Borders,
+        color: [f32; 4],
+    ) {
+        let scale_x = (dst_rect.width / (borders.left + borders.right)).min(1.0);
+        let scale_y = (dst_rect.height / (borders.top + borders.bottom)).min(1.0);
+
+        // Column and row boundaries in the source and destination
+        let src_xs = [
+            src_rect.x,
+            src_rect.x + borders.left,
+            src_rect.x + src_rect.width - borders.right,
+            src_rect.x + src_rect.width,
+        ];
+        let src_ys = [
+            src_rect.y,
+            src_rect.y + borders.top,
+            src_rect.y + src_rect.height - borders.bottom,
+            src_rect.y + src_rect.height,
+        ];
+        let dst_xs = [
+            dst_rect.x,
+            dst_rect.x + borders.left * scale_x,
+            dst_rect.x + dst_rect.width - borders.right * scale_x,
+            dst_rect.x + dst_rect.width,
+        ];
+        let dst_ys = [
+            dst_rect.y,
+            dst_rect.y + borders.top * scale_y,
+            dst_rect.y + dst_rect.height - borders.bottom * scale_y,
+            dst_rect.y + dst_rect.height,
+        ];
+
+        for row in 0..3 {
+            for column in 0..3 {
+                let dst = Rect::new(
+                    dst_xs[column],
+                    dst_ys[row],
+                    dst_xs[column + 1] - dst_xs[column],
+                    dst_ys[row + 1] - dst_ys[row],
+                );
+                // Skip slices with no area, like the edges of a panel that is exactly as big as
+                // its borders
+                if dst.width <= 0.0 || dst.height <= 0.0 {
+                    continue;
+                }
+                let src = Rect::new(
+                    src_xs[column],
+                    src_ys[row],
+                    src_xs[column + 1] - src_xs[column],
+                    src_ys[row + 1] - src_ys[row],
+                );
+                self.draw_sprite(texture, Some(src), dst, color);
+            }
+        }
+    }
+
+    /// Layer given to sprites drawn with `draw_sprite` and `draw_nine_slice` from now on
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
+
+    pub fn set_texture_size(&mut self, texture: TextureHandle, width: u32, height: u32) {
+        self.texture_sizes.insert(texture, Vec2::new(width as f32, height as f32));
+    }
+
+    pub fn clear(&mut self) {
+        self.sprites.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    /// Scale destination rectangles by the display's scale factor, see
+    /// `Renderer::get_scale_factor`, so sprites positioned in logical pixels keep their size on
+    /// HiDPI displays. `viewproj` should then map physical pixels.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    pub fn get_scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    pub fn set_viewproj(&mut self, viewproj: Mat4) {
+        self.viewproj = viewproj;
+    }
+
+    pub fn get_viewproj(&self) -> Mat4 {
+        self.viewproj
+    }
+
+    /// Vertices of every sprite, sorted by layer and then by texture. The sort is stable, so
+    /// sprites on the same layer with the same texture keep the order they were pushed in.
+    pub fn build_vertices(&self) -> Result<Vec<PerSpriteVertexData>> {
+        let mut sprites = self.sprites.iter().collect::<Vec<_>>();
+        sprites.sort_by_key(|sprite| (sprite.layer, sprite.texture));
+
+        let mut vertices = Vec::with_capacity(sprites.len() * VERTICES_PER_SPRITE);
+        for sprite in sprites {
+            let (uv_min, uv_max) = match sprite.src_rect {
+                Some(src_rect) => {
+                    let texture_size = self.texture_sizes
+                        .get(&sprite.texture)
+                        .ok_or_eyre(format!("Size of texture {:?} is unknown", sprite.texture))?;
+                    (
+                        Vec2::new(src_rect.x, src_rect.y) / *texture_size,
+                        Vec2::new(src_rect.x + src_rect.width, src_rect.y + src_rect.height)
+                            / *texture_size,
+                    )
+                }
+                None => (Vec2::ZERO, Vec2::ONE),
+            };
+            let rect = Rect::new(
+                sprite.dst_rect.x * self.scale_factor,
+                sprite.dst_rect.y * self.scale_factor,
+                sprite.dst_rect.width * self.scale_factor,
+                sprite.dst_rect.height * self.scale_factor,
+            );
+            let corners = [
+                (Vec2::new(rect.x, rect.y), uv_min),
+                (Vec2::new(rect.x + rect.width, rect.y), Vec2::new(uv_max.x, uv_min.y)),
+                (Vec2::new(rect.x + rect.width, rect.y + rect.height), uv_max),
+                (Vec2::new(rect.x, rect.y + rect.height), Vec2::new(uv_min.x, uv_max.y)),
+            ];
+            for corner in [0, 1, 2, 0, 2, 3] {
+                let (position, texcoord) = corners[corner];
+                vertices.push(PerSpriteVertexData {
+                    position: self.viewproj.project_point3(Vec3::new(position.x, position.y, 0.0)),
+                    texcoord,
+                    color: sprite.color,
+                    texture_index: sprite.texture.0 as u32,
+                });
+            }
+        }
+
+        Ok(vertices)
+    }
+
+    /// Describes the layout of `PerSpriteVertexData` for the sprite pipeline
+    pub fn get_input_description() -> VertexInputDescription {
+        let bindings = vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<PerSpriteVertexData>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }];
+
+        let attributes = vec![
+            // Position
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(PerSpriteVertexData, position) as u32,
+            },
+            // Texcoord
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(PerSpriteVertexData, texcoord) as u32,
+            },
+            // Color
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(PerSpriteVertexData, color) as u32,
+            },
+            // Texture index
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 3,
+                format: vk::Format::R32_UINT,
+                offset: offset_of!(PerSpriteVertexData, texture_index) as u32,
+            },
+        ];
+
+        VertexInputDescription {
+            bindings,
+            attributes,
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use std::ops::AddAssign;
+use std::time::Duration;
+
+/// Work submitted for one frame, for profiling. Counts what the recorded command buffers contain,
+/// so replayed cached draws count as much as freshly recorded ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: u32,
+    pub triangles: u64,
+    pub instances: u32,
+    pub pipeline_binds: u32,
+    pub descriptor_binds: u32,
+    /// Instances whose per-object data was copied to the GPU, which only happens when they change
+    pub objects_uploaded: u32,
+    /// CPU time spent recording the scene pass, including batching the instances. Replaying
+    /// cached draws takes next to none.
+    pub scene_record_time: Duration,
+    /// GPU counters for the scene pass, see `Renderer::set_pipeline_statistics_enabled`. They are
+    /// read back when the frame is reused, so they lag a couple of frames behind the other
+    /// counters.
+    pub pipeline_statistics: Option<PipelineStatistics>,
+}
+
+/// Counters from a `PIPELINE_STATISTICS` query, useful for judging vertex load and overdraw
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: u64,
+    pub input_assembly_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    pub clipping_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+impl AddAssign for RenderStats {
+    fn add_assign(&mut self, other: Self) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+        self.instances += other.instances;
+        self.pipeline_binds += other.pipeline_binds;
+        self.descriptor_binds += other.descriptor_binds;
+        self.objects_uploaded += other.objects_uploaded;
+        self.scene_record_time += other.scene_record_time;
+        self.pipeline_statistics = self.pipeline_statistics.or(other.pipeline_statistics);
+    }
+}
@@ -0,0 +1,342 @@
+use ash::vk;
+use ash::vk::Handle;
+use color_eyre::eyre::{eyre, OptionExt};
+use color_eyre::Result;
+use glam::{Mat4, Quat, Vec3};
+use openxr as xr;
+use crate::renderer::camera::Camera;
+use crate::renderer::config::RenderConfig;
+use crate::renderer::contexts::device_ctx::device::RenderDevice;
+use crate::renderer::contexts::device_ctx::instance::RenderInstance;
+use crate::renderer::contexts::device_ctx::RenderDeviceContext;
+use crate::renderer::shader_data::PerFrameData;
+use crate::renderer::Renderer;
+
+const VIEW_CONFIGURATION_TYPE: xr::ViewConfigurationType = xr::ViewConfigurationType::PRIMARY_STEREO;
+const SWAPCHAIN_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// Swapchain the XR runtime composites for one eye
+struct EyeSwapchain {
+    swapchain: xr::Swapchain<xr::Vulkan>,
+    images: Vec<vk::Image>,
+    extent: vk::Extent2D,
+}
+
+/// Stereo rendering through an OpenXR runtime. The runtime picks the physical device and adds the
+/// Vulkan instance and device extensions it needs, so the renderer is created through here rather
+/// than with `Renderer::new`.
+///
+/// Each eye is drawn in its own pass into its own swapchain, with the renderer's frame data set to
+/// that eye's view-projection matrix. The matrices are computed every frame from the located
+/// views, see `get_eye_view_projs`.
+pub struct XrRuntime {
+    instance: xr::Instance,
+    session: xr::Session<xr::Vulkan>,
+    frame_waiter: xr::FrameWaiter,
+    frame_stream: xr::FrameStream<xr::Vulkan>,
+    space: xr::Space,
+    eye_swapchains: Vec<EyeSwapchain>,
+    eye_view_projs: Vec<Mat4>,
+    session_running: bool,
+}
+
+impl XrRuntime {
+    /// Connect to the XR runtime and create a renderer on the device it asks for
+    pub fn new(application_name: &str, config: &RenderConfig) -> Result<(Self, Renderer)> {
+        let entry = unsafe {
+            xr::Entry::load()?
+        };
+
+        let available_extensions = entry.enumerate_extensions()?;
+        if !available_extensions.khr_vulkan_enable2 {
+            return Err(eyre!("OpenXR runtime does not support XR_KHR_vulkan_enable2"));
+        }
+        let mut enabled_extensions = xr::ExtensionSet::default();
+        enabled_extensions.khr_vulkan_enable2 = true;
+
+        let instance = entry.create_instance(
+            &xr::ApplicationInfo {
+                application_name,
+                application_version: 0,
+                engine_name: "raxa",
+                engine_version: 0,
+                api_version: xr::Version::new(1, 0, 0),
+            },
+            &enabled_extensions,
+            &[],
+        )?;
+        let system = instance.system(xr::FormFactor::HEAD_MOUNTED_DISPLAY)?;
+        // The runtime requires this query before the session is created
+        let requirements = instance.graphics_requirements::<xr::Vulkan>(system)?;
+        if requirements.min_api_version_supported > xr::Version::new(1, 3, 0) {
+            return Err(eyre!(
+                "OpenXR runtime requires Vulkan {} or newer",
+                requirements.min_api_version_supported,
+            ));
+        }
+
+        let view_configuration_views = instance.enumerate_view_configuration_views(
+            system,
+            VIEW_CONFIGURATION_TYPE,
+        )?;
+        let eye_extents = view_configuration_views
+            .iter()
+            .map(|view| vk::Extent2D {
+                width: view.recommended_image_rect_width,
+                height: view.recommended_image_rect_height,
+            })
+            .collect::<Vec<_>>();
+        // The draw images are shared by both eyes, so they are sized for the larger one
+        let draw_extent = vk::Extent2D {
+            width: eye_extents.iter().map(|e| e.width).max().ok_or_eyre("XR system has no views")?,
+            height: eye_extents.iter().map(|e| e.height).max().ok_or_eyre("XR system has no views")?,
+        };
+
+        // Let the runtime create the Vulkan instance and device so it can add its extensions
+        let render_instance = RenderInstance::new_with_instance_creator(
+            None,
+            |entry, instance_info| {
+                let vk_instance = unsafe {
+                    instance.create_vulkan_instance(
+                        system,
+                        std::mem::transmute(entry.static_fn().get_instance_proc_addr),
+                        instance_info as *const _ as *const _,
+                    )?
+                }
+                    .map_err(vk::Result::from_raw)?;
+                Ok(unsafe {
+                    ash::Instance::load(entry.static_fn(), vk::Instance::from_raw(vk_instance as _))
+                })
+            },
+        )?;
+        let physical_device = vk::PhysicalDevice::from_raw(
+            instance.vulkan_graphics_device(
+                system,
+                render_instance.instance.handle().as_raw() as _,
+            )? as _
+        );
+        let render_device = RenderDevice::new_with_device_creator(
+            &render_instance,
+            physical_device,
+            config,
+            |device_info| {
+                let vk_device = unsafe {
+                    instance.create_vulkan_device(
+                        system,
+                        std::mem::transmute(render_instance.entry.static_fn().get_instance_proc_addr),
+                        physical_device.as_raw() as _,
+                        device_info as *const _ as *const _,
+                    )?
+                }
+                    .map_err(vk::Result::from_raw)?;
+                Ok(unsafe {
+                    ash::Device::load(
+                        render_instance.instance.fp_v1_0(),
+                        vk::Device::from_raw(vk_device as _),
+                    )
+                })
+            },
+        )?;
+
+        let (session, frame_waiter, frame_stream) = unsafe {
+            instance.create_session::<xr::Vulkan>(
+                system,
+                &xr::vulkan::SessionCreateInfo {
+                    instance: render_instance.instance.handle().as_raw() as _,
+                    physical_device: physical_device.as_raw() as _,
+                    device: render_device.logical.handle().as_raw() as _,
+                    queue_family_index: render_device.graphics_queue.family.index,
+                    queue_index: 0,
+                },
+            )?
+        };
+        let space = session.create_reference_space(
+            xr::ReferenceSpaceType::LOCAL,
+            xr::Posef::IDENTITY,
+        )?;
+
+        let eye_swapchains = eye_extents
+            .iter()
+            .map(|extent| {
+                let swapchain = session.create_swapchain(&xr::SwapchainCreateInfo {
+                    create_flags: xr::SwapchainCreateFlags::EMPTY,
+                    usage_flags: xr::SwapchainUsageFlags::COLOR_ATTACHMENT
+                        | xr::SwapchainUsageFlags::TRANSFER_DST,
+                    format: SWAPCHAIN_FORMAT.as_raw() as _,
+                    sample_count: 1,
+                    width: extent.width,
+                    height: extent.height,
+                    face_count: 1,
+                    array_size: 1,
+                    mip_count: 1,
+                })?;
+                let images = swapchain
+                    .enumerate_images()?
+                    .into_iter()
+                    .map(vk::Image::from_raw)
+                    .collect();
+                Ok(EyeSwapchain {
+                    swapchain,
+                    images,
+                    extent: *extent,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let dev_ctx = RenderDeviceContext::from_parts(
+            render_instance,
+            render_device,
+            None,
+            None,
+            config.present_mode,
+        )?;
+        let renderer = Renderer::from_device_context(dev_ctx, Some(draw_extent), config)?;
+
+        let eye_count = eye_swapchains.len();
+        Ok((
+            Self {
+                instance,
+                session,
+                frame_waiter,
+                frame_stream,
+                space,
+                eye_swapchains,
+                eye_view_projs: vec![Mat4::IDENTITY; eye_count],
+                session_running: false,
+            },
+            renderer,
+        ))
+    }
+
+    /// Handle session state changes. Returns false once the runtime wants the application to exit.
+    pub fn poll_events(&mut self) -> Result<bool> {
+        let mut event_buffer = xr::EventDataBuffer::new();
+        while let Some(event) = self.instance.poll_event(&mut event_buffer)? {
+            match event {
+                xr::Event::SessionStateChanged(event) => match event.state() {
+                    xr::SessionState::READY => {
+                        self.session.begin(VIEW_CONFIGURATION_TYPE)?;
+                        self.session_running = true;
+                    }
+                    xr::SessionState::STOPPING => {
+                        self.session.end()?;
+                        self.session_running = false;
+                    }
+                    xr::SessionState::EXITING | xr::SessionState::LOSS_PENDING => {
+                        return Ok(false);
+                    }
+                    _ => {}
+                },
+                xr::Event::InstanceLossPending(_) => return Ok(false),
+                _ => {}
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Render both eyes and submit them to the compositor. Does nothing until the session is
+    /// running.
+    pub fn render_frame(
+        &mut self,
+        renderer: &mut Renderer,
+        camera: &Camera,
+    ) -> Result<()> {
+        if !self.session_running {
+            return Ok(());
+        }
+
+        let frame_state = self.frame_waiter.wait()?;
+        self.frame_stream.begin()?;
+
+        if !frame_state.should_render {
+            self.frame_stream.end(
+                frame_state.predicted_display_time,
+                xr::EnvironmentBlendMode::OPAQUE,
+                &[],
+            )?;
+            return Ok(());
+        }
+
+        // The eye matrices replace the camera's own, and the depth mapping follows the camera
+        renderer.set_reverse_z(camera.get_reverse_z());
+        let (_, views) = self.session.locate_views(
+            VIEW_CONFIGURATION_TYPE,
+            frame_state.predicted_display_time,
+            &self.space,
+        )?;
+
+        for (eye_index, view) in views.iter().enumerate() {
+            let eye_position = Vec3::new(
+                view.pose.position.x,
+                view.pose.position.y,
+                view.pose.position.z,
+            );
+            let eye_orientation = Quat::from_xyzw(
+                view.pose.orientation.x,
+                view.pose.orientation.y,
+                view.pose.orientation.z,
+                view.pose.orientation.w,
+            );
+            let eye_view_proj = camera.get_eye_proj_mat(
+                view.fov.angle_left,
+                view.fov.angle_right,
+                view.fov.angle_up,
+                view.fov.angle_down,
+            ) * camera.get_eye_view_mat(eye_position, eye_orientation);
+            self.eye_view_projs[eye_index] = eye_view_proj;
+            renderer.set_frame_data(PerFrameData::new(
+                eye_view_proj,
+                camera.get_near(),
+                camera.get_far(),
+            ));
+
+            let eye = &mut self.eye_swapchains[eye_index];
+            let image_index = eye.swapchain.acquire_image()?;
+            eye.swapchain.wait_image(xr::Duration::INFINITE)?;
+            renderer.draw_to_image(
+                eye.images[image_index as usize],
+                eye.extent,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            )?;
+            eye.swapchain.release_image()?;
+        }
+
+        let projection_views = views
+            .iter()
+            .zip(self.eye_swapchains.iter())
+            .map(|(view, eye)| {
+                xr::CompositionLayerProjectionView::new()
+                    .pose(view.pose)
+                    .fov(view.fov)
+                    .sub_image(
+                        xr::SwapchainSubImage::new()
+                            .swapchain(&eye.swapchain)
+                            .image_array_index(0)
+                            .image_rect(xr::Rect2Di {
+                                offset: xr::Offset2Di { x: 0, y: 0 },
+                                extent: xr::Extent2Di {
+                                    width: eye.extent.width as i32,
+                                    height: eye.extent.height as i32,
+                                },
+                            }),
+                    )
+            })
+            .collect::<Vec<_>>();
+        let projection_layer = xr::CompositionLayerProjection::new()
+            .space(&self.space)
+            .views(&projection_views);
+        self.frame_stream.end(
+            frame_state.predicted_display_time,
+            xr::EnvironmentBlendMode::OPAQUE,
+            &[&projection_layer],
+        )?;
+
+        Ok(())
+    }
+
+    /// View-projection matrix of each eye from the last rendered frame
+    pub fn get_eye_view_projs(&self) -> &[Mat4] {
+        &self.eye_view_projs
+    }
+}
@@ -0,0 +1,80 @@
+//! Draws two overlapping quads with additive blending and checks that their colors sum up where
+//! they overlap
+
+mod common;
+
+use ash::vk;
+use glam::{Mat4, Vec3};
+use raxa::renderer::config::RenderConfig;
+use raxa::renderer::resources::material::GraphicsMaterialFactoryBuilder;
+use raxa::renderer::resources::mesh::Mesh;
+use raxa::renderer::resources::shader::GraphicsShader;
+use raxa::renderer::scene::Transform;
+use raxa::renderer::shader_data::{PerFrameData, PerMaterialData};
+use raxa::renderer::Renderer;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 64,
+    height: 64,
+};
+
+#[test]
+fn overlapping_additive_quads_sum_up() {
+    if !common::has_vulkan_device() {
+        eprintln!("No Vulkan device found, skipping");
+        return;
+    }
+
+    let mut renderer = Renderer::new_headless(EXTENT, &RenderConfig::default()).unwrap();
+    let bindless_factory = renderer.get_material_factory();
+    let device = renderer.get_device();
+    // The bindless layouts, so the materials see the renderer's textures, without a depth test so
+    // the second quad isn't hidden behind the first one
+    let mut factory = GraphicsMaterialFactoryBuilder::new(
+        device.logical.clone(),
+        device.descriptor_allocator.clone(),
+    )
+        .with_shader(GraphicsShader::new("default", device.logical.clone()).unwrap())
+        .with_pipeline_layout(bindless_factory.get_pipeline_layout())
+        .with_descriptor_set_layout(bindless_factory.get_descriptor_set_layout())
+        .with_bindless_limits(*device.get_bindless_limits())
+        .with_material_data(
+            size_of::<PerMaterialData>() as u64,
+            2,
+            device.get_memory_allocator().clone(),
+        )
+        .with_additive_blending_enabled()
+        .with_depth_test(false, None)
+        .with_color_attachment_format(vk::Format::R8G8B8A8_SRGB)
+        .with_depth_attachment_format(vk::Format::D32_SFLOAT)
+        .build()
+        .unwrap();
+
+    let solid = |color: [u8; 4]| image::DynamicImage::ImageRgba8(
+        image::RgbaImage::from_pixel(1, 1, image::Rgba(color)),
+    );
+    let model = renderer.add_model(vec![Mesh::new_quad()]).unwrap();
+    // Half-size quads covering x from -0.75 to 0.25 and from -0.25 to 0.75 in clip space
+    for (color, x) in [([255, 0, 0, 255], -0.25), ([0, 255, 0, 255], 0.25)] {
+        let texture = renderer.add_texture(&solid(color)).unwrap();
+        let material = renderer.add_material(factory.create_material().unwrap());
+        renderer.set_material_data(material, &PerMaterialData {
+            texture_index: texture.get_index(),
+            sampler_index: renderer.get_texture_sampler_index(texture).unwrap(),
+        }).unwrap();
+        renderer.add_instance(model, material, Transform {
+            translation: Vec3::new(x, 0.0, 0.0),
+            scale: Vec3::splat(0.5),
+            ..Default::default()
+        });
+    }
+    renderer.set_frame_data(PerFrameData::new(Mat4::IDENTITY, 0.1, 100.0));
+
+    let pixels = common::draw_and_read_back(&mut renderer, EXTENT).unwrap();
+    let row = EXTENT.height / 2 * EXTENT.width;
+    let pixel_at = |x: u32| &pixels[(row + x) as usize][..3];
+    // Only the red quad, both quads, only the green quad
+    assert_eq!(pixel_at(16), [255, 0, 0]);
+    assert_eq!(pixel_at(32), [255, 255, 0]);
+    assert_eq!(pixel_at(48), [0, 255, 0]);
+}
@@ -0,0 +1,81 @@
+//! Helpers shared by the tests that render on a real device
+
+use std::time::{Duration, Instant};
+use ash::vk;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use raxa::renderer::config::RenderConfig;
+use raxa::renderer::resources::readback::ReadbackBuffer;
+use raxa::renderer::Renderer;
+
+/// Whether a device the renderer can use is available. Tests that render skip themselves without
+/// one, e.g. on CI machines without a GPU or software rasterizer.
+pub fn has_vulkan_device() -> bool {
+    Renderer::is_headless_supported(&RenderConfig::default())
+}
+
+/// Draw the renderer's scene into a new image of `extent` and read it back as RGBA8 pixels, row
+/// by row
+pub fn draw_and_read_back(renderer: &mut Renderer, extent: vk::Extent2D) -> Result<Vec<[u8; 4]>> {
+    let device = renderer.get_device();
+    let image = device.create_offscreen_image(extent.width, extent.height)?;
+    let size = extent.width as u64 * extent.height as u64 * 4;
+    let buffer = device.create_buffer(
+        size,
+        16,
+        vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+        vk_mem::MemoryUsage::AutoPreferDevice,
+        false,
+    )?;
+    let mut readback = ReadbackBuffer::new(size, 1, device)?;
+
+    renderer.draw_to_image(image.image, extent, vk::ImageLayout::TRANSFER_SRC_OPTIMAL)?;
+
+    let device = renderer.get_device();
+    let queue = device.graphics_queue.clone();
+    device.immediate_submit_on(&queue, |cmd, device| {
+        let barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ);
+        let barriers = [barrier];
+        let dependency_info = vk::DependencyInfo::default()
+            .memory_barriers(&barriers);
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            })
+            .image_extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+        unsafe {
+            device.cmd_pipeline_barrier2(cmd, &dependency_info);
+            device.cmd_copy_image_to_buffer(
+                cmd,
+                image.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                buffer.buffer,
+                &[region],
+            );
+        }
+        Ok(())
+    })?;
+    readback.schedule_copy(buffer.buffer, 0, &queue, device)?;
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(pixels) = readback.try_read_as::<[u8; 4]>()? {
+            return Ok(pixels);
+        }
+        if Instant::now() > deadline {
+            return Err(eyre!("Readback did not finish within 5 seconds"));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,48 @@
+//! Draws a triangle without a window and checks the pixels that come back
+
+mod common;
+
+use ash::vk;
+use glam::Mat4;
+use raxa::renderer::config::RenderConfig;
+use raxa::renderer::resources::mesh::Mesh;
+use raxa::renderer::scene::Transform;
+use raxa::renderer::shader_data::{PerFrameData, PerMaterialData};
+use raxa::renderer::Renderer;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 64,
+    height: 64,
+};
+const BACKGROUND: [u8; 4] = [0, 0, 0, 255];
+
+#[test]
+fn headless_triangle() {
+    if !common::has_vulkan_device() {
+        eprintln!("No Vulkan device found, skipping");
+        return;
+    }
+
+    let mut renderer = Renderer::new_headless(EXTENT, &RenderConfig::default()).unwrap();
+    let white = image::DynamicImage::ImageRgba8(
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([255, 255, 255, 255])),
+    );
+    let texture = renderer.add_texture(&white).unwrap();
+    let material = renderer.create_material().unwrap();
+    renderer.set_material_data(material, &PerMaterialData {
+        texture_index: texture.get_index(),
+        sampler_index: renderer.get_texture_sampler_index(texture).unwrap(),
+    }).unwrap();
+    let model = renderer.add_model(vec![Mesh::new_triangle()]).unwrap();
+    renderer.add_instance(model, material, Transform::default());
+    // The triangle's vertices are already in clip space
+    renderer.set_frame_data(PerFrameData::new(Mat4::IDENTITY, 0.1, 100.0));
+
+    let pixels = common::draw_and_read_back(&mut renderer, EXTENT).unwrap();
+    let pixel = |x: u32, y: u32| pixels[(y * EXTENT.width + x) as usize];
+
+    assert_ne!(pixel(32, 32), BACKGROUND, "the triangle covers the center");
+    for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+        assert_eq!(pixel(x, y), BACKGROUND, "the corner at {}, {} is background", x, y);
+    }
+}
@@ -0,0 +1,48 @@
+//! Changes a material's data between frames and checks that the next frame draws with it
+
+mod common;
+
+use ash::vk;
+use glam::Mat4;
+use raxa::renderer::config::RenderConfig;
+use raxa::renderer::resources::mesh::Mesh;
+use raxa::renderer::scene::Transform;
+use raxa::renderer::shader_data::{PerFrameData, PerMaterialData};
+use raxa::renderer::Renderer;
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 64,
+    height: 64,
+};
+
+#[test]
+fn material_data_changes_reach_the_next_frame() {
+    if !common::has_vulkan_device() {
+        eprintln!("No Vulkan device found, skipping");
+        return;
+    }
+
+    let mut renderer = Renderer::new_headless(EXTENT, &RenderConfig::default()).unwrap();
+    let solid = |color: [u8; 4]| image::DynamicImage::ImageRgba8(
+        image::RgbaImage::from_pixel(1, 1, image::Rgba(color)),
+    );
+    let red = renderer.add_texture(&solid([255, 0, 0, 255])).unwrap();
+    let green = renderer.add_texture(&solid([0, 255, 0, 255])).unwrap();
+    let material = renderer.create_material().unwrap();
+    let model = renderer.add_model(vec![Mesh::new_triangle()]).unwrap();
+    renderer.add_instance(model, material, Transform::default());
+    // The triangle's vertices are already in clip space
+    renderer.set_frame_data(PerFrameData::new(Mat4::IDENTITY, 0.1, 100.0));
+
+    for texture in [red, green, red] {
+        renderer.set_material_data(material, &PerMaterialData {
+            texture_index: texture.get_index(),
+            sampler_index: renderer.get_texture_sampler_index(texture).unwrap(),
+        }).unwrap();
+        let pixels = common::draw_and_read_back(&mut renderer, EXTENT).unwrap();
+        let center = pixels[(32 * EXTENT.width + 32) as usize];
+        let expected_channel = if texture == red { 0 } else { 1 };
+        assert_eq!(center[expected_channel], 255, "the center is {:?}", center);
+        assert_eq!(center[1 - expected_channel], 0, "the center is {:?}", center);
+    }
+}